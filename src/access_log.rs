@@ -0,0 +1,133 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::traffic::TrafficRecord;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "text" => Ok(AccessLogFormat::Text),
+            "json" => Ok(AccessLogFormat::Json),
+            other => Err(format!("unknown access log format {other:?}")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    at: Timestamp,
+    method: &'a str,
+    uri: &'a str,
+    destination: Option<&'a str>,
+    status: u16,
+    rule: Option<&'a str>,
+    faults: &'a [&'static str],
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deployment_marker: Option<&'a str>,
+}
+
+impl<'a> From<&'a TrafficRecord> for Entry<'a> {
+    fn from(record: &'a TrafficRecord) -> Self {
+        Self {
+            at: record.at,
+            method: &record.method,
+            uri: &record.uri,
+            destination: record.destination.as_deref(),
+            status: record.status,
+            rule: record.rule.as_deref(),
+            faults: &record.faults,
+            latency_ms: record.duration_ms,
+            deployment_marker: record.deployment_marker.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "HTTP {} {} {} destination={} rule={} faults={} latency={}ms",
+            self.status,
+            self.method,
+            self.uri,
+            self.destination.unwrap_or("-"),
+            self.rule.unwrap_or("-"),
+            match self.faults {
+                [] => "none".to_string(),
+                faults => faults.join(","),
+            },
+            self.latency_ms
+        )?;
+        if let Some(marker) = self.deployment_marker {
+            write!(f, " deployment-marker={marker}")?;
+        }
+        Ok(())
+    }
+}
+
+// One record per proxied request, written to the regular log unless a file
+// is configured. Files get one line per record and are appended to.
+#[derive(Default)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Option<(PathBuf, Mutex<File>)>,
+}
+
+impl AccessLog {
+    pub fn new(format: AccessLogFormat) -> Self {
+        Self { format, file: None }
+    }
+
+    pub fn to_file(format: AccessLogFormat, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            format,
+            file: Some((path, Mutex::new(file))),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    pub fn write(&self, record: &TrafficRecord) {
+        let entry = Entry::from(record);
+        let line = match self.format {
+            AccessLogFormat::Text => entry.to_string(),
+            AccessLogFormat::Json => match serde_json::to_string(&entry) {
+                Ok(line) => line,
+                Err(err) => {
+                    warn!("Failed to serialize access log record: {err}");
+                    return;
+                }
+            },
+        };
+        match &self.file {
+            Some((path, file)) => {
+                if let Err(err) = writeln!(file.lock(), "{line}") {
+                    warn!("Failed to write access log to {}: {err}", path.display());
+                }
+            }
+            None => info!("{line}"),
+        }
+    }
+}
@@ -1,90 +1,1905 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
     Router,
     body::Body,
-    extract::State,
-    http::{HeaderMap, Response, StatusCode},
-    routing::{get, post},
+    extract::{Path, Query, Request, State, rejection::QueryRejection},
+    http::{
+        HeaderMap, HeaderValue, Method, Response, StatusCode,
+        header::{ETAG, HOST, IF_MATCH},
+    },
+    middleware::{self, Next},
+    routing::{delete, get, post, put},
 };
-use serde_json::json;
+use jiff::{SignedDuration, Timestamp};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
 use tracing::info;
+use uuid::Uuid;
 
-use crate::response::json_response;
+use crate::admin_chaos::{AdminChaos, CONFIG_PATH as ADMIN_CHAOS_PATH};
+use crate::assertions::parse_expectations;
+use crate::binding::OutboundBinding;
+use crate::credentials::UpstreamCredentials;
+use crate::default_behavior::DefaultBehavior;
+use crate::envoy::import_fault_config;
+use crate::experiments::{self, TEMPLATES, Template, parse_params};
+use crate::export::{ExportFormat, render};
+use crate::limits::DestinationLimits;
+use crate::random;
+use crate::report::{
+    DEFAULT_SLO, ReportFormat, build as build_report, render_html, render_markdown,
+};
+use crate::response::{json_response, text_response};
+use crate::rules::{NamedRule, RequestPredicate, Rule, analyze, settings_layer};
+use crate::scenarios::{self, StartError};
+use crate::schema::settings_schema;
 use crate::settings::{Settings, SettingsLayer};
-use crate::state::AppState;
+use crate::snapshots::{RecordingSession, SnapshotWriter};
+use crate::state::{AddOneOffError, AppState, MAX_TRACKED_ONE_OFFS, VersionConflict};
+use crate::stats::{self, Granularity, StatsFormat};
+use crate::tasks::CancelError;
+use crate::toxiproxy;
+use crate::traffic::AdminChange;
+use crate::wiremock::import_mappings;
+
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
 
 pub fn router(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/api/v1/update", post(update))
         .route("/api/v1/reset", post(reset))
         .route("/api/v1/list", get(list_settings))
-        .route("/api/v1/one-off", post(add_one_off))
+        .route("/api/v1/reload", post(reload_config))
+        .route("/api/v1/schema", get(schema))
+        .route("/api/v1/one-off", post(add_one_off).get(list_one_offs))
+        .route("/api/v1/one-off/:id", delete(delete_one_off))
+        .route("/api/v1/one-off/:id/wait", get(wait_one_off))
+        .route("/api/v1/rules", get(list_rules).post(put_rule))
+        .route("/api/v1/rules/analysis", get(analyze_rules))
+        .route("/api/v1/rules/:name", delete(delete_rule))
+        .route("/api/v1/profiles", get(list_profiles))
+        .route(
+            "/api/v1/profiles/:name",
+            put(put_profile).delete(delete_profile),
+        )
         .route("/api/v1/list-headers", post(list_headers))
+        .route("/api/v1/metrics", get(metrics))
+        .route("/api/v1/export", get(export))
+        .route("/api/v1/status", get(status))
+        .route("/api/v1/report", get(report))
+        .route("/api/v1/stats/export", get(export_stats))
+        .route("/api/v1/recordings", get(list_recordings))
+        .route(
+            "/api/v1/recordings/:test_id",
+            get(recording).put(start_recording).delete(stop_recording),
+        )
+        .route("/api/v1/tasks", get(list_tasks))
+        .route(
+            "/api/v1/duplicates",
+            get(list_comparisons).delete(clear_comparisons),
+        )
+        .route("/api/v1/log-level", get(log_level).put(set_log_level))
+        .route(
+            "/api/v1/deployment-marker",
+            get(deployment_marker)
+                .put(set_deployment_marker)
+                .delete(clear_deployment_marker),
+        )
+        .route("/api/v1/tasks/:id", delete(cancel_task))
+        .route("/api/v1/experiments", get(list_experiments))
+        .route(
+            "/api/v1/experiments/:template/start",
+            post(start_experiment),
+        )
+        .route(
+            "/api/v1/scenario",
+            post(start_scenario).delete(cancel_scenario),
+        )
+        .route("/api/v1/scenario/status", get(scenario_status))
+        .route("/api/v1/kv", get(list_kv))
+        .route(
+            "/api/v1/kv/:key",
+            get(kv_entry).put(set_kv_entry).delete(delete_kv_entry),
+        )
+        .route(
+            "/api/v1/default-behavior",
+            get(default_behavior)
+                .put(set_default_behavior)
+                .delete(reset_default_behavior),
+        )
+        .route("/api/v1/outbound-bindings", get(list_bindings))
+        .route(
+            "/api/v1/outbound-bindings/:destination",
+            get(destination_binding)
+                .put(set_destination_binding)
+                .delete(delete_destination_binding),
+        )
+        .route(
+            "/api/v1/destination-settings",
+            get(list_destination_settings),
+        )
+        .route(
+            "/api/v1/destination-settings/:destination",
+            get(destination_settings)
+                .put(set_destination_settings)
+                .delete(delete_destination_settings),
+        )
+        .route("/api/v1/upstream-credentials", get(list_credentials))
+        .route(
+            "/api/v1/upstream-credentials/:destination",
+            get(destination_credentials)
+                .put(set_destination_credentials)
+                .delete(delete_destination_credentials),
+        )
+        .route("/api/v1/limits", get(list_limits))
+        .route(
+            "/api/v1/limits/:destination",
+            get(destination_limits)
+                .put(set_destination_limits)
+                .delete(delete_destination_limits),
+        )
+        .route("/api/v1/assertions", post(add_assertion))
+        .route("/api/v1/assertions/:id", delete(delete_assertion))
+        .route("/api/v1/assertions/:id/result", get(assertion_result))
+        .route("/api/v1/import/envoy", post(import_envoy))
+        .route("/api/v1/stubs", get(list_stubs).delete(clear_stubs))
+        .route("/api/v1/stubs/:id", delete(delete_stub))
+        .route("/api/v1/stubs/import/wiremock", post(import_wiremock))
+        .route("/api/v1/streams", get(list_streams))
+        .route("/api/v1/streams/:id", delete(terminate_stream))
         .route("/", get(service_root))
         .route("/health", get(health))
         .route("/healthcheck", get(health))
+        .merge(toxiproxy::routes())
+        .route(
+            ADMIN_CHAOS_PATH,
+            get(admin_chaos_config).put(set_admin_chaos),
+        )
         .fallback(not_found)
+        .layer(middleware::from_fn_with_state(state.clone(), admin_chaos))
+        .layer(middleware::from_fn_with_state(state.clone(), record_change))
         .with_state(state)
 }
 
-async fn update(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response<Body> {
-    let layer = SettingsLayer::from_headers(&headers);
-    let snapshot = state.merge_admin(layer);
-    json_response(StatusCode::OK, &snapshot, state.body_trailer())
+async fn record_change(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let settings = state
+        .layer_from_headers(request.headers())
+        .entries()
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    let response = next.run(request).await;
+    if method != Method::GET
+        && method != Method::HEAD
+        && path != "/api/v1/list-headers"
+        && response.extensions().get::<DryRun>().is_none()
+        && response.status().is_success()
+    {
+        state.traffic().record_admin_change(AdminChange {
+            at: state.clock().timestamp(),
+            method: method.to_string(),
+            path,
+            settings,
+            deployment_marker: state.deployment_marker(),
+        });
+    }
+    response
+}
+
+async fn admin_chaos(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response<Body> {
+    let Some(chaos) = state.admin_chaos() else {
+        return next.run(request).await;
+    };
+    if request.uri().path() == ADMIN_CHAOS_PATH {
+        return next.run(request).await;
+    }
+    let (delay, fail) = random::scope(state.seeded_rng(), async {
+        let roll = |percentage: u8| percentage > random::rng().gen_range(0..100);
+        (
+            chaos.delay_ms > 0 && roll(chaos.delay_percentage),
+            roll(chaos.fail_percentage),
+        )
+    })
+    .await;
+    if delay {
+        info!("admin-chaos delay {} ms", chaos.delay_ms);
+        state
+            .clock()
+            .sleep(Duration::from_millis(chaos.delay_ms))
+            .await;
+    }
+    if fail {
+        info!(
+            "admin-chaos HTTP {} {}",
+            chaos.fail_code,
+            request.uri().path()
+        );
+        return json_response(
+            StatusCode::from_u16(chaos.fail_code).unwrap_or(StatusCode::SERVICE_UNAVAILABLE),
+            &json!({"error":"admin-chaos"}),
+            state.dev_mode(),
+        );
+    }
+    next.run(request).await
+}
+
+async fn admin_chaos_config(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match state.admin_chaos() {
+        Some(chaos) => json_response(StatusCode::OK, &chaos, state.dev_mode()),
+        None => admin_chaos_disabled(&state),
+    }
+}
+
+async fn set_admin_chaos(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    let config = serde_json::from_str::<AdminChaos>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|config| config.validate().map(|()| config));
+    match config {
+        Ok(config) => {
+            if !state.set_admin_chaos(config.clone()) {
+                return admin_chaos_disabled(&state);
+            }
+            info!("Admin chaos set to {config:?}");
+            json_response(StatusCode::OK, &config, state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-admin-chaos","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+fn admin_chaos_disabled(state: &AppState) -> Response<Body> {
+    json_response(
+        StatusCode::NOT_IMPLEMENTED,
+        &json!({"error":"admin-chaos-disabled"}),
+        state.dev_mode(),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct UpdateQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DryRun;
+
+async fn update(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<UpdateQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let layer = state.layer_from_headers(&headers);
+    if query.dry_run {
+        return preview_update(&state, &layer, if_match);
+    }
+    match state.merge_admin_if(layer, if_match) {
+        Ok((snapshot, version)) => with_etag(
+            json_response(StatusCode::OK, &snapshot, state.dev_mode()),
+            version,
+        ),
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+fn preview_update(
+    state: &AppState,
+    layer: &SettingsLayer,
+    if_match: Option<u64>,
+) -> Response<Body> {
+    let (current, updated, version) = state.preview_admin(layer);
+    if if_match.is_some_and(|expected| expected != version) {
+        return version_conflict(state, VersionConflict { current: version });
+    }
+    let before = settings_object(&current);
+    let after = settings_object(&updated);
+    let changes: serde_json::Map<String, Value> = after
+        .iter()
+        .filter(|(name, value)| before.get(*name) != Some(*value))
+        .map(|(name, value)| {
+            let from = before.get(name).cloned().unwrap_or(Value::Null);
+            (name.clone(), json!({"from": from, "to": value}))
+        })
+        .collect();
+    let mut response = with_etag(
+        json_response(
+            StatusCode::OK,
+            &json!({"dry-run": true, "changes": changes, "settings": updated}),
+            state.dev_mode(),
+        ),
+        version,
+    );
+    response.extensions_mut().insert(DryRun);
+    response
+}
+
+fn settings_object(settings: &Settings) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(object)) => object,
+        _ => serde_json::Map::new(),
+    }
 }
 
 async fn reset(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response<Body> {
-    let layer = SettingsLayer::from_headers(&headers);
-    let snapshot = state.reset_admin(layer);
-    json_response(StatusCode::OK, &snapshot, state.body_trailer())
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let layer = state.layer_from_headers(&headers);
+    match state.reset_admin_if(layer, if_match) {
+        Ok((snapshot, version)) => with_etag(
+            json_response(StatusCode::OK, &snapshot, state.dev_mode()),
+            version,
+        ),
+        Err(conflict) => version_conflict(&state, conflict),
+    }
 }
 
 async fn list_settings(State(state): State<Arc<AppState>>) -> Response<Body> {
-    let snapshot = state.admin_snapshot();
-    json_response(StatusCode::OK, &snapshot, state.body_trailer())
+    let (snapshot, version) = state.versioned_admin_snapshot();
+    with_etag(
+        json_response(StatusCode::OK, &snapshot, state.dev_mode()),
+        version,
+    )
 }
 
-async fn add_one_off(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response<Body> {
-    let layer = SettingsLayer::from_headers(&headers);
-    let mut settings = Settings::default();
-    settings.apply_layer(&layer);
-    state.add_one_off(settings);
-    json_response(
-        StatusCode::OK,
-        &json!({"service":"lowdown","message":"Added one-off"}),
-        state.body_trailer(),
+async fn reload_config(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match state.reload_config() {
+        Ok(Some(config)) => {
+            let settings: serde_json::Map<String, Value> = config
+                .layer
+                .entries()
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = config.references.get(name).cloned().unwrap_or(value);
+                    (name.to_string(), value.into())
+                })
+                .collect();
+            let rules: Vec<&str> = config
+                .rules
+                .iter()
+                .map(|named| named.name.as_str())
+                .collect();
+            with_etag(
+                json_response(
+                    StatusCode::OK,
+                    &json!({
+                        "service":"lowdown",
+                        "message":"Reloaded config file",
+                        "path":state.config_file().map(|path| path.display().to_string()),
+                        "settings":settings,
+                        "rules":rules,
+                    }),
+                    state.dev_mode(),
+                ),
+                state.settings_version(),
+            )
+        }
+        Ok(None) => json_response(
+            StatusCode::CONFLICT,
+            &json!({
+                "error":"config-file-disabled",
+                "message":"set CONFIG_FILE to load settings from a file",
+            }),
+            state.dev_mode(),
+        ),
+        Err(message) => {
+            info!("Failed to reload config file: {message}");
+            json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({"error":"invalid-config-file","message":message}),
+                state.dev_mode(),
+            )
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_if_match(state: &AppState, headers: &HeaderMap) -> Result<Option<u64>, Response<Body>> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default().trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .strip_prefix("W/")
+        .unwrap_or(value)
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| {
+            json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({
+                    "error":"invalid-if-match",
+                    "message":format!("expected a settings version ETag, got {value:?}"),
+                }),
+                state.dev_mode(),
+            )
+        })
+}
+
+fn with_etag(mut response: Response<Body>, version: u64) -> Response<Body> {
+    if let Ok(value) = HeaderValue::from_str(&format!("\"{version}\"")) {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
+
+fn version_conflict(state: &AppState, conflict: VersionConflict) -> Response<Body> {
+    with_etag(
+        json_response(
+            StatusCode::CONFLICT,
+            &json!({
+                "error":"version-conflict",
+                "message":"settings were changed by another request",
+                "current-version":conflict.current,
+            }),
+            state.dev_mode(),
+        ),
+        conflict.current,
+    )
+}
+
+async fn schema(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(StatusCode::OK, &settings_schema(), state.dev_mode())
+}
+
+async fn add_one_off(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let rule = if body.trim().is_empty() {
+        Rule {
+            layer: state.layer_from_headers(&headers),
+            predicate: RequestPredicate::default(),
+            count: 1,
+        }
+    } else {
+        match Rule::parse(&body) {
+            Ok(rule) => rule,
+            Err(message) => {
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    &json!({"error":"invalid-rule","message":message}),
+                    state.dev_mode(),
+                );
+            }
+        }
+    };
+    let normalized = rule.to_json();
+    match state.add_one_off(rule, if_match) {
+        Ok((id, version)) => {
+            let mut response = json!({"service":"lowdown","message":"Added one-off","id":id});
+            if !body.trim().is_empty() {
+                response["rule"] = normalized;
+            }
+            with_etag(
+                json_response(StatusCode::OK, &response, state.dev_mode()),
+                version,
+            )
+        }
+        Err(AddOneOffError::Conflict(conflict)) => version_conflict(&state, conflict),
+        Err(AddOneOffError::Full) => json_response(
+            StatusCode::CONFLICT,
+            &json!({"error":"too-many-one-offs","limit":MAX_TRACKED_ONE_OFFS}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn list_one_offs(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let version = state.settings_version();
+    with_etag(
+        paginated_response(
+            &state,
+            state.pending_one_offs(),
+            &query,
+            |one_off| one_off.id.to_string(),
+            |page| json!({"one-offs": page}),
+        ),
+        version,
+    )
+}
+
+async fn delete_one_off(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let Ok(uuid) = Uuid::parse_str(&id) else {
+        return not_found(State(state)).await;
+    };
+    match state.delete_one_off(uuid, if_match) {
+        Ok(Some(version)) => with_etag(
+            json_response(
+                StatusCode::OK,
+                &json!({"service":"lowdown","message":"Deleted one-off","id":id}),
+                state.dev_mode(),
+            ),
+            version,
+        ),
+        Ok(None) => not_found(State(state)).await,
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+const DEFAULT_WAIT_MS: u64 = 30_000;
+const MAX_WAIT_MS: u64 = 300_000;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct WaitQuery {
+    timeout_ms: Option<u64>,
+}
+
+async fn wait_one_off(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    query: Result<Query<WaitQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let Some(mut progress) = Uuid::parse_str(&id)
+        .ok()
+        .and_then(|id| state.one_off_progress(id))
+    else {
+        return not_found(State(state)).await;
+    };
+    let timeout = query.timeout_ms.unwrap_or(DEFAULT_WAIT_MS).min(MAX_WAIT_MS);
+    let consumed = tokio::time::timeout(
+        Duration::from_millis(timeout),
+        progress.wait_for(|progress| progress.consumed),
+    )
+    .await
+    .is_ok_and(|result| result.is_ok());
+    let progress = progress.borrow().clone();
+    if consumed {
+        json_response(
+            StatusCode::OK,
+            &json!({"id": id, "consumed": true, "matches": progress.matches}),
+            state.dev_mode(),
+        )
+    } else {
+        json_response(
+            StatusCode::REQUEST_TIMEOUT,
+            &json!({
+                "error": "one-off-not-consumed",
+                "id": id,
+                "consumed": false,
+                "matches": progress.matches,
+            }),
+            state.dev_mode(),
+        )
+    }
+}
+
+async fn analyze_rules(State(state): State<Arc<AppState>>) -> Response<Body> {
+    let rules = state.named_rules();
+    let ordered: Vec<&NamedRule> = rules.iter().collect();
+    json_response(StatusCode::OK, &analyze(&ordered), state.dev_mode())
+}
+
+async fn list_rules(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let version = state.settings_version();
+    let rules: Vec<Value> = state.named_rules().iter().map(NamedRule::to_json).collect();
+    with_etag(
+        paginated_response(
+            &state,
+            rules,
+            &query,
+            |rule| rule["name"].as_str().unwrap_or_default().to_string(),
+            |page| json!({"rules": page}),
+        ),
+        version,
+    )
+}
+
+async fn put_rule(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let named = match NamedRule::parse(&body) {
+        Ok(named) => named,
+        Err(message) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({"error":"invalid-rule","message":message}),
+                state.dev_mode(),
+            );
+        }
+    };
+    let normalized = named.to_json();
+    match state.put_named_rule(named, if_match) {
+        Ok((created, version)) => {
+            let status = if created {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            with_etag(
+                json_response(status, &normalized, state.dev_mode()),
+                version,
+            )
+        }
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+async fn delete_rule(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    match state.delete_named_rule(&name, if_match) {
+        Ok(Some(version)) => with_etag(
+            json_response(
+                StatusCode::OK,
+                &json!({"service":"lowdown","message":"Deleted rule","name":name}),
+                state.dev_mode(),
+            ),
+            version,
+        ),
+        Ok(None) => not_found(State(state)).await,
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+async fn list_profiles(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let version = state.settings_version();
+    let references = state.profile_references();
+    let profiles = state.profiles().into_iter().map(|(name, layer)| {
+        let written = references.get(&name);
+        let settings = layer
+            .entries()
+            .into_iter()
+            .map(|(setting, value)| {
+                let value = written
+                    .and_then(|written| written.get(setting))
+                    .cloned()
+                    .unwrap_or(value);
+                (setting, value)
+            })
+            .collect::<BTreeMap<&str, String>>();
+        (name, settings)
+    });
+    with_etag(
+        paginated_response(
+            &state,
+            profiles,
+            &query,
+            |(name, _)| name.clone(),
+            |page| json!({"profiles": as_map(page)}),
+        ),
+        version,
     )
 }
 
-async fn list_headers(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response<Body> {
+async fn put_profile(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    let layer = serde_json::from_str::<BTreeMap<String, Value>>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|settings| settings_layer(&settings));
+    let layer = match layer {
+        Ok(layer) => layer,
+        Err(message) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({"error":"invalid-profile","message":message}),
+                state.dev_mode(),
+            );
+        }
+    };
+    let settings: BTreeMap<&str, String> = layer.entries().into_iter().collect();
+    match state.put_profile(&name, layer, if_match) {
+        Ok((created, version)) => {
+            let status = if created {
+                StatusCode::CREATED
+            } else {
+                StatusCode::OK
+            };
+            with_etag(
+                json_response(
+                    status,
+                    &json!({"name":name,"settings":settings}),
+                    state.dev_mode(),
+                ),
+                version,
+            )
+        }
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+async fn delete_profile(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    match state.delete_profile(&name, if_match) {
+        Ok(Some(version)) => with_etag(
+            json_response(
+                StatusCode::OK,
+                &json!({"service":"lowdown","message":"Deleted profile","name":name}),
+                state.dev_mode(),
+            ),
+            version,
+        ),
+        Ok(None) => not_found(State(state)).await,
+        Err(conflict) => version_conflict(&state, conflict),
+    }
+}
+
+async fn list_headers(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
     let mut header_names: Vec<String> = headers
         .keys()
         .map(|name| name.as_str().to_string())
         .collect();
     header_names.sort();
     for name in &header_names {
-        if name.to_ascii_lowercase().starts_with("x-lowdown-")
+        if state.is_settings_header(name)
             && let Some(value) = headers.get(name)
         {
             info!("x-lowdown- Header {name} => {:?}", value);
         }
     }
     for name in &header_names {
-        if !name.to_ascii_lowercase().starts_with("x-lowdown-")
+        if !state.is_settings_header(name)
             && let Some(value) = headers.get(name)
         {
             info!("Other header {name} => {:?}", value);
         }
     }
-    json_response(StatusCode::OK, &json!(header_names), state.body_trailer())
+    paginated_response(&state, header_names, &query, String::clone, |page| page)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+async fn export(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ExportQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("localhost:7070");
+    let body = render(
+        &state.configured_layer(),
+        &state.configured_references(),
+        query.format,
+        &format!("http://{host}"),
+    );
+    text_response(StatusCode::OK, body)
+}
+
+async fn import_envoy(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    let if_match = match parse_if_match(&state, &headers) {
+        Ok(if_match) => if_match,
+        Err(response) => return response,
+    };
+    match import_fault_config(&body) {
+        Ok(import) => {
+            for warning in &import.warnings {
+                info!("Envoy import: {warning}");
+            }
+            let imported = layer_json(&import.layer);
+            let (snapshot, version) = match state.merge_admin_if(import.layer, if_match) {
+                Ok(updated) => updated,
+                Err(conflict) => return version_conflict(&state, conflict),
+            };
+            with_etag(
+                json_response(
+                    StatusCode::OK,
+                    &json!({
+                        "imported": imported,
+                        "warnings": import.warnings,
+                        "settings": snapshot,
+                    }),
+                    state.dev_mode(),
+                ),
+                version,
+            )
+        }
+        Err(err) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-envoy-config","message":err.to_string()}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+fn layer_json(layer: &SettingsLayer) -> serde_json::Map<String, serde_json::Value> {
+    layer
+        .entries()
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), serde_json::Value::String(value)))
+        .collect()
+}
+
+async fn import_wiremock(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    match import_mappings(&body) {
+        Ok(import) => {
+            for warning in &import.warnings {
+                info!("WireMock import: {warning}");
+            }
+            let ids: Vec<Uuid> = import
+                .stubs
+                .into_iter()
+                .map(|stub| state.stubs().add(stub))
+                .collect();
+            info!("Imported {} WireMock stub(s)", ids.len());
+            json_response(
+                StatusCode::OK,
+                &json!({"imported": ids, "warnings": import.warnings}),
+                state.dev_mode(),
+            )
+        }
+        Err(err) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-wiremock-mappings","message":err.to_string()}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn list_stubs(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let mut stubs = state.stubs().summaries();
+    stubs.sort_by(|a, b| a.url.cmp(&b.url));
+    paginated_response(&state, stubs, &query, |stub| stub.url.clone(), |page| page)
+}
+
+async fn clear_stubs(State(state): State<Arc<AppState>>) -> Response<Body> {
+    state.stubs().clear();
+    json_response(
+        StatusCode::OK,
+        &json!({"service":"lowdown","message":"Removed all stubs"}),
+        state.dev_mode(),
+    )
+}
+
+async fn delete_stub(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response<Body> {
+    match Uuid::parse_str(&id) {
+        Ok(id) if state.stubs().remove(id) => json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed stub","id":id}),
+            state.dev_mode(),
+        ),
+        _ => not_found(State(state)).await,
+    }
+}
+
+async fn list_streams(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    paginated_response(
+        &state,
+        state.streams().list(),
+        &query,
+        |stream| stream.info.uri.clone(),
+        |page| page,
+    )
+}
+
+async fn terminate_stream(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    match Uuid::parse_str(&id) {
+        Ok(id) if state.streams().terminate(id) => json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Terminated stream","id":id}),
+            state.dev_mode(),
+        ),
+        _ => not_found(State(state)).await,
+    }
+}
+
+async fn list_experiments(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let templates = TEMPLATES.into_iter().map(Template::info);
+    paginated_response(
+        &state,
+        templates,
+        &query,
+        |template| template.name.to_string(),
+        |page| page,
+    )
+}
+
+async fn start_experiment(
+    State(state): State<Arc<AppState>>,
+    Path(template): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let Ok(template) = template.parse::<Template>() else {
+        return not_found(State(state)).await;
+    };
+    match parse_params(&body).and_then(|params| experiments::start(&state, template, &params)) {
+        Ok((settings, task)) => json_response(
+            StatusCode::OK,
+            &json!({"experiment": template, "settings": settings, "task": task}),
+            state.dev_mode(),
+        ),
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-experiment","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn start_scenario(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    let started = scenarios::parse(&body)
+        .map_err(StartError::Invalid)
+        .and_then(|spec| state.scenarios().start(&state, spec));
+    match started {
+        Ok(status) => {
+            info!("Started scenario");
+            json_response(StatusCode::OK, &status, state.dev_mode())
+        }
+        Err(StartError::Invalid(message)) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-scenario","message":message}),
+            state.dev_mode(),
+        ),
+        Err(StartError::Running) => json_response(
+            StatusCode::CONFLICT,
+            &json!({"error":"scenario-running"}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn scenario_status(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &state.scenarios().status(&state),
+        state.dev_mode(),
+    )
+}
+
+async fn cancel_scenario(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match state.scenarios().cancel(&state) {
+        Ok(status) => {
+            info!("Cancelled scenario");
+            json_response(StatusCode::OK, &status, state.dev_mode())
+        }
+        Err(CancelError::NotRunning(status)) => json_response(
+            StatusCode::CONFLICT,
+            &json!({"error":"scenario-not-running","status":status}),
+            state.dev_mode(),
+        ),
+        Err(CancelError::NotFound) => json_response(
+            StatusCode::CONFLICT,
+            &json!({"error":"scenario-not-running","status":"idle"}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct KvQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    filter: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct KvUpdate {
+    value: Value,
+    ttl_ms: Option<u64>,
+}
+
+async fn list_kv(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<KvQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let page = ListQuery {
+        limit: query.limit,
+        offset: query.offset,
+        filter: query.filter,
+    };
+    paginated_response(
+        &state,
+        state.kv().list(&query.prefix),
+        &page,
+        |(key, _)| key.clone(),
+        as_map,
+    )
+}
+
+async fn kv_entry(State(state): State<Arc<AppState>>, Path(key): Path<String>) -> Response<Body> {
+    match state.kv().entry(&key) {
+        Some(entry) => json_response(StatusCode::OK, &entry, state.dev_mode()),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn set_kv_entry(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+    body: String,
+) -> Response<Body> {
+    match serde_json::from_str::<KvUpdate>(&body) {
+        Ok(update) => {
+            let ttl = update.ttl_ms.map(Duration::from_millis);
+            state.kv().set(&key, update.value, ttl);
+            match state.kv().entry(&key) {
+                Some(entry) => json_response(StatusCode::OK, &entry, state.dev_mode()),
+                None => not_found(State(state)).await,
+            }
+        }
+        Err(err) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-kv-entry","message":err.to_string()}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn delete_kv_entry(
+    State(state): State<Arc<AppState>>,
+    Path(key): Path<String>,
+) -> Response<Body> {
+    match state.kv().remove(&key) {
+        Some(_) => json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed key","key":key}),
+            state.dev_mode(),
+        ),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn list_limits(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    paginated_response(
+        &state,
+        state.limits().list(),
+        &query,
+        |(destination, _)| destination.clone(),
+        as_map,
+    )
+}
+
+async fn destination_limits(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    match state.limits().get(&destination) {
+        Some(limits) => json_response(StatusCode::OK, &limits, state.dev_mode()),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn set_destination_limits(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let limits = serde_json::from_str::<DestinationLimits>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|limits| limits.validate().map(|()| limits));
+    match limits {
+        Ok(limits) => {
+            info!("Limits for {destination} set to {limits:?}");
+            state.limits().set(&destination, limits.clone());
+            json_response(StatusCode::OK, &limits, state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-limits","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn delete_destination_limits(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    if state.limits().remove(&destination) {
+        json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed limits","destination":destination}),
+            state.dev_mode(),
+        )
+    } else {
+        not_found(State(state)).await
+    }
+}
+
+async fn list_bindings(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    paginated_response(
+        &state,
+        state.bindings().list(),
+        &query,
+        |(destination, _)| destination.clone(),
+        as_map,
+    )
+}
+
+async fn destination_binding(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    match state.bindings().get(&destination) {
+        Some(binding) => json_response(StatusCode::OK, &binding, state.dev_mode()),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn set_destination_binding(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let binding = serde_json::from_str::<OutboundBinding>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|binding| binding.validate().map(|()| binding));
+    match binding {
+        Ok(binding) => {
+            info!("Outbound binding for {destination} set to {binding:?}");
+            state.bindings().set(&destination, binding.clone());
+            json_response(StatusCode::OK, &binding, state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-outbound-binding","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn delete_destination_binding(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    if state.bindings().remove(&destination) {
+        json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed outbound binding","destination":destination}),
+            state.dev_mode(),
+        )
+    } else {
+        not_found(State(state)).await
+    }
+}
+
+async fn list_destination_settings(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let settings = state
+        .destination_settings()
+        .list()
+        .into_iter()
+        .map(|(destination, layer)| {
+            let entries: BTreeMap<&str, String> = layer.entries().into_iter().collect();
+            (destination, entries)
+        });
+    paginated_response(
+        &state,
+        settings,
+        &query,
+        |(destination, _)| destination.clone(),
+        as_map,
+    )
+}
+
+async fn destination_settings(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    match state.destination_settings().get(&destination) {
+        Some(layer) => {
+            let settings: BTreeMap<&str, String> = layer.entries().into_iter().collect();
+            json_response(StatusCode::OK, &settings, state.dev_mode())
+        }
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn set_destination_settings(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let layer = serde_json::from_str::<BTreeMap<String, Value>>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|settings| settings_layer(&settings))
+        .and_then(|layer| {
+            if layer.destination_url.is_some() || layer.destination_urls.is_some() {
+                Err("destination settings can't change the destination".to_string())
+            } else if layer.is_empty() {
+                Err("set at least one setting".to_string())
+            } else {
+                Ok(layer)
+            }
+        });
+    match layer {
+        Ok(layer) => {
+            let settings: BTreeMap<&str, String> = layer.entries().into_iter().collect();
+            info!("Settings for {destination} set to {settings:?}");
+            state.destination_settings().set(&destination, layer);
+            state.settings_changed();
+            json_response(StatusCode::OK, &settings, state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-destination-settings","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn delete_destination_settings(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    if state.destination_settings().remove(&destination) {
+        state.settings_changed();
+        json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed destination settings","destination":destination}),
+            state.dev_mode(),
+        )
+    } else {
+        not_found(State(state)).await
+    }
+}
+
+async fn list_credentials(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let credentials = state
+        .credentials()
+        .list()
+        .into_iter()
+        .map(|(destination, credentials)| (destination, credentials.redacted()));
+    paginated_response(
+        &state,
+        credentials,
+        &query,
+        |(destination, _)| destination.clone(),
+        as_map,
+    )
+}
+
+async fn destination_credentials(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    match state.credentials().get(&destination) {
+        Some(credentials) => {
+            json_response(StatusCode::OK, &credentials.redacted(), state.dev_mode())
+        }
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn set_destination_credentials(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let credentials = serde_json::from_str::<UpstreamCredentials>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|credentials| credentials.validate().map(|()| credentials));
+    match credentials {
+        Ok(credentials) => {
+            info!(
+                "Upstream credentials for {destination} set to {:?}",
+                credentials.redacted()
+            );
+            state.credentials().set(&destination, credentials.clone());
+            json_response(StatusCode::OK, &credentials.redacted(), state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-upstream-credentials","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn delete_destination_credentials(
+    State(state): State<Arc<AppState>>,
+    Path(destination): Path<String>,
+) -> Response<Body> {
+    if state.credentials().remove(&destination) {
+        json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed upstream credentials","destination":destination}),
+            state.dev_mode(),
+        )
+    } else {
+        not_found(State(state)).await
+    }
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Response<Body> {
+    let mut snapshot = state.metrics().snapshot();
+    snapshot.deployment_marker = state.deployment_marker();
+    json_response(StatusCode::OK, &snapshot, state.dev_mode())
+}
+
+async fn deployment_marker(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &json!({"deployment-marker": state.deployment_marker()}),
+        state.dev_mode(),
+    )
+}
+
+async fn set_deployment_marker(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    let marker = body.trim();
+    if marker.is_empty() {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-deployment-marker","message":"marker must not be empty"}),
+            state.dev_mode(),
+        );
+    }
+    state.set_deployment_marker(Some(marker.to_string()));
+    deployment_marker(State(state)).await
+}
+
+async fn clear_deployment_marker(State(state): State<Arc<AppState>>) -> Response<Body> {
+    state.set_deployment_marker(None);
+    deployment_marker(State(state)).await
+}
+
+async fn default_behavior(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(StatusCode::OK, &state.default_behavior(), state.dev_mode())
+}
+
+async fn set_default_behavior(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    let behavior = serde_json::from_str::<DefaultBehavior>(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|behavior| behavior.validate().map(|()| behavior));
+    match behavior {
+        Ok(behavior) => {
+            state.set_default_behavior(behavior);
+            default_behavior(State(state)).await
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-default-behavior","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn reset_default_behavior(State(state): State<Arc<AppState>>) -> Response<Body> {
+    state.set_default_behavior(DefaultBehavior::default());
+    default_behavior(State(state)).await
+}
+
+async fn status(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &json!({
+            "upstreams": state.health().snapshot(),
+            "circuits": state.breakers().snapshot(),
+            "held": state.metrics().held(),
+            "servers": state.supervision().snapshot(),
+        }),
+        state.dev_mode(),
+    )
+}
+
+async fn add_assertion(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    match parse_expectations(&body) {
+        Ok(expectations) => {
+            let id = state
+                .assertions()
+                .add(state.traffic().last_seq(), expectations);
+            info!("Added assertion {id}");
+            json_response(StatusCode::OK, &json!({"id": id}), state.dev_mode())
+        }
+        Err(err) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-assertion","message":err.to_string()}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn assertion_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    let result = Uuid::parse_str(&id).ok().and_then(|id| {
+        state
+            .assertions()
+            .evaluate(id, |since| state.traffic().after(since))
+    });
+    match result {
+        Some(result) => json_response(StatusCode::OK, &result, state.dev_mode()),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn delete_assertion(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    match Uuid::parse_str(&id) {
+        Ok(id) if state.assertions().remove(id) => json_response(
+            StatusCode::OK,
+            &json!({"service":"lowdown","message":"Removed assertion","id":id}),
+            state.dev_mode(),
+        ),
+        _ => not_found(State(state)).await,
+    }
+}
+
+async fn log_level(State(state): State<Arc<AppState>>) -> Response<Body> {
+    match state.log_level() {
+        Some(log_level) => json_response(
+            StatusCode::OK,
+            &json!({"log-level": log_level.current()}),
+            state.dev_mode(),
+        ),
+        None => log_level_unavailable(&state),
+    }
+}
+
+async fn set_log_level(State(state): State<Arc<AppState>>, body: String) -> Response<Body> {
+    let Some(log_level) = state.log_level() else {
+        return log_level_unavailable(&state);
+    };
+    let directives = body.trim();
+    match log_level.set(directives) {
+        Ok(()) => {
+            info!("Log level set to {directives}");
+            json_response(
+                StatusCode::OK,
+                &json!({"log-level": directives}),
+                state.dev_mode(),
+            )
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-log-level","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+fn log_level_unavailable(state: &AppState) -> Response<Body> {
+    json_response(
+        StatusCode::NOT_IMPLEMENTED,
+        &json!({"error":"log-level-not-reloadable"}),
+        state.dev_mode(),
+    )
+}
+
+async fn list_comparisons(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    paginated_response(
+        &state,
+        state.comparisons().list(),
+        &query,
+        |comparison| comparison.uri.clone(),
+        |page| page,
+    )
+}
+
+async fn clear_comparisons(State(state): State<Arc<AppState>>) -> Response<Body> {
+    state.comparisons().clear();
+    json_response(
+        StatusCode::OK,
+        &json!({"service":"lowdown","message":"Removed all duplicate comparisons"}),
+        state.dev_mode(),
+    )
+}
+
+async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    paginated_response(
+        &state,
+        state.tasks().list(),
+        &query,
+        |task| task.description.clone(),
+        |page| page,
+    )
+}
+
+async fn cancel_task(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response<Body> {
+    let Ok(id) = Uuid::parse_str(&id) else {
+        return not_found(State(state)).await;
+    };
+    match state.tasks().cancel(id) {
+        Ok(task) => {
+            info!("Cancelled task {id}");
+            json_response(StatusCode::OK, &task, state.dev_mode())
+        }
+        Err(CancelError::NotRunning(status)) => json_response(
+            StatusCode::CONFLICT,
+            &json!({"error":"task-not-running","status":status}),
+            state.dev_mode(),
+        ),
+        Err(CancelError::NotFound) => not_found(State(state)).await,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ReportQuery {
+    since: Option<String>,
+    #[serde(default)]
+    format: ReportFormat,
+    slo: Option<f64>,
+}
+
+async fn report(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ReportQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let since = match query
+        .since
+        .as_deref()
+        .map(|since| parse_since(since, state.clock().timestamp()))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err(message) => return invalid_report(&state, message),
+    };
+    let slo = query.slo.unwrap_or(DEFAULT_SLO);
+    if !(slo > 0.0 && slo < 100.0) {
+        return invalid_report(&state, "slo must be between 0 and 100".to_string());
+    }
+    let (records, changes) = state.traffic().since(since);
+    let report = build_report(&records, changes, since, slo, state.clock().timestamp());
+    match query.format {
+        ReportFormat::Json => json_response(StatusCode::OK, &report, state.dev_mode()),
+        ReportFormat::Markdown => text_response(StatusCode::OK, render_markdown(&report)),
+        ReportFormat::Html => {
+            let mut response = text_response(StatusCode::OK, render_html(&report));
+            response.headers_mut().insert(
+                "content-type",
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            response
+        }
+    }
+}
+
+fn parse_since(value: &str, now: Timestamp) -> Result<Timestamp, String> {
+    if let Ok(timestamp) = value.parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+    let ago = value
+        .parse::<SignedDuration>()
+        .map_err(|_| format!("since must be a timestamp or a duration, got {value:?}"))?;
+    now.checked_sub(ago).map_err(|err| err.to_string())
+}
+
+fn invalid_report(state: &AppState, message: String) -> Response<Body> {
+    json_response(
+        StatusCode::BAD_REQUEST,
+        &json!({"error":"invalid-report-query","message":message}),
+        state.dev_mode(),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StatsExportQuery {
+    since: Option<String>,
+    #[serde(default)]
+    format: StatsFormat,
+    #[serde(default)]
+    granularity: Granularity,
+}
+
+async fn export_stats(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<StatsExportQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    let since = match query
+        .since
+        .as_deref()
+        .map(|since| parse_since(since, state.clock().timestamp()))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err(message) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                &json!({"error":"invalid-stats-query","message":message}),
+                state.dev_mode(),
+            );
+        }
+    };
+    let (records, _) = state.traffic().since(since);
+    match stats::export(&records, query.granularity, query.format) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", query.format.content_type())
+            .header(
+                "content-disposition",
+                format!(
+                    "attachment; filename=\"lowdown-stats.{}\"",
+                    query.format.extension()
+                ),
+            )
+            .body(Body::from(body))
+            .expect("building response"),
+        Err(message) => json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &json!({"error":"stats-export-failed","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn snapshot_writer(state: &AppState) -> Result<&Arc<SnapshotWriter>, Response<Body>> {
+    state.snapshots().ok_or_else(|| {
+        json_response(
+            StatusCode::CONFLICT,
+            &json!({
+                "error":"snapshots-disabled",
+                "message":"set LOWDOWN_SNAPSHOT_DIR or LOWDOWN_SNAPSHOT_MEMORY to record exchanges",
+            }),
+            state.dev_mode(),
+        )
+    })
+}
+
+async fn list_recordings(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<ListQuery>, QueryRejection>,
+) -> Response<Body> {
+    let query = match query {
+        Ok(Query(query)) => query,
+        Err(rejection) => return invalid_query(&state, rejection),
+    };
+    match snapshot_writer(&state) {
+        Ok(writer) => paginated_response(
+            &state,
+            writer.sessions(),
+            &query,
+            |session| session.test_id.clone(),
+            |page| page,
+        ),
+        Err(response) => response,
+    }
+}
+
+async fn recording(
+    State(state): State<Arc<AppState>>,
+    Path(test_id): Path<String>,
+) -> Response<Body> {
+    let writer = match snapshot_writer(&state) {
+        Ok(writer) => writer,
+        Err(response) => return response,
+    };
+    match writer.recording(&test_id) {
+        Some(recording) => json_response(StatusCode::OK, &recording, state.dev_mode()),
+        None => not_found(State(state)).await,
+    }
+}
+
+async fn start_recording(
+    State(state): State<Arc<AppState>>,
+    Path(test_id): Path<String>,
+    body: String,
+) -> Response<Body> {
+    let writer = match snapshot_writer(&state) {
+        Ok(writer) => writer,
+        Err(response) => return response,
+    };
+    let body = if body.trim().is_empty() { "{}" } else { &body };
+    let session = serde_json::from_str::<RecordingSession>(body)
+        .map_err(|err| err.to_string())
+        .and_then(|config| writer.start_session(&test_id, config));
+    match session {
+        Ok(session) => {
+            info!("Started recording session {}", session.test_id);
+            json_response(StatusCode::OK, &session, state.dev_mode())
+        }
+        Err(message) => json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"invalid-recording-session","message":message}),
+            state.dev_mode(),
+        ),
+    }
+}
+
+async fn stop_recording(
+    State(state): State<Arc<AppState>>,
+    Path(test_id): Path<String>,
+) -> Response<Body> {
+    let writer = match snapshot_writer(&state) {
+        Ok(writer) => writer,
+        Err(response) => return response,
+    };
+    match writer.stop_session(&test_id) {
+        Some(session) => {
+            info!("Stopped recording session {}", session.test_id);
+            json_response(StatusCode::OK, &session, state.dev_mode())
+        }
+        None => not_found(State(state)).await,
+    }
 }
 
 async fn service_root(State(state): State<Arc<AppState>>) -> Response<Body> {
     json_response(
         StatusCode::OK,
         &json!({"service":"lowdown"}),
-        state.body_trailer(),
+        state.dev_mode(),
     )
 }
 
@@ -92,7 +1907,53 @@ async fn health(State(state): State<Arc<AppState>>) -> Response<Body> {
     json_response(
         StatusCode::OK,
         &json!({"service":"lowdown","status":"healthy"}),
-        state.body_trailer(),
+        state.dev_mode(),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    offset: Option<usize>,
+    filter: Option<String>,
+}
+
+// Items are paged in the order they are given, which each endpoint keeps
+// stable; `page` shapes the entries that are left into the response body.
+fn paginated_response<T, P: Serialize>(
+    state: &AppState,
+    items: impl IntoIterator<Item = T>,
+    query: &ListQuery,
+    key: impl Fn(&T) -> String,
+    page: impl FnOnce(Vec<T>) -> P,
+) -> Response<Body> {
+    let mut items: Vec<T> = items.into_iter().collect();
+    if let Some(filter) = query.filter.as_deref().filter(|f| !f.is_empty()) {
+        let filter = filter.to_ascii_lowercase();
+        items.retain(|item| key(item).to_ascii_lowercase().contains(&filter));
+    }
+    let total = items.len();
+    let items: Vec<T> = items
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+    let mut response = json_response(StatusCode::OK, &page(items), state.dev_mode());
+    response
+        .headers_mut()
+        .insert(TOTAL_COUNT_HEADER, HeaderValue::from(total));
+    response
+}
+
+fn as_map<V>(page: Vec<(String, V)>) -> BTreeMap<String, V> {
+    page.into_iter().collect()
+}
+
+fn invalid_query(state: &AppState, rejection: QueryRejection) -> Response<Body> {
+    json_response(
+        StatusCode::BAD_REQUEST,
+        &json!({"error":"invalid-query","message":rejection.body_text()}),
+        state.dev_mode(),
     )
 }
 
@@ -100,6 +1961,6 @@ async fn not_found(State(state): State<Arc<AppState>>) -> Response<Body> {
     json_response(
         StatusCode::NOT_FOUND,
         &json!({"message":"not-found"}),
-        state.body_trailer(),
+        state.dev_mode(),
     )
 }
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_PATH: &str = "/api/v1/admin-chaos";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AdminChaos {
+    #[serde(default)]
+    pub delay_ms: u64,
+    #[serde(default)]
+    pub delay_percentage: u8,
+    #[serde(default)]
+    pub fail_percentage: u8,
+    #[serde(default = "default_fail_code")]
+    pub fail_code: u16,
+}
+
+fn default_fail_code() -> u16 {
+    503
+}
+
+impl Default for AdminChaos {
+    fn default() -> Self {
+        Self {
+            delay_ms: 0,
+            delay_percentage: 0,
+            fail_percentage: 0,
+            fail_code: default_fail_code(),
+        }
+    }
+}
+
+impl AdminChaos {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.delay_percentage > 100 || self.fail_percentage > 100 {
+            return Err("percentages must be between 0 and 100".to_string());
+        }
+        if !(400..=599).contains(&self.fail_code) {
+            return Err(format!(
+                "fail-code must be between 400 and 599, got {}",
+                self.fail_code
+            ));
+        }
+        Ok(())
+    }
+}
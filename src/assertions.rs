@@ -0,0 +1,197 @@
+use std::collections::{HashMap, VecDeque};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::traffic::TrafficRecord;
+
+pub const CAPACITY: usize = 1_000;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Expectation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ExpectationsDocument {
+    Many(Vec<Expectation>),
+    One(Expectation),
+}
+
+#[derive(Debug, Error)]
+pub enum AssertionError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("at least one expectation is required")]
+    Empty,
+    #[error("count cannot be combined with min-count or max-count")]
+    ConflictingCounts,
+}
+
+pub fn parse_expectations(body: &str) -> Result<Vec<Expectation>, AssertionError> {
+    let expectations = match serde_json::from_str(body)? {
+        ExpectationsDocument::Many(expectations) => expectations,
+        ExpectationsDocument::One(expectation) => vec![expectation],
+    };
+    if expectations.is_empty() {
+        return Err(AssertionError::Empty);
+    }
+    for expectation in &expectations {
+        if expectation.count.is_some()
+            && (expectation.min_count.is_some() || expectation.max_count.is_some())
+        {
+            return Err(AssertionError::ConflictingCounts);
+        }
+    }
+    Ok(expectations)
+}
+
+impl Expectation {
+    fn matches(&self, record: &TrafficRecord) -> bool {
+        let path = record.uri.split('?').next().unwrap_or_default();
+        self.method
+            .as_ref()
+            .is_none_or(|method| method.eq_ignore_ascii_case(&record.method))
+            && self.path.as_ref().is_none_or(|expected| expected == path)
+            && self
+                .path_prefix
+                .as_ref()
+                .is_none_or(|prefix| path.starts_with(prefix.as_str()))
+            && self
+                .destination
+                .as_ref()
+                .is_none_or(|expected| record.destination.as_ref() == Some(expected))
+            && self
+                .fault
+                .as_ref()
+                .is_none_or(|fault| record.faults.iter().any(|name| name == fault))
+            && self.status.is_none_or(|status| status == record.status)
+    }
+
+    fn satisfied_by(&self, actual: u64) -> bool {
+        match self.count {
+            Some(count) => actual == count,
+            None => {
+                actual
+                    >= self
+                        .min_count
+                        .unwrap_or(if self.max_count.is_some() { 0 } else { 1 })
+                    && self.max_count.is_none_or(|max| actual <= max)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpectationResult {
+    pub expectation: Expectation,
+    pub actual: u64,
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AssertionResult {
+    pub id: Uuid,
+    pub passed: bool,
+    pub traffic_truncated: bool,
+    pub results: Vec<ExpectationResult>,
+}
+
+struct Assertion {
+    since: u64,
+    expectations: Vec<Expectation>,
+}
+
+#[derive(Default)]
+pub struct AssertionRegistry {
+    assertions: Mutex<Assertions>,
+}
+
+#[derive(Default)]
+struct Assertions {
+    by_id: HashMap<Uuid, Assertion>,
+    created: VecDeque<Uuid>,
+}
+
+impl AssertionRegistry {
+    // Only the last `CAPACITY` assertions are kept.
+    pub fn add(&self, since: u64, expectations: Vec<Expectation>) -> Uuid {
+        let id = Uuid::new_v4();
+        let mut guard = self.assertions.lock();
+        if guard.created.len() == CAPACITY
+            && let Some(oldest) = guard.created.pop_front()
+        {
+            guard.by_id.remove(&oldest);
+        }
+        guard.created.push_back(id);
+        guard.by_id.insert(
+            id,
+            Assertion {
+                since,
+                expectations,
+            },
+        );
+        id
+    }
+
+    pub fn remove(&self, id: Uuid) -> bool {
+        let mut guard = self.assertions.lock();
+        guard.created.retain(|created| *created != id);
+        guard.by_id.remove(&id).is_some()
+    }
+
+    // Counts are incomplete once the traffic log has dropped requests made
+    // after the assertion was created, so such an assertion never passes.
+    pub fn evaluate(
+        &self,
+        id: Uuid,
+        records: impl Fn(u64) -> (Vec<TrafficRecord>, bool),
+    ) -> Option<AssertionResult> {
+        let guard = self.assertions.lock();
+        let assertion = guard.by_id.get(&id)?;
+        let (records, traffic_truncated) = records(assertion.since);
+        let results: Vec<ExpectationResult> = assertion
+            .expectations
+            .iter()
+            .map(|expectation| {
+                let actual = records
+                    .iter()
+                    .filter(|record| expectation.matches(record))
+                    .count() as u64;
+                ExpectationResult {
+                    expectation: expectation.clone(),
+                    actual,
+                    passed: expectation.satisfied_by(actual),
+                }
+            })
+            .collect();
+        Some(AssertionResult {
+            id,
+            passed: !traffic_truncated && results.iter().all(|result| result.passed),
+            traffic_truncated,
+            results,
+        })
+    }
+}
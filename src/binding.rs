@@ -0,0 +1,60 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::destinations::DestinationRegistry;
+
+pub const INTERFACE_SUPPORTED: bool = cfg!(any(
+    target_os = "android",
+    target_os = "fuchsia",
+    target_os = "illumos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "solaris",
+    target_os = "tvos",
+    target_os = "visionos",
+    target_os = "watchos",
+));
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OutboundBinding {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_address: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+}
+
+impl OutboundBinding {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.is_empty() {
+            return Err("set local-address, interface or both".to_string());
+        }
+        match &self.interface {
+            Some(interface) if interface.is_empty() => {
+                Err("interface must not be empty".to_string())
+            }
+            Some(_) if !INTERFACE_SUPPORTED => {
+                Err("binding to an interface is not supported on this platform".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local_address.is_none() && self.interface.is_none()
+    }
+
+    pub fn or(&self, fallback: &OutboundBinding) -> OutboundBinding {
+        OutboundBinding {
+            local_address: self.local_address.or(fallback.local_address),
+            interface: self
+                .interface
+                .clone()
+                .or_else(|| fallback.interface.clone()),
+        }
+    }
+}
+
+pub type BindingRegistry = DestinationRegistry<OutboundBinding>;
@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::info;
+
+use crate::clock::{Clock, SystemClock};
+use crate::http_client::{
+    HttpClient, HttpClientError, OutgoingRequest, ProxiedResponse, SharedHttpClient,
+    is_upstream_failure,
+};
+use crate::limits::{LimitRegistry, authority};
+
+pub const DEFAULT_BREAKER_OPEN_MS: u64 = 10_000;
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing_since: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CircuitStatus {
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+}
+
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, Circuit>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self {
+            circuits: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl CircuitBreakers {
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // While open, one probe is let through per open period; the others are
+    // short-circuited until it succeeds.
+    fn admit(&self, destination: &str, open: Duration) -> Result<(), HttpClientError> {
+        let now = self.clock.now();
+        let mut guard = self.circuits.lock();
+        let circuit = guard.entry(destination.to_string()).or_default();
+        let Some(open_until) = circuit.open_until else {
+            return Ok(());
+        };
+        if now < open_until
+            || circuit
+                .probing_since
+                .is_some_and(|since| now.duration_since(since) < open)
+        {
+            return Err(HttpClientError::CircuitOpen(destination.to_string()));
+        }
+        circuit.probing_since = Some(now);
+        Ok(())
+    }
+
+    fn finish(&self, destination: &str, threshold: u32, open: Duration, failed: bool) {
+        let mut guard = self.circuits.lock();
+        let circuit = guard.entry(destination.to_string()).or_default();
+        circuit.probing_since = None;
+        if !failed {
+            if circuit.open_until.is_some() {
+                info!("Circuit to {destination} closed");
+            }
+            *circuit = Circuit::default();
+            return;
+        }
+        circuit.failures = circuit.failures.saturating_add(1);
+        if circuit.open_until.is_some() || circuit.failures >= threshold {
+            if circuit.open_until.is_none() {
+                info!(
+                    "Circuit to {destination} opened after {} failures",
+                    circuit.failures
+                );
+            }
+            circuit.open_until = Some(self.clock.now() + open);
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, CircuitStatus> {
+        let now = self.clock.now();
+        self.circuits
+            .lock()
+            .iter()
+            .map(|(destination, circuit)| {
+                let state = match circuit.open_until {
+                    None => "closed",
+                    Some(until) if now < until && circuit.probing_since.is_none() => "open",
+                    Some(_) => "half-open",
+                };
+                let status = CircuitStatus {
+                    state,
+                    consecutive_failures: circuit.failures,
+                };
+                (destination.clone(), status)
+            })
+            .collect()
+    }
+}
+
+pub struct CircuitBreakingClient {
+    limits: Arc<LimitRegistry>,
+    breakers: Arc<CircuitBreakers>,
+    inner: SharedHttpClient,
+}
+
+impl CircuitBreakingClient {
+    pub fn new(
+        limits: Arc<LimitRegistry>,
+        breakers: Arc<CircuitBreakers>,
+        inner: SharedHttpClient,
+    ) -> Self {
+        Self {
+            limits,
+            breakers,
+            inner,
+        }
+    }
+}
+
+#[async_trait]
+impl HttpClient for CircuitBreakingClient {
+    async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError> {
+        let breaker = authority(&request.url).and_then(|destination| {
+            let limits = self.limits.get(&destination)?;
+            let open = limits.breaker_open_ms.unwrap_or(DEFAULT_BREAKER_OPEN_MS);
+            Some((
+                destination,
+                limits.breaker_failures?,
+                Duration::from_millis(open),
+            ))
+        });
+        let Some((destination, threshold, open)) = breaker else {
+            return self.inner.execute(request).await;
+        };
+        self.breakers.admit(&destination, open)?;
+        let result = self.inner.execute(request).await;
+        let failed = match &result {
+            Ok(response) => is_upstream_failure(response.status),
+            Err(HttpClientError::RateLimited(_)) => return result,
+            Err(_) => true,
+        };
+        self.breakers.finish(&destination, threshold, open, failed);
+        result
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::str::FromStr;
+
+use axum::{
+    body::Body,
+    http::{
+        HeaderMap, HeaderValue, Method, Response, StatusCode,
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE, ORIGIN, VARY,
+        },
+    },
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PreflightMode {
+    #[default]
+    PassThrough,
+    Synthetic,
+    Fault,
+}
+
+impl PreflightMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PreflightMode::PassThrough => "pass-through",
+            PreflightMode::Synthetic => "synthetic",
+            PreflightMode::Fault => "fault",
+        }
+    }
+}
+
+impl FromStr for PreflightMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "pass-through" => Ok(PreflightMode::PassThrough),
+            "synthetic" => Ok(PreflightMode::Synthetic),
+            "fault" => Ok(PreflightMode::Fault),
+            other => Err(format!("unknown preflight mode {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for PreflightMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::OPTIONS && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+pub fn synthetic_preflight(headers: &HeaderMap) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .expect("building response");
+    let response_headers = response.headers_mut();
+    allow_origin(response_headers, headers);
+    let methods = headers
+        .get(ACCESS_CONTROL_REQUEST_METHOD)
+        .cloned()
+        .unwrap_or(HeaderValue::from_static("*"));
+    response_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    if let Some(requested) = headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+        response_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+    }
+    response_headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("600"));
+    response
+}
+
+pub fn is_grpc_web(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .to_ascii_lowercase()
+                .starts_with("application/grpc-web")
+        })
+}
+
+// gRPC-Web clients read failures from grpc-status, so injected failures use a
+// trailers-only HTTP 200 response instead of the HTTP status code.
+pub fn grpc_web_failure(status: StatusCode, message: &str, headers: &HeaderMap) -> Response<Body> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .cloned()
+        .unwrap_or(HeaderValue::from_static("application/grpc-web+proto"));
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header("grpc-status", grpc_status(status).to_string())
+        .header("grpc-message", message)
+        .body(Body::empty())
+        .expect("building response");
+    response.headers_mut().insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("grpc-status, grpc-message"),
+    );
+    response
+}
+
+pub fn allow_origin(response_headers: &mut HeaderMap, request_headers: &HeaderMap) {
+    if let Some(origin) = request_headers.get(ORIGIN) {
+        response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        response_headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+        response_headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+}
+
+fn grpc_status(status: StatusCode) -> u8 {
+    match status.as_u16() {
+        400 => 13,
+        401 => 16,
+        403 => 7,
+        404 => 12,
+        429 | 502 | 503 | 504 => 14,
+        _ => 2,
+    }
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use crate::config_file::ConfigFile;
+use crate::rules::{NamedRule, analyze};
+use crate::schema::DEPRECATED_SETTINGS;
+use crate::settings::SettingsLayer;
+
+const USAGE: &str = "usage: lowdown check -f <config file>";
+
+// Returns the process exit code: 0 when the file is clean, 1 when it has
+// errors or warnings.
+pub fn run(args: &[String]) -> i32 {
+    let path = match args {
+        [flag, path] if flag == "-f" || flag == "--file" => path,
+        _ => {
+            eprintln!("{USAGE}");
+            return 1;
+        }
+    };
+    let config = match ConfigFile::load(Path::new(path)) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("error: {path}: {err}");
+            return 1;
+        }
+    };
+    let warnings = lint(&config);
+    for warning in &warnings {
+        println!("warning: {path}: {warning}");
+    }
+    println!(
+        "{path}: {} settings, {} rules, {} warnings",
+        config.layer.entries().len(),
+        config.rules.len(),
+        warnings.len()
+    );
+    i32::from(!warnings.is_empty())
+}
+
+pub fn lint(config: &ConfigFile) -> Vec<String> {
+    let mut warnings = deprecated(&config.layer, "settings");
+    for rule in &config.rules {
+        warnings.extend(deprecated(
+            &rule.rule.layer,
+            &format!("rule {:?}", rule.name),
+        ));
+    }
+    for (name, layer) in &config.profiles {
+        warnings.extend(deprecated(layer, &format!("profile {name:?}")));
+    }
+    let mut rules: Vec<&NamedRule> = config.rules.iter().collect();
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    let analysis = analyze(&rules);
+    for never in analysis.never_match {
+        warnings.push(format!(
+            "rule {:?} can never match: {}",
+            never.rule, never.reason
+        ));
+    }
+    for shadowed in analysis.shadowed {
+        warnings.push(format!(
+            "rule {:?} is unreachable, rule {:?} matches every request it matches first",
+            shadowed.rule, shadowed.by
+        ));
+    }
+    for overlapping in analysis.overlapping {
+        warnings.push(format!(
+            "rule {:?} only partly applies, rule {:?} matches {:?} first with different settings",
+            overlapping.rule, overlapping.by, overlapping.uri
+        ));
+    }
+    warnings
+}
+
+fn deprecated(layer: &SettingsLayer, place: &str) -> Vec<String> {
+    layer
+        .entries()
+        .into_iter()
+        .filter_map(|(name, _)| {
+            let (_, replacement) = DEPRECATED_SETTINGS
+                .iter()
+                .find(|(deprecated, _)| *deprecated == name)?;
+            Some(format!("{place}: {name} is deprecated, use {replacement}"))
+        })
+        .collect()
+}
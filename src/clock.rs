@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use jiff::{SignedDuration, Timestamp};
+use parking_lot::Mutex;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn timestamp(&self) -> Timestamp;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        Timestamp::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+// Time only moves when something sleeps on it or `advance` is called, and a
+// sleep completes right away by moving the clock to its deadline. Concurrent
+// sleeps therefore run back to back, which keeps fault timings reproducible.
+pub struct VirtualClock {
+    origin: Instant,
+    started_at: Timestamp,
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            started_at: Timestamp::now(),
+            offset: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.offset.lock() += duration;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock()
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    // Wall-clock time starts at the real time the clock was created.
+    fn timestamp(&self) -> Timestamp {
+        SignedDuration::try_from(self.elapsed())
+            .ok()
+            .and_then(|elapsed| self.started_at.checked_add(elapsed).ok())
+            .unwrap_or(Timestamp::MAX)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let offset = self.offset.clone();
+        Box::pin(async move {
+            *offset.lock() += duration;
+            tokio::task::yield_now().await;
+        })
+    }
+}
+
+tokio::task_local! {
+    static CLOCK: Arc<dyn Clock>;
+}
+
+// Like `random::scope`: code running inside `future` reads time from `clock`.
+// Bodies that are streamed after the handler returns capture `current()`
+// when they are built.
+pub async fn scope<F: Future>(clock: Arc<dyn Clock>, future: F) -> F::Output {
+    CLOCK.scope(clock, future).await
+}
+
+pub fn current() -> Arc<dyn Clock> {
+    CLOCK
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::new(SystemClock))
+}
+
+pub fn now() -> Instant {
+    current().now()
+}
+
+pub fn timestamp() -> Timestamp {
+    current().timestamp()
+}
+
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+pub async fn sleep(duration: Duration) {
+    current().sleep(duration).await
+}
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::clock;
+use crate::http_client::ProxiedResponse;
+
+const CAPACITY: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Comparison {
+    pub at: Timestamp,
+    pub method: String,
+    pub uri: String,
+    pub primary: ComparedResponse,
+    pub duplicate: ComparedResponse,
+    pub status_matches: bool,
+    pub body_matches: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ComparedResponse {
+    pub destination: String,
+    pub status: u16,
+    pub body_bytes: usize,
+}
+
+impl Comparison {
+    pub fn new(
+        method: String,
+        uri: String,
+        primary: (&str, &ProxiedResponse),
+        duplicate: (&str, &ProxiedResponse),
+    ) -> Self {
+        let compared = |(destination, response): (&str, &ProxiedResponse)| ComparedResponse {
+            destination: destination.to_string(),
+            status: response.status.as_u16(),
+            body_bytes: response.bytes().map_or(0, Bytes::len),
+        };
+        Self {
+            at: clock::timestamp(),
+            method,
+            uri,
+            status_matches: primary.1.status == duplicate.1.status,
+            body_matches: primary.1.bytes() == duplicate.1.bytes(),
+            primary: compared(primary),
+            duplicate: compared(duplicate),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ComparisonLog {
+    comparisons: Mutex<VecDeque<Comparison>>,
+}
+
+impl ComparisonLog {
+    pub fn record(&self, comparison: Comparison) {
+        let mut guard = self.comparisons.lock();
+        if guard.len() == CAPACITY {
+            guard.pop_front();
+        }
+        guard.push_back(comparison);
+    }
+
+    pub fn list(&self) -> Vec<Comparison> {
+        self.comparisons.lock().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.comparisons.lock().clear();
+    }
+}
@@ -0,0 +1,39 @@
+use http::HeaderMap;
+use http::header::{ETAG, Entry, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use rand::Rng;
+
+use crate::random;
+
+const STALE_LAST_MODIFIED: &str = "Thu, 01 Jan 1970 00:00:00 GMT";
+
+pub fn has_validators(headers: &HeaderMap) -> bool {
+    headers.contains_key(ETAG) || headers.contains_key(LAST_MODIFIED)
+}
+
+pub fn is_conditional(headers: &HeaderMap) -> bool {
+    headers.contains_key(IF_NONE_MATCH) || headers.contains_key(IF_MODIFIED_SINCE)
+}
+
+pub fn strip_validators(headers: &mut HeaderMap) {
+    headers.remove(ETAG);
+    headers.remove(LAST_MODIFIED);
+}
+
+pub fn corrupt_validators(headers: &mut HeaderMap) {
+    if let Entry::Occupied(mut etags) = headers.entry(ETAG) {
+        for etag in etags.iter_mut() {
+            let weak = if etag.as_bytes().starts_with(b"W/") {
+                "W/"
+            } else {
+                ""
+            };
+            let tag: u64 = random::rng().r#gen();
+            *etag = HeaderValue::from_str(&format!("{weak}\"lowdown-{tag:016x}\"")).expect("ascii");
+        }
+    }
+    if let Entry::Occupied(mut last_modified) = headers.entry(LAST_MODIFIED) {
+        for value in last_modified.iter_mut() {
+            *value = HeaderValue::from_static(STALE_LAST_MODIFIED);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::rules::{NamedRule, settings_layer};
+use crate::settings::SettingsLayer;
+
+const ENV_REFERENCE: &str = "env://";
+const FILE_REFERENCE: &str = "file://";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigDocument {
+    #[serde(default)]
+    settings: BTreeMap<String, Value>,
+    #[serde(default)]
+    rules: Vec<Value>,
+    #[serde(default)]
+    profiles: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub layer: SettingsLayer,
+    pub rules: Vec<NamedRule>,
+    pub profiles: BTreeMap<String, SettingsLayer>,
+    // Settings given as `env://` or `file://` references, as written in the
+    // file, so exports can show them instead of the secret.
+    pub references: BTreeMap<String, String>,
+    // The same for each profile's settings.
+    pub profile_references: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if extension == "toml" {
+            return Err("TOML config files are not supported, use YAML or JSON".to_string());
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut document: ConfigDocument = if text.trim().is_empty() {
+            ConfigDocument::default()
+        } else {
+            serde_yaml::from_str(text).map_err(|err| err.to_string())?
+        };
+        let mut references = BTreeMap::new();
+        for (name, value) in &mut document.settings {
+            let written = value_text(value);
+            if resolve_references(value).map_err(|err| format!("settings.{name}: {err}"))? {
+                references.insert(name.clone(), written);
+            }
+        }
+        let layer = settings_layer(&document.settings)?;
+        let mut rules: Vec<NamedRule> = Vec::new();
+        for (idx, mut value) in document.rules.into_iter().enumerate() {
+            let written = rule_texts(&value);
+            resolve_references(&mut value).map_err(|err| format!("rules[{idx}]: {err}"))?;
+            let resolved = rule_texts(&value);
+            let mut rule =
+                NamedRule::from_value(value).map_err(|err| format!("rules[{idx}]: {err}"))?;
+            rule.references = written
+                .into_iter()
+                .filter(|(pointer, text)| resolved.get(pointer) != Some(text))
+                .collect();
+            if rules.iter().any(|existing| existing.name == rule.name) {
+                return Err(format!("rules[{idx}]: duplicate rule name {:?}", rule.name));
+            }
+            rules.push(rule);
+        }
+        let mut profiles = BTreeMap::new();
+        let mut profile_references = BTreeMap::new();
+        for (name, settings) in &mut document.profiles {
+            let mut written = BTreeMap::new();
+            for (key, value) in settings.iter_mut() {
+                let text = value_text(value);
+                if resolve_references(value)
+                    .map_err(|err| format!("profiles.{name}.{key}: {err}"))?
+                {
+                    written.insert(key.clone(), text);
+                }
+            }
+            let layer =
+                settings_layer(settings).map_err(|err| format!("profiles.{name}: {err}"))?;
+            profiles.insert(name.clone(), layer);
+            if !written.is_empty() {
+                profile_references.insert(name.clone(), written);
+            }
+        }
+        Ok(Self {
+            layer,
+            rules,
+            profiles,
+            references,
+            profile_references,
+        })
+    }
+}
+
+// Replaces every `env://NAME` and `file:///path` string with the variable or
+// the file's contents (without its trailing newline), and tells whether there
+// was any.
+fn resolve_references(value: &mut Value) -> Result<bool, String> {
+    match value {
+        Value::String(text) => {
+            let resolved = if let Some(name) = text.strip_prefix(ENV_REFERENCE) {
+                std::env::var(name)
+                    .map_err(|_| format!("environment variable {name} is not set"))?
+            } else if let Some(path) = text.strip_prefix(FILE_REFERENCE) {
+                std::fs::read_to_string(path)
+                    .map_err(|err| format!("failed to read {path}: {err}"))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string()
+            } else {
+                return Ok(false);
+            };
+            *text = resolved;
+            Ok(true)
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .try_fold(false, |found, item| Ok(resolve_references(item)? || found)),
+        Value::Object(entries) => entries
+            .values_mut()
+            .try_fold(false, |found, item| Ok(resolve_references(item)? || found)),
+        _ => Ok(false),
+    }
+}
+
+// The text of each setting and matcher of a rule document, by its JSON pointer
+// in `NamedRule::to_json`.
+fn rule_texts(rule: &Value) -> BTreeMap<String, String> {
+    let mut texts = BTreeMap::new();
+    for section in ["settings", "match"] {
+        let Some(Value::Object(entries)) = rule.get(section) else {
+            continue;
+        };
+        for (name, value) in entries {
+            match (section, name.as_str(), value) {
+                ("match", "headers", Value::Object(headers)) => {
+                    for (header, value) in headers {
+                        let pointer = format!("/match/headers/{}", header.to_ascii_lowercase());
+                        texts.insert(pointer, value_text(value));
+                    }
+                }
+                _ => {
+                    texts.insert(format!("/{section}/{name}"), value_text(value));
+                }
+            }
+        }
+    }
+    texts
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(value_text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,338 @@
+use std::future::Future;
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::pin::{Pin, pin};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker, ready};
+use std::time::Duration;
+
+use axum::{Router, body::Body, extract::Request};
+use futures_util::TryFutureExt;
+use http::{HeaderMap, Method, StatusCode, Version, header::CONTENT_LENGTH};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use parking_lot::Mutex;
+use socket2::SockRef;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, info};
+
+// Inserted into each request's extensions by `serve`; a handler triggers it to
+// have the connection closed with a TCP RST instead of a response.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionReset(Arc<Notify>);
+
+impl ConnectionReset {
+    pub fn trigger(&self) {
+        self.0.notify_one();
+    }
+}
+
+// Also inserted by `serve`: the client connection's socket, so a fault can
+// make it behave like a poor network stack for the rest of the connection.
+#[derive(Debug, Clone)]
+pub struct ClientSocket {
+    socket: Arc<std::net::TcpStream>,
+    interim: Arc<Mutex<Interim>>,
+}
+
+impl ClientSocket {
+    pub fn degrade(&self, buffer_bytes: u32) -> std::io::Result<()> {
+        let socket = SockRef::from(&*self.socket);
+        socket.set_tcp_nodelay(false)?;
+        if buffer_bytes != 0 {
+            socket.set_send_buffer_size(buffer_bytes as usize)?;
+            socket.set_recv_buffer_size(buffer_bytes as usize)?;
+        }
+        Ok(())
+    }
+
+    // hyper has no server API for informational responses, so they are queued
+    // here and written through the connection's stream ahead of hyper's next
+    // write, which for a request still waiting on its handler is the response
+    // head. HTTP/2 would need them framed by hyper, and HTTP/1.0 clients must
+    // not get them at all.
+    pub fn send_interim(
+        &self,
+        version: Version,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> std::io::Result<()> {
+        if version != Version::HTTP_11 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("interim responses need an HTTP/1.1 connection, not {version:?}"),
+            ));
+        }
+        let reason = match status.as_u16() {
+            103 => "Early Hints",
+            _ => status.canonical_reason().unwrap_or("Informational"),
+        };
+        let mut interim = self.interim.lock();
+        let pending = &mut interim.pending;
+        pending.extend_from_slice(format!("HTTP/1.1 {} {reason}\r\n", status.as_str()).as_bytes());
+        for (name, value) in headers {
+            pending.extend_from_slice(name.as_str().as_bytes());
+            pending.extend_from_slice(b": ");
+            pending.extend_from_slice(value.as_bytes());
+            pending.extend_from_slice(b"\r\n");
+        }
+        pending.extend_from_slice(b"\r\n");
+        if let Some(waker) = interim.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Interim {
+    pending: Vec<u8>,
+    waker: Option<Waker>,
+}
+
+// The stream hyper serves a connection over. Queued interim responses go out
+// before any bytes hyper writes, and the connection task is woken to flush
+// them even while it is only waiting on a handler.
+struct InterimIo<I> {
+    io: I,
+    interim: Arc<Mutex<Interim>>,
+}
+
+impl<I: AsyncWrite + Unpin> InterimIo<I> {
+    fn poll_interim(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut interim = self.interim.lock();
+        interim.waker = Some(cx.waker().clone());
+        while !interim.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.io).poll_write(cx, &interim.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            interim.pending.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I: AsyncRead + AsyncWrite + Unpin> AsyncRead for InterimIo<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        this.interim.lock().waker = Some(cx.waker().clone());
+        Pin::new(&mut this.io).poll_read(cx, buf)
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for InterimIo<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_interim(cx))?;
+        Pin::new(&mut this.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_interim(cx))?;
+        Pin::new(&mut this.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_interim(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_interim(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerOptions {
+    pub reuse_port: bool,
+    pub backlog: u32,
+    pub nodelay: bool,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        Self {
+            reuse_port: false,
+            backlog: 1024,
+            nodelay: true,
+        }
+    }
+}
+
+impl ListenerOptions {
+    // With `reuse_port`, a new lowdown can bind the port while the old one is
+    // still draining, and the kernel spreads new connections over both.
+    pub fn bind(&self, address: SocketAddr) -> std::io::Result<TcpListener> {
+        let socket = match address {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        #[cfg(not(windows))]
+        socket.set_reuseaddr(true)?;
+        if self.reuse_port {
+            set_reuse_port(&socket)?;
+        }
+        socket.bind(address)?;
+        socket.listen(self.backlog)
+    }
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &TcpSocket) -> std::io::Result<()> {
+    socket.set_reuseport(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &TcpSocket) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn serve(
+    listener: TcpListener,
+    router: Router,
+    tls: Option<TlsAcceptor>,
+    options: ListenerOptions,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let builder = Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    // Connections belong to this future: dropping it, e.g. when the shutdown
+    // grace period runs out, closes the ones still open.
+    let mut connections = JoinSet::new();
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            Some(_) = connections.join_next() => continue,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    debug!("accept error: {err}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+        let _ = stream.set_nodelay(options.nodelay);
+        // A duplicate of the socket stays here so SO_LINGER can still be set
+        // once hyper owns the stream.
+        let (stream, socket) = match split_socket(stream) {
+            Ok(split) => split,
+            Err(err) => {
+                debug!("failed to duplicate connection socket: {err}");
+                continue;
+            }
+        };
+        let builder = builder.clone();
+        let router = router.clone();
+        let watcher = graceful.watcher();
+        let tls = tls.clone();
+        connections.spawn(async move {
+            match tls {
+                Some(acceptor) => {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            serve_connection(builder, watcher, router, stream, socket).await
+                        }
+                        Ok(Err(err)) => debug!("TLS handshake failed: {err}"),
+                        Err(_) => debug!("TLS handshake timed out"),
+                    }
+                }
+                None => serve_connection(builder, watcher, router, stream, socket).await,
+            }
+        });
+    }
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+async fn serve_connection<I>(
+    builder: Builder<TokioExecutor>,
+    watcher: Watcher,
+    router: Router,
+    io: I,
+    socket: std::net::TcpStream,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let reset = ConnectionReset::default();
+    let handler_reset = reset.clone();
+    let socket = Arc::new(socket);
+    let interim = Arc::new(Mutex::new(Interim::default()));
+    let client_socket = ClientSocket {
+        socket: socket.clone(),
+        interim: interim.clone(),
+    };
+    let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+        let mut request = request.map(Body::new);
+        request.extensions_mut().insert(handler_reset.clone());
+        request.extensions_mut().insert(client_socket.clone());
+        let connect = request.method() == Method::CONNECT;
+        router.clone().oneshot(request).map_ok(move |mut response| {
+            // axum sets content-length: 0 on empty bodies, which hyper refuses
+            // to send on a successful CONNECT.
+            if connect && response.status().is_success() {
+                response.headers_mut().remove(CONTENT_LENGTH);
+            }
+            response
+        })
+    });
+    let connection = watcher.watch(
+        builder
+            .serve_connection_with_upgrades(TokioIo::new(InterimIo { io, interim }), service)
+            .into_owned(),
+    );
+    let mut connection = pin!(connection);
+    tokio::select! {
+        result = &mut connection => {
+            if let Err(err) = result {
+                debug!("connection error: {err}");
+            }
+        }
+        () = reset.0.notified() => {
+            let _ = SockRef::from(&*socket).set_linger(Some(Duration::ZERO));
+            info!("Resetting connection");
+        }
+    }
+}
+
+fn split_socket(stream: TcpStream) -> std::io::Result<(TcpStream, std::net::TcpStream)> {
+    let stream = stream.into_std()?;
+    let socket = stream.try_clone()?;
+    Ok((TcpStream::from_std(stream)?, socket))
+}
@@ -0,0 +1,59 @@
+use std::io::Read;
+
+use brotli::Decompressor;
+use bytes::Bytes;
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use http::{HeaderMap, header::CONTENT_ENCODING};
+
+// Decoded bodies are only used for matching, so a body that would inflate past
+// this is matched as it was sent.
+const MAX_DECODED_BYTES: u64 = 64 * 1024 * 1024;
+
+pub fn has_content_encoding(headers: &HeaderMap) -> bool {
+    !encodings(headers).is_empty()
+}
+
+pub fn decode_body(headers: &HeaderMap, body: &Bytes) -> Option<Bytes> {
+    let encodings = encodings(headers);
+    if encodings.is_empty() {
+        return None;
+    }
+    let mut decoded = body.to_vec();
+    for encoding in encodings.iter().rev() {
+        decoded = decode(encoding, &decoded)?;
+    }
+    Some(Bytes::from(decoded))
+}
+
+fn encodings(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+        .collect()
+}
+
+fn decode(encoding: &str, data: &[u8]) -> Option<Vec<u8>> {
+    let reader: Box<dyn Read + '_> = match encoding {
+        "gzip" | "x-gzip" => Box::new(MultiGzDecoder::new(data)),
+        // Some clients send raw deflate data instead of the zlib stream HTTP asks for.
+        "deflate" if is_zlib(data) => Box::new(ZlibDecoder::new(data)),
+        "deflate" => Box::new(DeflateDecoder::new(data)),
+        "br" => Box::new(Decompressor::new(data, 4096)),
+        _ => return None,
+    };
+    let mut decoded = Vec::new();
+    reader
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .ok()?;
+    (decoded.len() as u64 <= MAX_DECODED_BYTES).then_some(decoded)
+}
+
+fn is_zlib(data: &[u8]) -> bool {
+    matches!(data, [first, second, ..]
+        if first & 0x0f == 8 && u16::from_be_bytes([*first, *second]) % 31 == 0)
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::Bytes;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::random;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CorruptResponseMode {
+    #[default]
+    Truncate,
+    BitFlip,
+    EmptyBody,
+}
+
+impl CorruptResponseMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CorruptResponseMode::Truncate => "truncate",
+            CorruptResponseMode::BitFlip => "bit-flip",
+            CorruptResponseMode::EmptyBody => "empty-body",
+        }
+    }
+}
+
+impl FromStr for CorruptResponseMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "truncate" => Ok(CorruptResponseMode::Truncate),
+            "bit-flip" => Ok(CorruptResponseMode::BitFlip),
+            "empty-body" => Ok(CorruptResponseMode::EmptyBody),
+            other => Err(format!("unknown corrupt-response mode {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for CorruptResponseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Bit flips hit about one byte in a hundred, and always at least one.
+pub fn corrupt_body(body: &Bytes, mode: CorruptResponseMode) -> Bytes {
+    let mut rng = random::rng();
+    match mode {
+        CorruptResponseMode::Truncate if body.is_empty() => Bytes::new(),
+        CorruptResponseMode::Truncate => body.slice(..rng.gen_range(0..body.len())),
+        CorruptResponseMode::BitFlip if body.is_empty() => Bytes::new(),
+        CorruptResponseMode::BitFlip => {
+            let mut bytes = body.to_vec();
+            for _ in 0..(bytes.len() / 100).max(1) {
+                let index = rng.gen_range(0..bytes.len());
+                bytes[index] ^= 1 << rng.gen_range(0..8);
+            }
+            Bytes::from(bytes)
+        }
+        CorruptResponseMode::EmptyBody => Bytes::new(),
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::Engine;
+use http::HeaderValue;
+use http::header::{AUTHORIZATION, HeaderName};
+use serde::{Deserialize, Serialize};
+
+use crate::destinations::DestinationRegistry;
+use crate::http_client::{
+    HttpClient, HttpClientError, OutgoingRequest, ProxiedResponse, SharedHttpClient,
+};
+use crate::limits::authority;
+
+const SECRET_PLACEHOLDER: &str = "{secret}";
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    Bearer,
+    Basic,
+    Header,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct UpstreamCredentials {
+    pub scheme: AuthScheme,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_file: Option<PathBuf>,
+}
+
+impl UpstreamCredentials {
+    pub fn validate(&self) -> Result<(), String> {
+        let sources = [
+            self.secret.is_some(),
+            self.secret_env.is_some(),
+            self.secret_file.is_some(),
+        ];
+        if sources.into_iter().filter(|set| *set).count() != 1 {
+            return Err("set exactly one of secret, secret-env or secret-file".to_string());
+        }
+        match self.scheme {
+            AuthScheme::Basic if self.username.is_none() => {
+                return Err("basic credentials need a username".to_string());
+            }
+            AuthScheme::Header if self.header.is_none() => {
+                return Err("header credentials need a header".to_string());
+            }
+            AuthScheme::Bearer | AuthScheme::Basic
+                if self.header.is_some() || self.template.is_some() =>
+            {
+                return Err("header and template only apply to the header scheme".to_string());
+            }
+            AuthScheme::Bearer | AuthScheme::Header if self.username.is_some() => {
+                return Err("username only applies to the basic scheme".to_string());
+            }
+            _ => {}
+        }
+        if let Some(template) = &self.template
+            && !template.contains(SECRET_PLACEHOLDER)
+        {
+            return Err(format!("template must contain {SECRET_PLACEHOLDER}"));
+        }
+        self.header_entry().map(|_| ())
+    }
+
+    // Secrets from the environment or a file are read for every request, so
+    // a rotated secret file is picked up without touching lowdown.
+    pub fn header_entry(&self) -> Result<(HeaderName, HeaderValue), String> {
+        let secret = self.resolve_secret()?;
+        let (name, value) = match self.scheme {
+            AuthScheme::Bearer => (AUTHORIZATION, format!("Bearer {secret}")),
+            AuthScheme::Basic => {
+                let username = self.username.as_deref().unwrap_or_default();
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{username}:{secret}"));
+                (AUTHORIZATION, format!("Basic {encoded}"))
+            }
+            AuthScheme::Header => {
+                let name = self.header.as_deref().unwrap_or_default();
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header name {name:?}"))?;
+                let template = self.template.as_deref().unwrap_or(SECRET_PLACEHOLDER);
+                (name, template.replace(SECRET_PLACEHOLDER, &secret))
+            }
+        };
+        let mut value = HeaderValue::from_str(&value)
+            .map_err(|_| "the secret can't be used in a header value".to_string())?;
+        value.set_sensitive(true);
+        Ok((name, value))
+    }
+
+    fn resolve_secret(&self) -> Result<String, String> {
+        if let Some(secret) = &self.secret {
+            return Ok(secret.clone());
+        }
+        if let Some(name) = &self.secret_env {
+            return std::env::var(name)
+                .map_err(|_| format!("environment variable {name} is not set"));
+        }
+        let path = self.secret_file.as_ref().ok_or("no secret source")?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    pub fn redacted(&self) -> UpstreamCredentials {
+        UpstreamCredentials {
+            secret: self.secret.as_ref().map(|_| REDACTED.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+pub type CredentialRegistry = DestinationRegistry<UpstreamCredentials>;
+
+// Adds the destination's credentials to every request lowdown sends there,
+// after the request was recorded, so secrets stay out of traffic logs and
+// snapshots.
+pub struct AuthenticatingClient {
+    credentials: Arc<CredentialRegistry>,
+    inner: SharedHttpClient,
+}
+
+impl AuthenticatingClient {
+    pub fn new(credentials: Arc<CredentialRegistry>, inner: SharedHttpClient) -> Self {
+        Self { credentials, inner }
+    }
+}
+
+#[async_trait]
+impl HttpClient for AuthenticatingClient {
+    async fn execute(
+        &self,
+        mut request: OutgoingRequest,
+    ) -> Result<ProxiedResponse, HttpClientError> {
+        let credentials = authority(&request.url).and_then(|destination| {
+            let credentials = self.credentials.get(&destination)?;
+            Some((destination, credentials))
+        });
+        if let Some((destination, credentials)) = credentials {
+            let (name, value) = credentials
+                .header_entry()
+                .map_err(|err| HttpClientError::Credentials(destination, err))?;
+            request.headers.insert(name, value);
+        }
+        self.inner.execute(request).await
+    }
+}
@@ -0,0 +1,81 @@
+use axum::body::Body;
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::request::Parts;
+use http::{HeaderValue, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::response::{header_map, json_response};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum DefaultBehavior {
+    #[default]
+    Error,
+    Upstream {
+        url: String,
+    },
+    Respond {
+        status: u16,
+        #[serde(default)]
+        body: String,
+        #[serde(
+            default,
+            rename = "content-type",
+            skip_serializing_if = "Option::is_none"
+        )]
+        content_type: Option<String>,
+    },
+    Echo,
+}
+
+impl DefaultBehavior {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            DefaultBehavior::Upstream { url } => match Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+                Ok(_) => Err("url must use http or https".to_string()),
+                Err(err) => Err(format!("invalid url: {err}")),
+            },
+            DefaultBehavior::Respond {
+                status,
+                content_type,
+                ..
+            } => {
+                StatusCode::from_u16(*status)
+                    .map_err(|_| "status must be between 100 and 999".to_string())?;
+                match content_type {
+                    Some(value) if HeaderValue::from_str(value).is_err() => {
+                        Err("invalid content-type".to_string())
+                    }
+                    _ => Ok(()),
+                }
+            }
+            DefaultBehavior::Error | DefaultBehavior::Echo => Ok(()),
+        }
+    }
+}
+
+pub fn respond(status: u16, body: String, content_type: Option<&str>) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    if let Some(value) = content_type.and_then(|value| HeaderValue::from_str(value).ok()) {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+}
+
+pub fn echo(parts: &Parts, body: &Bytes, dev_mode: bool) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &json!({
+            "method": parts.method.as_str(),
+            "uri": parts.uri.to_string(),
+            "headers": header_map(&parts.headers),
+            "body": String::from_utf8_lossy(body),
+        }),
+        dev_mode,
+    )
+}
@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use parking_lot::RwLock;
+
+pub struct DestinationRegistry<T> {
+    entries: RwLock<BTreeMap<String, T>>,
+}
+
+impl<T> Default for DestinationRegistry<T> {
+    fn default() -> Self {
+        Self {
+            entries: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> DestinationRegistry<T> {
+    pub fn get(&self, destination: &str) -> Option<T> {
+        self.entries
+            .read()
+            .get(&destination.to_ascii_lowercase())
+            .cloned()
+    }
+
+    pub fn set(&self, destination: &str, value: T) {
+        self.entries
+            .write()
+            .insert(destination.to_ascii_lowercase(), value);
+    }
+
+    pub fn remove(&self, destination: &str) -> bool {
+        self.entries
+            .write()
+            .remove(&destination.to_ascii_lowercase())
+            .is_some()
+    }
+
+    pub fn list(&self) -> BTreeMap<String, T> {
+        self.entries.read().clone()
+    }
+}
@@ -0,0 +1,249 @@
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::settings::{MatchPattern, Millis, Percentage, SettingsLayer, StatusCodeSetting};
+
+#[derive(Debug, Error)]
+pub enum EnvoyImportError {
+    #[error("could not parse Envoy fault config: {0}")]
+    Parse(String),
+}
+
+#[derive(Debug, Default)]
+pub struct EnvoyImport {
+    pub layer: SettingsLayer,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HttpFault {
+    abort: Option<FaultAbort>,
+    delay: Option<FaultDelay>,
+    #[serde(default)]
+    headers: Vec<HeaderMatcher>,
+    #[serde(alias = "upstreamCluster")]
+    upstream_cluster: Option<String>,
+    #[serde(default, alias = "downstreamNodes")]
+    downstream_nodes: Vec<String>,
+    #[serde(alias = "maxActiveFaults")]
+    max_active_faults: Option<Value>,
+    #[serde(alias = "responseRateLimit")]
+    response_rate_limit: Option<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FaultAbort {
+    #[serde(alias = "httpStatus")]
+    http_status: Option<u16>,
+    #[serde(alias = "grpcStatus")]
+    grpc_status: Option<Value>,
+    #[serde(alias = "headerAbort")]
+    header_abort: Option<Value>,
+    percentage: Option<FractionalPercent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FaultDelay {
+    #[serde(alias = "fixedDelay")]
+    fixed_delay: Option<ProtoDuration>,
+    #[serde(alias = "headerDelay")]
+    header_delay: Option<Value>,
+    percentage: Option<FractionalPercent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FractionalPercent {
+    #[serde(default)]
+    numerator: u64,
+    denominator: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ProtoDuration {
+    Text(String),
+    Parts {
+        #[serde(default)]
+        seconds: u64,
+        #[serde(default)]
+        nanos: u32,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HeaderMatcher {
+    name: String,
+    #[serde(alias = "exactMatch")]
+    exact_match: Option<String>,
+    #[serde(alias = "stringMatch")]
+    string_match: Option<StringMatcher>,
+    #[serde(default, alias = "invertMatch")]
+    invert_match: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StringMatcher {
+    exact: Option<String>,
+}
+
+pub fn import_fault_config(text: &str) -> Result<EnvoyImport, EnvoyImportError> {
+    let value: Value =
+        serde_yaml::from_str(text).map_err(|err| EnvoyImportError::Parse(err.to_string()))?;
+    let config = value
+        .get("typed_config")
+        .or_else(|| value.get("typedConfig"))
+        .cloned()
+        .unwrap_or(value);
+    let fault: HttpFault =
+        serde_json::from_value(config).map_err(|err| EnvoyImportError::Parse(err.to_string()))?;
+    Ok(convert(fault))
+}
+
+fn convert(fault: HttpFault) -> EnvoyImport {
+    let mut import = EnvoyImport::default();
+
+    if let Some(abort) = fault.abort {
+        match abort.http_status {
+            Some(status) => match StatusCodeSetting::new(status) {
+                Some(status) => {
+                    import.layer.fail_before_code = Some(status);
+                    import.layer.fail_before_percentage =
+                        Some(percentage(abort.percentage, "abort", &mut import.warnings).into());
+                }
+                None => import.warnings.push(format!(
+                    "abort.http_status {status} is not a valid status code"
+                )),
+            },
+            None => import
+                .warnings
+                .push("abort without http_status is not supported".to_string()),
+        }
+        if abort.grpc_status.is_some() {
+            import
+                .warnings
+                .push("abort.grpc_status is not supported".to_string());
+        }
+        if abort.header_abort.is_some() {
+            import
+                .warnings
+                .push("abort.header_abort is not supported".to_string());
+        }
+    }
+
+    if let Some(delay) = fault.delay {
+        match delay.fixed_delay.map(duration_ms) {
+            Some(Ok(ms)) => {
+                import.layer.delay_before_ms = Some(Millis::new(ms));
+                import.layer.delay_before_percentage =
+                    Some(percentage(delay.percentage, "delay", &mut import.warnings).into());
+            }
+            Some(Err(text)) => import.warnings.push(format!(
+                "delay.fixed_delay {text:?} is not a valid duration"
+            )),
+            None => import
+                .warnings
+                .push("delay without fixed_delay is not supported".to_string()),
+        }
+        if delay.header_delay.is_some() {
+            import
+                .warnings
+                .push("delay.header_delay is not supported".to_string());
+        }
+    }
+
+    let mut headers = fault.headers.into_iter();
+    if let Some(header) = headers.next() {
+        let exact = header
+            .exact_match
+            .or_else(|| header.string_match.and_then(|m| m.exact));
+        match exact {
+            Some(value) if !header.invert_match => {
+                import.layer.match_header_name = Some(MatchPattern::exact_lowercase(&header.name));
+                import.layer.match_header_value = Some(MatchPattern::exact(&value));
+            }
+            _ => import.warnings.push(format!(
+                "header matcher for {} is not an exact match and was skipped",
+                header.name
+            )),
+        }
+    }
+    if headers.next().is_some() {
+        import
+            .warnings
+            .push("only the first header matcher is supported".to_string());
+    }
+
+    if let Some(cluster) = fault.upstream_cluster {
+        import
+            .warnings
+            .push(format!("upstream_cluster {cluster} is not supported"));
+    }
+    if !fault.downstream_nodes.is_empty() {
+        import
+            .warnings
+            .push("downstream_nodes is not supported".to_string());
+    }
+    if fault.max_active_faults.is_some() {
+        import
+            .warnings
+            .push("max_active_faults is not supported".to_string());
+    }
+    if fault.response_rate_limit.is_some() {
+        import
+            .warnings
+            .push("response_rate_limit is not supported".to_string());
+    }
+
+    import
+}
+
+fn percentage(
+    fraction: Option<FractionalPercent>,
+    fault: &str,
+    warnings: &mut Vec<String>,
+) -> Percentage {
+    let Some(fraction) = fraction else {
+        warnings.push(format!("{fault} has no percentage and was imported as 0%"));
+        return Percentage::ZERO;
+    };
+    let denominator: u64 = match fraction.denominator.as_deref().unwrap_or("HUNDRED") {
+        "HUNDRED" => 100,
+        "TEN_THOUSAND" => 10_000,
+        "MILLION" => 1_000_000,
+        other => {
+            warnings.push(format!(
+                "{fault} percentage denominator {other} is unknown, assuming HUNDRED"
+            ));
+            100
+        }
+    };
+    let scaled = fraction.numerator.saturating_mul(100);
+    let percent = scaled.saturating_add(denominator / 2) / denominator;
+    if scaled % denominator != 0 {
+        warnings.push(format!(
+            "{fault} percentage {}/{denominator} was rounded to {percent}%",
+            fraction.numerator
+        ));
+    }
+    if percent > 100 {
+        warnings.push(format!("{fault} percentage was capped at 100%"));
+    }
+    Percentage::saturating(percent)
+}
+
+fn duration_ms(duration: ProtoDuration) -> Result<u64, String> {
+    match duration {
+        ProtoDuration::Parts { seconds, nanos } => Ok(seconds
+            .saturating_mul(1000)
+            .saturating_add(u64::from(nanos) / 1_000_000)),
+        ProtoDuration::Text(text) => {
+            let seconds = text
+                .strip_suffix('s')
+                .and_then(|value| value.parse::<f64>().ok())
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .ok_or_else(|| text.clone())?;
+            Ok((seconds * 1000.0).round() as u64)
+        }
+    }
+}
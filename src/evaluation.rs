@@ -0,0 +1,97 @@
+use http::{HeaderMap, HeaderValue};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::random;
+use crate::settings::Percentage;
+
+pub const DEBUG_HEADER: &str = "x-lowdown-debug";
+pub const TRACE_HEADER: &str = "x-lowdown-trace";
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Evaluation {
+    pub layers: Vec<&'static str>,
+    pub rule: String,
+    pub matchers: Vec<MatcherResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<&'static str>,
+    pub matched: bool,
+    pub rolls: Vec<Roll>,
+    pub faults: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatcherResult {
+    pub name: &'static str,
+    pub matched: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Roll {
+    pub fault: &'static str,
+    pub percentage: Percentage,
+    pub triggered: bool,
+}
+
+pub fn requested(headers: &HeaderMap) -> bool {
+    sampling_forced(headers)
+        || headers
+            .get(DEBUG_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+}
+
+pub fn sampling_forced(headers: &HeaderMap) -> bool {
+    headers
+        .get(TRACE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("always"))
+}
+
+// Sets the W3C trace-context sampled flag, keeping the caller's trace and
+// span ids when its traceparent is valid and starting a new trace otherwise.
+pub fn force_sampled(headers: &mut HeaderMap) {
+    let parent = headers
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            let parts: Vec<&str> = value.split('-').collect();
+            let valid = parts.len() == 4
+                && [2, 32, 16, 2]
+                    .iter()
+                    .zip(&parts)
+                    .all(|(len, part)| part.len() == *len && is_lower_hex(part));
+            valid
+                .then(|| (parts[1].to_string(), parts[2].to_string(), parts[3]))
+                .and_then(|(trace, span, flags)| {
+                    u8::from_str_radix(flags, 16)
+                        .ok()
+                        .map(|flags| (trace, span, flags))
+                })
+        });
+    let (trace, span, flags) = parent.unwrap_or_else(|| {
+        let mut rng = random::rng();
+        (
+            format!("{:032x}", rng.r#gen::<u128>().max(1)),
+            format!("{:016x}", rng.r#gen::<u64>().max(1)),
+            0,
+        )
+    });
+    let value = format!("00-{trace}-{span}-{:02x}", flags | 0x01);
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(TRACEPARENT_HEADER, value);
+    }
+}
+
+fn is_lower_hex(text: &str) -> bool {
+    text.bytes()
+        .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+impl Evaluation {
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        HeaderValue::from_str(&serde_json::to_string(self).ok()?).ok()
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::settings::{
+    MatchPattern, Millis, Percentage, Probability, Settings, SettingsLayer, StatusCodeSetting,
+};
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Template {
+    DependencyOutage,
+    SlowDependency,
+    FlakyDependency,
+    DuplicateDelivery,
+    ThunderingHerd,
+}
+
+pub const TEMPLATES: [Template; 5] = [
+    Template::DependencyOutage,
+    Template::SlowDependency,
+    Template::FlakyDependency,
+    Template::DuplicateDelivery,
+    Template::ThunderingHerd,
+];
+
+const SCOPE: [&str; 4] = [
+    "match-host",
+    "match-uri-starts-with",
+    "duration-seconds",
+    "guardrails",
+];
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExperimentParams {
+    pub percentage: Option<u8>,
+    pub delay_ms: Option<u64>,
+    pub status: Option<u16>,
+    pub match_host: Option<String>,
+    pub match_uri_starts_with: Option<String>,
+    pub duration_seconds: Option<u64>,
+    pub guardrails: Option<Guardrails>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Guardrails {
+    pub max_upstream_error_percentage: Option<u8>,
+    pub max_proxy_error_percentage: Option<u8>,
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u64,
+}
+
+fn default_min_requests() -> u64 {
+    10
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    requests: u64,
+    upstream_errors: u64,
+    responses: u64,
+    error_responses: u64,
+}
+
+impl Totals {
+    fn current(state: &AppState) -> Self {
+        state.metrics().snapshot().destinations.values().fold(
+            Totals::default(),
+            |totals, destination| Totals {
+                requests: totals.requests + destination.requests,
+                upstream_errors: totals.upstream_errors + destination.upstream_errors,
+                responses: totals.responses + destination.responses,
+                error_responses: totals.error_responses + destination.error_responses,
+            },
+        )
+    }
+}
+
+impl Guardrails {
+    fn validate(&self) -> Result<(), String> {
+        let limits = [
+            self.max_upstream_error_percentage,
+            self.max_proxy_error_percentage,
+        ];
+        if limits.iter().all(Option::is_none) {
+            return Err(
+                "guardrails need max-upstream-error-percentage or max-proxy-error-percentage"
+                    .to_string(),
+            );
+        }
+        if limits.iter().flatten().any(|limit| *limit > 100) {
+            return Err("guardrail percentages must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    fn tripped(&self, baseline: &Totals, current: &Totals) -> Option<String> {
+        let checks = [
+            (
+                "upstream error rate",
+                self.max_upstream_error_percentage,
+                current.upstream_errors - baseline.upstream_errors,
+                current.requests - baseline.requests,
+            ),
+            (
+                "proxy error rate",
+                self.max_proxy_error_percentage,
+                current.error_responses - baseline.error_responses,
+                current.responses - baseline.responses,
+            ),
+        ];
+        checks.into_iter().find_map(|(name, limit, errors, total)| {
+            let limit = u64::from(limit?);
+            let percentage = (errors * 100).checked_div(total)?;
+            (total >= self.min_requests && percentage > limit)
+                .then(|| format!("{name} {percentage}% is above {limit}% ({errors} of {total})"))
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateInfo {
+    pub name: Template,
+    pub description: &'static str,
+    pub parameters: Vec<&'static str>,
+}
+
+impl Template {
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::DependencyOutage => "dependency-outage",
+            Template::SlowDependency => "slow-dependency",
+            Template::FlakyDependency => "flaky-dependency",
+            Template::DuplicateDelivery => "duplicate-delivery",
+            Template::ThunderingHerd => "thundering-herd",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Template::DependencyOutage => {
+                "Fail requests without calling the backend (default 100% with 503)"
+            }
+            Template::SlowDependency => {
+                "Delay requests before calling the backend (default 100%, 2000 ms)"
+            }
+            Template::FlakyDependency => {
+                "Intermittent errors and latency spikes (default 20% with 503, and 20% delayed 1000 ms)"
+            }
+            Template::DuplicateDelivery => "Send requests to the backend twice (default 50%)",
+            Template::ThunderingHerd => {
+                "Hold every request, then release them together on fresh connections (default 5000 ms)"
+            }
+        }
+    }
+
+    fn own_parameters(self) -> &'static [&'static str] {
+        match self {
+            Template::DependencyOutage => &["percentage", "status"],
+            Template::SlowDependency => &["percentage", "delay-ms"],
+            Template::FlakyDependency => &["percentage", "status", "delay-ms"],
+            Template::DuplicateDelivery => &["percentage"],
+            Template::ThunderingHerd => &["delay-ms"],
+        }
+    }
+
+    pub fn info(self) -> TemplateInfo {
+        TemplateInfo {
+            name: self,
+            description: self.description(),
+            parameters: self
+                .own_parameters()
+                .iter()
+                .chain(&SCOPE)
+                .copied()
+                .collect(),
+        }
+    }
+
+    pub fn layer(self, params: &ExperimentParams) -> Result<SettingsLayer, String> {
+        let given = [
+            ("percentage", params.percentage.is_some()),
+            ("delay-ms", params.delay_ms.is_some()),
+            ("status", params.status.is_some()),
+        ];
+        if let Some((name, _)) = given
+            .iter()
+            .find(|(name, set)| *set && !self.own_parameters().contains(name))
+        {
+            return Err(format!("{self} does not take {name}"));
+        }
+        let percentage = |default: u8| {
+            Percentage::new(params.percentage.unwrap_or(default))
+                .map(Probability::from)
+                .ok_or_else(|| "percentage must be between 0 and 100".to_string())
+        };
+        let status = |default: u16| {
+            StatusCodeSetting::new(params.status.unwrap_or(default))
+                .ok_or_else(|| "status must be between 100 and 999".to_string())
+        };
+        let delay = |default: u64| Millis::new(params.delay_ms.unwrap_or(default));
+        if params.duration_seconds == Some(0) {
+            return Err("duration-seconds must be at least 1".to_string());
+        }
+        if let Some(guardrails) = &params.guardrails {
+            guardrails.validate()?;
+        }
+
+        let mut layer = SettingsLayer {
+            match_host: params.match_host.as_deref().map(MatchPattern::exact),
+            match_uri_starts_with: params
+                .match_uri_starts_with
+                .as_deref()
+                .map(MatchPattern::prefix),
+            ..SettingsLayer::default()
+        };
+        match self {
+            Template::DependencyOutage => {
+                layer.fail_before_percentage = Some(percentage(100)?);
+                layer.fail_before_code = Some(status(503)?);
+            }
+            Template::SlowDependency => {
+                layer.delay_before_percentage = Some(percentage(100)?);
+                layer.delay_before_ms = Some(delay(2000));
+            }
+            Template::FlakyDependency => {
+                layer.fail_before_percentage = Some(percentage(20)?);
+                layer.fail_before_code = Some(status(503)?);
+                layer.delay_before_percentage = Some(percentage(20)?);
+                layer.delay_before_ms = Some(delay(1000));
+            }
+            Template::DuplicateDelivery => {
+                layer.duplicate_percentage = Some(percentage(50)?);
+            }
+            Template::ThunderingHerd => {
+                layer.delay_before_percentage = Some(Probability::ALWAYS);
+                layer.delay_before_ms = Some(delay(5000));
+                layer.force_new_connection_percentage = Some(Probability::ALWAYS);
+            }
+        }
+        Ok(layer)
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        TEMPLATES
+            .into_iter()
+            .find(|template| template.name() == text)
+            .ok_or_else(|| format!("unknown experiment template {text:?}"))
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub fn parse_params(body: &str) -> Result<ExperimentParams, String> {
+    if body.trim().is_empty() {
+        return Ok(ExperimentParams::default());
+    }
+    serde_json::from_str(body).map_err(|err| err.to_string())
+}
+
+struct RestoreAdmin {
+    state: Arc<AppState>,
+    layer: SettingsLayer,
+    template: Template,
+}
+
+impl Drop for RestoreAdmin {
+    fn drop(&mut self) {
+        info!(
+            "Experiment {} ended, restoring admin settings",
+            self.template
+        );
+        self.state.reset_admin(std::mem::take(&mut self.layer));
+    }
+}
+
+pub fn start(
+    state: &Arc<AppState>,
+    template: Template,
+    params: &ExperimentParams,
+) -> Result<(Settings, Option<Uuid>), String> {
+    let layer = template.layer(params)?;
+    let previous = state.admin_layer();
+    let snapshot = state.merge_admin(layer);
+    info!("Started experiment {template}");
+    if params.duration_seconds.is_none() && params.guardrails.is_none() {
+        return Ok((snapshot, None));
+    }
+
+    let restore = RestoreAdmin {
+        state: state.clone(),
+        layer: previous,
+        template,
+    };
+    let duration = params.duration_seconds.map(Duration::from_secs);
+    let guardrails = params.guardrails.clone();
+    let baseline = Totals::current(state);
+    let watched = state.clone();
+    let clock = state.clock();
+    let description = match params.duration_seconds {
+        Some(seconds) => format!("{template} for {seconds}s"),
+        None => format!("{template} until cancelled"),
+    };
+    let task = state
+        .tasks()
+        .spawn("experiment", description, move |progress| async move {
+            let _restore = restore;
+            let started = clock.now();
+            loop {
+                clock.sleep(CHECK_INTERVAL).await;
+                let tripped = guardrails.as_ref().and_then(|guardrails| {
+                    guardrails.tripped(&baseline, &Totals::current(&watched))
+                });
+                if let Some(reason) = tripped {
+                    warn!("Guardrail tripped for experiment {template}: {reason}, rolling back");
+                    return Err(format!("guardrail tripped: {reason}"));
+                }
+                if let Some(duration) = duration {
+                    let elapsed = clock.now().saturating_duration_since(started);
+                    if elapsed >= duration {
+                        return Ok(());
+                    }
+                    progress.set((elapsed.as_millis() * 100 / duration.as_millis()) as u8);
+                }
+            }
+        });
+    Ok((snapshot, Some(task)))
+}
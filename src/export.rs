@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::settings::{HEADER_PREFIX, SettingsLayer};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Env,
+    Headers,
+    Curl,
+}
+
+// Settings in `references` are rendered as their `env://` or `file://`
+// reference, so secrets don't end up in the output.
+pub fn render(
+    layer: &SettingsLayer,
+    references: &BTreeMap<String, String>,
+    format: ExportFormat,
+    admin_base: &str,
+) -> String {
+    let entries: Vec<(&str, String)> = layer
+        .entries()
+        .into_iter()
+        .map(|(key, value)| match references.get(key) {
+            Some(reference) => (key, reference.clone()),
+            None => (key, value),
+        })
+        .collect();
+    match format {
+        ExportFormat::Env => entries
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", env_name(key), shell_quote(value)))
+            .collect(),
+        ExportFormat::Headers => entries
+            .iter()
+            .map(|(key, value)| format!("{HEADER_PREFIX}{key}: {value}\n"))
+            .collect(),
+        ExportFormat::Curl => {
+            let mut command = "curl -XPOST".to_string();
+            for (key, value) in &entries {
+                command.push_str(" \\\n  -H ");
+                command.push_str(&shell_quote(&format!("{HEADER_PREFIX}{key}: {value}")));
+            }
+            command.push_str(&format!(" \\\n  {admin_base}/api/v1/reset\n"));
+            command
+        }
+    }
+}
+
+fn env_name(key: &str) -> String {
+    key.replace('-', "_").to_ascii_uppercase()
+}
+
+fn shell_quote(value: &str) -> String {
+    let safe = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:".contains(c));
+    if safe {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
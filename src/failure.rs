@@ -0,0 +1,110 @@
+use std::fmt;
+use std::str::FromStr;
+
+use axum::body::Body;
+use bytes::Bytes;
+use http::{HeaderValue, Response, StatusCode, header::CONTENT_TYPE};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureMode {
+    #[default]
+    JsonError,
+    EmptyBody,
+    HeadersOnly,
+    ConnectionClose,
+    HtmlErrorPage,
+    MalformedJson,
+}
+
+impl FailureMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureMode::JsonError => "json-error",
+            FailureMode::EmptyBody => "empty-body",
+            FailureMode::HeadersOnly => "headers-only",
+            FailureMode::ConnectionClose => "connection-close",
+            FailureMode::HtmlErrorPage => "html-error-page",
+            FailureMode::MalformedJson => "malformed-json",
+        }
+    }
+}
+
+impl FromStr for FailureMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "json-error" => Ok(FailureMode::JsonError),
+            "empty-body" => Ok(FailureMode::EmptyBody),
+            "headers-only" => Ok(FailureMode::HeadersOnly),
+            "connection-close" => Ok(FailureMode::ConnectionClose),
+            "html-error-page" => Ok(FailureMode::HtmlErrorPage),
+            "malformed-json" => Ok(FailureMode::MalformedJson),
+            other => Err(format!("unknown fail-before mode {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for FailureMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub fn empty_body(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+// The head goes out before the body fails, so clients see the status and
+// headers and then lose the connection.
+pub fn headers_only(status: StatusCode) -> Response<Body> {
+    let aborted = futures_util::stream::once(async {
+        tokio::task::yield_now().await;
+        Err::<Bytes, _>(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "response body dropped",
+        ))
+    });
+    let mut response = Response::new(Body::from_stream(aborted));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+// Modeled on the default error pages of nginx and most CDNs.
+pub fn html_error_page(status: StatusCode) -> Response<Body> {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
+    );
+    let page = format!(
+        "<html>\r\n<head><title>{title}</title></head>\r\n<body>\r\n\
+         <center><h1>{title}</h1></center>\r\n<hr><center>lowdown</center>\r\n\
+         </body>\r\n</html>\r\n"
+    );
+    let mut response = Response::new(Body::from(page));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    response
+}
+
+pub fn malformed_json(status: StatusCode, value: &serde_json::Value) -> Response<Body> {
+    let mut text = value.to_string();
+    text.truncate(text.len().saturating_sub(1).max(1));
+    let mut response = Response::new(Body::from(text));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
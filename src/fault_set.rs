@@ -0,0 +1,123 @@
+use crate::settings::{
+    MAX_STREAM_GARBAGE_BYTES, Millis, Percentage, Probability, Settings, StatusCodeSetting,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    DelayBefore(Millis),
+    DelayAfter(Millis),
+    FailBefore(StatusCodeSetting),
+    FailAfter(StatusCodeSetting),
+    Duplicate,
+    StreamStall(Millis),
+    StreamGarbage(u64),
+    StreamAbort(Percentage),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultSet {
+    entries: Vec<(u32, Fault)>,
+}
+
+impl FaultSet {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (weight, fault) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("missing weight in {entry:?}"))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight in {entry:?}"))?;
+            entries.push((weight, parse_fault(fault.trim())?));
+        }
+        match entries
+            .iter()
+            .try_fold(0u32, |total, (weight, _)| total.checked_add(*weight))
+        {
+            Some(total) if total <= 100 => {}
+            Some(total) => return Err(format!("weights add up to {total}, more than 100")),
+            None => return Err("weights add up to more than 100".to_string()),
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn choose(&self, roll: u32) -> Option<&Fault> {
+        let mut upper = 0u32;
+        for (weight, fault) in &self.entries {
+            upper = upper.saturating_add(*weight);
+            if roll < upper {
+                return Some(fault);
+            }
+        }
+        None
+    }
+}
+
+fn parse_fault(text: &str) -> Result<Fault, String> {
+    let (name, value) = text.split_once('=').unwrap_or((text, ""));
+    let number = || {
+        value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid value in {text:?}"))
+    };
+    let status = || {
+        value
+            .parse::<StatusCodeSetting>()
+            .map_err(|_| format!("invalid status code in {text:?}"))
+    };
+    match name.trim() {
+        "delay-before" => Ok(Fault::DelayBefore(Millis::new(number()?))),
+        "delay-after" => Ok(Fault::DelayAfter(Millis::new(number()?))),
+        "fail-before" => Ok(Fault::FailBefore(status()?)),
+        "fail-after" => Ok(Fault::FailAfter(status()?)),
+        "duplicate" => Ok(Fault::Duplicate),
+        "stream-stall" => Ok(Fault::StreamStall(Millis::new(number()?))),
+        "stream-garbage" => match number()? {
+            bytes if bytes > MAX_STREAM_GARBAGE_BYTES => Err(format!(
+                "stream-garbage above {MAX_STREAM_GARBAGE_BYTES} bytes in {text:?}"
+            )),
+            bytes => Ok(Fault::StreamGarbage(bytes)),
+        },
+        "stream-abort" => Ok(Fault::StreamAbort(Percentage::saturating(number()?))),
+        other => Err(format!("unknown fault {other:?}")),
+    }
+}
+
+impl Fault {
+    pub fn apply(&self, settings: &mut Settings) {
+        match *self {
+            Fault::DelayBefore(ms) => {
+                settings.delay_before_ms = ms;
+                settings.delay_before_percentage = Probability::ALWAYS;
+            }
+            Fault::DelayAfter(ms) => {
+                settings.delay_after_ms = ms;
+                settings.delay_after_percentage = Probability::ALWAYS;
+            }
+            Fault::FailBefore(code) => {
+                settings.fail_before_code = code;
+                settings.fail_before_percentage = Probability::ALWAYS;
+            }
+            Fault::FailAfter(code) => {
+                settings.fail_after_code = code;
+                settings.fail_after_percentage = Probability::ALWAYS;
+            }
+            Fault::Duplicate => settings.duplicate_percentage = Probability::ALWAYS,
+            Fault::StreamStall(ms) => {
+                settings.stream_stall_ms = ms;
+                settings.stream_fault_percentage = Probability::ALWAYS;
+            }
+            Fault::StreamGarbage(bytes) => {
+                settings.stream_garbage_bytes = bytes;
+                settings.stream_fault_percentage = Probability::ALWAYS;
+            }
+            Fault::StreamAbort(percent) => {
+                settings.stream_abort_at_percent = percent;
+                settings.stream_fault_percentage = Probability::ALWAYS;
+            }
+        }
+    }
+}
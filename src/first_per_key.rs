@@ -0,0 +1,42 @@
+use crate::settings::RequestContext;
+
+pub fn render_key(template: &str, ctx: &RequestContext) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        match placeholder {
+            "method" => out.push_str(ctx.method.as_str()),
+            "uri" => out.push_str(&ctx.uri),
+            "path" => out.push_str(ctx.uri.split('?').next().unwrap_or_default()),
+            _ => {
+                if let Some(name) = placeholder.strip_prefix("header:") {
+                    if let Some(value) = ctx.headers.get(&name.to_ascii_lowercase()) {
+                        out.push_str(value);
+                    }
+                } else if let Some(name) = placeholder.strip_prefix("cookie:") {
+                    if let Some(value) = cookie(ctx, name) {
+                        out.push_str(value);
+                    }
+                } else {
+                    out.push_str(&rest[start..=start + end]);
+                }
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn cookie<'a>(ctx: &'a RequestContext, name: &str) -> Option<&'a str> {
+    ctx.headers.get("cookie")?.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::clock::{Clock, SystemClock};
+
+pub const CAPACITY: usize = 1_000;
+
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    pub window: Duration,
+    pub failure_percentage: u8,
+    pub min_requests: usize,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            failure_percentage: 50,
+            min_requests: 5,
+        }
+    }
+}
+
+pub struct UpstreamHealth {
+    config: HealthConfig,
+    destinations: Mutex<HashMap<String, VecDeque<(Instant, bool)>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for UpstreamHealth {
+    fn default() -> Self {
+        Self::new(HealthConfig::default())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DestinationHealth {
+    pub requests: usize,
+    pub failures: usize,
+    #[serde(rename = "failure-percentage")]
+    pub failure_percentage: u8,
+    pub healthy: bool,
+}
+
+impl UpstreamHealth {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            destinations: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // The window is measured on `clock`, so it follows a virtual clock too.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Destinations with nothing left in the window are forgotten, and at most
+    // `CAPACITY` are tracked; past that the one heard from least recently goes.
+    pub fn record(&self, destination: &str, failed: bool) {
+        let now = self.clock.now();
+        let mut guard = self.destinations.lock();
+        if !guard.contains_key(destination) {
+            guard.retain(|_, results| {
+                self.prune(results, now);
+                !results.is_empty()
+            });
+            if guard.len() >= CAPACITY
+                && let Some(idle) = guard
+                    .iter()
+                    .min_by_key(|(_, results)| results.back().map(|(at, _)| *at))
+                    .map(|(destination, _)| destination.clone())
+            {
+                guard.remove(&idle);
+            }
+        }
+        let results = guard.entry(destination.to_string()).or_default();
+        results.push_back((now, failed));
+        self.prune(results, now);
+    }
+
+    pub fn is_unhealthy(&self, destination: &str) -> bool {
+        let mut guard = self.destinations.lock();
+        guard
+            .get_mut(destination)
+            .is_some_and(|results| !self.summarize(results).healthy)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, DestinationHealth> {
+        let mut guard = self.destinations.lock();
+        guard
+            .iter_mut()
+            .map(|(destination, results)| (destination.clone(), self.summarize(results)))
+            .collect()
+    }
+
+    fn summarize(&self, results: &mut VecDeque<(Instant, bool)>) -> DestinationHealth {
+        self.prune(results, self.clock.now());
+        let requests = results.len();
+        let failures = results.iter().filter(|(_, failed)| *failed).count();
+        let failure_percentage = (failures * 100).checked_div(requests).unwrap_or(0) as u8;
+        DestinationHealth {
+            requests,
+            failures,
+            failure_percentage,
+            healthy: requests < self.config.min_requests
+                || failure_percentage < self.config.failure_percentage,
+        }
+    }
+
+    fn prune(&self, results: &mut VecDeque<(Instant, bool)>, now: Instant) {
+        while let Some((at, _)) = results.front()
+            && now.duration_since(*at) > self.config.window
+        {
+            results.pop_front();
+        }
+    }
+}
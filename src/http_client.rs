@@ -1,40 +1,235 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use axum::body::{self, Body};
 use bytes::Bytes;
+use futures_util::stream;
 use http::{HeaderMap, Method, StatusCode};
-use reqwest::Client;
+use http_body::Frame;
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::ext::ReasonPhrase;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use parking_lot::Mutex;
+use reqwest::{
+    Client,
+    dns::{Addrs, Name, Resolve, Resolving},
+};
+use serde::Serialize;
+use sync_wrapper::SyncStream;
 use thiserror::Error;
+use tokio::net::lookup_host;
+use tower::{Layer, Service};
+use url::{Host, Url};
 
-#[derive(Clone, Debug)]
+use crate::binding::OutboundBinding;
+use crate::clock;
+
+pub const HTTP3_SUPPORTED: bool = cfg!(feature = "http3");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpstreamProtocol {
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+    Http3,
+}
+
+impl UpstreamProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UpstreamProtocol::Auto => "auto",
+            UpstreamProtocol::Http1 => "http1",
+            UpstreamProtocol::Http2 => "http2",
+            UpstreamProtocol::Http3 => "http3",
+        }
+    }
+}
+
+impl FromStr for UpstreamProtocol {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "auto" => Ok(UpstreamProtocol::Auto),
+            "http1" => Ok(UpstreamProtocol::Http1),
+            "http2" => Ok(UpstreamProtocol::Http2),
+            "http3" if HTTP3_SUPPORTED => Ok(UpstreamProtocol::Http3),
+            "http3" => Err("http3 requires lowdown to be built with the http3 feature".to_string()),
+            other => Err(format!("unknown upstream protocol {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
 pub struct OutgoingRequest {
     pub method: Method,
     pub url: String,
     pub headers: HeaderMap,
-    pub body: Bytes,
+    pub body: RequestBody,
+    pub trailers: Option<HeaderMap>,
+    pub force_new_connection: bool,
+    pub binding: Option<OutboundBinding>,
+    pub protocol: UpstreamProtocol,
+    pub tls: TlsOverride,
+    pub preserve_encoding: bool,
+    pub dns_delay: Option<Duration>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOverride {
+    pub server_name: Option<String>,
+    pub skip_verify: bool,
+}
+
+impl OutgoingRequest {
+    pub fn try_clone(&self) -> Option<Self> {
+        let body = match &self.body {
+            RequestBody::Buffered(bytes) => RequestBody::Buffered(bytes.clone()),
+            RequestBody::Streaming(_) => return None,
+        };
+        Some(Self {
+            method: self.method.clone(),
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            body,
+            trailers: self.trailers.clone(),
+            force_new_connection: self.force_new_connection,
+            binding: self.binding.clone(),
+            protocol: self.protocol,
+            tls: self.tls.clone(),
+            preserve_encoding: self.preserve_encoding,
+            dns_delay: self.dns_delay,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestBody {
+    Buffered(Bytes),
+    Streaming(Body),
+}
+
+impl RequestBody {
+    pub async fn collect(self) -> Result<Bytes, HttpClientError> {
+        match self {
+            RequestBody::Buffered(bytes) => Ok(bytes),
+            RequestBody::Streaming(body) => body::to_bytes(body, usize::MAX)
+                .await
+                .map_err(|err| HttpClientError::Transport(err.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct ProxiedResponse {
     pub status: StatusCode,
     pub headers: HeaderMap,
-    pub body: Bytes,
+    pub body: ResponseBody,
+    pub trailers: Option<HeaderMap>,
+    pub new_connection: Option<bool>,
+    pub reason: Option<ReasonPhrase>,
+}
+
+// Streaming bodies carry their trailers as the last frame; `trailers` is only
+// filled in once the body is buffered.
+#[derive(Debug)]
+pub enum ResponseBody {
+    Buffered(Bytes),
+    Streaming(Body),
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        ResponseBody::Buffered(Bytes::new())
+    }
 }
 
 impl ProxiedResponse {
     pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> Self {
+        Self::with_body(status, headers, ResponseBody::Buffered(body))
+    }
+
+    pub fn streaming(status: StatusCode, headers: HeaderMap, body: Body) -> Self {
+        Self::with_body(status, headers, ResponseBody::Streaming(body))
+    }
+
+    fn with_body(status: StatusCode, headers: HeaderMap, body: ResponseBody) -> Self {
         Self {
             status,
             headers,
             body,
+            trailers: None,
+            new_connection: None,
+            reason: None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<&Bytes> {
+        match &self.body {
+            ResponseBody::Buffered(bytes) => Some(bytes),
+            ResponseBody::Streaming(_) => None,
+        }
+    }
+
+    pub fn bytes_mut(&mut self) -> Option<&mut Bytes> {
+        match &mut self.body {
+            ResponseBody::Buffered(bytes) => Some(bytes),
+            ResponseBody::Streaming(_) => None,
         }
     }
+
+    pub fn clear_body(&mut self) {
+        self.body = ResponseBody::default();
+        self.trailers = None;
+    }
+
+    pub async fn buffer(&mut self) -> Result<(), HttpClientError> {
+        if let ResponseBody::Streaming(body) = &mut self.body {
+            let collected = std::mem::take(body)
+                .collect()
+                .await
+                .map_err(|err| client_error(&err))?;
+            self.trailers = collected.trailers().cloned();
+            self.body = ResponseBody::Buffered(collected.to_bytes());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum HttpClientError {
     #[error("request failed: {0}")]
     Transport(String),
+    #[error("request timed out: {0}")]
+    Timeout(String),
+    #[error("rate limit for {0} exceeded")]
+    RateLimited(String),
+    #[error("circuit to {0} is open")]
+    CircuitOpen(String),
+    #[error("credentials for {0} are unavailable: {1}")]
+    Credentials(String, String),
 }
 
 #[async_trait]
@@ -42,14 +237,351 @@ pub trait HttpClient: Send + Sync {
     async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError>;
 }
 
+tokio::task_local! {
+    static CONNECTION_OPENED: Arc<AtomicBool>;
+    static DNS_DELAY: Option<Duration>;
+    static INTERIM: InterimSink;
+}
+
+// Receives the interim 1xx responses the upstream sends ahead of its final
+// response.
+pub type InterimSink = Arc<dyn Fn(StatusCode, &HeaderMap) + Send + Sync>;
+
+// Requests made by `future` hand their upstream's interim responses to `sink`.
+// Only `ReqwestHttpClient` sees them, and only for plain HTTP/1.1 upstreams.
+pub async fn forward_interim<F: Future>(sink: InterimSink, future: F) -> F::Output {
+    INTERIM.scope(sink, future).await
+}
+
+// Unset fields keep reqwest's defaults: no timeouts, no limit on idle
+// connections, which are closed after 90 seconds, and TCP keep-alive probes
+// every 15 seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub connect_timeout: Option<Duration>,
+    pub request_timeout: Option<Duration>,
+    pub pool_max_idle_per_host: Option<usize>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+}
+
 pub struct ReqwestHttpClient {
+    binding: OutboundBinding,
+    config: ClientConfig,
     client: Client,
+    fresh_client: Client,
+    clients: Mutex<HashMap<ClientKey, Client>>,
+    interim_client: InterimClient,
+}
+
+type InterimClient = hyper_util::client::legacy::Client<TrackedConnector<HttpConnector>, Body>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct ClientKey {
+    binding: OutboundBinding,
+    fresh: bool,
+    protocol: UpstreamProtocol,
+    skip_verify: bool,
+    preserve_encoding: bool,
+    resolve: Option<(String, SocketAddr)>,
 }
 
 impl ReqwestHttpClient {
     pub fn new() -> Result<Self, reqwest::Error> {
+        Self::with_binding(OutboundBinding::default())
+    }
+
+    pub fn with_binding(binding: OutboundBinding) -> Result<Self, reqwest::Error> {
+        Self::with_config(binding, ClientConfig::default())
+    }
+
+    pub fn with_config(
+        binding: OutboundBinding,
+        config: ClientConfig,
+    ) -> Result<Self, reqwest::Error> {
+        let key = ClientKey {
+            binding: binding.clone(),
+            ..ClientKey::default()
+        };
         Ok(Self {
-            client: Client::builder().build()?,
+            client: build_client(&key, &config)?,
+            fresh_client: build_client(&ClientKey { fresh: true, ..key }, &config)?,
+            interim_client: build_interim_client(&config),
+            binding,
+            config,
+            clients: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn client_for(
+        &self,
+        request: &OutgoingRequest,
+        resolve: Option<(String, SocketAddr)>,
+    ) -> Result<Client, HttpClientError> {
+        let key = ClientKey {
+            binding: request
+                .binding
+                .as_ref()
+                .map_or_else(|| self.binding.clone(), |binding| binding.or(&self.binding)),
+            fresh: request.force_new_connection,
+            protocol: request.protocol,
+            skip_verify: request.tls.skip_verify,
+            preserve_encoding: request.preserve_encoding,
+            resolve,
+        };
+        if key.binding == self.binding
+            && key.protocol == UpstreamProtocol::Auto
+            && !key.skip_verify
+            && !key.preserve_encoding
+            && key.resolve.is_none()
+        {
+            return Ok(if key.fresh {
+                self.fresh_client.clone()
+            } else {
+                self.client.clone()
+            });
+        }
+        let mut guard = self.clients.lock();
+        if let Some(client) = guard.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = build_client(&key, &self.config)
+            .map_err(|err| HttpClientError::Transport(err.to_string()))?;
+        guard.insert(key, client.clone());
+        Ok(client)
+    }
+
+    // reqwest drops interim responses, so requests that want them go through
+    // hyper's client, which only reports them over HTTP/1. That rules out
+    // TLS upstreams, whose connections may negotiate HTTP/2, and every
+    // per-request connection override reqwest would otherwise apply.
+    fn forwards_interim(&self, request: &OutgoingRequest) -> bool {
+        request.url.starts_with("http://")
+            && matches!(
+                request.protocol,
+                UpstreamProtocol::Auto | UpstreamProtocol::Http1
+            )
+            && request.binding.is_none()
+            && self.binding == OutboundBinding::default()
+            && !request.force_new_connection
+            && request.dns_delay.is_none()
+    }
+
+    async fn execute_forwarding_interim(
+        &self,
+        request: OutgoingRequest,
+        sink: InterimSink,
+    ) -> Result<ProxiedResponse, HttpClientError> {
+        let body = match (request.body, request.trailers) {
+            (RequestBody::Buffered(bytes), None) => Body::from(bytes),
+            (RequestBody::Buffered(bytes), Some(trailers)) => {
+                Body::new(StreamBody::new(stream::iter([
+                    Ok::<_, Infallible>(Frame::data(bytes)),
+                    Ok(Frame::trailers(trailers)),
+                ])))
+            }
+            (RequestBody::Streaming(body), _) => body,
+        };
+        let mut outgoing = http::Request::builder()
+            .method(request.method)
+            .uri(&request.url)
+            .body(body)
+            .map_err(|err| HttpClientError::Transport(err.to_string()))?;
+        *outgoing.headers_mut() = request.headers;
+        hyper::ext::on_informational(&mut outgoing, move |response| {
+            sink(response.status(), response.headers());
+        });
+
+        let opened = Arc::new(AtomicBool::new(false));
+        let send = CONNECTION_OPENED.scope(opened.clone(), self.interim_client.request(outgoing));
+        let result = match self.config.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send).await.map_err(|_| {
+                HttpClientError::Timeout(format!(
+                    "no response from {} within {timeout:?}",
+                    request.url
+                ))
+            })?,
+            None => send.await,
+        };
+        let (parts, body) = result.map_err(|err| client_error(&err))?.into_parts();
+        let mut proxied = ProxiedResponse::streaming(parts.status, parts.headers, Body::new(body));
+        proxied.reason = parts.extensions.get::<ReasonPhrase>().cloned();
+        proxied.new_connection = Some(opened.load(Ordering::Relaxed));
+        Ok(proxied)
+    }
+}
+
+// Overriding SNI means connecting to the destination's address while the URL
+// names the server-name host, so reqwest sends it in the TLS handshake.
+async fn override_server_name(
+    url: &str,
+    server_name: &str,
+    dns_delay: Option<Duration>,
+) -> Result<(String, Option<(String, SocketAddr)>), HttpClientError> {
+    let invalid = |message: String| HttpClientError::Transport(message);
+    let mut parsed = Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+    if parsed.scheme() != "https" {
+        return Ok((url.to_string(), None));
+    }
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let address = match parsed.host() {
+        Some(Host::Ipv4(ip)) => SocketAddr::new(ip.into(), port),
+        Some(Host::Ipv6(ip)) => SocketAddr::new(ip.into(), port),
+        Some(Host::Domain(domain)) => {
+            if let Some(delay) = dns_delay {
+                clock::sleep(delay).await;
+            }
+            lookup_host((domain, port))
+                .await
+                .map_err(|err| invalid(format!("failed to resolve {domain}: {err}")))?
+                .next()
+                .ok_or_else(|| invalid(format!("no addresses for {domain}")))?
+        }
+        None => return Err(invalid(format!("{url} has no host"))),
+    };
+    parsed
+        .set_host(Some(server_name))
+        .map_err(|err| invalid(format!("invalid tls-server-name {server_name:?}: {err}")))?;
+    Ok((parsed.to_string(), Some((server_name.to_string(), address))))
+}
+
+fn build_client(key: &ClientKey, config: &ClientConfig) -> Result<Client, reqwest::Error> {
+    // Redirects go back to the client like any other response. Following them
+    // would also carry injected upstream credentials to whatever host the
+    // backend redirects to.
+    let mut builder = Client::builder()
+        .connector_layer(ConnectionTracking)
+        .dns_resolver(Arc::new(DelayingResolver))
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(timeout) = config.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(timeout);
+    }
+    if let Some(interval) = config.tcp_keepalive {
+        builder = builder.tcp_keepalive(interval);
+    }
+    if let Some(timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = config.request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    if key.fresh {
+        builder = builder.pool_max_idle_per_host(0);
+    } else if let Some(max) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if key.skip_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if key.preserve_encoding {
+        builder = builder.no_gzip().no_brotli().no_deflate();
+    }
+    if let Some((domain, address)) = &key.resolve {
+        builder = builder.resolve(domain, *address);
+    }
+    match key.protocol {
+        UpstreamProtocol::Auto => {}
+        UpstreamProtocol::Http1 => builder = builder.http1_only(),
+        UpstreamProtocol::Http2 => builder = builder.http2_prior_knowledge(),
+        #[cfg(feature = "http3")]
+        UpstreamProtocol::Http3 => builder = builder.http3_prior_knowledge(),
+        #[cfg(not(feature = "http3"))]
+        UpstreamProtocol::Http3 => {}
+    }
+    if let Some(address) = key.binding.local_address {
+        builder = builder.local_address(address);
+    }
+    #[cfg(any(
+        target_os = "android",
+        target_os = "fuchsia",
+        target_os = "illumos",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "solaris",
+        target_os = "tvos",
+        target_os = "visionos",
+        target_os = "watchos",
+    ))]
+    if let Some(interface) = &key.binding.interface {
+        builder = builder.interface(interface);
+    }
+    builder.build()
+}
+
+// Matches what `build_client` leaves reqwest to do for a default key, apart
+// from decompressing responses, which reach the client as the upstream sent
+// them.
+fn build_interim_client(config: &ClientConfig) -> InterimClient {
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    connector.set_connect_timeout(config.connect_timeout);
+    connector.set_keepalive(Some(
+        config.tcp_keepalive.unwrap_or(Duration::from_secs(15)),
+    ));
+    let mut builder = hyper_util::client::legacy::Client::builder(TokioExecutor::new());
+    builder.pool_timer(TokioTimer::new());
+    if let Some(timeout) = config.pool_idle_timeout {
+        builder.pool_idle_timeout(timeout);
+    }
+    if let Some(max) = config.pool_max_idle_per_host {
+        builder.pool_max_idle_per_host(max);
+    }
+    builder.build(ConnectionTracking.layer(connector))
+}
+
+// The connector is only invoked when the pool has no idle connection to hand
+// out, and it runs inside the task that issued the request, so flagging the
+// task-local here tells `execute` whether its request opened a new connection.
+#[derive(Clone)]
+struct ConnectionTracking;
+
+impl<S> Layer<S> for ConnectionTracking {
+    type Service = TrackedConnector<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TrackedConnector { inner }
+    }
+}
+
+#[derive(Clone)]
+struct TrackedConnector<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for TrackedConnector<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let _ = CONNECTION_OPENED.try_with(|opened| opened.store(true, Ordering::Relaxed));
+        self.inner.call(request)
+    }
+}
+
+// Resolution starts on the first poll of the connect future, which happens in
+// the task that issued the request, so the task-local delay is still in scope.
+// IP literals and names pinned with `resolve` never reach the resolver.
+struct DelayingResolver;
+
+impl Resolve for DelayingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let delay = DNS_DELAY.try_with(|delay| *delay).ok().flatten();
+        let clock = clock::current();
+        Box::pin(async move {
+            if let Some(delay) = delay {
+                clock.sleep(delay).await;
+            }
+            let addresses = lookup_host((name.as_str(), 0)).await?;
+            Ok(Box::new(addresses.collect::<Vec<_>>().into_iter()) as Addrs)
         })
     }
 }
@@ -57,33 +589,78 @@ impl ReqwestHttpClient {
 #[async_trait]
 impl HttpClient for ReqwestHttpClient {
     async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError> {
-        let builder = self
-            .client
+        if let Ok(sink) = INTERIM.try_with(Arc::clone)
+            && self.forwards_interim(&request)
+        {
+            return self.execute_forwarding_interim(request, sink).await;
+        }
+        let (url, resolve) = match &request.tls.server_name {
+            Some(server_name) => {
+                override_server_name(&request.url, server_name, request.dns_delay).await?
+            }
+            None => (request.url.clone(), None),
+        };
+        let client = self.client_for(&request, resolve)?;
+        let builder = client
             .request(
                 reqwest::Method::from_bytes(request.method.as_str().as_bytes())
                     .unwrap_or(reqwest::Method::GET),
-                &request.url,
+                &url,
             )
-            .headers(request.headers.clone())
-            .body(request.body.clone());
+            .headers(request.headers)
+            .body(match (request.body, request.trailers) {
+                (RequestBody::Buffered(bytes), None) => reqwest::Body::from(bytes),
+                (RequestBody::Buffered(bytes), Some(trailers)) => {
+                    reqwest::Body::wrap(StreamBody::new(stream::iter([
+                        Ok::<_, Infallible>(Frame::data(bytes)),
+                        Ok(Frame::trailers(trailers)),
+                    ])))
+                }
+                (RequestBody::Streaming(body), _) => {
+                    reqwest::Body::wrap(StreamBody::new(SyncStream::new(BodyStream::new(body))))
+                }
+            });
+        #[cfg(feature = "http3")]
+        let builder = match request.protocol {
+            UpstreamProtocol::Http3 => builder.version(reqwest::Version::HTTP_3),
+            _ => builder,
+        };
 
-        match builder.send().await {
+        let opened = Arc::new(AtomicBool::new(false));
+        let send = DNS_DELAY.scope(request.dns_delay, builder.send());
+        match CONNECTION_OPENED.scope(opened.clone(), send).await {
             Ok(response) => {
                 let status = response.status();
                 let headers = response.headers().clone();
-                let body = response
-                    .bytes()
-                    .await
-                    .map_err(|err| HttpClientError::Transport(err.to_string()))?;
-                Ok(ProxiedResponse::new(
+                let (parts, body) = http::Response::from(response).into_parts();
+                let mut proxied = ProxiedResponse::streaming(
                     StatusCode::from_u16(status.as_u16()).unwrap_or(status),
                     headers,
-                    body,
-                ))
+                    Body::new(body),
+                );
+                proxied.reason = parts.extensions.get::<ReasonPhrase>().cloned();
+                proxied.new_connection = Some(opened.load(Ordering::Relaxed));
+                Ok(proxied)
             }
-            Err(err) => Err(HttpClientError::Transport(err.to_string())),
+            Err(err) => Err(client_error(&err)),
         }
     }
 }
 
+fn client_error(err: &(dyn std::error::Error + 'static)) -> HttpClientError {
+    let timed_out = std::iter::successors(Some(err), |err| err.source()).any(|err| {
+        err.downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout)
+    });
+    if timed_out {
+        HttpClientError::Timeout(err.to_string())
+    } else {
+        HttpClientError::Transport(err.to_string())
+    }
+}
+
 pub type SharedHttpClient = Arc<dyn HttpClient>;
+
+pub fn is_upstream_failure(status: StatusCode) -> bool {
+    status.as_u16() >= 500
+}
@@ -0,0 +1,233 @@
+use std::future::IntoFuture;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, anyhow};
+use axum::{Router, body::Body, extract::State, response::Response, routing::get};
+use http::StatusCode;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, warn};
+
+use crate::connection::ListenerOptions;
+use crate::response::json_response;
+use crate::state::AppState;
+use crate::supervisor::DEFAULT_SHUTDOWN_GRACE;
+use crate::{admin, connection, proxy};
+
+const USAGE: &str = "usage: lowdown instances -n <count> -p <first port>-<last port>";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Instance {
+    pub index: usize,
+    pub proxy: SocketAddr,
+    pub admin: SocketAddr,
+}
+
+pub struct InstancesBuilder {
+    count: usize,
+    bind: IpAddr,
+    ports: Option<RangeInclusive<u16>>,
+    tls: Option<TlsAcceptor>,
+    listener_options: ListenerOptions,
+    shutdown_grace: Duration,
+}
+
+impl InstancesBuilder {
+    pub fn bind(mut self, address: IpAddr) -> Self {
+        self.bind = address;
+        self
+    }
+
+    pub fn ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    pub fn tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    pub fn listener_options(mut self, options: ListenerOptions) -> Self {
+        self.listener_options = options;
+        self
+    }
+
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    // Instance n listens on the (2n)th and (2n+1)th ports of the range, for its
+    // proxy and admin server. Everything is bound before any instance starts,
+    // so a taken port fails the whole set instead of leaving half of it up.
+    pub async fn spawn(
+        self,
+        mut state: impl FnMut(usize) -> anyhow::Result<Arc<AppState>>,
+    ) -> anyhow::Result<Instances> {
+        if self.count == 0 {
+            return Err(anyhow!("at least one instance is required"));
+        }
+        let port = |offset: usize| match &self.ports {
+            Some(ports) => *ports.start() + offset as u16,
+            None => 0,
+        };
+        if let Some(ports) = &self.ports
+            && ports.len() < self.count * 2
+        {
+            return Err(anyhow!(
+                "port range {}-{} is too small for {} instances, which need {} ports",
+                ports.start(),
+                ports.end(),
+                self.count,
+                self.count * 2
+            ));
+        }
+        let mut listeners = Vec::with_capacity(self.count);
+        for index in 0..self.count {
+            let proxy = SocketAddr::new(self.bind, port(index * 2));
+            let admin = SocketAddr::new(self.bind, port(index * 2 + 1));
+            listeners.push((
+                self.listener_options
+                    .bind(proxy)
+                    .with_context(|| format!("failed to bind proxy listener at {proxy}"))?,
+                self.listener_options
+                    .bind(admin)
+                    .with_context(|| format!("failed to bind admin listener at {admin}"))?,
+            ));
+        }
+
+        let (shutdown, stopped) = watch::channel(false);
+        let mut instances = Instances {
+            instances: Vec::with_capacity(self.count),
+            states: Vec::with_capacity(self.count),
+            shutdown,
+            shutdown_grace: self.shutdown_grace,
+            tasks: Vec::with_capacity(self.count * 2),
+        };
+        for (index, (proxy_listener, admin_listener)) in listeners.into_iter().enumerate() {
+            let state = state(index)?;
+            instances.instances.push(Instance {
+                index,
+                proxy: proxy_listener.local_addr()?,
+                admin: admin_listener.local_addr()?,
+            });
+            instances.tasks.push(tokio::spawn(connection::serve(
+                proxy_listener,
+                proxy::router(state.clone()),
+                self.tls.clone(),
+                self.listener_options,
+                wait_for_shutdown(stopped.clone()),
+            )));
+            let admin_server = axum::serve(
+                admin_listener,
+                admin::router(state.clone()).into_make_service(),
+            )
+            .tcp_nodelay(self.listener_options.nodelay)
+            .with_graceful_shutdown(wait_for_shutdown(stopped.clone()));
+            instances
+                .tasks
+                .push(tokio::spawn(admin_server.into_future()));
+            instances.states.push(state);
+        }
+        Ok(instances)
+    }
+}
+
+async fn wait_for_shutdown(mut stopped: watch::Receiver<bool>) {
+    let _ = stopped.wait_for(|stopped| *stopped).await;
+}
+
+pub struct Instances {
+    instances: Vec<Instance>,
+    states: Vec<Arc<AppState>>,
+    shutdown: watch::Sender<bool>,
+    shutdown_grace: Duration,
+    tasks: Vec<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Instances {
+    pub fn builder(count: usize) -> InstancesBuilder {
+        InstancesBuilder {
+            count,
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ports: None,
+            tls: None,
+            listener_options: ListenerOptions::default(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+        }
+    }
+
+    pub fn list(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn states(&self) -> &[Arc<AppState>] {
+        &self.states
+    }
+
+    pub fn index_router(&self) -> Router {
+        Router::new()
+            .route("/api/v1/instances", get(list_instances))
+            .with_state(Arc::new(self.instances.clone()))
+    }
+
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let deadline = tokio::time::Instant::now() + self.shutdown_grace;
+        for mut task in self.tasks {
+            match tokio::time::timeout_at(deadline, &mut task).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(err))) => error!("instance server exited with error: {err}"),
+                Ok(Err(err)) => error!("instance server task failed: {err}"),
+                Err(_) => {
+                    warn!(
+                        "instance server did not drain within {} ms, closing its remaining connections",
+                        self.shutdown_grace.as_millis()
+                    );
+                    task.abort();
+                }
+            }
+        }
+    }
+}
+
+async fn list_instances(State(instances): State<Arc<Vec<Instance>>>) -> Response<Body> {
+    json_response(StatusCode::OK, &json!({"instances": *instances}), false)
+}
+
+pub fn parse_args(args: &[String]) -> Result<(usize, RangeInclusive<u16>), String> {
+    let (mut count, mut ports) = (None, None);
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(USAGE)?;
+        match flag.as_str() {
+            "-n" | "--count" => {
+                count = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid instance count {value:?}"))?,
+                );
+            }
+            "-p" | "--ports" => {
+                let (first, last) = value
+                    .split_once('-')
+                    .and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)))
+                    .filter(|(first, last): &(u16, u16)| first <= last && *first != 0)
+                    .ok_or_else(|| format!("invalid port range {value:?}"))?;
+                ports = Some(first..=last);
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    match (count, ports) {
+        (Some(count), Some(ports)) => Ok((count, ports)),
+        _ => Err(USAGE.to_string()),
+    }
+}
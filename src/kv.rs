@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use jiff::{SignedDuration, Timestamp};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::clock::{Clock, SystemClock};
+
+const CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KvEntry {
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Timestamp>,
+}
+
+impl KvEntry {
+    fn new(value: Value, ttl: Option<Duration>, now: Timestamp) -> Self {
+        let expires_at = ttl
+            .and_then(|ttl| SignedDuration::try_from(ttl).ok())
+            .and_then(|ttl| now.checked_add(ttl).ok());
+        Self { value, expires_at }
+    }
+
+    fn is_live(&self, now: Timestamp) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+// Entries with the order they were written in, so the oldest can be evicted
+// once the store is full.
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<String, (u64, KvEntry)>,
+    written: BTreeMap<u64, String>,
+    next: u64,
+}
+
+impl Entries {
+    fn get(&self, key: &str, now: Timestamp) -> Option<&KvEntry> {
+        self.entries
+            .get(key)
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.is_live(now))
+    }
+
+    fn insert(&mut self, key: &str, entry: KvEntry) {
+        match self.entries.get_mut(key) {
+            Some((written, existing)) => {
+                self.written.remove(written);
+                *written = self.next;
+                *existing = entry;
+            }
+            None => {
+                while self.entries.len() >= CAPACITY
+                    && let Some((_, oldest)) = self.written.pop_first()
+                {
+                    debug!("Key-value store is full, evicting {oldest:?}");
+                    self.entries.remove(&oldest);
+                }
+                self.entries.insert(key.to_string(), (self.next, entry));
+            }
+        }
+        self.written.insert(self.next, key.to_string());
+        self.next += 1;
+    }
+
+    fn remove(&mut self, key: &str) -> Option<KvEntry> {
+        let (written, entry) = self.entries.remove(key)?;
+        self.written.remove(&written);
+        Some(entry)
+    }
+}
+
+pub struct KvStore {
+    entries: Mutex<Entries>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for KvStore {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            path: None,
+            dirty: AtomicBool::new(false),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl KvStore {
+    pub fn persistent(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let loaded: HashMap<String, KvEntry> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        let mut entries = Entries::default();
+        for (key, entry) in loaded {
+            entries.insert(&key, entry);
+        }
+        Ok(Self {
+            entries: Mutex::new(entries),
+            path: Some(path),
+            ..Self::default()
+        })
+    }
+
+    // TTLs are measured on `clock`, so they follow a virtual clock too.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let now = self.clock.timestamp();
+        self.entries
+            .lock()
+            .get(key, now)
+            .map(|entry| entry.value.clone())
+    }
+
+    pub fn set(&self, key: &str, value: Value, ttl: Option<Duration>) {
+        let now = self.clock.timestamp();
+        self.entries
+            .lock()
+            .insert(key, KvEntry::new(value, ttl, now));
+        self.touch();
+    }
+
+    pub fn set_if_absent(&self, key: &str, value: Value, ttl: Option<Duration>) -> bool {
+        let now = self.clock.timestamp();
+        let mut guard = self.entries.lock();
+        if guard.get(key, now).is_some() {
+            return false;
+        }
+        guard.insert(key, KvEntry::new(value, ttl, now));
+        self.touch();
+        true
+    }
+
+    pub fn increment(&self, key: &str, delta: i64, ttl: Option<Duration>) -> i64 {
+        let now = self.clock.timestamp();
+        let mut guard = self.entries.lock();
+        let current = guard
+            .get(key, now)
+            .map(|entry| (entry.value.as_i64().unwrap_or(0), entry.expires_at));
+        let value = current.map_or(0, |(value, _)| value).saturating_add(delta);
+        let entry = match current {
+            Some((_, expires_at)) => KvEntry {
+                value: value.into(),
+                expires_at,
+            },
+            None => KvEntry::new(value.into(), ttl, now),
+        };
+        guard.insert(key, entry);
+        self.touch();
+        value
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let now = self.clock.timestamp();
+        let removed = self.entries.lock().remove(key);
+        if removed.is_some() {
+            self.touch();
+        }
+        removed
+            .filter(|entry| entry.is_live(now))
+            .map(|entry| entry.value)
+    }
+
+    pub fn entry(&self, key: &str) -> Option<KvEntry> {
+        let now = self.clock.timestamp();
+        self.entries.lock().get(key, now).cloned()
+    }
+
+    pub fn list(&self, prefix: &str) -> BTreeMap<String, KvEntry> {
+        let now = self.clock.timestamp();
+        self.entries
+            .lock()
+            .entries
+            .iter()
+            .filter(|(key, (_, entry))| key.starts_with(prefix) && entry.is_live(now))
+            .map(|(key, (_, entry))| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let now = self.clock.timestamp();
+        let live: HashMap<String, KvEntry> = self
+            .entries
+            .lock()
+            .entries
+            .iter()
+            .filter(|(_, (_, entry))| entry.is_live(now))
+            .map(|(key, (_, entry))| (key.clone(), entry.clone()))
+            .collect();
+        let result = serde_json::to_vec(&live)
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| {
+                let temp = path.with_extension("tmp");
+                std::fs::write(&temp, bytes)?;
+                std::fs::rename(&temp, path)
+            });
+        if result.is_err() {
+            self.touch();
+        }
+        result
+    }
+
+    fn touch(&self) {
+        if self.path.is_some() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -1,50 +1,377 @@
+pub mod access_log;
 pub mod admin;
+pub mod admin_chaos;
+pub mod assertions;
+pub mod binding;
+pub mod breaker;
+pub mod browser;
+pub mod check;
+pub mod clock;
+pub mod comparisons;
+pub mod conditional;
+pub mod config_file;
+pub mod connection;
+pub mod content_encoding;
+pub mod corruption;
+pub mod credentials;
+pub mod default_behavior;
+pub mod destinations;
+pub mod envoy;
+pub mod evaluation;
+pub mod experiments;
+pub mod export;
+pub mod failure;
+pub mod fault_set;
+pub mod first_per_key;
+pub mod health;
 pub mod http_client;
+pub mod instances;
+pub mod kv;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod mutation;
+pub mod probability;
 pub mod proxy;
+pub mod random;
+pub mod report;
 pub mod response;
+pub mod rules;
+pub mod scenarios;
+pub mod schema;
+pub mod setting_types;
 pub mod settings;
+pub mod snapshots;
 pub mod state;
+pub mod stats;
+pub mod sticky;
+pub mod streaming;
+pub mod streams;
+pub mod stubs;
+pub mod supervisor;
+pub mod systemd;
+pub mod tasks;
+pub mod tee;
+pub mod tls;
+pub mod toxiproxy;
+pub mod traffic;
+pub mod transform;
+pub mod tunnel;
+pub mod wiremock;
 
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
+use access_log::{AccessLog, AccessLogFormat};
 use admin::router as admin_router;
 use anyhow::{Context, anyhow};
-use http_client::ReqwestHttpClient;
+use binding::OutboundBinding;
+use connection::ListenerOptions;
+use health::HealthConfig;
+use http_client::{ClientConfig, ReqwestHttpClient};
+use instances::Instances;
+use kv::KvStore;
+use logging::LogLevel;
 use proxy::router as proxy_router;
-use settings::SettingsLayer;
+use report::DEFAULT_SLO;
+use settings::{HeaderPolicy, Precedence, SettingsLayer};
+use snapshots::SnapshotWriter;
 use state::AppState;
+use supervisor::{DEFAULT_SHUTDOWN_GRACE, RestartPolicy, Supervision, Supervisor};
 use tracing::{error, info};
 
 use axum::Router;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
 
-pub async fn run() -> anyhow::Result<()> {
+const KV_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub async fn run(log_level: LogLevel) -> anyhow::Result<()> {
     let config = server_config_from_env()?;
+    let state = state_from_env(Arc::new(log_level), None)?;
+    spawn_kv_flush(state.clone());
+
+    let proxy = proxy_router(state.clone());
+    let admin = admin_router(state.clone());
+
+    let summary_file = config.summary_file.clone();
+    let result = run_servers(config, state.supervision(), proxy, admin).await;
+    flush_kv(&state);
+    shutdown_summary(&state, summary_file.as_deref());
+    result
+}
+
+pub async fn run_instances(log_level: LogLevel, args: &[String]) -> anyhow::Result<()> {
+    let (count, ports) = instances::parse_args(args).map_err(|err| anyhow!(err))?;
+    let log_level = Arc::new(log_level);
+    let config = server_config_from_env()?;
+    let mut builder = Instances::builder(count)
+        .bind(config.proxy_addr.ip())
+        .ports(ports)
+        .listener_options(config.listener_options)
+        .shutdown_grace(config.shutdown_grace);
+    if let Some(tls) = config.proxy_tls {
+        builder = builder.tls(tls);
+    }
+    let instances = builder
+        .spawn(|index| state_from_env(log_level.clone(), Some(index)))
+        .await?;
+    for instance in instances.list() {
+        info!(
+            "Instance {} serving proxy at {} and admin at {}",
+            instance.index, instance.proxy, instance.admin
+        );
+    }
+    let states = instances.states().to_vec();
+    for state in &states {
+        spawn_kv_flush(state.clone());
+    }
+
+    info!("Starting instance index at {}", config.admin_addr);
+    let listener = TcpListener::bind(config.admin_addr)
+        .await
+        .context("failed to bind admin listener")?;
+    systemd::notify("READY=1");
+    let result = axum::serve(listener, instances.index_router())
+        .with_graceful_shutdown(async {
+            shutdown_signal("instance index").await;
+            systemd::notify("STOPPING=1");
+        })
+        .await
+        .context("instance index error");
+    instances.shutdown().await;
+    for (index, state) in states.iter().enumerate() {
+        flush_kv(state);
+        let summary_file = config
+            .summary_file
+            .as_deref()
+            .map(|path| instance_path(path, index));
+        shutdown_summary(state, summary_file.as_deref());
+    }
+    result
+}
+
+// `summary.md` becomes `summary.0.md`, keeping the extension that picks the
+// summary format.
+fn instance_path(path: &Path, index: usize) -> PathBuf {
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path.with_file_name(format!(
+            "{}.{index}.{}",
+            stem.to_string_lossy(),
+            extension.to_string_lossy()
+        )),
+        _ => PathBuf::from(format!("{}.{index}", path.display())),
+    }
+}
+
+// Every instance reads the same environment; `instance` only keeps their
+// persisted key-value stores apart.
+fn state_from_env(
+    log_level: Arc<LogLevel>,
+    instance: Option<usize>,
+) -> anyhow::Result<Arc<AppState>> {
     let env_layer = SettingsLayer::from_env();
-    let development_trailer = if std::env::var("LOWDOWN_DEVELOPMENT")
+    let mikkmokk_compat = env_flag("LOWDOWN_MIKKMOKK_COMPAT");
+    let admin_chaos = env_flag("LOWDOWN_ADMIN_CHAOS");
+    let dev_mode =
+        env_flag("LOWDOWN_DEVELOPMENT") || (mikkmokk_compat && env_flag("MIKKMOKK_DEVELOPMENT"));
+
+    let header_policy = HeaderPolicy::parse(
+        &std::env::var("LOWDOWN_HEADER_POLICY").unwrap_or_default(),
+        std::env::var("LOWDOWN_HEADER_ALLOWLIST").ok().as_deref(),
+    )
+    .map_err(|err| anyhow!("invalid LOWDOWN_HEADER_POLICY: {err}"))?;
+    let precedence =
+        Precedence::parse(&std::env::var("LOWDOWN_SETTINGS_PRECEDENCE").unwrap_or_default())
+            .map_err(|err| anyhow!("invalid LOWDOWN_SETTINGS_PRECEDENCE: {err}"))?;
+
+    let binding = outbound_binding_from_env()?;
+    if !binding.is_empty() {
+        info!("Binding outbound connections to {binding:?}");
+    }
+    let client = Arc::new(
+        ReqwestHttpClient::with_config(binding, client_config_from_env()?)
+            .context("failed to create outbound HTTP client")?,
+    );
+    let mut builder = AppState::builder(client);
+    if let Ok(mut path) = std::env::var("LOWDOWN_KV_PATH") {
+        if let Some(index) = instance {
+            path = format!("{path}.{index}");
+        }
+        let kv = KvStore::persistent(&path)
+            .with_context(|| format!("failed to load key-value store from {path}"))?;
+        builder = builder.kv_store(kv);
+    }
+    if let Ok(dir) = std::env::var("LOWDOWN_SNAPSHOT_DIR") {
+        info!("Writing snapshots of tagged exchanges to {dir}");
+        builder = builder.snapshots(SnapshotWriter::new(dir));
+    } else if env_flag("LOWDOWN_SNAPSHOT_MEMORY") {
+        info!("Keeping snapshots of tagged exchanges in memory");
+        builder = builder.snapshots(SnapshotWriter::in_memory());
+    }
+    if let Ok(dir) = std::env::var("LOWDOWN_RESPONSE_TEE_DIR") {
+        info!("Writing response bodies selected by response-tee-path to {dir}");
+        builder = builder.response_tee_dir(dir);
+    }
+    if let Ok(seed) = std::env::var("RANDOM_SEED") {
+        let seed = seed
+            .parse()
+            .with_context(|| format!("invalid RANDOM_SEED {seed:?}"))?;
+        info!("Seeding fault rolls with {seed}");
+        builder = builder.random_seed(seed);
+    }
+    if let Ok(path) = std::env::var("CONFIG_FILE") {
+        builder = builder.config_file(path);
+    }
+    let format = match std::env::var("ACCESS_LOG_FORMAT") {
+        Ok(format) => format
+            .parse()
+            .map_err(|err| anyhow!("invalid ACCESS_LOG_FORMAT: {err}"))?,
+        Err(_) => AccessLogFormat::default(),
+    };
+    builder = builder.access_log(match std::env::var("ACCESS_LOG_FILE") {
+        Ok(mut path) => {
+            if let Some(index) = instance {
+                path = format!("{path}.{index}");
+            }
+            info!("Writing the access log to {path}");
+            AccessLog::to_file(format, &path)
+                .with_context(|| format!("failed to open access log {path}"))?
+        }
+        Err(_) => AccessLog::new(format),
+    });
+    let state = Arc::new(
+        builder
+            .env_layer(env_layer)
+            .dev_mode(dev_mode)
+            .admin_chaos(admin_chaos)
+            .mikkmokk_compat(mikkmokk_compat)
+            .header_policy(header_policy)
+            .precedence(precedence)
+            .health_config(health_config_from_env())
+            .log_level(log_level)
+            .build(),
+    );
+    state
+        .reload_config()
+        .map_err(|err| anyhow!("invalid CONFIG_FILE: {err}"))?;
+    if instance.unwrap_or_default() == 0 {
+        state.log_env_overrides();
+    }
+    Ok(state)
+}
+
+fn spawn_kv_flush(state: Arc<AppState>) {
+    let Some(path) = state.kv().path() else {
+        return;
+    };
+    info!("Persisting key-value store to {}", path.display());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KV_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush_kv(&state);
+        }
+    });
+}
+
+fn shutdown_summary(state: &AppState, path: Option<&Path>) {
+    let (records, changes) = state.traffic().since(None);
+    let summary = report::build(
+        &records,
+        changes,
+        Some(state.started_at()),
+        DEFAULT_SLO,
+        state.clock().timestamp(),
+    );
+    report::log_summary(&summary);
+    if let Some(path) = path {
+        match report::write_summary(&summary, path) {
+            Ok(()) => info!("Wrote fault injection summary to {}", path.display()),
+            Err(err) => error!("failed to write summary to {}: {err}", path.display()),
+        }
+    }
+}
+
+fn flush_kv(state: &AppState) {
+    if let Err(err) = state.kv().flush() {
+        error!("failed to persist key-value store: {err}");
+    }
+}
+
+fn env_flag(key: &str) -> bool {
+    std::env::var(key)
         .map(|v| v.eq_ignore_ascii_case("true"))
         .unwrap_or(false)
-    {
-        "\n".to_string()
-    } else {
-        String::new()
-    };
+}
 
-    let client =
-        Arc::new(ReqwestHttpClient::new().context("failed to create outbound HTTP client")?);
-    let state = Arc::new(AppState::new(env_layer, development_trailer, client));
-    state.log_env_overrides();
+fn health_config_from_env() -> HealthConfig {
+    let defaults = HealthConfig::default();
+    let parse = |key: &str| std::env::var(key).ok().and_then(|v| v.parse::<u64>().ok());
+    HealthConfig {
+        window: parse("LOWDOWN_HEALTH_WINDOW_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.window),
+        failure_percentage: parse("LOWDOWN_HEALTH_FAILURE_PERCENTAGE")
+            .map(|value| value.min(100) as u8)
+            .unwrap_or(defaults.failure_percentage),
+        min_requests: parse("LOWDOWN_HEALTH_MIN_REQUESTS")
+            .map(|value| value as usize)
+            .unwrap_or(defaults.min_requests),
+    }
+}
 
-    let proxy = proxy_router(state.clone());
-    let admin = admin_router(state);
+fn outbound_binding_from_env() -> anyhow::Result<OutboundBinding> {
+    let local_address = std::env::var("LOWDOWN_OUTBOUND_LOCAL_ADDRESS")
+        .ok()
+        .map(|value| value.parse())
+        .transpose()
+        .context("invalid LOWDOWN_OUTBOUND_LOCAL_ADDRESS")?;
+    let binding = OutboundBinding {
+        local_address,
+        interface: std::env::var("LOWDOWN_OUTBOUND_INTERFACE").ok(),
+    };
+    if !binding.is_empty() {
+        binding
+            .validate()
+            .map_err(|err| anyhow!("invalid outbound binding: {err}"))?;
+    }
+    Ok(binding)
+}
 
-    run_servers(config, proxy, admin).await
+fn client_config_from_env() -> anyhow::Result<ClientConfig> {
+    fn parse<T: std::str::FromStr>(key: &str) -> anyhow::Result<Option<T>> {
+        std::env::var(key)
+            .ok()
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid {key}: {value:?}"))
+            })
+            .transpose()
+    }
+    Ok(ClientConfig {
+        connect_timeout: parse("LOWDOWN_UPSTREAM_CONNECT_TIMEOUT_MS")?.map(Duration::from_millis),
+        request_timeout: parse("LOWDOWN_UPSTREAM_TIMEOUT_MS")?.map(Duration::from_millis),
+        pool_max_idle_per_host: parse("LOWDOWN_UPSTREAM_POOL_MAX_IDLE")?,
+        pool_idle_timeout: parse("LOWDOWN_UPSTREAM_POOL_IDLE_TIMEOUT_SECS")?
+            .map(Duration::from_secs),
+        tcp_keepalive: parse("LOWDOWN_UPSTREAM_TCP_KEEPALIVE_SECS")?.map(Duration::from_secs),
+    })
 }
 
 struct ServerConfig {
     proxy_addr: SocketAddr,
     admin_addr: SocketAddr,
+    proxy_tls: Option<TlsAcceptor>,
+    restart_policy: RestartPolicy,
+    restart_interval: Duration,
+    listener_options: ListenerOptions,
+    shutdown_grace: Duration,
+    summary_file: Option<PathBuf>,
 }
 
 fn server_config_from_env() -> anyhow::Result<ServerConfig> {
@@ -52,9 +379,56 @@ fn server_config_from_env() -> anyhow::Result<ServerConfig> {
         .context("invalid proxy bind configuration")?;
     let admin_addr = resolve_addr("ADMIN_BIND", "ADMIN_PORT", "127.0.0.1", 7070)
         .context("invalid admin bind configuration")?;
+    let proxy_tls = match (
+        std::env::var("PROXY_TLS_CERT"),
+        std::env::var("PROXY_TLS_KEY"),
+    ) {
+        (Ok(cert), Ok(key)) => Some(
+            tls::acceptor(Path::new(&cert), Path::new(&key))
+                .context("invalid PROXY_TLS_CERT / PROXY_TLS_KEY")?,
+        ),
+        (Err(_), Err(_)) => None,
+        _ => {
+            return Err(anyhow!(
+                "PROXY_TLS_CERT and PROXY_TLS_KEY must be set together"
+            ));
+        }
+    };
+    let restart_policy = match std::env::var("LOWDOWN_SERVER_RESTART") {
+        Ok(policy) => policy
+            .parse()
+            .map_err(|err| anyhow!("invalid LOWDOWN_SERVER_RESTART: {err}"))?,
+        Err(_) => RestartPolicy::default(),
+    };
+    let restart_interval = Duration::from_secs(
+        std::env::var("LOWDOWN_SERVER_RETRY_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(5),
+    );
+    let defaults = ListenerOptions::default();
+    let listener_options = ListenerOptions {
+        reuse_port: env_flag("LOWDOWN_SERVER_REUSEPORT"),
+        backlog: std::env::var("LOWDOWN_SERVER_BACKLOG")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(defaults.backlog),
+        nodelay: std::env::var("LOWDOWN_SERVER_NODELAY").map_or(defaults.nodelay, |value| {
+            !value.eq_ignore_ascii_case("false")
+        }),
+    };
     Ok(ServerConfig {
         proxy_addr,
         admin_addr,
+        proxy_tls,
+        restart_policy,
+        restart_interval,
+        listener_options,
+        shutdown_grace: std::env::var("SHUTDOWN_GRACE_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map_or(DEFAULT_SHUTDOWN_GRACE, Duration::from_secs),
+        summary_file: std::env::var_os("LOWDOWN_SUMMARY_FILE").map(PathBuf::from),
     })
 }
 
@@ -77,48 +451,117 @@ fn resolve_addr(
 
 async fn run_servers(
     config: ServerConfig,
+    supervision: Arc<Supervision>,
     proxy_router: Router,
     admin_router: Router,
 ) -> anyhow::Result<()> {
-    info!("Starting admin server at {}", config.admin_addr);
-    info!("Starting proxy server at {}", config.proxy_addr);
-
-    let proxy_listener = TcpListener::bind(config.proxy_addr)
-        .await
-        .context("failed to bind proxy listener")?;
-    let admin_listener = TcpListener::bind(config.admin_addr)
-        .await
-        .context("failed to bind admin listener")?;
-
-    let proxy_shutdown = shutdown_signal("proxy");
-    let admin_shutdown = shutdown_signal("admin");
-
-    let proxy_server = axum::serve(proxy_listener, proxy_router.into_make_service())
-        .with_graceful_shutdown(proxy_shutdown);
-    let admin_server = axum::serve(admin_listener, admin_router.into_make_service())
-        .with_graceful_shutdown(admin_shutdown);
+    let inherited = systemd::listeners(&["proxy", "admin"]).context("invalid socket activation")?;
+    for (name, listener) in &inherited {
+        info!(
+            "Using socket activation listener at {} for the {name} server",
+            listener.local_addr()?
+        );
+    }
+    if !inherited.contains_key("admin") {
+        info!("Starting admin server at {}", config.admin_addr);
+    }
+    if !inherited.contains_key("proxy") {
+        let tls = if config.proxy_tls.is_some() {
+            " with TLS"
+        } else {
+            ""
+        };
+        info!("Starting proxy server at {}{tls}", config.proxy_addr);
+    }
 
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal("proxy and admin").await;
+        systemd::notify("STOPPING=1");
+        let _ = stop.send(true);
+    });
+    spawn_systemd_notifications(supervision.clone());
+    let supervisor = Supervisor::new(
+        supervision,
+        config.restart_policy,
+        config.restart_interval,
+        stopped,
+    )
+    .listener_options(config.listener_options)
+    .inherited(inherited)
+    .shutdown_grace(config.shutdown_grace);
+    let tls = config.proxy_tls;
+    let options = config.listener_options;
     tokio::try_join!(
-        async {
-            proxy_server.await.map_err(|err| {
-                error!("proxy server exited with error: {err}");
-                anyhow!("proxy server error: {err}")
-            })
-        },
-        async {
-            admin_server.await.map_err(|err| {
-                error!("admin server exited with error: {err}");
-                anyhow!("admin server error: {err}")
-            })
-        }
+        supervisor.run("proxy", config.proxy_addr, |listener, shutdown| {
+            connection::serve(
+                listener,
+                proxy_router.clone(),
+                tls.clone(),
+                options,
+                shutdown,
+            )
+        }),
+        supervisor.run("admin", config.admin_addr, |listener, shutdown| {
+            axum::serve(listener, admin_router.clone().into_make_service())
+                .tcp_nodelay(options.nodelay)
+                .with_graceful_shutdown(shutdown)
+                .into_future()
+        }),
     )?;
 
     Ok(())
 }
 
+// READY=1 once both servers accept connections. Watchdog pings stop while a
+// server is down, so systemd restarts a lowdown that can't recover by itself.
+fn spawn_systemd_notifications(supervision: Arc<Supervision>) {
+    tokio::spawn(async move {
+        supervision.running(2).await;
+        systemd::notify("READY=1");
+        let Some(interval) = systemd::watchdog_interval() else {
+            return;
+        };
+        loop {
+            if supervision.all_running() {
+                systemd::notify("WATCHDOG=1");
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+// Ctrl+C, or SIGTERM as sent by Kubernetes and systemd.
 async fn shutdown_signal(component: &'static str) {
-    if let Err(err) = tokio::signal::ctrl_c().await {
-        error!("failed to install CTRL+C handler for {component}: {err}");
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("failed to install CTRL+C handler for {component}: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        () = interrupt => {}
+        () = terminate(component) => {}
     }
     info!("Shutting down {component} server");
 }
+
+#[cfg(unix)]
+async fn terminate(component: &'static str) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            terminate.recv().await;
+        }
+        Err(err) => {
+            error!("failed to install SIGTERM handler for {component}: {err}");
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate(_component: &'static str) {
+    std::future::pending::<()>().await;
+}
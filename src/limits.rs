@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use tracing::debug;
+use url::Url;
+
+use crate::clock;
+use crate::destinations::DestinationRegistry;
+use crate::http_client::{
+    HttpClient, HttpClientError, OutgoingRequest, ProxiedResponse, SharedHttpClient,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RateLimitMode {
+    #[default]
+    Queue,
+    Reject,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DestinationLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_request_body_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_body_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rps: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_mode: Option<RateLimitMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaker_failures: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaker_open_ms: Option<u64>,
+}
+
+impl DestinationLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_request_body_bytes.is_none()
+            && self.max_response_body_bytes.is_none()
+            && self.max_rps.is_none()
+            && self.breaker_failures.is_none()
+        {
+            return Err("set max-request-body-bytes, max-response-body-bytes, max-rps, breaker-failures or a combination".to_string());
+        }
+        if self.max_rps == Some(0) {
+            return Err("max-rps must be at least 1".to_string());
+        }
+        if self.rate_limit_mode.is_some() && self.max_rps.is_none() {
+            return Err("rate-limit-mode requires max-rps".to_string());
+        }
+        if self.breaker_failures == Some(0) {
+            return Err("breaker-failures must be at least 1".to_string());
+        }
+        if self.breaker_open_ms.is_some() && self.breaker_failures.is_none() {
+            return Err("breaker-open-ms requires breaker-failures".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub type LimitRegistry = DestinationRegistry<DestinationLimits>;
+
+// Spaces calls to a destination at least 1/max-rps apart, for every request
+// lowdown sends: proxied, duplicated and shadowed alike.
+pub struct RateLimitingClient {
+    limits: Arc<LimitRegistry>,
+    next_slots: Mutex<HashMap<String, Instant>>,
+    inner: SharedHttpClient,
+}
+
+impl RateLimitingClient {
+    pub fn new(limits: Arc<LimitRegistry>, inner: SharedHttpClient) -> Self {
+        Self {
+            limits,
+            next_slots: Mutex::new(HashMap::new()),
+            inner,
+        }
+    }
+
+    fn reserve(
+        &self,
+        destination: &str,
+        max_rps: u32,
+        mode: RateLimitMode,
+    ) -> Result<Duration, HttpClientError> {
+        let now = clock::now();
+        let mut slots = self.next_slots.lock();
+        let slot = slots.get(destination).copied().unwrap_or(now).max(now);
+        if mode == RateLimitMode::Reject && slot > now {
+            return Err(HttpClientError::RateLimited(destination.to_string()));
+        }
+        slots.insert(
+            destination.to_string(),
+            slot + Duration::from_secs(1) / max_rps,
+        );
+        Ok(slot - now)
+    }
+}
+
+#[async_trait]
+impl HttpClient for RateLimitingClient {
+    async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError> {
+        let limit = authority(&request.url).and_then(|destination| {
+            let limits = self.limits.get(&destination)?;
+            Some((destination, limits.max_rps?, limits.rate_limit_mode))
+        });
+        if let Some((destination, max_rps, mode)) = limit {
+            let wait = self.reserve(&destination, max_rps, mode.unwrap_or_default())?;
+            if !wait.is_zero() {
+                debug!(
+                    "Queued request to {destination} for {} ms",
+                    wait.as_millis()
+                );
+                clock::sleep(wait).await;
+            }
+        }
+        self.inner.execute(request).await
+    }
+}
+
+pub fn authority(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?.to_ascii_lowercase();
+    Some(match parsed.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    })
+}
@@ -0,0 +1,51 @@
+use parking_lot::Mutex;
+use tracing_subscriber::{EnvFilter, fmt, prelude::*, reload};
+
+type Reload = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+pub struct LogLevel {
+    current: Mutex<String>,
+    reload: Reload,
+}
+
+impl LogLevel {
+    pub fn new(
+        initial: impl Into<String>,
+        reload: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            current: Mutex::new(initial.into()),
+            reload: Box::new(reload),
+        }
+    }
+
+    pub fn current(&self) -> String {
+        self.current.lock().clone()
+    }
+
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        if directives.is_empty() {
+            return Err("log level directives are required".to_string());
+        }
+        let mut guard = self.current.lock();
+        (self.reload)(directives)?;
+        *guard = directives.to_string();
+        Ok(())
+    }
+}
+
+pub fn init() -> LogLevel {
+    let initial = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|directives| EnvFilter::try_new(directives).is_ok())
+        .unwrap_or_else(|| "info".to_string());
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&initial));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_target(false).compact())
+        .init();
+    LogLevel::new(initial, move |directives| {
+        let filter = EnvFilter::try_new(directives).map_err(|err| err.to_string())?;
+        handle.reload(filter).map_err(|err| err.to_string())
+    })
+}
@@ -1,13 +1,12 @@
-use tracing_subscriber::EnvFilter;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .compact()
-        .init();
-
-    lowdown::run().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "check") {
+        std::process::exit(lowdown::check::run(&args[1..]));
+    }
+    let log_level = lowdown::logging::init();
+    if args.first().is_some_and(|arg| arg == "instances") {
+        return lowdown::run_instances(log_level, &args[1..]).await;
+    }
+    lowdown::run(log_level).await
 }
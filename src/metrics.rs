@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use http::StatusCode;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::http_client::is_upstream_failure;
+
+type HeldRequests = BTreeMap<String, BTreeMap<&'static str, u64>>;
+
+#[derive(Default)]
+pub struct Metrics {
+    destinations: Mutex<BTreeMap<String, DestinationMetrics>>,
+    held: Arc<Mutex<HeldRequests>>,
+}
+
+pub struct HoldGuard {
+    held: Arc<Mutex<HeldRequests>>,
+    rule: String,
+    fault: &'static str,
+}
+
+impl Drop for HoldGuard {
+    fn drop(&mut self) {
+        let mut guard = self.held.lock();
+        if let Some(faults) = guard.get_mut(&self.rule) {
+            if let Some(count) = faults.get_mut(self.fault) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    faults.remove(self.fault);
+                }
+            }
+            if faults.is_empty() {
+                guard.remove(&self.rule);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DestinationMetrics {
+    pub requests: u64,
+    #[serde(rename = "upstream-errors")]
+    pub upstream_errors: u64,
+    pub responses: u64,
+    #[serde(rename = "error-responses")]
+    pub error_responses: u64,
+    #[serde(rename = "new-connections")]
+    pub new_connections: u64,
+    #[serde(rename = "reused-connections")]
+    pub reused_connections: u64,
+    #[serde(rename = "unknown-connections")]
+    pub unknown_connections: u64,
+    #[serde(rename = "forced-new-connections")]
+    pub forced_new_connections: u64,
+    #[serde(rename = "shadow-requests")]
+    pub shadow_requests: u64,
+    #[serde(rename = "shadow-errors")]
+    pub shadow_errors: u64,
+    #[serde(rename = "shadow-statuses")]
+    pub shadow_statuses: BTreeMap<u16, u64>,
+    #[serde(rename = "bytes-out")]
+    pub bytes_out: u64,
+    #[serde(rename = "bytes-in")]
+    pub bytes_in: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub destinations: BTreeMap<String, DestinationMetrics>,
+    pub held: HeldRequests,
+    #[serde(rename = "deployment-marker", skip_serializing_if = "Option::is_none")]
+    pub deployment_marker: Option<String>,
+}
+
+impl Metrics {
+    pub fn record_upstream(
+        &self,
+        destination: &str,
+        forced: bool,
+        new_connection: Option<bool>,
+        failed: bool,
+    ) {
+        let mut guard = self.destinations.lock();
+        let entry = guard.entry(destination.to_string()).or_default();
+        entry.requests += 1;
+        if failed {
+            entry.upstream_errors += 1;
+        }
+        match new_connection {
+            Some(true) => entry.new_connections += 1,
+            Some(false) => entry.reused_connections += 1,
+            None => entry.unknown_connections += 1,
+        }
+        if forced {
+            entry.forced_new_connections += 1;
+        }
+    }
+
+    pub fn record_shadow(&self, destination: &str, status: Option<StatusCode>) {
+        let mut guard = self.destinations.lock();
+        let entry = guard.entry(destination.to_string()).or_default();
+        entry.shadow_requests += 1;
+        match status {
+            Some(status) => *entry.shadow_statuses.entry(status.as_u16()).or_default() += 1,
+            None => entry.shadow_errors += 1,
+        }
+    }
+
+    pub fn record_response(&self, destination: &str, status: StatusCode) {
+        let mut guard = self.destinations.lock();
+        let entry = guard.entry(destination.to_string()).or_default();
+        entry.responses += 1;
+        if is_upstream_failure(status) {
+            entry.error_responses += 1;
+        }
+    }
+
+    pub fn record_bytes(&self, destination: &str, bytes_out: u64, bytes_in: u64) {
+        let mut guard = self.destinations.lock();
+        let entry = guard.entry(destination.to_string()).or_default();
+        entry.bytes_out += bytes_out;
+        entry.bytes_in += bytes_in;
+    }
+
+    pub fn hold(&self, rule: &str, fault: &'static str) -> HoldGuard {
+        *self
+            .held
+            .lock()
+            .entry(rule.to_string())
+            .or_default()
+            .entry(fault)
+            .or_default() += 1;
+        HoldGuard {
+            held: self.held.clone(),
+            rule: rule.to_string(),
+            fault,
+        }
+    }
+
+    pub fn held(&self) -> HeldRequests {
+        self.held.lock().clone()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            destinations: self.destinations.lock().clone(),
+            held: self.held(),
+            deployment_marker: None,
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::Bytes;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::random;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MutationMode {
+    #[default]
+    Null,
+    Remove,
+    TypeFlip,
+    Randomize,
+    Delta,
+}
+
+impl MutationMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MutationMode::Null => "null",
+            MutationMode::Remove => "remove",
+            MutationMode::TypeFlip => "type-flip",
+            MutationMode::Randomize => "randomize",
+            MutationMode::Delta => "delta",
+        }
+    }
+}
+
+impl FromStr for MutationMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "null" => Ok(MutationMode::Null),
+            "remove" => Ok(MutationMode::Remove),
+            "type-flip" => Ok(MutationMode::TypeFlip),
+            "randomize" => Ok(MutationMode::Randomize),
+            "delta" => Ok(MutationMode::Delta),
+            other => Err(format!("unknown mutation mode {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for MutationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+// The subset of JSONPath that addresses fields: `$.user.email`,
+// `$.items[0].id`, `$.items[*].id` and `$['odd key']`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rest = text
+            .trim()
+            .strip_prefix('$')
+            .ok_or_else(|| format!("{text:?} does not start with $"))?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                segments.push(match &after[..end] {
+                    "" => return Err(format!("empty field name in {text:?}")),
+                    "*" => Segment::Wildcard,
+                    name => Segment::Key(name.to_string()),
+                });
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after
+                    .find(']')
+                    .ok_or_else(|| format!("unclosed [ in {text:?}"))?;
+                let inner = after[..end].trim();
+                segments.push(if inner == "*" {
+                    Segment::Wildcard
+                } else if let Some(name) = quoted(inner) {
+                    Segment::Key(name.to_string())
+                } else {
+                    Segment::Index(
+                        inner
+                            .parse()
+                            .map_err(|_| format!("invalid index {inner:?} in {text:?}"))?,
+                    )
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(format!("unexpected {rest:?} in {text:?}"));
+            }
+        }
+        if segments.is_empty() {
+            return Err(format!("{text:?} does not select a field"));
+        }
+        Ok(Self(segments))
+    }
+}
+
+fn quoted(text: &str) -> Option<&str> {
+    text.strip_prefix('\'')
+        .and_then(|inner| inner.strip_suffix('\''))
+        .or_else(|| {
+            text.strip_prefix('"')
+                .and_then(|inner| inner.strip_suffix('"'))
+        })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mutation {
+    pub mode: MutationMode,
+    pub delta: i64,
+}
+
+// Returns None when the body isn't JSON or the path selects nothing it can
+// change, so the message goes out untouched.
+pub fn mutate_body(body: &Bytes, path: &JsonPath, mutation: Mutation) -> Option<Bytes> {
+    let mut value: Value = serde_json::from_slice(body).ok()?;
+    if mutate(&mut value, &path.0, mutation) == 0 {
+        return None;
+    }
+    serde_json::to_vec(&value).ok().map(Bytes::from)
+}
+
+fn mutate(value: &mut Value, segments: &[Segment], mutation: Mutation) -> usize {
+    let (segment, rest) = match segments {
+        [] => return 0,
+        [segment, rest @ ..] => (segment, rest),
+    };
+    if rest.is_empty() && mutation.mode == MutationMode::Remove {
+        return remove(value, segment);
+    }
+    let mut count = 0;
+    let visit = |child: &mut Value| {
+        if !rest.is_empty() {
+            count += mutate(child, rest, mutation);
+        } else if let Some(replaced) = replacement(child, mutation) {
+            *child = replaced;
+            count += 1;
+        }
+    };
+    match (value, segment) {
+        (Value::Object(map), Segment::Key(key)) => map.get_mut(key).into_iter().for_each(visit),
+        (Value::Array(items), Segment::Index(index)) => {
+            items.get_mut(*index).into_iter().for_each(visit)
+        }
+        (Value::Object(map), Segment::Wildcard) => map.values_mut().for_each(visit),
+        (Value::Array(items), Segment::Wildcard) => items.iter_mut().for_each(visit),
+        _ => {}
+    }
+    count
+}
+
+fn remove(value: &mut Value, segment: &Segment) -> usize {
+    match (value, segment) {
+        (Value::Object(map), Segment::Key(key)) => usize::from(map.remove(key).is_some()),
+        (Value::Array(items), Segment::Index(index)) if *index < items.len() => {
+            items.remove(*index);
+            1
+        }
+        (Value::Object(map), Segment::Wildcard) => std::mem::take(map).len(),
+        (Value::Array(items), Segment::Wildcard) => std::mem::take(items).len(),
+        _ => 0,
+    }
+}
+
+fn replacement(value: &Value, mutation: Mutation) -> Option<Value> {
+    match mutation.mode {
+        MutationMode::Null | MutationMode::Remove => Some(Value::Null),
+        MutationMode::TypeFlip => Some(type_flip(value)),
+        MutationMode::Randomize => Some(randomize(value)),
+        MutationMode::Delta => shifted(value, mutation.delta),
+    }
+}
+
+// Only numbers can be shifted; integers stay integers unless they overflow.
+fn shifted(value: &Value, delta: i64) -> Option<Value> {
+    let number = value.as_number()?;
+    Some(
+        match number.as_i64().and_then(|int| int.checked_add(delta)) {
+            Some(int) => Value::from(int),
+            None => Value::from(number.as_f64()? + delta as f64),
+        },
+    )
+}
+
+// Strings that hold a number become that number, other scalars become
+// strings, and arrays and objects swap shapes.
+fn type_flip(value: &Value) -> Value {
+    match value {
+        Value::Null => Value::from(0),
+        Value::Bool(flag) => Value::from(flag.to_string()),
+        Value::Number(number) => Value::from(number.to_string()),
+        Value::String(text) => text
+            .parse::<serde_json::Number>()
+            .map_or_else(|_| Value::from(text.len()), Value::Number),
+        Value::Array(items) => Value::Object(
+            items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| (index.to_string(), item.clone()))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Object(map) => Value::Array(map.values().cloned().collect()),
+    }
+}
+
+// Keeps the type of scalars so the request still parses, but not its meaning.
+fn randomize(value: &Value) -> Value {
+    let mut rng = random::rng();
+    let text = |length: usize| -> String {
+        random::rng()
+            .sample_iter(Alphanumeric)
+            .take(length)
+            .map(char::from)
+            .collect()
+    };
+    match value {
+        Value::Bool(_) => Value::Bool(rng.r#gen()),
+        Value::Number(number) if number.is_f64() => Value::from(rng.r#gen::<f64>() * 1e6),
+        Value::Number(_) => Value::from(rng.gen_range(-1_000_000i64..=1_000_000)),
+        Value::String(original) => Value::from(text(original.chars().count().max(1))),
+        Value::Null | Value::Array(_) | Value::Object(_) => Value::from(text(8)),
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt;
+
+use crate::settings::{Percentage, RequestContext};
+
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbabilityExpression {
+    source: String,
+    root: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Value(Percentage),
+    If {
+        condition: Condition,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Compare(Operand, Operator, Operand),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Header(String),
+    Method,
+    Path,
+    Uri,
+    Host,
+    Marker,
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+impl ProbabilityExpression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let root = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(Self {
+                source: source.trim().to_string(),
+                root,
+            }),
+            Some(token) => Err(format!("unexpected {token}")),
+        }
+    }
+
+    pub fn evaluate(&self, ctx: &RequestContext) -> Percentage {
+        let mut expr = &self.root;
+        loop {
+            match expr {
+                Expr::Value(percentage) => return *percentage,
+                Expr::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    expr = if condition.holds(ctx) {
+                        then
+                    } else {
+                        otherwise
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProbabilityExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Condition {
+    fn holds(&self, ctx: &RequestContext) -> bool {
+        match self {
+            Condition::Compare(left, operator, right) => {
+                operator.apply(left.resolve(ctx), right.resolve(ctx))
+            }
+            Condition::Not(inner) => !inner.holds(ctx),
+            Condition::And(left, right) => left.holds(ctx) && right.holds(ctx),
+            Condition::Or(left, right) => left.holds(ctx) || right.holds(ctx),
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, ctx: &'a RequestContext) -> &'a str {
+        let header = |name: &str| ctx.headers.get(name).map_or("", String::as_str);
+        match self {
+            Operand::Header(name) => header(name),
+            Operand::Method => ctx.method.as_str(),
+            Operand::Path => ctx.uri.split('?').next().unwrap_or_default(),
+            Operand::Uri => &ctx.uri,
+            Operand::Host => header("host"),
+            Operand::Marker => ctx.deployment_marker.as_deref().unwrap_or_default(),
+            Operand::Literal(text) => text,
+        }
+    }
+}
+
+impl Operator {
+    fn apply(self, left: &str, right: &str) -> bool {
+        let numbers = || {
+            Some((
+                left.trim().parse::<f64>().ok()?,
+                right.trim().parse::<f64>().ok()?,
+            ))
+        };
+        match self {
+            Operator::Eq => left == right,
+            Operator::Ne => left != right,
+            Operator::Contains => left.contains(right),
+            Operator::StartsWith => left.starts_with(right),
+            Operator::EndsWith => left.ends_with(right),
+            Operator::Lt => numbers().is_some_and(|(l, r)| l < r),
+            Operator::Le => numbers().is_some_and(|(l, r)| l <= r),
+            Operator::Gt => numbers().is_some_and(|(l, r)| l > r),
+            Operator::Ge => numbers().is_some_and(|(l, r)| l >= r),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word:?}"),
+            Token::Text(text) => write!(f, "string {text:?}"),
+            Token::Number(number) => write!(f, "number {number}"),
+            Token::Symbol(symbol) => write!(f, "{symbol:?}"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(escaped) => text.push(escaped),
+                        None => return Err("unterminated string".to_string()),
+                    },
+                    Some(c) => text.push(c),
+                    None => return Err("unterminated string".to_string()),
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            {
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word.to_ascii_lowercase()));
+        } else {
+            chars.next();
+            let next = chars.peek().copied();
+            let symbol = match (c, next) {
+                ('=', Some('=')) | ('!', Some('=')) | ('<', Some('=')) | ('>', Some('=')) => {
+                    chars.next();
+                    match c {
+                        '=' => "==",
+                        '!' => "!=",
+                        '<' => "<=",
+                        _ => ">=",
+                    }
+                }
+                ('<', _) => "<",
+                ('>', _) => ">",
+                ('(', _) => "(",
+                (')', _) => ")",
+                _ => return Err(format!("unexpected character {c:?}")),
+            };
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let matched = matches!(self.peek(), Some(Token::Word(w)) if w == word);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), String> {
+        if self.eat_word(word) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(format!("expected {word:?}, found {token}")),
+            None => Err(format!("expected {word:?}")),
+        }
+    }
+
+    fn expr(&mut self, depth: usize) -> Result<Expr, String> {
+        if depth > MAX_DEPTH {
+            return Err("expression is nested too deeply".to_string());
+        }
+        if self.eat_word("if") {
+            let condition = self.or(depth + 1)?;
+            self.expect_word("then")?;
+            let then = self.expr(depth + 1)?;
+            self.expect_word("else")?;
+            let otherwise = self.expr(depth + 1)?;
+            return Ok(Expr::If {
+                condition,
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            });
+        }
+        match self.next()? {
+            Token::Number(number) => number
+                .parse()
+                .map(Expr::Value)
+                .map_err(|_| format!("{number} is not a percentage between 0 and 100")),
+            Token::Symbol("(") => {
+                let expr = self.expr(depth + 1)?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            token => Err(format!("expected a percentage or \"if\", found {token}")),
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            token => Err(format!("expected {symbol:?}, found {token}")),
+        }
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Condition, String> {
+        let mut condition = self.and(depth)?;
+        while self.eat_word("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and(depth)?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Condition, String> {
+        let mut condition = self.unary(depth)?;
+        while self.eat_word("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary(depth)?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Condition, String> {
+        if depth > MAX_DEPTH {
+            return Err("expression is nested too deeply".to_string());
+        }
+        if self.eat_word("not") {
+            return Ok(Condition::Not(Box::new(self.unary(depth + 1)?)));
+        }
+        if self.peek() == Some(&Token::Symbol("(")) {
+            self.position += 1;
+            let condition = self.or(depth + 1)?;
+            self.expect_symbol(")")?;
+            return Ok(condition);
+        }
+        let left = self.operand()?;
+        let operator = match self.next()? {
+            Token::Symbol("==") => Operator::Eq,
+            Token::Symbol("!=") => Operator::Ne,
+            Token::Symbol("<") => Operator::Lt,
+            Token::Symbol("<=") => Operator::Le,
+            Token::Symbol(">") => Operator::Gt,
+            Token::Symbol(">=") => Operator::Ge,
+            Token::Word(word) if word == "contains" => Operator::Contains,
+            Token::Word(word) if word == "starts-with" => Operator::StartsWith,
+            Token::Word(word) if word == "ends-with" => Operator::EndsWith,
+            token => return Err(format!("expected a comparison, found {token}")),
+        };
+        let right = self.operand()?;
+        Ok(Condition::Compare(left, operator, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next()? {
+            Token::Text(text) | Token::Number(text) => Ok(Operand::Literal(text)),
+            Token::Word(word) => match word.as_str() {
+                "header" => match self.next()? {
+                    Token::Word(name) | Token::Text(name) => {
+                        Ok(Operand::Header(name.to_ascii_lowercase()))
+                    }
+                    token => Err(format!("expected a header name, found {token}")),
+                },
+                "method" => Ok(Operand::Method),
+                "path" => Ok(Operand::Path),
+                "uri" => Ok(Operand::Uri),
+                "host" => Ok(Operand::Host),
+                "marker" => Ok(Operand::Marker),
+                _ => Err(format!(
+                    "unknown attribute {word:?}, expected header, method, path, uri, host or marker"
+                )),
+            },
+            token => Err(format!("expected a value, found {token}")),
+        }
+    }
+}
@@ -2,70 +2,377 @@ use std::{
     convert::Infallible,
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     task::{Context, Poll},
     time::Duration,
 };
 
 use axum::{
     Router,
-    body::{self, Body},
+    body::Body,
     http::{
         Request, Response, StatusCode, Uri,
-        header::{ACCESS_CONTROL_ALLOW_ORIGIN, HOST, HeaderName, HeaderValue, ORIGIN},
+        header::{
+            ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, EXPECT,
+            HOST, HeaderName, HeaderValue, LINK, ORIGIN, TRANSFER_ENCODING, USER_AGENT, VIA,
+        },
     },
 };
 use bytes::Bytes;
-use http::{HeaderMap, Method};
+use http::{HeaderMap, Method, Version, request::Parts};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::ext::ReasonPhrase;
+use hyper::upgrade::OnUpgrade;
 use rand::Rng;
 use serde_json::json;
-use tokio::time::sleep;
-use tracing::{debug, info, warn};
+use tokio::net::TcpStream;
+use tracing::{Instrument, debug, info, info_span, warn};
 use url::Url;
+use uuid::Uuid;
 
-use crate::http_client::{HttpClientError, OutgoingRequest, ProxiedResponse};
-use crate::response::json_response;
+use crate::browser::{
+    PreflightMode, allow_origin, grpc_web_failure, is_grpc_web, is_preflight, synthetic_preflight,
+};
+use crate::clock;
+use crate::comparisons::Comparison;
+use crate::conditional::{corrupt_validators, has_validators, is_conditional, strip_validators};
+use crate::connection::{ClientSocket, ConnectionReset};
+use crate::content_encoding::{decode_body, has_content_encoding};
+use crate::corruption::corrupt_body;
+use crate::default_behavior::{DefaultBehavior, echo, respond};
+use crate::evaluation::{self, Evaluation, MatcherResult, Roll, TRACE_HEADER};
+use crate::failure::{self, FailureMode};
+use crate::fault_set::FaultSet;
+use crate::first_per_key::render_key;
+use crate::http_client::{
+    HttpClientError, InterimSink, OutgoingRequest, ProxiedResponse, RequestBody, ResponseBody,
+    TlsOverride, UpstreamProtocol, forward_interim, is_upstream_failure,
+};
+use crate::mutation::{JsonPath, Mutation, mutate_body};
+use crate::random;
+use crate::response::{json_response, to_json};
 use crate::settings::{
-    Settings, SettingsLayer, from_parts as request_context_from_parts, matches_request,
+    Percentage, RequestContext, Settings, explain_match, from_parts as request_context_from_parts,
+    request_target,
 };
 use crate::state::AppState;
+use crate::sticky::select_destination;
+use crate::streaming::{
+    StreamFaults, body_with_trailers, corrupt_trailers, faulty_body, faulty_trailers,
+    throttled_body, unsized_body,
+};
+use crate::streams::{StreamControl, StreamInfo, registered_body};
+use crate::tee;
+use crate::traffic::{DEFAULT_RULE, TrafficRecord};
+use crate::transform::{Message, Phase, Pipeline};
+use crate::tunnel;
 use tower::Service;
 
 const DESTINATION_HEADER: &str = "x-lowdown-destination-url";
+const DROPPED_STATUS: u16 = 444;
+const FORWARDED_BY_HEADER: &str = "x-forwarded-by";
+// Points at nothing, so a client acting on the hint fetches a missing resource.
+const BOGUS_HINT: &str = "</lowdown-early-hint.css>; rel=preload; as=style";
 
 pub fn router(state: Arc<AppState>) -> Router {
     Router::new().fallback_service(ProxyService { state })
 }
 
 async fn proxy_entry(state: Arc<AppState>, req: Request<Body>) -> Response<Body> {
-    let req = rewrite_forwarding(req);
-    match handle_proxy(state, req).await {
-        Ok(response) => response,
-        Err(response) => response,
+    let req = rewrite_forwarding(req, state.mikkmokk_compat());
+    let (req, capture) = match state.snapshots() {
+        Some(snapshots) => {
+            if let Some(response) = snapshots.replay(&req, state.dev_mode()) {
+                return response;
+            }
+            snapshots.capture(req)
+        }
+        None => (req, None),
+    };
+    let started = clock::now();
+    let method = req.method().to_string();
+    let uri = request_target(req.uri());
+    let deployment_marker = state.deployment_marker();
+    let connection_reset = req.extensions().get::<ConnectionReset>().cloned();
+    let mut trace = Trace {
+        evaluation: evaluation::requested(req.headers()).then(Evaluation::default),
+        ..Trace::default()
+    };
+    let handled = handle_proxy(state.clone(), req, deployment_marker.clone(), &mut trace);
+    let handled = match &deployment_marker {
+        Some(marker) => {
+            handled
+                .instrument(info_span!("proxy", marker = %marker))
+                .await
+        }
+        None => handled.await,
+    };
+    let (mut response, generated) = match handled {
+        Ok(response) => (response, false),
+        Err(response) => (response, true),
+    };
+    if response.status().canonical_reason().is_none()
+        && response.extensions().get::<ReasonPhrase>().is_none()
+    {
+        let reason = fallback_reason(response.status());
+        response.extensions_mut().insert(reason);
+    }
+    if state.dev_mode() {
+        add_debug_headers(response.headers_mut(), &trace);
+    }
+    if let Some(mut evaluation) = trace.evaluation.take() {
+        evaluation.rule = trace.rule_name().to_string();
+        evaluation.matched = trace.matched;
+        evaluation.faults = trace.faults.clone();
+        if generated {
+            response = append_trace(response, &evaluation).await;
+        }
+        if let Some(value) = evaluation.header_value() {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(TRACE_HEADER), value);
+        }
+    }
+    if let Some(destination) = trace.destination.as_deref() {
+        state
+            .metrics()
+            .record_response(destination, response.status());
+    }
+    let record = TrafficRecord {
+        seq: 0,
+        at: clock::timestamp(),
+        method,
+        uri,
+        destination: trace.destination,
+        rule: trace.rule,
+        deployment_marker,
+        status: response.status().as_u16(),
+        faults: trace.faults.clone(),
+        duration_ms: clock::elapsed(started).as_millis() as u64,
+    };
+    state.access_log().write(&record);
+    state.traffic().record(record);
+    // The connection task drops this handler along with the connection.
+    if trace.faults.contains(&"reset-connection")
+        && let Some(reset) = connection_reset
+    {
+        reset.trigger();
+        return std::future::pending().await;
+    }
+    match capture {
+        Some(capture) => capture.finish(response, trace.faults),
+        None => response,
+    }
+}
+
+#[derive(Default)]
+struct Trace {
+    destination: Option<String>,
+    rule: Option<String>,
+    matched: bool,
+    faults: Vec<&'static str>,
+    evaluation: Option<Evaluation>,
+    rate_limit: Option<RateLimit>,
+}
+
+struct RateLimit {
+    state: Arc<AppState>,
+    max_per_second: u64,
+}
+
+impl Trace {
+    fn rule_name(&self) -> &str {
+        self.rule.as_deref().unwrap_or(DEFAULT_RULE)
+    }
+
+    fn roll(&mut self, fault: &'static str, percentage: Percentage, matches: bool) -> bool {
+        let mut triggered = matches && percentage.roll();
+        if triggered && let Some(limit) = &self.rate_limit {
+            let second = clock::timestamp().as_second();
+            let count = limit.state.kv().increment(
+                &format!("triggered:{}\n{second}", self.rule_name()),
+                1,
+                Some(Duration::from_secs(2)),
+            );
+            if count > limit.max_per_second as i64 {
+                debug!("Rule {} hit max-triggered-per-second", self.rule_name());
+                triggered = false;
+                self.skip("max-triggered-per-second");
+            }
+        }
+        if matches
+            && !percentage.is_zero()
+            && let Some(evaluation) = self.evaluation.as_mut()
+        {
+            evaluation.rolls.push(Roll {
+                fault,
+                percentage,
+                triggered,
+            });
+        }
+        triggered
+    }
+
+    fn skip(&mut self, reason: &'static str) {
+        if let Some(evaluation) = self.evaluation.as_mut() {
+            evaluation.skipped.get_or_insert(reason);
+        }
+    }
+}
+
+async fn append_trace(response: Response<Body>, evaluation: &Evaluation) -> Response<Body> {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut object)) => {
+            object.insert("trace".to_string(), json!(evaluation));
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::from(serde_json::Value::Object(object).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
+
+fn fallback_reason(status: StatusCode) -> ReasonPhrase {
+    ReasonPhrase::from_static(match status.as_u16() {
+        100..=199 => b"Informational",
+        200..=299 => b"Success",
+        300..=399 => b"Redirection",
+        400..=499 => b"Client Error",
+        500..=599 => b"Server Error",
+        _ => b"Unknown",
+    })
+}
+
+fn add_debug_headers(headers: &mut HeaderMap, trace: &Trace) {
+    headers.insert(
+        HeaderName::from_static("x-lowdown-debug-matched"),
+        HeaderValue::from_static(if trace.matched { "true" } else { "false" }),
+    );
+    let faults = if trace.faults.is_empty() {
+        "none".to_string()
+    } else {
+        trace.faults.join(",")
+    };
+    if let Ok(value) = HeaderValue::from_str(&faults) {
+        headers.insert(HeaderName::from_static("x-lowdown-debug-faults"), value);
+    }
+    if let Some(value) = trace
+        .rule
+        .as_deref()
+        .and_then(|rule| HeaderValue::from_str(rule).ok())
+    {
+        headers.insert(HeaderName::from_static("x-lowdown-debug-rule"), value);
     }
 }
 
 async fn handle_proxy(
     state: Arc<AppState>,
     req: Request<Body>,
+    deployment_marker: Option<String>,
+    trace: &mut Trace,
 ) -> Result<Response<Body>, Response<Body>> {
+    let started = clock::now();
     let (parts, body) = req.into_parts();
-    let body_bytes = body::to_bytes(body, usize::MAX).await.map_err(|err| {
-        warn!("Failed to read request body: {err}");
+
+    let request_layer = state.request_layer(&parts.headers);
+    let profile = state.request_profile(&parts.headers).map_err(|name| {
         json_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &json!({"error":"invalid-request"}),
-            state.body_trailer(),
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"unknown-profile","profile":name}),
+            state.dev_mode(),
         )
     })?;
+    let mut ctx = request_context_from_parts(&parts.method, &parts.uri, &parts.headers);
+    ctx.deployment_marker = deployment_marker;
+    let (body, inspected) = if state.rules_need_body() {
+        let collected = body.collect().await.map_err(|err| {
+            warn!("Failed to read request body: {err}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &json!({"error":"invalid-request"}),
+                state.dev_mode(),
+            )
+        })?;
+        let trailers = collected.trailers().cloned();
+        let bytes = collected.to_bytes();
+        let decoded = decode_body(&parts.headers, &bytes);
+        (
+            body_with_trailers(bytes.clone(), trailers),
+            Some(decoded.unwrap_or(bytes)),
+        )
+    } else {
+        (body, None)
+    };
+    let settings_version = state.settings_version();
+    // The backend is picked from the pool before rules apply, so its
+    // destination settings and rules matching its host take effect.
+    let mut settings = state.effective_settings(&request_layer, profile.as_ref(), None);
+    let pool = settings.destination_urls.clone();
+    if let Some(url) = pick_from_pool(&settings, &ctx) {
+        settings = state.effective_settings(&request_layer, profile.as_ref(), Some(&url));
+        settings.destination_url = Some(url);
+    }
+    let (settings, named) = state.apply_named_rule(&ctx, inspected.as_deref(), settings);
+    let (mut settings, one_off) = state.apply_one_off(&ctx, inspected.as_deref(), settings);
+    trace.rule = match (one_off, named) {
+        (Some(id), _) => Some(format!("one-off:{id}")),
+        (None, Some(name)) => Some(format!("rule:{name}")),
+        (None, None) => None,
+    };
+    if settings.destination_urls != pool
+        && let Some(url) = pick_from_pool(&settings, &ctx)
+    {
+        settings.destination_url = Some(url);
+    }
 
-    let request_layer = SettingsLayer::from_headers(&parts.headers);
-    let mut settings = state.effective_settings(&request_layer);
-    let ctx = request_context_from_parts(&parts.method, &parts.uri, &parts.headers);
-    settings = state.apply_one_off(&ctx, settings);
+    let connect = parts.method == Method::CONNECT;
+    if settings.destination_url.is_none() && !connect {
+        match state.default_behavior() {
+            DefaultBehavior::Error => {}
+            DefaultBehavior::Upstream { url } => {
+                debug!("No destination, using default upstream {url}");
+                settings.destination_url = Some(url);
+            }
+            DefaultBehavior::Respond {
+                status,
+                body,
+                content_type,
+            } => return Ok(respond(status, body, content_type.as_deref())),
+            DefaultBehavior::Echo => {
+                let body = axum::body::to_bytes(body, usize::MAX)
+                    .await
+                    .unwrap_or_default();
+                return Ok(echo(&parts, &body, state.dev_mode()));
+            }
+        }
+    }
 
     let destination = match settings.destination_url.clone() {
-        Some(url) => match Destination::parse(&url, state.body_trailer()) {
+        _ if connect => match parts.uri.authority() {
+            Some(authority) => Destination::tunnel(authority.as_str()),
+            None => {
+                return Err(json_response(
+                    StatusCode::BAD_REQUEST,
+                    &json!({"error":"invalid-connect-target"}),
+                    state.dev_mode(),
+                ));
+            }
+        },
+        Some(url) => match Destination::parse(&url, state.dev_mode()) {
             Ok(dest) => dest,
             Err(response) => return Err(response),
         },
@@ -73,114 +380,896 @@ async fn handle_proxy(
             return Err(json_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 &json!({"error":"missing-destination-url"}),
-                state.body_trailer(),
+                state.dev_mode(),
             ));
         }
     };
 
-    let matches = matches_request(&ctx, &settings);
+    trace.destination = Some(destination.authority.clone());
+
+    let match_results = explain_match(&ctx, &settings);
+    let mut matches = match_results.iter().all(|(_, matched)| *matched);
+    if let Some(evaluation) = trace.evaluation.as_mut() {
+        evaluation.layers = state.active_layers(
+            &request_layer,
+            profile.is_some(),
+            settings.destination_url.as_deref(),
+        );
+        evaluation.matchers = match_results
+            .into_iter()
+            .map(|(name, matched)| MatcherResult { name, matched })
+            .collect();
+    }
+    if matches
+        && settings.pause_faults_when_unhealthy
+        && state.health().is_unhealthy(&destination.authority)
+    {
+        info!(
+            "Faults paused, upstream {} is unhealthy",
+            destination.authority
+        );
+        trace.skip("unhealthy-destination");
+        matches = false;
+    }
+    if is_preflight(&parts.method, &parts.headers) {
+        match settings.preflight_mode {
+            PreflightMode::Synthetic => {
+                info!("HTTP 204 OPTIONS {} synthetic preflight", ctx.uri);
+                return Ok(synthetic_preflight(&parts.headers));
+            }
+            PreflightMode::PassThrough => {
+                if matches {
+                    trace.skip("preflight");
+                }
+                matches = false;
+            }
+            PreflightMode::Fault => {}
+        }
+    }
+    let session_index = settings.session_key.as_deref().and_then(|template| {
+        let key = render_key(template, &ctx);
+        let window =
+            Some(settings.session_window_ms.as_duration()).filter(|window| !window.is_zero());
+        (!key.is_empty()).then(|| {
+            state
+                .kv()
+                .increment(&format!("session:{template}\n{key}"), 1, window)
+        })
+    });
+    if matches
+        && settings.match_session_request_index != 0
+        && session_index != Some(settings.match_session_request_index as i64)
+    {
+        debug!("Request {session_index:?} in session, skipping faults");
+        trace.skip("session-request-index");
+        matches = false;
+    }
+    if matches && let Some(template) = settings.trigger_first_per_key.as_deref() {
+        let key = render_key(template, &ctx);
+        let window =
+            Some(settings.trigger_first_window_ms.as_duration()).filter(|window| !window.is_zero());
+        if !state.kv().set_if_absent(
+            &format!("first-seen:{template}\n{key}"),
+            serde_json::Value::Bool(true),
+            window,
+        ) {
+            debug!("Not the first request for key {key:?}, skipping faults");
+            trace.skip("not-first-per-key");
+            matches = false;
+        }
+    }
+    trace.matched = matches;
+    if matches && let Some(text) = settings.fault_set.clone() {
+        apply_fault_set(&text, &mut settings);
+        trace.rule.get_or_insert_with(|| "fault-set".to_string());
+    }
+    if settings.max_triggered_per_second != 0 {
+        trace.rate_limit = Some(RateLimit {
+            state: state.clone(),
+            max_per_second: settings.max_triggered_per_second,
+        });
+    }
+
+    if trace.roll("drop", settings.drop_percentage.evaluate(&ctx), matches) {
+        info!("HTTP {} {} drop", parts.method, ctx.uri);
+        trace.faults.push("drop");
+        if !settings.drop_hold_ms.is_zero() {
+            let _hold = state.metrics().hold(trace.rule_name(), "drop");
+            clock::sleep(settings.drop_hold_ms.as_duration()).await;
+        }
+        return Err(dropped_connection());
+    }
+
+    if trace.roll(
+        "reset-connection",
+        settings.reset_connection_percentage.evaluate(&ctx),
+        matches,
+    ) {
+        info!("HTTP {} {} reset-connection", parts.method, ctx.uri);
+        trace.faults.push("reset-connection");
+        return Err(dropped_connection());
+    }
+
+    if trace.roll(
+        "degrade-socket",
+        settings.degrade_socket_percentage.evaluate(&ctx),
+        matches,
+    ) && let Some(socket) = parts.extensions.get::<ClientSocket>()
+    {
+        match socket.degrade(settings.degrade_socket_buffer_bytes) {
+            Ok(()) => {
+                info!("HTTP {} {} degrade-socket", parts.method, ctx.uri);
+                trace.faults.push("degrade-socket");
+            }
+            Err(err) => warn!("Failed to degrade client socket: {err}"),
+        }
+    }
+
+    if trace.roll(
+        "interim-response",
+        settings.interim_response_percentage.evaluate(&ctx),
+        matches,
+    ) && let Some(socket) = parts.extensions.get::<ClientSocket>()
+    {
+        match interim_status(settings.interim_response_code) {
+            Some(status) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(LINK, HeaderValue::from_static(BOGUS_HINT));
+                match socket.send_interim(parts.version, status, &headers) {
+                    Ok(()) => {
+                        info!(
+                            "HTTP {} {} interim-response {status}",
+                            parts.method, ctx.uri
+                        );
+                        trace.faults.push("interim-response");
+                    }
+                    Err(err) => debug!("Skipping interim-response: {err}"),
+                }
+            }
+            None => warn!(
+                "Ignoring interim-response-code {}, not a 1xx other than 101",
+                settings.interim_response_code
+            ),
+        }
+    }
 
-    if should_trigger(settings.delay_before_percentage, matches) && settings.delay_before_ms > 0 {
-        info!("before-delay {} ms", settings.delay_before_ms);
-        sleep(Duration::from_millis(settings.delay_before_ms)).await;
+    if trace.roll(
+        "delay-before",
+        settings.delay_before_percentage.evaluate(&ctx),
+        matches,
+    ) && let delay = settings
+        .delay_before_ms
+        .jittered(settings.delay_before_jitter_ms)
+        && !delay.is_zero()
+    {
+        info!("before-delay {delay} ms");
+        trace.faults.push("delay-before");
+        let _hold = state.metrics().hold(trace.rule_name(), "delay-before");
+        clock::sleep(delay.as_duration()).await;
     }
 
-    if should_trigger(settings.fail_before_percentage, matches) {
+    let fail_before_percentage = match settings.fail_before_every_nth {
+        0 => settings.fail_before_percentage.evaluate(&ctx),
+        _ if !matches => Percentage::ZERO,
+        nth => {
+            let count = state.kv().increment(
+                &format!("every-nth:fail-before\n{}", trace.rule_name()),
+                1,
+                None,
+            );
+            if count % nth as i64 == 0 {
+                Percentage::ALWAYS
+            } else {
+                Percentage::ZERO
+            }
+        }
+    };
+    if trace.roll("fail-before", fail_before_percentage, matches) {
         info!("HTTP {} {} fail-before", settings.fail_before_code, ctx.uri);
-        return Err(json_response(
-            status_from_code(settings.fail_before_code),
-            &json!({"error":"fail-before"}),
-            state.body_trailer(),
+        trace.faults.push("fail-before");
+        if settings.shadow_fail_before && !connect {
+            spawn_shadow(
+                state.clone(),
+                &destination,
+                &parts,
+                body,
+                &ctx.uri,
+                &settings,
+            );
+        }
+        return Err(injected_failure(
+            &state,
+            &parts,
+            settings.fail_before_code.status(),
+            json!({"error":"fail-before"}),
+            settings.fail_before_mode,
         ));
     }
 
-    let outgoing_headers =
-        build_destination_headers(&parts.headers, &destination, state.body_trailer())?;
+    if connect {
+        let stream = StreamInfo {
+            id: Uuid::new_v4(),
+            method: parts.method.to_string(),
+            uri: ctx.uri.clone(),
+            destination: destination.authority.clone(),
+            rule: trace.rule_name().to_string(),
+            faults: trace.faults.clone(),
+            started_at: clock::timestamp(),
+            settings_version,
+            expiry_mode: settings.stream_expiry_mode,
+        };
+        return open_tunnel(&state, parts, &destination, stream).await;
+    }
+
+    let duplicate = trace.roll(
+        "duplicate",
+        settings.duplicate_percentage.evaluate(&ctx),
+        matches,
+    );
+    let force_new_connection = trace.roll(
+        "force-new-connection",
+        settings.force_new_connection_percentage.evaluate(&ctx),
+        matches,
+    );
+    if duplicate {
+        trace.faults.push("duplicate");
+    }
+    let transform = settings
+        .transform
+        .clone()
+        .filter(|pipeline| matches && !pipeline.is_empty());
+    if transform.is_some() {
+        trace.faults.push("transform");
+    }
+    if force_new_connection {
+        info!("force-new-connection to {}", destination.authority);
+        trace.faults.push("force-new-connection");
+    }
+    // Pooled connections skip resolution, so a delayed lookup needs a fresh one.
+    let mut dns_delay = None;
+    if trace.roll(
+        "dns-delay",
+        settings.dns_delay_percentage.evaluate(&ctx),
+        matches,
+    ) && !settings.dns_delay_ms.is_zero()
+    {
+        info!(
+            "dns-delay {} ms for {}",
+            settings.dns_delay_ms, destination.authority
+        );
+        trace.faults.push("dns-delay");
+        dns_delay = Some(settings.dns_delay_ms.as_duration());
+    }
+    let mut protocol = settings.upstream_protocol;
+    if protocol == UpstreamProtocol::Http3
+        && trace.roll(
+            "http3-fallback",
+            settings.http3_fallback_percentage.evaluate(&ctx),
+            matches,
+        )
+    {
+        info!("http3-fallback to http2 for {}", destination.authority);
+        trace.faults.push("http3-fallback");
+        protocol = UpstreamProtocol::Http2;
+    }
+    let tls = if matches {
+        tls_override(&settings)
+    } else {
+        TlsOverride::default()
+    };
+    if tls != TlsOverride::default() {
+        info!("tls-override for {}: {tls:?}", destination.authority);
+        trace.faults.push("tls-override");
+    }
+    let limits = state
+        .limits()
+        .get(&destination.authority)
+        .unwrap_or_default();
+    let mutation =
+        mutation_path("mutate-request-path", &settings.mutate_request_path).filter(|_| {
+            trace.roll(
+                "mutate-request",
+                settings.mutate_request_percentage.evaluate(&ctx),
+                matches,
+            )
+        });
+    let retry_attempts = if matches { settings.retry_attempts } else { 0 };
+    let buffered = settings.request_buffering
+        || duplicate
+        || retry_attempts > 0
+        || mutation.is_some()
+        || limits.max_request_body_bytes.is_some()
+        || transform.as_ref().is_some_and(Pipeline::edits_request_body);
+    let duplicate_destination = match settings.duplicate_destination_url.as_deref() {
+        Some(url) if duplicate => Some(Destination::parse(url, state.dev_mode())?),
+        _ => None,
+    };
+
+    let mut outgoing_headers =
+        build_destination_headers(&parts.headers, &destination, state.dev_mode())?;
+    add_proxy_identity(&mut outgoing_headers, parts.version, &settings);
+    if evaluation::sampling_forced(&parts.headers) {
+        evaluation::force_sampled(&mut outgoing_headers);
+    }
+    if has_content_encoding(&outgoing_headers)
+        && trace.roll(
+            "strip-content-encoding",
+            settings.strip_content_encoding_percentage.evaluate(&ctx),
+            matches,
+        )
+    {
+        info!("strip-content-encoding {}", ctx.uri);
+        trace.faults.push("strip-content-encoding");
+        outgoing_headers.remove(CONTENT_ENCODING);
+    }
     let original_origin = parts.headers.get(ORIGIN).cloned();
+    let bytes_out = Arc::new(AtomicU64::new(0));
+
+    let (mut outgoing_body, outgoing_trailers) = if buffered {
+        // The client's 100-continue has already been answered by reading the body here.
+        outgoing_headers.remove(EXPECT);
+        let limit = limits
+            .max_request_body_bytes
+            .map_or(usize::MAX, |max| usize::try_from(max).unwrap_or(usize::MAX));
+        let collected = Limited::new(body, limit).collect().await.map_err(|err| {
+            if err.downcast_ref::<LengthLimitError>().is_some() {
+                info!(
+                    "Request body for {} exceeds {limit} bytes",
+                    destination.authority
+                );
+                return json_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    &json!({"error":"request-body-too-large","limit":limit}),
+                    state.dev_mode(),
+                );
+            }
+            warn!("Failed to read request body: {err}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &json!({"error":"invalid-request"}),
+                state.dev_mode(),
+            )
+        })?;
+        let trailers = collected.trailers().cloned();
+        let bytes = collected.to_bytes();
+        bytes_out.store(bytes.len() as u64, Ordering::Relaxed);
+        (RequestBody::Buffered(bytes), trailers)
+    } else {
+        (
+            RequestBody::Streaming(count_body_bytes(body, bytes_out.clone())),
+            None,
+        )
+    };
+
+    if let Some(pipeline) = transform
+        .as_ref()
+        .filter(|pipeline| pipeline.has_phase(Phase::Request))
+    {
+        let mut unbuffered = Bytes::new();
+        let body = match &mut outgoing_body {
+            RequestBody::Buffered(bytes) => bytes,
+            RequestBody::Streaming(_) => &mut unbuffered,
+        };
+        let length = body.len();
+        pipeline
+            .run(
+                Phase::Request,
+                Message {
+                    headers: &mut outgoing_headers,
+                    body: &mut *body,
+                    status: None,
+                },
+            )
+            .await;
+        if body.len() != length {
+            outgoing_headers.remove(CONTENT_LENGTH);
+            bytes_out.store(body.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    if let Some(path) = &mutation
+        && let RequestBody::Buffered(bytes) = &mut outgoing_body
+        && let Some(mutated) = mutate_body(
+            bytes,
+            path,
+            Mutation {
+                mode: settings.mutate_request_mode,
+                delta: settings.mutate_request_delta,
+            },
+        )
+    {
+        info!(
+            "mutate-request {} {}",
+            settings.mutate_request_mode, ctx.uri
+        );
+        trace.faults.push("mutate-request");
+        *bytes = mutated;
+        outgoing_headers.remove(CONTENT_LENGTH);
+        bytes_out.store(bytes.len() as u64, Ordering::Relaxed);
+    }
 
     let outgoing = OutgoingRequest {
         method: parts.method.clone(),
         url: format!("{}{}", destination.raw, ctx.uri),
         headers: outgoing_headers,
-        body: body_bytes,
+        body: outgoing_body,
+        trailers: outgoing_trailers,
+        force_new_connection: force_new_connection || dns_delay.is_some(),
+        binding: state.bindings().get(&destination.authority),
+        protocol,
+        tls,
+        preserve_encoding: settings.strict_passthrough,
+        dns_delay,
     };
-
-    let duplicate = should_trigger(settings.duplicate_percentage, matches);
+    let method = outgoing.method.clone();
+    let url = outgoing.url.clone();
 
     let client = state.client();
-    let first = client.execute(outgoing.clone());
-    let second = if duplicate {
-        Some(client.execute(outgoing.clone()))
-    } else {
-        None
+    let second_destination = duplicate_destination.as_ref().unwrap_or(&destination);
+    let second_url = format!("{}{}", second_destination.raw, ctx.uri);
+    let second = match outgoing.try_clone() {
+        Some(mut copy) if duplicate => {
+            if let Some(other) = &duplicate_destination {
+                copy.url = second_url.clone();
+                copy.headers = build_destination_headers(&copy.headers, other, state.dev_mode())?;
+                copy.binding = state.bindings().get(&other.authority);
+                info!("duplicate to {}", other.authority);
+            }
+            Some(client.execute(copy))
+        }
+        _ => None,
     };
+    // Retries mask backend failures, the inverse of the fail faults. Failed
+    // attempts still count for health and metrics.
+    let first = async {
+        let mut outgoing = outgoing;
+        let mut retries = 0;
+        loop {
+            let next = (retries < retry_attempts)
+                .then(|| outgoing.try_clone())
+                .flatten();
+            let result = client.execute(outgoing).await;
+            let failed = match &result {
+                Ok(response) => is_upstream_failure(response.status),
+                Err(
+                    HttpClientError::RateLimited(_)
+                    | HttpClientError::CircuitOpen(_)
+                    | HttpClientError::Credentials(..),
+                ) => false,
+                Err(_) => true,
+            };
+            let Some(next) = next.filter(|_| failed) else {
+                return (result, retries);
+            };
+            retries += 1;
+            match &result {
+                Ok(response) => info!(
+                    "retry {retries}/{retry_attempts} {method} {url} after HTTP {}",
+                    response.status.as_u16()
+                ),
+                Err(err) => info!("retry {retries}/{retry_attempts} {method} {url} after {err}"),
+            }
+            let _ = record_upstream(
+                &state,
+                &destination,
+                force_new_connection,
+                bytes_out.load(Ordering::Relaxed),
+                result,
+            );
+            let backoff = settings
+                .retry_backoff_ms
+                .as_duration()
+                .saturating_mul(2u32.saturating_pow(retries - 1));
+            if !backoff.is_zero() {
+                clock::sleep(backoff).await;
+            }
+            outgoing = next;
+        }
+    };
+    let buffer_response = duplicate
+        || limits.max_response_body_bytes.is_some()
+        || (state.response_tee_dir().is_some() && settings.response_tee_path.is_some())
+        || transform
+            .as_ref()
+            .is_some_and(Pipeline::edits_response_body)
+        || (matches && needs_response_body(&settings, &ctx));
 
-    let first_response = map_client_response(
-        first.await,
-        &outgoing.url,
-        &outgoing.method,
-        state.body_trailer(),
+    // The backend's interim responses reach the client as they arrive, ahead
+    // of whatever the faults make of its final response.
+    let (first_result, retries) = match parts.extensions.get::<ClientSocket>() {
+        Some(socket) if parts.version == Version::HTTP_11 => {
+            let socket = socket.clone();
+            let sink: InterimSink = Arc::new(move |status: StatusCode, headers: &HeaderMap| {
+                // lowdown answers `Expect: 100-continue` itself.
+                if status != StatusCode::CONTINUE
+                    && let Err(err) = socket.send_interim(Version::HTTP_11, status, headers)
+                {
+                    debug!("Dropping upstream interim response {status}: {err}");
+                }
+            });
+            forward_interim(sink, first).await
+        }
+        _ => first.await,
+    };
+    if retries > 0 {
+        trace.faults.push("retry");
+    }
+    let first_result = receive(first_result, buffer_response).await;
+    let first_result = record_upstream(
+        &state,
+        &destination,
+        force_new_connection,
+        bytes_out.load(Ordering::Relaxed),
+        first_result,
     );
+    if let (Some(dir), Some(template), Ok(response)) = (
+        state.response_tee_dir(),
+        settings.response_tee_path.as_deref(),
+        &first_result,
+    ) && let Some(path) = tee::render_path(dir, template, &ctx)
+        && let Some(body) = response.bytes()
+    {
+        tee::spawn_write(path, body.clone());
+    }
+    let first_response = map_client_response(first_result, &url, &method, state.dev_mode());
     let second_response = match second {
-        Some(call) => Some(map_client_response(
-            call.await,
-            &outgoing.url,
-            &outgoing.method,
-            state.body_trailer(),
-        )),
+        Some(call) => {
+            let result = receive(call.await, buffer_response).await;
+            let result = record_upstream(
+                &state,
+                second_destination,
+                force_new_connection,
+                bytes_out.load(Ordering::Relaxed),
+                result,
+            );
+            Some(map_client_response(
+                result,
+                &second_url,
+                &method,
+                state.dev_mode(),
+            ))
+        }
         None => None,
     };
 
     log_duplicate_status(
-        &outgoing.method,
-        &outgoing.url,
+        &method,
+        &url,
         duplicate,
         &first_response,
         second_response.as_ref(),
     );
 
-    let mut proxied = select_response(first_response, second_response);
+    let mut proxied = match (&duplicate_destination, second_response) {
+        (Some(other), Some(second_response)) => {
+            state.comparisons().record(Comparison::new(
+                method.to_string(),
+                ctx.uri.clone(),
+                (&destination.authority, &first_response),
+                (&other.authority, &second_response),
+            ));
+            first_response
+        }
+        (_, second_response) => select_response(first_response, second_response),
+    };
+
+    if let Some(max) = limits.max_response_body_bytes
+        && proxied.bytes().map_or(0, Bytes::len) as u64 > max
+    {
+        info!(
+            "Response body from {} exceeds {max} bytes",
+            destination.authority
+        );
+        return Err(json_response(
+            StatusCode::BAD_GATEWAY,
+            &json!({"error":"response-body-too-large","limit":max}),
+            state.dev_mode(),
+        ));
+    }
+
+    let floor = settings.min_response_time_ms.as_duration();
+    if matches
+        && let Some(padding) = floor
+            .checked_sub(clock::elapsed(started))
+            .filter(|padding| !padding.is_zero())
+    {
+        debug!("min-response-time padding {} ms", padding.as_millis());
+        trace.faults.push("min-response-time");
+        let _hold = state.metrics().hold(trace.rule_name(), "min-response-time");
+        clock::sleep(padding).await;
+    }
 
-    if should_trigger(settings.delay_after_percentage, matches) && settings.delay_after_ms > 0 {
-        info!("delay-after {} ms", settings.delay_after_ms);
-        sleep(Duration::from_millis(settings.delay_after_ms)).await;
+    if trace.roll(
+        "delay-after",
+        settings.delay_after_percentage.evaluate(&ctx),
+        matches,
+    ) && let delay = settings
+        .delay_after_ms
+        .jittered(settings.delay_after_jitter_ms)
+        && !delay.is_zero()
+    {
+        info!("delay-after {delay} ms");
+        trace.faults.push("delay-after");
+        let _hold = state.metrics().hold(trace.rule_name(), "delay-after");
+        clock::sleep(delay.as_duration()).await;
     }
 
-    if should_trigger(settings.fail_after_percentage, matches) {
+    if trace.roll(
+        "fail-after",
+        settings.fail_after_percentage.evaluate(&ctx),
+        matches,
+    ) {
         info!(
             "HTTP {} {} fail-after. Destination response code: {}",
             settings.fail_after_code, ctx.uri, proxied.status
         );
-        return Err(json_response(
-            status_from_code(settings.fail_after_code),
-            &json!({
+        trace.faults.push("fail-after");
+        return Err(injected_failure(
+            &state,
+            &parts,
+            settings.fail_after_code.status(),
+            json!({
                 "error":"fail-after",
                 "destination-response-code": proxied.status.as_u16()
             }),
-            state.body_trailer(),
+            FailureMode::JsonError,
         ));
     }
 
-    rewrite_response_headers(&mut proxied, original_origin);
-
-    log_result(
+    if trace.roll(
+        "override-status",
+        settings.override_status_percentage.evaluate(&ctx),
         matches,
-        &settings,
-        &outgoing.method,
-        &ctx.uri,
-        proxied.status,
-    );
+    ) {
+        let status = settings.override_status_codes.pick();
+        info!(
+            "override-status {} with {} {}",
+            proxied.status.as_u16(),
+            status.as_u16(),
+            ctx.uri
+        );
+        trace.faults.push("override-status");
+        proxied.status = status;
+        proxied.reason = None;
+    }
+
+    if !settings.strict_passthrough {
+        rewrite_response_headers(&mut proxied, original_origin);
+    }
+
+    if let Some(pipeline) = transform
+        .as_ref()
+        .filter(|pipeline| pipeline.has_phase(Phase::Response))
+    {
+        let mut unbuffered = Bytes::new();
+        let body = match &mut proxied.body {
+            ResponseBody::Buffered(bytes) => bytes,
+            ResponseBody::Streaming(_) => &mut unbuffered,
+        };
+        let upstream_status = proxied.status;
+        pipeline
+            .run(
+                Phase::Response,
+                Message {
+                    headers: &mut proxied.headers,
+                    body,
+                    status: Some(&mut proxied.status),
+                },
+            )
+            .await;
+        if proxied.status != upstream_status {
+            proxied.reason = None;
+        }
+    }
+
+    if has_validators(&proxied.headers) {
+        if trace.roll(
+            "strip-validators",
+            settings.strip_validators_percentage.evaluate(&ctx),
+            matches,
+        ) {
+            info!("strip-validators {}", ctx.uri);
+            trace.faults.push("strip-validators");
+            strip_validators(&mut proxied.headers);
+        } else if trace.roll(
+            "corrupt-validators",
+            settings.corrupt_validators_percentage.evaluate(&ctx),
+            matches,
+        ) {
+            info!("corrupt-validators {}", ctx.uri);
+            trace.faults.push("corrupt-validators");
+            corrupt_validators(&mut proxied.headers);
+        }
+    }
+    if proxied.status.is_success()
+        && !is_conditional(&parts.headers)
+        && trace.roll(
+            "force-not-modified",
+            settings.force_not_modified_percentage.evaluate(&ctx),
+            matches,
+        )
+    {
+        info!(
+            "force-not-modified {}. Destination response code: {}",
+            ctx.uri, proxied.status
+        );
+        trace.faults.push("force-not-modified");
+        proxied.status = StatusCode::NOT_MODIFIED;
+        proxied.clear_body();
+    }
+
+    let bodiless = !settings.fault_bodiless_responses && is_bodiless(&parts.method, proxied.status);
+    if bodiless {
+        if proxied.bytes().is_none_or(|body| !body.is_empty()) || proxied.trailers.is_some() {
+            debug!(
+                "Dropping body of {} response to {}",
+                proxied.status, parts.method
+            );
+        }
+        proxied.clear_body();
+    }
+    let body_faults = matches && !bodiless;
+
+    // A streamed body may still end with trailers.
+    if proxied.trailers.is_some() || matches!(proxied.body, ResponseBody::Streaming(_)) {
+        if trace.roll(
+            "drop-trailers",
+            settings.drop_trailers_percentage.evaluate(&ctx),
+            body_faults,
+        ) {
+            info!("drop-trailers {}", ctx.uri);
+            trace.faults.push("drop-trailers");
+            fault_trailers(&mut proxied, true);
+        } else if trace.roll(
+            "corrupt-trailers",
+            settings.corrupt_trailers_percentage.evaluate(&ctx),
+            body_faults,
+        ) {
+            info!("corrupt-trailers {}", ctx.uri);
+            trace.faults.push("corrupt-trailers");
+            fault_trailers(&mut proxied, false);
+        }
+    }
+
+    if let Some(path) = mutation_path("mutate-response-path", &settings.mutate_response_path)
+        && trace.roll(
+            "mutate-response",
+            settings.mutate_response_percentage.evaluate(&ctx),
+            body_faults,
+        )
+        && let Some(body) = proxied.bytes_mut()
+        && let Some(mutated) = mutate_body(
+            body,
+            &path,
+            Mutation {
+                mode: settings.mutate_response_mode,
+                delta: settings.mutate_response_delta,
+            },
+        )
+    {
+        info!(
+            "mutate-response {} {}",
+            settings.mutate_response_mode, ctx.uri
+        );
+        trace.faults.push("mutate-response");
+        *body = mutated;
+    }
+
+    if trace.roll(
+        "corrupt-response",
+        settings.corrupt_response_percentage.evaluate(&ctx),
+        body_faults,
+    ) && let Some(body) = proxied.bytes_mut()
+    {
+        info!(
+            "corrupt-response {} {}",
+            settings.corrupt_response_mode, ctx.uri
+        );
+        trace.faults.push("corrupt-response");
+        *body = corrupt_body(body, settings.corrupt_response_mode);
+    }
+
+    let stream_faults = if trace.roll(
+        "stream-fault",
+        settings.stream_fault_percentage.evaluate(&ctx),
+        body_faults,
+    ) {
+        Some(("stream-fault", StreamFaults::from_settings(&settings)))
+    } else if body_faults {
+        Some(("stream-loss", StreamFaults::loss_from_settings(&settings)))
+    } else {
+        None
+    }
+    .filter(|(_, faults)| !faults.is_noop());
+    let throttle = trace.roll(
+        "throttle",
+        settings.throttle_percentage.evaluate(&ctx),
+        body_faults,
+    ) && settings.throttle_bytes_per_second > 0;
+    let mut holds = Vec::new();
+    if let Some((name, _)) = &stream_faults {
+        trace.faults.push(name);
+        holds.push(state.metrics().hold(trace.rule_name(), name));
+    }
+    if throttle {
+        info!(
+            "throttle {} to {} bytes/s",
+            ctx.uri, settings.throttle_bytes_per_second
+        );
+        trace.faults.push("throttle");
+        holds.push(state.metrics().hold(trace.rule_name(), "throttle"));
+    }
 
-    Ok(build_response(proxied, state.body_trailer()))
+    let untouched = settings.strict_passthrough
+        && trace.faults.is_empty()
+        && !transform
+            .as_ref()
+            .is_some_and(|pipeline| pipeline.has_phase(Phase::Response));
+    // Faulted bodies are registered as streams, so changing the settings can
+    // stop their faults and the admin API can list and terminate them.
+    let stream = (!holds.is_empty()).then(|| {
+        state.streams().register(StreamInfo {
+            id: Uuid::new_v4(),
+            method: parts.method.to_string(),
+            uri: ctx.uri.clone(),
+            destination: destination.authority.clone(),
+            rule: trace.rule_name().to_string(),
+            faults: trace.faults.clone(),
+            started_at: clock::timestamp(),
+            settings_version,
+            expiry_mode: settings.stream_expiry_mode,
+        })
+    });
+    let control = stream
+        .as_ref()
+        .map(|(control, _)| control.clone())
+        .unwrap_or_default();
+    let response = build_response(
+        proxied,
+        stream_faults.map(|(_, faults)| faults),
+        &parts.method,
+        untouched,
+        state.dev_mode(),
+        control.clone(),
+    );
+    let response = if throttle {
+        response.map(|body| throttled_body(body, settings.throttle_bytes_per_second, control))
+    } else {
+        response
+    };
+    Ok(match stream {
+        Some((control, registration)) => {
+            response.map(|body| registered_body(body, control, registration, holds))
+        }
+        None => response,
+    })
 }
 
-fn rewrite_forwarding(mut req: Request<Body>) -> Request<Body> {
+fn rewrite_forwarding(mut req: Request<Body>, mikkmokk_compat: bool) -> Request<Body> {
+    // An HTTP/1 absolute-form target (`GET http://host/path`) is what clients
+    // send to a forward proxy; HTTP/2 carries an authority on every request.
+    if req.version() < Version::HTTP_2
+        && req.method() != Method::CONNECT
+        && let (Some(scheme), Some(authority)) = (req.uri().scheme(), req.uri().authority())
+    {
+        let destination = format!("{scheme}://{authority}");
+        let path = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| Uri::from_static("/"), |pq| Uri::from(pq.clone()));
+        if let Ok(value) = HeaderValue::from_str(&destination) {
+            req.headers_mut()
+                .insert(HeaderName::from_static(DESTINATION_HEADER), value);
+        }
+        *req.uri_mut() = path;
+        return req;
+    }
     let uri_str = req
         .uri()
         .path_and_query()
         .map(|pq| pq.as_str().to_string())
         .unwrap_or_else(|| req.uri().path().to_string());
-    if let Some((scheme, host, new_path)) = parse_forward_target(&uri_str) {
+    if let Some((scheme, host, new_path)) = parse_forward_target(&uri_str, mikkmokk_compat) {
         let destination = format!("{scheme}://{host}");
         if let Ok(value) = HeaderValue::from_str(&destination) {
             req.headers_mut()
@@ -195,8 +1284,18 @@ fn rewrite_forwarding(mut req: Request<Body>) -> Request<Body> {
     req
 }
 
-fn parse_forward_target(uri: &str) -> Option<(String, String, String)> {
-    for prefix in ["/lowdown-fwd-", "/lowdown-forward-"] {
+fn parse_forward_target(uri: &str, mikkmokk_compat: bool) -> Option<(String, String, String)> {
+    let prefixes: &[&str] = if mikkmokk_compat {
+        &[
+            "/lowdown-fwd-",
+            "/lowdown-forward-",
+            "/mikkmokk-fwd-",
+            "/mikkmokk-forward-",
+        ]
+    } else {
+        &["/lowdown-fwd-", "/lowdown-forward-"]
+    };
+    for prefix in prefixes {
         if let Some(rest) = uri.strip_prefix(prefix) {
             for scheme in ["http", "https"] {
                 let marker = format!("{scheme}/");
@@ -218,27 +1317,74 @@ fn parse_forward_target(uri: &str) -> Option<(String, String, String)> {
     None
 }
 
+fn pick_from_pool(settings: &Settings, ctx: &RequestContext) -> Option<String> {
+    let pool = settings.destination_urls.as_deref()?;
+    let key = settings
+        .sticky_key
+        .as_deref()
+        .map(|template| render_key(template, ctx));
+    let url = select_destination(pool, key.as_deref())?;
+    debug!("Selected destination {url} from pool");
+    Some(url)
+}
+
+fn tls_override(settings: &Settings) -> TlsOverride {
+    TlsOverride {
+        server_name: settings.tls_server_name.clone(),
+        skip_verify: settings.tls_skip_verify,
+    }
+}
+
 #[allow(clippy::result_large_err)]
 fn build_destination_headers(
     headers: &HeaderMap,
     destination: &Destination,
-    trailer: &str,
+    dev_mode: bool,
 ) -> Result<HeaderMap, Response<Body>> {
     let mut map = headers.clone();
     map.insert(
         HOST,
-        HeaderValue::from_str(&destination.authority).map_err(|_| invalid_destination(trailer))?,
+        HeaderValue::from_str(&destination.authority).map_err(|_| invalid_destination(dev_mode))?,
     );
     if headers.get(ORIGIN).is_some() {
         map.insert(
             ORIGIN,
             HeaderValue::from_str(&destination.origin())
-                .map_err(|_| invalid_destination(trailer))?,
+                .map_err(|_| invalid_destination(dev_mode))?,
         );
     }
     Ok(map)
 }
 
+fn add_proxy_identity(headers: &mut HeaderMap, version: Version, settings: &Settings) {
+    if let Some(value) = settings
+        .user_agent
+        .as_deref()
+        .and_then(|agent| HeaderValue::from_str(agent).ok())
+    {
+        headers.insert(USER_AGENT, value);
+    }
+    if settings.add_via_header {
+        let protocol = match version {
+            Version::HTTP_09 => "0.9",
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_2 => "2",
+            Version::HTTP_3 => "3",
+            _ => "1.1",
+        };
+        let via = format!("{protocol} lowdown (lowdown/{})", env!("CARGO_PKG_VERSION"));
+        if let Ok(value) = HeaderValue::from_str(&via) {
+            headers.append(VIA, value);
+        }
+    }
+    if settings.add_forwarded_by_header {
+        headers.insert(
+            HeaderName::from_static(FORWARDED_BY_HEADER),
+            HeaderValue::from_static(concat!("lowdown/", env!("CARGO_PKG_VERSION"))),
+        );
+    }
+}
+
 fn rewrite_response_headers(response: &mut ProxiedResponse, client_origin: Option<HeaderValue>) {
     if let Some(origin) = client_origin
         && response.headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN)
@@ -252,7 +1398,7 @@ fn rewrite_response_headers(response: &mut ProxiedResponse, client_origin: Optio
 fn select_response(first: ProxiedResponse, second: Option<ProxiedResponse>) -> ProxiedResponse {
     match second {
         Some(second) => {
-            if rand::thread_rng().gen_bool(0.5) {
+            if random::rng().gen_bool(0.5) {
                 first
             } else {
                 second
@@ -293,84 +1439,429 @@ fn log_duplicate_status(
     }
 }
 
-fn log_result(matches: bool, settings: &Settings, method: &Method, uri: &str, status: StatusCode) {
-    let all_zero = settings.fail_before_percentage == 0
-        && settings.fail_after_percentage == 0
-        && settings.duplicate_percentage == 0
-        && settings.delay_before_percentage == 0
-        && settings.delay_after_percentage == 0;
-    if all_zero || !matches {
-        info!(
-            "HTTP {} {} {}. No match / all percentages were zero.",
-            status.as_u16(),
-            method,
-            uri
-        );
-    } else {
-        info!("HTTP {} {} {}", status.as_u16(), method, uri);
-    }
-}
-
-fn invalid_destination(trailer: &str) -> Response<Body> {
+fn invalid_destination(dev_mode: bool) -> Response<Body> {
     json_response(
         StatusCode::INTERNAL_SERVER_ERROR,
         &json!({"error":"invalid-destination-url"}),
-        trailer,
+        dev_mode,
     )
 }
 
-fn should_trigger(percentage: u8, matches: bool) -> bool {
-    matches && percentage > rand::thread_rng().gen_range(0..100)
-}
-
 fn map_client_response(
     result: Result<ProxiedResponse, HttpClientError>,
     url: &str,
     method: &Method,
-    trailer: &str,
+    dev_mode: bool,
 ) -> ProxiedResponse {
     match result {
         Ok(response) => response,
+        Err(HttpClientError::RateLimited(destination)) => {
+            info!("{method} {url} rejected by the max-rps limit for {destination}");
+            proxied_json(
+                StatusCode::TOO_MANY_REQUESTS,
+                json!({"error":"destination-rate-limited","destination":destination}),
+                dev_mode,
+            )
+        }
+        Err(HttpClientError::CircuitOpen(destination)) => {
+            info!("{method} {url} short-circuited, circuit to {destination} is open");
+            proxied_json(
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"error":"circuit-open","destination":destination}),
+                dev_mode,
+            )
+        }
+        Err(HttpClientError::Credentials(destination, message)) => {
+            warn!(
+                "Not sending {method} {url}, credentials for {destination} are unavailable: {message}"
+            );
+            proxied_json(
+                StatusCode::BAD_GATEWAY,
+                json!({"error":"upstream-credentials-unavailable","destination":destination}),
+                dev_mode,
+            )
+        }
+        Err(HttpClientError::Timeout(message)) => {
+            warn!("{method} {url} timed out: {message}");
+            proxied_json(
+                StatusCode::GATEWAY_TIMEOUT,
+                json!({"error":"upstream-timeout","url":url}),
+                dev_mode,
+            )
+        }
         Err(err) => {
             warn!("Unexpected error when {} {}: {err}", method, url);
             proxied_json(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 json!({"error":"unexpected-error","url":url}),
-                trailer,
+                dev_mode,
             )
         }
     }
 }
 
-fn status_from_code(code: u16) -> StatusCode {
-    StatusCode::from_u16(code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+// Faults that rewrite the body need the whole upstream response.
+fn needs_response_body(settings: &Settings, ctx: &RequestContext) -> bool {
+    !settings.corrupt_response_percentage.evaluate(ctx).is_zero()
+        || (settings.mutate_response_path.is_some()
+            && !settings.mutate_response_percentage.evaluate(ctx).is_zero())
+}
+
+fn fault_trailers(proxied: &mut ProxiedResponse, drop: bool) {
+    match &mut proxied.body {
+        ResponseBody::Streaming(body) => {
+            *body = faulty_trailers(std::mem::take(body), drop);
+        }
+        ResponseBody::Buffered(_) if drop => proxied.trailers = None,
+        ResponseBody::Buffered(_) => {
+            if let Some(trailers) = proxied.trailers.as_mut() {
+                corrupt_trailers(trailers);
+            }
+        }
+    }
+}
+
+async fn receive(
+    result: Result<ProxiedResponse, HttpClientError>,
+    buffer: bool,
+) -> Result<ProxiedResponse, HttpClientError> {
+    let mut response = result?;
+    if buffer {
+        response.buffer().await?;
+    }
+    Ok(response)
+}
+
+fn record_upstream(
+    state: &Arc<AppState>,
+    destination: &Destination,
+    forced: bool,
+    bytes_out: u64,
+    mut result: Result<ProxiedResponse, HttpClientError>,
+) -> Result<ProxiedResponse, HttpClientError> {
+    if let Err(
+        HttpClientError::RateLimited(_)
+        | HttpClientError::CircuitOpen(_)
+        | HttpClientError::Credentials(..),
+    ) = result
+    {
+        return result;
+    }
+    let bytes_in = match result.as_mut().map(|response| &mut response.body) {
+        Ok(ResponseBody::Buffered(bytes)) => bytes.len() as u64,
+        Ok(ResponseBody::Streaming(body)) => {
+            let state = state.clone();
+            let authority = destination.authority.clone();
+            *body = Body::new(std::mem::take(body).map_frame(move |frame| {
+                if let Some(data) = frame.data_ref() {
+                    state
+                        .metrics()
+                        .record_bytes(&authority, 0, data.len() as u64);
+                }
+                frame
+            }));
+            0
+        }
+        Err(_) => 0,
+    };
+    state
+        .metrics()
+        .record_bytes(&destination.authority, bytes_out, bytes_in);
+    let new_connection = result
+        .as_ref()
+        .ok()
+        .and_then(|response| response.new_connection);
+    let failed = result
+        .as_ref()
+        .map_or(true, |response| is_upstream_failure(response.status));
+    state
+        .metrics()
+        .record_upstream(&destination.authority, forced, new_connection, failed);
+    state.health().record(&destination.authority, failed);
+    result
+}
+
+fn count_body_bytes(body: Body, counter: Arc<AtomicU64>) -> Body {
+    Body::new(body.map_frame(move |frame| {
+        if let Some(data) = frame.data_ref() {
+            counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        frame
+    }))
+}
+
+// Tunnels are registered as streams like faulted bodies, so they can be listed
+// and terminated, and a settings change can close them.
+async fn open_tunnel(
+    state: &Arc<AppState>,
+    mut parts: Parts,
+    destination: &Destination,
+    stream: StreamInfo,
+) -> Result<Response<Body>, Response<Body>> {
+    let Some(upgrade) = parts.extensions.remove::<OnUpgrade>() else {
+        return Err(json_response(
+            StatusCode::BAD_REQUEST,
+            &json!({"error":"connect-not-supported"}),
+            state.dev_mode(),
+        ));
+    };
+    let authority = destination.authority.clone();
+    let upstream = TcpStream::connect(&authority).await;
+    state.health().record(&authority, upstream.is_err());
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            info!("CONNECT {authority} failed: {err}");
+            return Err(json_response(
+                StatusCode::BAD_GATEWAY,
+                &json!({"error":"tunnel-connect-failed","destination":authority}),
+                state.dev_mode(),
+            ));
+        }
+    };
+    info!("CONNECT {authority} tunnel open");
+    let tunnel_state = state.clone();
+    let registered = state.streams().register(stream);
+    tunnel::relay(upgrade, upstream, registered, move |sent, received| {
+        debug!("CONNECT {authority} closed after {sent} bytes out, {received} bytes in");
+        tunnel_state
+            .metrics()
+            .record_bytes(&authority, sent, received);
+    });
+    Ok(Response::new(Body::empty()))
+}
+
+fn interim_status(code: u16) -> Option<StatusCode> {
+    StatusCode::from_u16(code)
+        .ok()
+        .filter(|status| status.is_informational() && *status != StatusCode::SWITCHING_PROTOCOLS)
+}
+
+fn dropped_connection() -> Response<Body> {
+    let aborted = futures_util::stream::once(async {
+        Err::<Bytes, _>(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "request dropped",
+        ))
+    });
+    let mut response = Response::new(Body::from_stream(aborted));
+    *response.status_mut() = StatusCode::from_u16(DROPPED_STATUS).unwrap_or(StatusCode::OK);
+    response
+}
+
+fn injected_failure(
+    state: &AppState,
+    parts: &Parts,
+    status: StatusCode,
+    value: serde_json::Value,
+    mode: FailureMode,
+) -> Response<Body> {
+    let headers = &parts.headers;
+    let mut response = match mode {
+        FailureMode::JsonError if is_grpc_web(headers) => {
+            let message = value["error"].as_str().unwrap_or_default();
+            grpc_web_failure(status, message, headers)
+        }
+        FailureMode::JsonError => json_response(status, &value, state.dev_mode()),
+        FailureMode::EmptyBody => failure::empty_body(status),
+        FailureMode::HeadersOnly => failure::headers_only(status),
+        FailureMode::ConnectionClose => return dropped_connection(),
+        FailureMode::HtmlErrorPage => failure::html_error_page(status),
+        FailureMode::MalformedJson => failure::malformed_json(status, &value),
+    };
+    if is_bodiless(&parts.method, status) {
+        *response.body_mut() = Body::empty();
+    }
+    allow_origin(response.headers_mut(), headers);
+    response
+}
+
+fn is_bodiless(method: &Method, status: StatusCode) -> bool {
+    *method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+}
+
+fn mutation_path(name: &str, text: &Option<String>) -> Option<JsonPath> {
+    JsonPath::parse(text.as_deref()?)
+        .map_err(|err| warn!("Ignoring invalid {name}: {err}"))
+        .ok()
+}
+
+fn apply_fault_set(text: &str, settings: &mut Settings) {
+    match FaultSet::parse(text) {
+        Ok(fault_set) => {
+            if let Some(fault) = fault_set.choose(random::rng().gen_range(0..100)) {
+                info!("fault-set selected {fault:?}");
+                fault.apply(settings);
+            }
+        }
+        Err(err) => warn!("Invalid fault-set {text:?}: {err}"),
+    }
+}
+
+fn spawn_shadow(
+    state: Arc<AppState>,
+    destination: &Destination,
+    parts: &http::request::Parts,
+    body: Body,
+    uri: &str,
+    settings: &Settings,
+) {
+    let Ok(mut headers) = build_destination_headers(&parts.headers, destination, state.dev_mode())
+    else {
+        warn!("Skipping shadow request for {uri}: invalid headers");
+        return;
+    };
+    headers.remove(EXPECT);
+    add_proxy_identity(&mut headers, parts.version, settings);
+    let method = parts.method.clone();
+    let url = format!("{}{}", destination.raw, uri);
+    let authority = destination.authority.clone();
+    let protocol = settings.upstream_protocol;
+    let tls = tls_override(settings);
+    let description = format!("{method} {url}");
+    state
+        .clone()
+        .tasks()
+        .spawn("shadow", description, |_| async move {
+            let collected = match body.collect().await {
+                Ok(collected) => collected,
+                Err(err) => {
+                    warn!("Skipping shadow request for {url}: {err}");
+                    return Err(err.to_string());
+                }
+            };
+            let trailers = collected.trailers().cloned();
+            let request = OutgoingRequest {
+                method: method.clone(),
+                url: url.clone(),
+                headers,
+                body: RequestBody::Buffered(collected.to_bytes()),
+                trailers,
+                force_new_connection: false,
+                binding: state.bindings().get(&authority),
+                protocol,
+                tls,
+                preserve_encoding: false,
+                dns_delay: None,
+            };
+            let status = match state.client().execute(request).await {
+                Ok(response) => {
+                    info!("Shadow {method} {url} => HTTP {}", response.status);
+                    Some(response.status)
+                }
+                Err(err) => {
+                    info!("Shadow {method} {url} failed: {err}");
+                    None
+                }
+            };
+            state
+                .health()
+                .record(&authority, status.is_none_or(is_upstream_failure));
+            state.metrics().record_shadow(&authority, status);
+            Ok(())
+        });
 }
 
-fn proxied_json(status: StatusCode, value: serde_json::Value, trailer: &str) -> ProxiedResponse {
+fn proxied_json(status: StatusCode, value: serde_json::Value, dev_mode: bool) -> ProxiedResponse {
     let mut headers = HeaderMap::new();
     headers.insert("content-type", HeaderValue::from_static("application/json"));
-    let mut body = serde_json::to_string(&value).unwrap_or_else(|_| "{}".to_string());
-    body.push_str(trailer);
+    let body = to_json(&value, dev_mode).unwrap_or_else(|_| "{}".to_string());
     ProxiedResponse::new(status, headers, Bytes::from(body))
 }
 
-fn build_response(proxied: ProxiedResponse, trailer: &str) -> Response<Body> {
+fn build_response(
+    mut proxied: ProxiedResponse,
+    stream_faults: Option<StreamFaults>,
+    method: &Method,
+    untouched: bool,
+    dev_mode: bool,
+    control: StreamControl,
+) -> Response<Body> {
+    let upstream_length = match &proxied.body {
+        ResponseBody::Buffered(bytes) => Some(bytes.len()),
+        ResponseBody::Streaming(_) => proxied
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse().ok()),
+    };
+    let length = upstream_length.filter(|_| stream_faults.is_none() && proxied.trailers.is_none());
+    if !untouched {
+        finalize_framing(&mut proxied.headers, proxied.status, method, length);
+    }
+    let body = match (proxied.body, stream_faults) {
+        (body, Some(faults)) => {
+            info!(
+                "stream-fault stall {} ms after {} bytes, {} garbage bytes after {} bytes, abort at {}%, loss {}%/{}% burst",
+                faults.stall_ms,
+                faults.stall_after_bytes,
+                faults.garbage_bytes,
+                faults.garbage_after_bytes,
+                faults.abort_at_percent,
+                faults.loss_percentage,
+                faults.loss_burst_percentage
+            );
+            let body = match body {
+                ResponseBody::Streaming(body) => body,
+                ResponseBody::Buffered(body) => body_with_trailers(body, proxied.trailers),
+            };
+            faulty_body(
+                body,
+                upstream_length.map(|len| len as u64),
+                &faults,
+                control,
+            )
+        }
+        (ResponseBody::Streaming(body), None) => body,
+        (ResponseBody::Buffered(body), None)
+            if untouched && !proxied.headers.contains_key(CONTENT_LENGTH) =>
+        {
+            unsized_body(body, proxied.trailers)
+        }
+        (ResponseBody::Buffered(body), None) => body_with_trailers(body, proxied.trailers),
+    };
     Response::builder()
         .status(proxied.status)
-        .body(Body::from(proxied.body))
+        .body(body)
         .map(|mut response| {
             *response.headers_mut() = proxied.headers;
+            let keep_reason = untouched || proxied.status.canonical_reason().is_none();
+            if let Some(reason) = proxied.reason.filter(|_| keep_reason) {
+                response.extensions_mut().insert(reason);
+            }
             response
         })
         .unwrap_or_else(|_| {
             json_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 &json!({"error":"internal"}),
-                trailer,
+                dev_mode,
             )
         })
 }
 
+fn finalize_framing(
+    headers: &mut HeaderMap,
+    status: StatusCode,
+    method: &Method,
+    length: Option<usize>,
+) {
+    headers.remove(TRANSFER_ENCODING);
+    if *method == Method::HEAD || status == StatusCode::NOT_MODIFIED {
+        return;
+    }
+    match length {
+        Some(length) if status != StatusCode::NO_CONTENT => {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+        _ => {
+            headers.remove(CONTENT_LENGTH);
+        }
+    }
+}
+
 struct Destination {
     raw: String,
     scheme: String,
@@ -379,13 +1870,13 @@ struct Destination {
 
 impl Destination {
     #[allow(clippy::result_large_err)]
-    fn parse(url: &str, trailer: &str) -> Result<Self, Response<Body>> {
+    fn parse(url: &str, dev_mode: bool) -> Result<Self, Response<Body>> {
         match Url::parse(url) {
             Ok(parsed) => {
                 let host = parsed
                     .host_str()
                     .map(|h| h.to_string())
-                    .ok_or_else(|| invalid_destination(trailer))?;
+                    .ok_or_else(|| invalid_destination(dev_mode))?;
                 let authority = match parsed.port() {
                     Some(port) => format!("{host}:{port}"),
                     None => host,
@@ -396,7 +1887,15 @@ impl Destination {
                     authority,
                 })
             }
-            Err(_) => Err(invalid_destination(trailer)),
+            Err(_) => Err(invalid_destination(dev_mode)),
+        }
+    }
+
+    fn tunnel(authority: &str) -> Self {
+        Self {
+            raw: authority.to_string(),
+            scheme: "tcp".to_string(),
+            authority: authority.to_string(),
         }
     }
 
@@ -422,6 +1921,10 @@ impl Service<Request<Body>> for ProxyService {
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         let state = self.state.clone();
-        Box::pin(async move { Ok(proxy_entry(state, req).await) })
+        let seeded = state.seeded_rng();
+        Box::pin(async move {
+            let clock = state.clock();
+            Ok(clock::scope(clock, random::scope(seeded, proxy_entry(state, req))).await)
+        })
     }
 }
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use rand::rngs::{StdRng, ThreadRng};
+use rand::{RngCore, SeedableRng};
+
+#[derive(Clone)]
+pub struct SeededRng(Arc<Mutex<StdRng>>);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+}
+
+tokio::task_local! {
+    static SEEDED: SeededRng;
+}
+
+// Draws made while `future` runs come from `seeded`, so a sequential run
+// against a seeded instance replays the same faults. Work that outlives the
+// request, like streamed bodies, still uses the thread generator.
+pub async fn scope<F: Future>(seeded: Option<SeededRng>, future: F) -> F::Output {
+    match seeded {
+        Some(rng) => SEEDED.scope(rng, future).await,
+        None => future.await,
+    }
+}
+
+pub enum Random {
+    Seeded(SeededRng),
+    Thread(ThreadRng),
+}
+
+pub fn rng() -> Random {
+    SEEDED
+        .try_with(|seeded| Random::Seeded(seeded.clone()))
+        .unwrap_or_else(|_| Random::Thread(rand::thread_rng()))
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Random::Seeded(rng) => rng.0.lock().next_u32(),
+            Random::Thread(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Random::Seeded(rng) => rng.0.lock().next_u64(),
+            Random::Thread(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Random::Seeded(rng) => rng.0.lock().fill_bytes(dest),
+            Random::Thread(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Random::Seeded(rng) => rng.0.lock().try_fill_bytes(dest),
+            Random::Thread(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
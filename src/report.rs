@@ -0,0 +1,365 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::traffic::{AdminChange, DEFAULT_RULE, TrafficRecord};
+
+pub const DEFAULT_SLO: f64 = 99.9;
+
+const TOP_ROUTES: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Markdown,
+    Html,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    pub since: Option<Timestamp>,
+    pub generated_at: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<i64>,
+    pub requests: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub requests_by_deployment_marker: BTreeMap<String, u64>,
+    pub statuses: BTreeMap<u16, u64>,
+    pub faulted_requests: u64,
+    pub faults: BTreeMap<&'static str, u64>,
+    pub faults_by_rule: BTreeMap<String, BTreeMap<&'static str, u64>>,
+    pub faulted_routes: Vec<RouteFaults>,
+    pub latency_ms: Latency,
+    pub error_budget: ErrorBudget,
+    pub admin_changes: Vec<AdminChange>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RouteFaults {
+    pub route: String,
+    pub requests: u64,
+    pub faulted_requests: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Latency {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ErrorBudget {
+    pub slo: f64,
+    pub errors: u64,
+    pub allowed_errors: f64,
+    pub consumed_percentage: f64,
+}
+
+pub fn build(
+    records: &[TrafficRecord],
+    admin_changes: Vec<AdminChange>,
+    since: Option<Timestamp>,
+    slo: f64,
+    generated_at: Timestamp,
+) -> Report {
+    let mut statuses = BTreeMap::new();
+    let mut requests_by_deployment_marker = BTreeMap::new();
+    let mut faults = BTreeMap::new();
+    let mut faults_by_rule: BTreeMap<String, BTreeMap<&'static str, u64>> = BTreeMap::new();
+    let mut routes: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut faulted_requests = 0;
+    let mut errors = 0;
+    for record in records {
+        *statuses.entry(record.status).or_default() += 1;
+        let path = record.uri.split('?').next().unwrap_or_default();
+        let route = routes
+            .entry(format!("{} {path}", record.method))
+            .or_default();
+        route.0 += 1;
+        route.1 += u64::from(!record.faults.is_empty());
+        if let Some(marker) = &record.deployment_marker {
+            *requests_by_deployment_marker
+                .entry(marker.clone())
+                .or_default() += 1;
+        }
+        if record.status >= 500 {
+            errors += 1;
+        }
+        if record.faults.is_empty() {
+            continue;
+        }
+        faulted_requests += 1;
+        let rule = record
+            .rule
+            .clone()
+            .unwrap_or_else(|| DEFAULT_RULE.to_string());
+        let by_rule = faults_by_rule.entry(rule).or_default();
+        for fault in &record.faults {
+            *faults.entry(*fault).or_default() += 1;
+            *by_rule.entry(*fault).or_default() += 1;
+        }
+    }
+
+    // The routes that took the most faults, so a run's summary shows where
+    // they landed.
+    let mut faulted_routes: Vec<_> = routes
+        .into_iter()
+        .filter(|(_, (_, faulted))| *faulted > 0)
+        .map(|(route, (requests, faulted_requests))| RouteFaults {
+            route,
+            requests,
+            faulted_requests,
+        })
+        .collect();
+    faulted_routes.sort_by_key(|route| Reverse(route.faulted_requests));
+    faulted_routes.truncate(TOP_ROUTES);
+
+    let requests = records.len() as u64;
+    let allowed_errors = requests as f64 * (100.0 - slo) / 100.0;
+    let consumed_percentage = if allowed_errors > 0.0 {
+        errors as f64 / allowed_errors * 100.0
+    } else {
+        0.0
+    };
+
+    Report {
+        since,
+        generated_at,
+        duration_seconds: since.map(|since| generated_at.duration_since(since).as_secs()),
+        requests,
+        requests_by_deployment_marker,
+        statuses,
+        faulted_requests,
+        faults,
+        faults_by_rule,
+        faulted_routes,
+        latency_ms: latency(records),
+        error_budget: ErrorBudget {
+            slo,
+            errors,
+            allowed_errors,
+            consumed_percentage,
+        },
+        admin_changes,
+    }
+}
+
+fn latency(records: &[TrafficRecord]) -> Latency {
+    latency_percentiles(records.iter().map(|record| record.duration_ms).collect())
+}
+
+pub fn latency_percentiles(mut durations: Vec<u64>) -> Latency {
+    if durations.is_empty() {
+        return Latency::default();
+    }
+    durations.sort_unstable();
+    let percentile = |p: usize| durations[(durations.len() * p).div_ceil(100).max(1) - 1];
+    Latency {
+        p50: percentile(50),
+        p90: percentile(90),
+        p99: percentile(99),
+        max: durations[durations.len() - 1],
+    }
+}
+
+pub fn render_markdown(report: &Report) -> String {
+    let mut out = String::from("# lowdown report\n\n");
+    let since = report
+        .since
+        .map(|since| since.to_string())
+        .unwrap_or_else(|| "start".to_string());
+    let _ = writeln!(out, "Traffic from {since} to {}.\n", report.generated_at);
+    for (label, value) in summary_rows(report) {
+        let _ = writeln!(out, "- {label}: {value}");
+    }
+    out.push_str("\n## Faults\n\n| Rule | Fault | Count |\n| --- | --- | --- |\n");
+    for (rule, fault, count) in fault_rows(report) {
+        let _ = writeln!(out, "| {rule} | {fault} | {count} |");
+    }
+    out.push_str("\n## Faulted routes\n\n| Route | Requests | Faulted |\n| --- | --- | --- |\n");
+    for route in &report.faulted_routes {
+        let _ = writeln!(
+            out,
+            "| {} | {} | {} |",
+            route.route, route.requests, route.faulted_requests
+        );
+    }
+    out.push_str("\n## Admin changes\n\n| Time | Request | Settings |\n| --- | --- | --- |\n");
+    for change in &report.admin_changes {
+        let _ = writeln!(
+            out,
+            "| {} | {} {} | {} |",
+            change.at,
+            change.method,
+            change.path,
+            settings_text(change)
+        );
+    }
+    out
+}
+
+pub fn render_html(report: &Report) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>lowdown report</title></head><body>\n<h1>lowdown report</h1>\n<ul>\n",
+    );
+    for (label, value) in summary_rows(report) {
+        let _ = writeln!(out, "<li>{}: {}</li>", escape(label), escape(&value));
+    }
+    out.push_str(
+        "</ul>\n<h2>Faults</h2>\n<table>\n<tr><th>Rule</th><th>Fault</th><th>Count</th></tr>\n",
+    );
+    for (rule, fault, count) in fault_rows(report) {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{count}</td></tr>",
+            escape(rule),
+            escape(fault)
+        );
+    }
+    out.push_str("</table>\n<h2>Faulted routes</h2>\n<table>\n<tr><th>Route</th><th>Requests</th><th>Faulted</th></tr>\n");
+    for route in &report.faulted_routes {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&route.route),
+            route.requests,
+            route.faulted_requests
+        );
+    }
+    out.push_str("</table>\n<h2>Admin changes</h2>\n<table>\n<tr><th>Time</th><th>Request</th><th>Settings</th></tr>\n");
+    for change in &report.admin_changes {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{} {}</td><td>{}</td></tr>",
+            change.at,
+            escape(&change.method),
+            escape(&change.path),
+            escape(&settings_text(change))
+        );
+    }
+    out.push_str("</table>\n</body></html>\n");
+    out
+}
+
+// Written when lowdown shuts down, so short runs keep their results without
+// scraping the admin API first. The file extension picks the format.
+pub fn write_summary(report: &Report, path: &Path) -> io::Result<()> {
+    let text = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("md" | "markdown") => render_markdown(report),
+        Some("html" | "htm") => render_html(report),
+        _ => serde_json::to_string_pretty(report).map_err(io::Error::other)?,
+    };
+    std::fs::write(path, text)
+}
+
+pub fn log_summary(report: &Report) {
+    info!(
+        "Fault injection summary: {} requests over {}s, {} with faults",
+        report.requests,
+        report.duration_seconds.unwrap_or_default(),
+        report.faulted_requests
+    );
+    let faults = fault_rows(report)
+        .into_iter()
+        .map(|(rule, fault, count)| format!("{rule}/{fault}={count}"))
+        .collect::<Vec<_>>();
+    if !faults.is_empty() {
+        info!("Faults by rule: {}", faults.join(", "));
+    }
+    let routes = report
+        .faulted_routes
+        .iter()
+        .map(|route| {
+            format!(
+                "{} ({} of {})",
+                route.route, route.faulted_requests, route.requests
+            )
+        })
+        .collect::<Vec<_>>();
+    if !routes.is_empty() {
+        info!("Most faulted routes: {}", routes.join(", "));
+    }
+}
+
+fn summary_rows(report: &Report) -> Vec<(&'static str, String)> {
+    let statuses = report
+        .statuses
+        .iter()
+        .map(|(status, count)| format!("{status}={count}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let latency = &report.latency_ms;
+    let budget = &report.error_budget;
+    let markers = report
+        .requests_by_deployment_marker
+        .iter()
+        .map(|(marker, count)| format!("{marker}={count}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut rows = vec![
+        ("Requests", report.requests.to_string()),
+        ("Requests by deployment marker", markers),
+        ("Faulted requests", report.faulted_requests.to_string()),
+        ("Statuses", statuses),
+        (
+            "Latency (ms)",
+            format!(
+                "p50 {} / p90 {} / p99 {} / max {}",
+                latency.p50, latency.p90, latency.p99, latency.max
+            ),
+        ),
+        (
+            "Error budget",
+            format!(
+                "{} errors of {:.1} allowed at {}% SLO ({:.1}% consumed)",
+                budget.errors, budget.allowed_errors, budget.slo, budget.consumed_percentage
+            ),
+        ),
+    ];
+    if let Some(seconds) = report.duration_seconds {
+        rows.insert(0, ("Duration", format!("{seconds}s")));
+    }
+    rows
+}
+
+fn fault_rows(report: &Report) -> Vec<(&str, &str, u64)> {
+    report
+        .faults_by_rule
+        .iter()
+        .flat_map(|(rule, faults)| {
+            faults
+                .iter()
+                .map(move |(fault, count)| (rule.as_str(), *fault, *count))
+        })
+        .collect()
+}
+
+fn settings_text(change: &AdminChange) -> String {
+    change
+        .settings
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,20 +1,23 @@
+use std::collections::BTreeMap;
+
 use axum::{
     body::Body,
-    http::{Response, StatusCode},
+    http::{HeaderMap, Response, StatusCode},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
-pub fn json_response<T: Serialize>(status: StatusCode, value: &T, trailer: &str) -> Response<Body> {
-    match serde_json::to_string(value) {
-        Ok(mut body) => {
-            body.push_str(trailer);
-            Response::builder()
-                .status(status)
-                .header("content-type", "application/json")
-                .body(Body::from(body))
-                .expect("building response")
-        }
+pub fn json_response<T: Serialize>(
+    status: StatusCode,
+    value: &T,
+    dev_mode: bool,
+) -> Response<Body> {
+    match to_json(value, dev_mode) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .expect("building response"),
         Err(err) => {
             error!("failed to serialize JSON response: {err}");
             Response::builder()
@@ -25,3 +28,67 @@ pub fn json_response<T: Serialize>(status: StatusCode, value: &T, trailer: &str)
         }
     }
 }
+
+pub fn to_json<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<String> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HeaderValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl HeaderValues {
+    fn push(&mut self, value: String) {
+        match self {
+            HeaderValues::One(first) => {
+                *self = HeaderValues::Many(vec![std::mem::take(first), value])
+            }
+            HeaderValues::Many(values) => values.push(value),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        match self {
+            HeaderValues::One(value) => std::slice::from_ref(value),
+            HeaderValues::Many(values) => values.as_slice(),
+        }
+        .iter()
+        .map(String::as_str)
+    }
+
+    pub fn replace_each(&mut self, replacement: &str) {
+        match self {
+            HeaderValues::One(value) => *value = replacement.to_string(),
+            HeaderValues::Many(values) => values.fill(replacement.to_string()),
+        }
+    }
+}
+
+pub fn header_map(headers: &HeaderMap) -> BTreeMap<String, HeaderValues> {
+    let mut map = BTreeMap::<String, HeaderValues>::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match map.get_mut(name.as_str()) {
+            Some(values) => values.push(value),
+            None => {
+                map.insert(name.as_str().to_string(), HeaderValues::One(value));
+            }
+        }
+    }
+    map
+}
+
+pub fn text_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(Body::from(body))
+        .expect("building response")
+}
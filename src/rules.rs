@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashMap};
+
+use http::Method;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+use crate::schema::settings_schema;
+use crate::settings::{MatchPattern, RequestContext, Settings, SettingsLayer, explain_match};
+
+const MATCH_PREFIX: &str = "match-";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RuleDocument {
+    name: Option<String>,
+    priority: Option<i64>,
+    #[serde(default)]
+    settings: BTreeMap<String, Value>,
+    #[serde(default, rename = "match")]
+    matchers: BTreeMap<String, Value>,
+    count: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub layer: SettingsLayer,
+    pub predicate: RequestPredicate,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NamedRule {
+    pub name: String,
+    pub priority: i64,
+    pub rule: Rule,
+    // Values the config file gave as `env://` or `file://` references, by
+    // their JSON pointer in `to_json`, shown as written instead of resolved.
+    pub references: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestPredicate {
+    headers: BTreeMap<String, Option<String>>,
+    body_contains: Option<String>,
+    body_regex: Option<Regex>,
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document: RuleDocument = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        if document.name.is_some() || document.priority.is_some() {
+            return Err("name and priority are only allowed in named rules".to_string());
+        }
+        Self::from_document(document)
+    }
+
+    fn from_document(document: RuleDocument) -> Result<Self, String> {
+        let mut layer = settings_layer(&document.settings)?;
+        let known = known_settings();
+        let mut set = |name: &str, value: &Value| set_value(&mut layer, &known, name, value);
+
+        let mut predicate = RequestPredicate::default();
+        for (name, value) in &document.matchers {
+            match name.as_str() {
+                "headers" => predicate.headers = parse_headers(value)?,
+                "body-contains" => predicate.body_contains = Some(string(name, value)?),
+                "body-regex" => {
+                    let pattern = string(name, value)?;
+                    let regex =
+                        Regex::new(&pattern).map_err(|err| format!("invalid body-regex: {err}"))?;
+                    predicate.body_regex = Some(regex);
+                }
+                _ => set(&format!("{MATCH_PREFIX}{name}"), value)?,
+            }
+        }
+
+        let count = document.count.unwrap_or(1);
+        if count == 0 {
+            return Err("count must be at least 1".to_string());
+        }
+        Ok(Self {
+            layer,
+            predicate,
+            count,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut settings = Map::new();
+        let mut matchers = Map::new();
+        for (name, value) in self.layer.entries() {
+            match name.strip_prefix(MATCH_PREFIX) {
+                Some(matcher) => matchers.insert(matcher.to_string(), value.into()),
+                None => settings.insert(name.to_string(), value.into()),
+            };
+        }
+        let predicate = &self.predicate;
+        if !predicate.headers.is_empty() {
+            let headers: Map<String, Value> = predicate
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.as_deref().unwrap_or("*").into()))
+                .collect();
+            matchers.insert("headers".to_string(), headers.into());
+        }
+        if let Some(text) = &predicate.body_contains {
+            matchers.insert("body-contains".to_string(), text.as_str().into());
+        }
+        if let Some(regex) = &predicate.body_regex {
+            matchers.insert("body-regex".to_string(), regex.as_str().into());
+        }
+        json!({"settings": settings, "match": matchers, "count": self.count})
+    }
+}
+
+impl NamedRule {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::from_value(serde_json::from_str(text).map_err(|err| err.to_string())?)
+    }
+
+    pub fn from_value(value: Value) -> Result<Self, String> {
+        let mut document: RuleDocument =
+            serde_json::from_value(value).map_err(|err| err.to_string())?;
+        let name = document.name.take().unwrap_or_default();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(format!(
+                "name must be non-empty and only use letters, digits, '-', '_' or '.', got {name:?}"
+            ));
+        }
+        if document.count.is_some() {
+            return Err("count is only allowed in one-off rules".to_string());
+        }
+        let priority = document.priority.take().unwrap_or(0);
+        Ok(Self {
+            name,
+            priority,
+            rule: Rule::from_document(document)?,
+            references: BTreeMap::new(),
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = self.rule.to_json();
+        if let Value::Object(fields) = &mut value {
+            fields.remove("count");
+            fields.insert("name".to_string(), self.name.as_str().into());
+            fields.insert("priority".to_string(), self.priority.into());
+        }
+        for (pointer, reference) in &self.references {
+            if let Some(field) = value.pointer_mut(pointer) {
+                *field = reference.as_str().into();
+            }
+        }
+        value
+    }
+}
+
+impl RequestPredicate {
+    pub fn needs_body(&self) -> bool {
+        self.body_contains.is_some() || self.body_regex.is_some()
+    }
+
+    pub fn matches(&self, ctx: &RequestContext, body: Option<&[u8]>) -> bool {
+        let headers =
+            self.headers
+                .iter()
+                .all(|(name, expected)| match (ctx.headers.get(name), expected) {
+                    (Some(actual), Some(expected)) => actual == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                });
+        if !headers {
+            return false;
+        }
+        if !self.needs_body() {
+            return true;
+        }
+        let Some(body) = body else {
+            return false;
+        };
+        let body = String::from_utf8_lossy(body);
+        self.body_contains
+            .as_deref()
+            .is_none_or(|text| body.contains(text))
+            && self
+                .body_regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&body))
+    }
+}
+
+pub fn settings_layer(settings: &BTreeMap<String, Value>) -> Result<SettingsLayer, String> {
+    let known = known_settings();
+    let mut layer = SettingsLayer::default();
+    for (name, value) in settings {
+        set_value(&mut layer, &known, name, value)?;
+    }
+    Ok(layer)
+}
+
+fn known_settings() -> Vec<String> {
+    settings_schema()
+        .into_iter()
+        .map(|setting| setting.name)
+        .collect()
+}
+
+fn set_value(
+    layer: &mut SettingsLayer,
+    known: &[String],
+    name: &str,
+    value: &Value,
+) -> Result<(), String> {
+    if !known.iter().any(|known| known == name) {
+        return Err(format!("unknown setting {name:?}"));
+    }
+    let scalar = |value: &Value| match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    };
+    let text = match value {
+        Value::Array(items) => items
+            .iter()
+            .map(scalar)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        _ => scalar(value),
+    }
+    .ok_or_else(|| format!("{name} must be a string, number, boolean or a list of them"))?;
+    layer.set(name, &text);
+    if layer.entries().iter().any(|(entry, _)| *entry == name) {
+        Ok(())
+    } else {
+        Err(format!("invalid value for {name}: {text:?}"))
+    }
+}
+
+fn string(name: &str, value: &Value) -> Result<String, String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format!("{name} must be a string"))
+}
+
+fn parse_headers(value: &Value) -> Result<BTreeMap<String, Option<String>>, String> {
+    let Value::Object(headers) = value else {
+        return Err("headers must be an object of header names to values".to_string());
+    };
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = string(&format!("header {name}"), value)?;
+            let expected = (value != "*").then_some(value);
+            Ok((name.to_ascii_lowercase(), expected))
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RuleAnalysis {
+    pub never_match: Vec<NeverMatch>,
+    pub shadowed: Vec<ShadowedRule>,
+    pub overlapping: Vec<OverlappingRule>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NeverMatch {
+    pub rule: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadowedRule {
+    pub rule: String,
+    pub by: String,
+}
+
+// `by` comes first and takes `uri`, and the two rules apply different
+// settings, so `rule` only applies to part of what it matches.
+#[derive(Debug, Serialize)]
+pub struct OverlappingRule {
+    pub rule: String,
+    pub by: String,
+    pub uri: String,
+}
+
+// Rules are given in evaluation order. A rule that can never match is not
+// considered as shadowing or overlapping later ones.
+pub fn analyze(rules: &[&NamedRule]) -> RuleAnalysis {
+    let mut analysis = RuleAnalysis::default();
+    let mut live = Vec::new();
+    for rule in rules {
+        match contradiction(&rule.rule) {
+            Some(reason) => analysis.never_match.push(NeverMatch {
+                rule: rule.name.clone(),
+                reason,
+            }),
+            None => live.push(*rule),
+        }
+    }
+    let matchers: Vec<Matchers> = live.iter().map(|rule| Matchers::new(&rule.rule)).collect();
+    let mut applies: Vec<usize> = Vec::new();
+    for (index, rule) in live.iter().enumerate() {
+        if let Some(by) = (0..index).find(|&earlier| matchers[earlier].covers(&matchers[index])) {
+            analysis.shadowed.push(ShadowedRule {
+                rule: rule.name.clone(),
+                by: live[by].name.clone(),
+            });
+            continue;
+        }
+        for &earlier in &applies {
+            if matchers[earlier].settings != matchers[index].settings
+                && let Some(uri) = matchers[earlier].overlap(&matchers[index])
+            {
+                analysis.overlapping.push(OverlappingRule {
+                    rule: rule.name.clone(),
+                    by: live[earlier].name.clone(),
+                    uri,
+                });
+            }
+        }
+        applies.push(index);
+    }
+    analysis
+}
+
+// An exact match-uri is the only URI the rule can match, so the other URI
+// matchers are run against it with the regular matcher.
+fn contradiction(rule: &Rule) -> Option<String> {
+    let mut settings = Settings::default();
+    settings.apply_layer(&rule.layer);
+    let patterns = [
+        ("match-uri", &settings.match_uri),
+        ("match-uri-regex", &settings.match_uri_regex),
+        ("match-uri-starts-with", &settings.match_uri_starts_with),
+        ("match-method", &settings.match_method),
+        ("match-host", &settings.match_host),
+        ("match-header-name", &settings.match_header_name),
+        ("match-header-value", &settings.match_header_value),
+        ("match-deployment-marker", &settings.match_deployment_marker),
+    ];
+    if let Some((name, pattern)) = patterns
+        .iter()
+        .find(|(_, pattern)| matches!(pattern, MatchPattern::Invalid(_)))
+    {
+        return Some(format!(
+            "{name} {:?} is not a valid pattern",
+            pattern.as_str()
+        ));
+    }
+    let MatchPattern::Exact(uri) = &settings.match_uri else {
+        return None;
+    };
+    let ctx = RequestContext::new(Method::GET, uri.clone(), HashMap::new());
+    explain_match(&ctx, &settings)
+        .into_iter()
+        .find(|(name, matched)| name.starts_with("match-uri-") && !matched)
+        .map(|(name, _)| format!("match-uri {uri:?} never satisfies {name}"))
+}
+
+const URI_MATCHERS: [&str; 3] = ["uri", "uri-regex", "uri-starts-with"];
+
+// The URI matchers are compared through the matcher itself, so a prefix
+// contains longer prefixes and a regex covers the exact URIs it accepts. The
+// other matchers are compared by value.
+struct Matchers {
+    uri: Settings,
+    others: Value,
+    settings: Value,
+}
+
+impl Matchers {
+    fn new(rule: &Rule) -> Self {
+        let mut json = rule.to_json();
+        let mut others = json["match"].take();
+        if let Some(others) = others.as_object_mut() {
+            for name in URI_MATCHERS {
+                others.remove(name);
+            }
+        }
+        let mut uri = Settings::default();
+        uri.apply_layer(&rule.layer);
+        Self {
+            uri,
+            others,
+            settings: json["settings"].take(),
+        }
+    }
+
+    // A rule is shadowed when an earlier one accepts every request it could
+    // match, so the earlier rule always wins.
+    fn covers(&self, specific: &Matchers) -> bool {
+        covers(&self.others, &specific.others) && self.covers_uris(&specific.uri)
+    }
+
+    // Regexes are only compared against exact URIs; any other pair involving
+    // one counts as not covered.
+    fn covers_uris(&self, specific: &Settings) -> bool {
+        if let MatchPattern::Exact(uri) = &specific.match_uri {
+            return accepts_uri(&self.uri, uri);
+        }
+        uri_patterns(&self.uri)
+            .into_iter()
+            .all(|pattern| match pattern {
+                MatchPattern::Any => true,
+                MatchPattern::Prefix(prefix) => matches!(
+                    &specific.match_uri_starts_with,
+                    MatchPattern::Prefix(other) if other.starts_with(prefix.as_str())
+                ),
+                MatchPattern::Regex(regex) => matches!(
+                    &specific.match_uri_regex,
+                    MatchPattern::Regex(other) if other.as_str() == regex.as_str()
+                ),
+                _ => false,
+            })
+    }
+
+    // A URI both rules accept, looked for among the exact URIs and prefixes
+    // they name, when nothing else tells their requests apart.
+    fn overlap(&self, other: &Matchers) -> Option<String> {
+        if !compatible(&self.others, &other.others) {
+            return None;
+        }
+        uri_patterns(&self.uri)
+            .into_iter()
+            .chain(uri_patterns(&other.uri))
+            .filter_map(|pattern| match pattern {
+                MatchPattern::Exact(uri) | MatchPattern::Prefix(uri) => Some(uri.as_str()),
+                _ => None,
+            })
+            .chain(["/"])
+            .find(|uri| accepts_uri(&self.uri, uri) && accepts_uri(&other.uri, uri))
+            .map(str::to_string)
+    }
+}
+
+fn uri_patterns(settings: &Settings) -> [&MatchPattern; 3] {
+    [
+        &settings.match_uri,
+        &settings.match_uri_regex,
+        &settings.match_uri_starts_with,
+    ]
+}
+
+fn accepts_uri(settings: &Settings, uri: &str) -> bool {
+    uri_patterns(settings)
+        .into_iter()
+        .all(|pattern| pattern.matches(Some(uri)))
+}
+
+// Two different values for the same matcher rule out a request matching both,
+// even where both could hold, e.g. two body-contains strings.
+fn compatible(first: &Value, second: &Value) -> bool {
+    let (Some(first), Some(second)) = (first.as_object(), second.as_object()) else {
+        return false;
+    };
+    let agree = |first: &Value, second: &Value| first == "*" || second == "*" || first == second;
+    first
+        .iter()
+        .all(|(name, value)| match (value, second.get(name)) {
+            (_, None) => true,
+            (Value::Object(headers), Some(Value::Object(other))) => {
+                headers.iter().all(|(header, expected)| {
+                    other
+                        .get(header)
+                        .is_none_or(|actual| agree(expected, actual))
+                })
+            }
+            (value, Some(other)) => agree(value, other),
+        })
+}
+
+fn covers(general: &Value, specific: &Value) -> bool {
+    let (Some(general), Some(specific)) = (general.as_object(), specific.as_object()) else {
+        return false;
+    };
+    general
+        .iter()
+        .all(|(name, value)| match (name.as_str(), value) {
+            (_, Value::String(text)) if text == "*" => true,
+            ("headers", Value::Object(headers)) => headers.iter().all(|(header, expected)| {
+                let actual = specific
+                    .get("headers")
+                    .and_then(|headers| headers.get(header));
+                match actual {
+                    Some(_) if expected == "*" => true,
+                    Some(actual) => actual == expected,
+                    None => false,
+                }
+            }),
+            _ => specific.get(name) == Some(value),
+        })
+}
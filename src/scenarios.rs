@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use jiff::{SignedDuration, Timestamp};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::rules::settings_layer;
+use crate::settings::SettingsLayer;
+use crate::state::AppState;
+use crate::tasks::{CancelError, TaskStatus};
+
+pub const MAX_PHASE_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ScenarioSpec {
+    pub name: Option<String>,
+    pub phases: Vec<PhaseSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PhaseSpec {
+    pub name: Option<String>,
+    pub duration_seconds: u64,
+    #[serde(default)]
+    pub settings: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Phase {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    duration_seconds: u64,
+    settings: BTreeMap<&'static str, String>,
+    #[serde(skip)]
+    layer: SettingsLayer,
+}
+
+struct Run {
+    name: Option<String>,
+    task: Uuid,
+    phases: Vec<Phase>,
+    current: usize,
+    phase_started_at: Timestamp,
+}
+
+pub enum StartError {
+    Invalid(String),
+    Running,
+}
+
+#[derive(Default)]
+pub struct ScenarioRunner {
+    run: Arc<Mutex<Option<Run>>>,
+}
+
+pub fn parse(body: &str) -> Result<ScenarioSpec, String> {
+    serde_json::from_str(body).map_err(|err| err.to_string())
+}
+
+struct RestoreAdmin {
+    state: Arc<AppState>,
+    layer: SettingsLayer,
+}
+
+impl Drop for RestoreAdmin {
+    fn drop(&mut self) {
+        info!("Scenario ended, restoring admin settings");
+        self.state.reset_admin(std::mem::take(&mut self.layer));
+    }
+}
+
+impl ScenarioRunner {
+    // Each phase replaces the admin settings with the ones from before the
+    // scenario plus its own, so phases don't leak into each other. A phase
+    // without settings is a recovery period.
+    pub fn start(&self, state: &Arc<AppState>, spec: ScenarioSpec) -> Result<Value, StartError> {
+        if spec.phases.is_empty() {
+            return Err(StartError::Invalid(
+                "a scenario needs at least one phase".to_string(),
+            ));
+        }
+        let mut phases = Vec::with_capacity(spec.phases.len());
+        for (index, phase) in spec.phases.into_iter().enumerate() {
+            if phase.duration_seconds == 0 {
+                return Err(StartError::Invalid(format!(
+                    "phases[{index}]: duration-seconds must be at least 1"
+                )));
+            }
+            if phase.duration_seconds > MAX_PHASE_SECONDS {
+                return Err(StartError::Invalid(format!(
+                    "phases[{index}]: duration-seconds must be at most {MAX_PHASE_SECONDS}"
+                )));
+            }
+            let layer = settings_layer(&phase.settings)
+                .map_err(|err| StartError::Invalid(format!("phases[{index}]: {err}")))?;
+            phases.push(Phase {
+                name: phase.name,
+                duration_seconds: phase.duration_seconds,
+                settings: layer.entries().into_iter().collect(),
+                layer,
+            });
+        }
+
+        let mut guard = self.run.lock();
+        if guard
+            .as_ref()
+            .and_then(|run| state.tasks().get(run.task))
+            .is_some_and(|task| task.status == TaskStatus::Running)
+        {
+            return Err(StartError::Running);
+        }
+        let restore = RestoreAdmin {
+            state: state.clone(),
+            layer: state.admin_layer(),
+        };
+        let total = phases.iter().fold(0u64, |total, phase| {
+            total.saturating_add(phase.duration_seconds)
+        });
+        let description = match &spec.name {
+            Some(name) => format!("{name}: {} phases over {total}s", phases.len()),
+            None => format!("{} phases over {total}s", phases.len()),
+        };
+        let run = self.run.clone();
+        let steps = phases.clone();
+        let clock = state.clock();
+        let task = state
+            .tasks()
+            .spawn("scenario", description, move |progress| async move {
+                let mut elapsed: u64 = 0;
+                for (index, phase) in steps.into_iter().enumerate() {
+                    if let Some(run) = run.lock().as_mut() {
+                        run.current = index;
+                        run.phase_started_at = clock.timestamp();
+                    }
+                    let mut layer = restore.layer.clone();
+                    layer.merge(&phase.layer);
+                    restore.state.reset_admin(layer);
+                    info!(
+                        "Scenario phase {index} ({}) for {}s",
+                        phase.name.as_deref().unwrap_or("unnamed"),
+                        phase.duration_seconds
+                    );
+                    progress.set((elapsed.saturating_mul(100) / total).min(100) as u8);
+                    clock
+                        .sleep(Duration::from_secs(phase.duration_seconds))
+                        .await;
+                    elapsed = elapsed.saturating_add(phase.duration_seconds);
+                }
+                drop(restore);
+                Ok(())
+            });
+        *guard = Some(Run {
+            name: spec.name,
+            task,
+            phases,
+            current: 0,
+            phase_started_at: state.clock().timestamp(),
+        });
+        Ok(status_of(guard.as_ref(), state))
+    }
+
+    pub fn status(&self, state: &AppState) -> Value {
+        status_of(self.run.lock().as_ref(), state)
+    }
+
+    pub fn cancel(&self, state: &AppState) -> Result<Value, CancelError> {
+        let guard = self.run.lock();
+        let run = guard.as_ref().ok_or(CancelError::NotFound)?;
+        state.tasks().cancel(run.task)?;
+        Ok(status_of(Some(run), state))
+    }
+}
+
+fn status_of(run: Option<&Run>, state: &AppState) -> Value {
+    let Some(run) = run else {
+        return json!({"status":"idle"});
+    };
+    let status = state
+        .tasks()
+        .get(run.task)
+        .map_or(TaskStatus::Completed, |task| task.status);
+    let mut value = json!({
+        "name": run.name,
+        "status": status,
+        "task": run.task,
+        "phases": run.phases,
+    });
+    if status == TaskStatus::Running
+        && let Some(phase) = run.phases.get(run.current)
+    {
+        let seconds = i64::try_from(phase.duration_seconds).unwrap_or(i64::MAX);
+        value["phase"] = json!({
+            "index": run.current,
+            "name": phase.name,
+            "started-at": run.phase_started_at,
+            "ends-at": run
+                .phase_started_at
+                .saturating_add(SignedDuration::from_secs(seconds))
+                .ok(),
+        });
+    }
+    value
+}
@@ -0,0 +1,458 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::settings::{HEADER_PREFIX, Settings};
+
+// Settings that are still accepted but have a replacement, as (name, replacement).
+pub const DEPRECATED_SETTINGS: &[(&str, &str)] = &[];
+
+const DESCRIPTIONS: &[(&str, &str)] = &[
+    (
+        "add-forwarded-by-header",
+        "Add an x-forwarded-by header naming lowdown to upstream requests",
+    ),
+    (
+        "add-via-header",
+        "Append a Via header naming lowdown to upstream requests",
+    ),
+    (
+        "corrupt-response-mode",
+        "How corrupt-response mangles the body: truncate, bit-flip or empty-body",
+    ),
+    (
+        "corrupt-response-percentage",
+        "Chance that the response body is corrupted according to corrupt-response-mode",
+    ),
+    (
+        "corrupt-trailers-percentage",
+        "Chance that response trailers are replaced with random values",
+    ),
+    (
+        "corrupt-validators-percentage",
+        "Chance that ETag and Last-Modified response headers are replaced with wrong values",
+    ),
+    (
+        "delay-after-jitter-ms",
+        "Random spread added to or taken from delay-after-ms, in milliseconds",
+    ),
+    (
+        "degrade-socket-buffer-bytes",
+        "Send and receive buffer size of a degraded client connection, 0 to keep the system default",
+    ),
+    (
+        "degrade-socket-percentage",
+        "Chance that the client connection gets TCP_NODELAY turned off and its buffers shrunk",
+    ),
+    (
+        "delay-after-ms",
+        "Delay added after the backend responded, in milliseconds",
+    ),
+    (
+        "delay-after-percentage",
+        "Chance that delay-after-ms is applied",
+    ),
+    (
+        "delay-before-jitter-ms",
+        "Random spread added to or taken from delay-before-ms, in milliseconds",
+    ),
+    (
+        "delay-before-ms",
+        "Delay added before calling the backend, in milliseconds",
+    ),
+    (
+        "delay-before-percentage",
+        "Chance that delay-before-ms is applied",
+    ),
+    ("destination-url", "Backend URL requests are forwarded to"),
+    (
+        "destination-urls",
+        "Comma-separated pool of backend URLs, optionally weighted as weight:url, replacing destination-url",
+    ),
+    (
+        "dns-delay-ms",
+        "Delay added to the backend host name lookup, in milliseconds",
+    ),
+    (
+        "dns-delay-percentage",
+        "Chance that dns-delay-ms is applied",
+    ),
+    (
+        "drop-hold-ms",
+        "How long a dropped request's connection is held open before it is closed",
+    ),
+    (
+        "drop-percentage",
+        "Chance that the request is never forwarded nor answered",
+    ),
+    (
+        "drop-trailers-percentage",
+        "Chance that response trailers are dropped",
+    ),
+    (
+        "duplicate-destination-url",
+        "Backend URL duplicates are sent to instead of the primary backend",
+    ),
+    (
+        "duplicate-percentage",
+        "Chance that the request is sent to the backend twice",
+    ),
+    (
+        "fail-after-code",
+        "Status code returned instead of the backend's response",
+    ),
+    (
+        "fail-after-percentage",
+        "Chance that the backend's response is replaced with fail-after-code",
+    ),
+    (
+        "fail-before-code",
+        "Status code returned without calling the backend",
+    ),
+    (
+        "fail-before-every-nth",
+        "Fail every nth matching request before calling the backend, replacing fail-before-percentage; 0 disables it",
+    ),
+    (
+        "fail-before-mode",
+        "Shape of the fail-before response: json-error, empty-body, headers-only, connection-close, html-error-page or malformed-json",
+    ),
+    (
+        "fail-before-percentage",
+        "Chance that fail-before-code is returned without calling the backend",
+    ),
+    (
+        "fault-bodiless-responses",
+        "Apply stream and trailer faults to HEAD, 1xx, 204 and 304 responses and keep their bodies",
+    ),
+    (
+        "fault-set",
+        "Weighted list of mutually exclusive faults, e.g. 30:delay-before=2000;10:fail-before=503",
+    ),
+    (
+        "force-new-connection-percentage",
+        "Chance that the backend call bypasses the connection pool",
+    ),
+    (
+        "force-not-modified-percentage",
+        "Chance that a successful response to a non-conditional request becomes an empty 304",
+    ),
+    (
+        "http3-fallback-percentage",
+        "Chance that a request configured for HTTP/3 falls back to HTTP/2",
+    ),
+    (
+        "interim-response-code",
+        "1xx status of the injected interim response, e.g. 103 Early Hints",
+    ),
+    (
+        "interim-response-percentage",
+        "Chance that an interim 1xx response is sent to the client before the final one",
+    ),
+    (
+        "match-days",
+        "Days faults apply on, e.g. mon-fri, or * for every day",
+    ),
+    (
+        "match-deployment-marker",
+        "Deployment marker faults apply to, or * for any",
+    ),
+    (
+        "match-header-name",
+        "Request header name that must equal match-header-value, or * for any",
+    ),
+    (
+        "match-header-value",
+        "Value of match-header-name that requests must carry, or * for any",
+    ),
+    (
+        "match-host",
+        "Destination host faults apply to, or * for any",
+    ),
+    ("match-method", "HTTP method faults apply to, or * for any"),
+    (
+        "match-session-request-index",
+        "Only inject faults on the Nth request (1-based) of each session, 0 for any",
+    ),
+    (
+        "match-time-range",
+        "Wall-clock window HH:MM-HH:MM faults apply in, or * for all day",
+    ),
+    (
+        "match-timezone",
+        "IANA time zone for match-time-range and match-days, the system zone if unset",
+    ),
+    (
+        "match-uri",
+        "Exact request path faults apply to, or * for any",
+    ),
+    (
+        "match-uri-regex",
+        "Regex the whole request path must match, or * for any",
+    ),
+    (
+        "match-uri-starts-with",
+        "Request path prefix faults apply to, or * for any",
+    ),
+    (
+        "max-triggered-per-second",
+        "Most faults each rule injects per second, 0 for no limit",
+    ),
+    (
+        "min-response-time-ms",
+        "Floor on the backend response time; faster responses are padded",
+    ),
+    (
+        "mutate-request-delta",
+        "Amount the delta mode adds to numeric request body fields, may be negative",
+    ),
+    (
+        "mutate-request-mode",
+        "How mutate-request changes the selected fields: null, remove, type-flip, randomize or delta",
+    ),
+    (
+        "mutate-request-path",
+        "JSONPath of the request body fields mutate-request changes, e.g. $.user.email",
+    ),
+    (
+        "mutate-request-percentage",
+        "Chance that JSON fields of the request body are mutated before it is forwarded",
+    ),
+    (
+        "mutate-response-delta",
+        "Amount the delta mode adds to numeric response body fields, may be negative",
+    ),
+    (
+        "mutate-response-mode",
+        "How mutate-response changes the selected fields: null, remove, type-flip, randomize or delta",
+    ),
+    (
+        "mutate-response-path",
+        "JSONPath of the response body fields mutate-response changes, e.g. $.items[0].price",
+    ),
+    (
+        "mutate-response-percentage",
+        "Chance that JSON fields of the backend's response body are mutated",
+    ),
+    (
+        "override-status-codes",
+        "Comma-separated status codes (100-999) override-status picks from",
+    ),
+    (
+        "override-status-percentage",
+        "Chance that the backend's status code is replaced, keeping its headers and body",
+    ),
+    (
+        "pause-faults-when-unhealthy",
+        "Skip faults while the destination is unhealthy",
+    ),
+    (
+        "preflight-mode",
+        "How CORS preflights are handled: pass-through, synthetic or fault",
+    ),
+    (
+        "request-buffering",
+        "Read the whole request body before calling the backend",
+    ),
+    (
+        "reset-connection-percentage",
+        "Chance that the client connection is closed with a TCP reset instead of a response",
+    ),
+    (
+        "response-tee-path",
+        "Path template under LOWDOWN_RESPONSE_TEE_DIR to write each upstream response body to",
+    ),
+    (
+        "retry-attempts",
+        "Times a matched request is sent again after a 5xx or failed backend call",
+    ),
+    (
+        "retry-backoff-ms",
+        "Wait before the first retry, doubled for each further one, in milliseconds",
+    ),
+    (
+        "session-key",
+        "Key template identifying a session, e.g. {cookie:session} or {header:x-session-id}",
+    ),
+    (
+        "session-window-ms",
+        "How long a session's request count is kept, 0 for forever",
+    ),
+    (
+        "shadow-fail-before",
+        "Still send requests blocked by fail-before to the backend in the background",
+    ),
+    (
+        "sticky-key",
+        "Key template pinning requests to one backend of destination-urls",
+    ),
+    (
+        "stream-abort-at-percent",
+        "Abort the response body after this share of it was sent",
+    ),
+    (
+        "stream-expiry-mode",
+        "What happens to a faulted streaming response when the settings change: continue, stop-faults or terminate",
+    ),
+    (
+        "stream-fault-percentage",
+        "Chance that the stream-* faults are applied to the response body",
+    ),
+    (
+        "stream-garbage-after-bytes",
+        "Response body bytes sent before garbage is injected",
+    ),
+    (
+        "stream-garbage-bytes",
+        "Number of random bytes injected into the response body",
+    ),
+    (
+        "stream-loss-burst-percentage",
+        "Chance that a packet after a lost one is lost too",
+    ),
+    (
+        "stream-loss-packet-bytes",
+        "Packet size used for stream-loss-percentage, at least 64 bytes",
+    ),
+    (
+        "stream-loss-percentage",
+        "Chance that each response body packet is lost and stalls",
+    ),
+    (
+        "stream-loss-stall-ms",
+        "Stall per lost packet, in milliseconds",
+    ),
+    (
+        "stream-stall-after-bytes",
+        "Response body bytes sent before the stall",
+    ),
+    (
+        "stream-stall-ms",
+        "Stall in the middle of the response body, in milliseconds",
+    ),
+    (
+        "strict-passthrough",
+        "Forward upstream responses without decompressing or reframing them when no fault applies",
+    ),
+    (
+        "strip-content-encoding-percentage",
+        "Chance that the Content-Encoding header of an encoded request body is removed before forwarding",
+    ),
+    (
+        "strip-validators-percentage",
+        "Chance that ETag and Last-Modified response headers are removed",
+    ),
+    (
+        "throttle-bytes-per-second",
+        "Rate a throttled response body is sent to the client at",
+    ),
+    (
+        "throttle-percentage",
+        "Chance that the response body is sent at throttle-bytes-per-second",
+    ),
+    (
+        "tls-server-name",
+        "Server name sent in the TLS handshake (SNI) and verified instead of the destination's host",
+    ),
+    (
+        "tls-skip-verify",
+        "Accept any backend certificate, including expired, self-signed or mismatched ones",
+    ),
+    (
+        "transform",
+        "JSON list of steps (set-header, remove-header, replace-body-regex, set-status, delay) run in order on matching requests and responses",
+    ),
+    (
+        "trigger-first-per-key",
+        "Key template; faults only apply to the first request per key",
+    ),
+    (
+        "trigger-first-window-ms",
+        "How long a trigger-first-per-key key stays warm, 0 for forever",
+    ),
+    (
+        "upstream-protocol",
+        "HTTP version used to reach the backend: auto, http1, http2 or http3",
+    ),
+    (
+        "user-agent",
+        "User-Agent sent upstream instead of the client's, unset to forward it as is",
+    ),
+];
+
+#[derive(Debug, Serialize)]
+pub struct SettingSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub nullable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<u64>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub values: Option<&'static [&'static str]>,
+    pub default: Value,
+    pub description: &'static str,
+    pub header: String,
+    pub env: String,
+}
+
+pub fn settings_schema() -> Vec<SettingSchema> {
+    let Ok(Value::Object(defaults)) = serde_json::to_value(Settings::default()) else {
+        return Vec::new();
+    };
+    defaults
+        .into_iter()
+        .map(|(name, default)| {
+            let kind = match &default {
+                Value::Bool(_) => "boolean",
+                Value::Number(_) => "integer",
+                _ => "string",
+            };
+            let (minimum, maximum) = match kind {
+                "integer" if name.ends_with("-percentage") || name.ends_with("-percent") => {
+                    (Some(0), Some(100))
+                }
+                "integer" if name.ends_with("-code") => (Some(100), Some(999)),
+                "integer" if name.ends_with("-delta") => (None, None),
+                "integer" => (Some(0), None),
+                _ => (None, None),
+            };
+            SettingSchema {
+                kind,
+                nullable: default.is_null(),
+                minimum,
+                maximum,
+                values: match name.as_str() {
+                    "corrupt-response-mode" => Some(&["truncate", "bit-flip", "empty-body"][..]),
+                    "fail-before-mode" => Some(
+                        &[
+                            "json-error",
+                            "empty-body",
+                            "headers-only",
+                            "connection-close",
+                            "html-error-page",
+                            "malformed-json",
+                        ][..],
+                    ),
+                    "mutate-request-mode" | "mutate-response-mode" => {
+                        Some(&["null", "remove", "type-flip", "randomize", "delta"][..])
+                    }
+                    "preflight-mode" => Some(&["pass-through", "synthetic", "fault"][..]),
+                    "stream-expiry-mode" => Some(&["continue", "stop-faults", "terminate"][..]),
+                    "upstream-protocol" => Some(&["auto", "http1", "http2", "http3"][..]),
+                    _ => None,
+                },
+                description: DESCRIPTIONS
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, description)| *description)
+                    .unwrap_or_default(),
+                header: format!("{HEADER_PREFIX}{name}"),
+                env: name.replace('-', "_").to_ascii_uppercase(),
+                default,
+                name,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http::StatusCode;
+use rand::Rng;
+use regex::Regex;
+use serde::{Serialize, Serializer};
+use tracing::warn;
+
+use crate::probability::ProbabilityExpression;
+use crate::random;
+use crate::settings::RequestContext;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct Percentage(u8);
+
+impl Percentage {
+    pub const ZERO: Percentage = Percentage(0);
+    pub const ALWAYS: Percentage = Percentage(100);
+
+    pub fn new(value: u8) -> Option<Self> {
+        (value <= 100).then_some(Self(value))
+    }
+
+    pub fn saturating(value: u64) -> Self {
+        Self(value.min(100) as u8)
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn roll(self) -> bool {
+        self.0 > random::rng().gen_range(0..100)
+    }
+}
+
+impl FromStr for Percentage {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.trim()
+            .parse()
+            .ok()
+            .and_then(Self::new)
+            .ok_or_else(|| format!("{text:?} is not a percentage between 0 and 100"))
+    }
+}
+
+impl fmt::Display for Percentage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probability {
+    Fixed(Percentage),
+    Expression(Arc<ProbabilityExpression>),
+}
+
+impl Probability {
+    pub const ZERO: Probability = Probability::Fixed(Percentage::ZERO);
+    pub const ALWAYS: Probability = Probability::Fixed(Percentage::ALWAYS);
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    pub fn evaluate(&self, ctx: &RequestContext) -> Percentage {
+        match self {
+            Probability::Fixed(percentage) => *percentage,
+            Probability::Expression(expression) => expression.evaluate(ctx),
+        }
+    }
+}
+
+impl Default for Probability {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<Percentage> for Probability {
+    fn from(percentage: Percentage) -> Self {
+        Probability::Fixed(percentage)
+    }
+}
+
+impl FromStr for Probability {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.trim_start().starts_with(|c: char| c.is_ascii_digit()) {
+            return text.parse().map(Probability::Fixed);
+        }
+        ProbabilityExpression::parse(text)
+            .map(|expression| Probability::Expression(Arc::new(expression)))
+            .map_err(|err| format!("{text:?} is not a percentage or expression: {err}"))
+    }
+}
+
+impl fmt::Display for Probability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probability::Fixed(percentage) => percentage.fmt(f),
+            Probability::Expression(expression) => expression.fmt(f),
+        }
+    }
+}
+
+impl Serialize for Probability {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Probability::Fixed(percentage) => percentage.serialize(serializer),
+            Probability::Expression(expression) => serializer.collect_str(expression),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusCodeSetting(StatusCode);
+
+impl StatusCodeSetting {
+    pub fn new(code: u16) -> Option<Self> {
+        StatusCode::from_u16(code).ok().map(Self)
+    }
+
+    pub fn status(self) -> StatusCode {
+        self.0
+    }
+
+    pub fn as_u16(self) -> u16 {
+        self.0.as_u16()
+    }
+}
+
+impl From<StatusCode> for StatusCodeSetting {
+    fn from(status: StatusCode) -> Self {
+        Self(status)
+    }
+}
+
+impl FromStr for StatusCodeSetting {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.trim()
+            .parse()
+            .ok()
+            .and_then(Self::new)
+            .ok_or_else(|| format!("{text:?} is not a status code between 100 and 999"))
+    }
+}
+
+impl fmt::Display for StatusCodeSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_u16().fmt(f)
+    }
+}
+
+impl Serialize for StatusCodeSetting {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.as_u16())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusCodes(Vec<StatusCodeSetting>);
+
+impl StatusCodes {
+    pub fn pick(&self) -> StatusCode {
+        let idx = random::rng().gen_range(0..self.0.len());
+        self.0[idx].status()
+    }
+}
+
+impl From<StatusCode> for StatusCodes {
+    fn from(status: StatusCode) -> Self {
+        Self(vec![status.into()])
+    }
+}
+
+impl FromStr for StatusCodes {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let codes = text
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<StatusCodeSetting>, _>>()?;
+        if codes.is_empty() {
+            return Err(format!("{text:?} has no status codes"));
+        }
+        Ok(Self(codes))
+    }
+}
+
+impl fmt::Display for StatusCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, code) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            code.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for StatusCodes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(transparent)]
+pub struct Millis(u64);
+
+impl Millis {
+    pub const fn new(ms: u64) -> Self {
+        Self(ms)
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+
+    // Picked uniformly from self ± jitter, never below zero.
+    pub fn jittered(self, jitter: Millis) -> Millis {
+        if jitter.is_zero() {
+            return self;
+        }
+        let low = self.0.saturating_sub(jitter.0);
+        let high = self.0.saturating_add(jitter.0);
+        Millis(random::rng().gen_range(low..=high))
+    }
+}
+
+impl FromStr for Millis {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.trim()
+            .parse()
+            .map(Self)
+            .map_err(|_| format!("{text:?} is not a number of milliseconds"))
+    }
+}
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum MatchPattern {
+    #[default]
+    Any,
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+    Invalid(String),
+}
+
+impl MatchPattern {
+    pub fn exact(text: &str) -> Self {
+        Self::parse(text, |text| Self::Exact(text.to_string()))
+    }
+
+    pub fn exact_uppercase(text: &str) -> Self {
+        Self::parse(text, |text| Self::Exact(text.to_ascii_uppercase()))
+    }
+
+    pub fn exact_lowercase(text: &str) -> Self {
+        Self::parse(text, |text| Self::Exact(text.to_ascii_lowercase()))
+    }
+
+    pub fn prefix(text: &str) -> Self {
+        Self::parse(text, |text| Self::Prefix(text.to_string()))
+    }
+
+    pub fn regex(text: &str) -> Self {
+        Self::parse(text, |text| match Regex::new(text) {
+            Ok(regex) => Self::Regex(regex),
+            Err(err) => {
+                warn!("Invalid regex pattern {text:?}: {err}");
+                Self::Invalid(text.to_string())
+            }
+        })
+    }
+
+    fn parse(text: &str, pattern: impl FnOnce(&str) -> Self) -> Self {
+        if text == "*" {
+            Self::Any
+        } else {
+            pattern(text)
+        }
+    }
+
+    pub fn is_any(&self) -> bool {
+        matches!(self, Self::Any)
+    }
+
+    pub fn matches(&self, subject: Option<&str>) -> bool {
+        let Some(subject) = subject else {
+            return self.is_any();
+        };
+        match self {
+            Self::Any => true,
+            Self::Exact(expected) => expected == subject,
+            Self::Prefix(prefix) => subject.starts_with(prefix.as_str()),
+            Self::Regex(regex) => regex
+                .find(subject)
+                .is_some_and(|m| m.start() == 0 && m.end() == subject.len()),
+            Self::Invalid(_) => false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Any => "*",
+            Self::Exact(text) | Self::Prefix(text) | Self::Invalid(text) => text,
+            Self::Regex(regex) => regex.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for MatchPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for MatchPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
@@ -1,46 +1,218 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-use http::{HeaderMap, Method, Uri};
-use regex::Regex;
+use http::{HeaderMap, Method, StatusCode, Uri};
+use jiff::{civil::Time, civil::Weekday, tz::TimeZone};
 use serde::Serialize;
 use tracing::warn;
 
+use crate::browser::PreflightMode;
+use crate::clock;
+use crate::corruption::CorruptResponseMode;
+use crate::failure::FailureMode;
+use crate::http_client::UpstreamProtocol;
+use crate::mutation::MutationMode;
+pub use crate::setting_types::{
+    MatchPattern, Millis, Percentage, Probability, StatusCodeSetting, StatusCodes,
+};
+use crate::streams::StreamExpiryMode;
+use crate::transform::Pipeline;
+
 pub const HEADER_PREFIX: &str = "x-lowdown-";
+pub const MAX_STREAM_GARBAGE_BYTES: u64 = 4 * 1024 * 1024;
+pub const MIN_STREAM_LOSS_PACKET_BYTES: u64 = 64;
+pub const MIKKMOKK_HEADER_PREFIX: &str = "x-mikkmokk-";
+pub const PROFILE_HEADER: &str = "x-lowdown-profile";
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Settings {
     #[serde(rename = "fail-before-code")]
-    pub fail_before_code: u16,
+    pub fail_before_code: StatusCodeSetting,
     #[serde(rename = "fail-before-percentage")]
-    pub fail_before_percentage: u8,
+    pub fail_before_percentage: Probability,
     #[serde(rename = "fail-after-percentage")]
-    pub fail_after_percentage: u8,
+    pub fail_after_percentage: Probability,
     #[serde(rename = "fail-after-code")]
-    pub fail_after_code: u16,
+    pub fail_after_code: StatusCodeSetting,
     #[serde(rename = "duplicate-percentage")]
-    pub duplicate_percentage: u8,
+    pub duplicate_percentage: Probability,
     #[serde(rename = "delay-before-percentage")]
-    pub delay_before_percentage: u8,
+    pub delay_before_percentage: Probability,
     #[serde(rename = "delay-before-ms")]
-    pub delay_before_ms: u64,
+    pub delay_before_ms: Millis,
     #[serde(rename = "delay-after-percentage")]
-    pub delay_after_percentage: u8,
+    pub delay_after_percentage: Probability,
     #[serde(rename = "delay-after-ms")]
-    pub delay_after_ms: u64,
+    pub delay_after_ms: Millis,
+    #[serde(rename = "stream-fault-percentage")]
+    pub stream_fault_percentage: Probability,
+    #[serde(rename = "stream-stall-after-bytes")]
+    pub stream_stall_after_bytes: u64,
+    #[serde(rename = "stream-stall-ms")]
+    pub stream_stall_ms: Millis,
+    #[serde(rename = "stream-garbage-after-bytes")]
+    pub stream_garbage_after_bytes: u64,
+    #[serde(rename = "stream-garbage-bytes")]
+    pub stream_garbage_bytes: u64,
+    #[serde(rename = "stream-abort-at-percent")]
+    pub stream_abort_at_percent: Percentage,
+    #[serde(rename = "request-buffering")]
+    pub request_buffering: bool,
+    #[serde(rename = "force-new-connection-percentage")]
+    pub force_new_connection_percentage: Probability,
+    #[serde(rename = "shadow-fail-before")]
+    pub shadow_fail_before: bool,
+    #[serde(rename = "pause-faults-when-unhealthy")]
+    pub pause_faults_when_unhealthy: bool,
+    #[serde(rename = "stream-loss-percentage")]
+    pub stream_loss_percentage: Percentage,
+    #[serde(rename = "stream-loss-burst-percentage")]
+    pub stream_loss_burst_percentage: Percentage,
+    #[serde(rename = "stream-loss-stall-ms")]
+    pub stream_loss_stall_ms: Millis,
+    #[serde(rename = "stream-loss-packet-bytes")]
+    pub stream_loss_packet_bytes: u64,
+    #[serde(rename = "match-time-range")]
+    pub match_time_range: String,
+    #[serde(rename = "match-days")]
+    pub match_days: String,
+    #[serde(rename = "match-timezone")]
+    pub match_timezone: Option<String>,
+    #[serde(rename = "fault-set")]
+    pub fault_set: Option<String>,
+    #[serde(rename = "preflight-mode")]
+    pub preflight_mode: PreflightMode,
+    #[serde(rename = "drop-trailers-percentage")]
+    pub drop_trailers_percentage: Probability,
+    #[serde(rename = "corrupt-trailers-percentage")]
+    pub corrupt_trailers_percentage: Probability,
+    #[serde(rename = "trigger-first-per-key")]
+    pub trigger_first_per_key: Option<String>,
+    #[serde(rename = "trigger-first-window-ms")]
+    pub trigger_first_window_ms: Millis,
+    #[serde(rename = "match-deployment-marker")]
+    pub match_deployment_marker: MatchPattern,
+    #[serde(rename = "duplicate-destination-url")]
+    pub duplicate_destination_url: Option<String>,
+    #[serde(rename = "destination-urls")]
+    pub destination_urls: Option<String>,
+    #[serde(rename = "sticky-key")]
+    pub sticky_key: Option<String>,
+    #[serde(rename = "min-response-time-ms")]
+    pub min_response_time_ms: Millis,
+    #[serde(rename = "fault-bodiless-responses")]
+    pub fault_bodiless_responses: bool,
+    #[serde(rename = "drop-percentage")]
+    pub drop_percentage: Probability,
+    #[serde(rename = "drop-hold-ms")]
+    pub drop_hold_ms: Millis,
+    #[serde(rename = "strip-content-encoding-percentage")]
+    pub strip_content_encoding_percentage: Probability,
+    #[serde(rename = "corrupt-response-percentage")]
+    pub corrupt_response_percentage: Probability,
+    #[serde(rename = "throttle-percentage")]
+    pub throttle_percentage: Probability,
+    #[serde(rename = "reset-connection-percentage")]
+    pub reset_connection_percentage: Probability,
+    #[serde(rename = "override-status-percentage")]
+    pub override_status_percentage: Probability,
+    #[serde(rename = "dns-delay-percentage")]
+    pub dns_delay_percentage: Probability,
+    #[serde(rename = "mutate-request-percentage")]
+    pub mutate_request_percentage: Probability,
+    #[serde(rename = "mutate-response-percentage")]
+    pub mutate_response_percentage: Probability,
+    #[serde(rename = "degrade-socket-percentage")]
+    pub degrade_socket_percentage: Probability,
+    #[serde(rename = "interim-response-percentage")]
+    pub interim_response_percentage: Probability,
+    #[serde(rename = "strip-validators-percentage")]
+    pub strip_validators_percentage: Probability,
+    #[serde(rename = "corrupt-validators-percentage")]
+    pub corrupt_validators_percentage: Probability,
+    #[serde(rename = "force-not-modified-percentage")]
+    pub force_not_modified_percentage: Probability,
+    #[serde(rename = "transform")]
+    pub transform: Option<Pipeline>,
+    #[serde(rename = "upstream-protocol")]
+    pub upstream_protocol: UpstreamProtocol,
+    #[serde(rename = "http3-fallback-percentage")]
+    pub http3_fallback_percentage: Probability,
+    #[serde(rename = "tls-server-name")]
+    pub tls_server_name: Option<String>,
+    #[serde(rename = "tls-skip-verify")]
+    pub tls_skip_verify: bool,
+    #[serde(rename = "session-key")]
+    pub session_key: Option<String>,
+    #[serde(rename = "session-window-ms")]
+    pub session_window_ms: Millis,
+    #[serde(rename = "match-session-request-index")]
+    pub match_session_request_index: u64,
+    #[serde(rename = "response-tee-path")]
+    pub response_tee_path: Option<String>,
+    #[serde(rename = "max-triggered-per-second")]
+    pub max_triggered_per_second: u64,
+    #[serde(rename = "strict-passthrough")]
+    pub strict_passthrough: bool,
+    #[serde(rename = "add-forwarded-by-header")]
+    pub add_forwarded_by_header: bool,
+    #[serde(rename = "add-via-header")]
+    pub add_via_header: bool,
+    #[serde(rename = "user-agent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "corrupt-response-mode")]
+    pub corrupt_response_mode: CorruptResponseMode,
+    #[serde(rename = "throttle-bytes-per-second")]
+    pub throttle_bytes_per_second: u64,
+    #[serde(rename = "override-status-codes")]
+    pub override_status_codes: StatusCodes,
+    #[serde(rename = "fail-before-mode")]
+    pub fail_before_mode: FailureMode,
+    #[serde(rename = "dns-delay-ms")]
+    pub dns_delay_ms: Millis,
+    #[serde(rename = "fail-before-every-nth")]
+    pub fail_before_every_nth: u64,
+    #[serde(rename = "delay-before-jitter-ms")]
+    pub delay_before_jitter_ms: Millis,
+    #[serde(rename = "delay-after-jitter-ms")]
+    pub delay_after_jitter_ms: Millis,
+    #[serde(rename = "mutate-request-path")]
+    pub mutate_request_path: Option<String>,
+    #[serde(rename = "mutate-request-mode")]
+    pub mutate_request_mode: MutationMode,
+    #[serde(rename = "mutate-request-delta")]
+    pub mutate_request_delta: i64,
+    #[serde(rename = "mutate-response-path")]
+    pub mutate_response_path: Option<String>,
+    #[serde(rename = "mutate-response-mode")]
+    pub mutate_response_mode: MutationMode,
+    #[serde(rename = "mutate-response-delta")]
+    pub mutate_response_delta: i64,
+    #[serde(rename = "degrade-socket-buffer-bytes")]
+    pub degrade_socket_buffer_bytes: u32,
+    #[serde(rename = "retry-attempts")]
+    pub retry_attempts: u32,
+    #[serde(rename = "retry-backoff-ms")]
+    pub retry_backoff_ms: Millis,
+    #[serde(rename = "stream-expiry-mode")]
+    pub stream_expiry_mode: StreamExpiryMode,
+    #[serde(rename = "interim-response-code")]
+    pub interim_response_code: u16,
     #[serde(rename = "match-uri")]
-    pub match_uri: String,
+    pub match_uri: MatchPattern,
     #[serde(rename = "match-uri-regex")]
-    pub match_uri_regex: String,
+    pub match_uri_regex: MatchPattern,
     #[serde(rename = "match-method")]
-    pub match_method: String,
+    pub match_method: MatchPattern,
     #[serde(rename = "match-uri-starts-with")]
-    pub match_uri_starts_with: String,
+    pub match_uri_starts_with: MatchPattern,
     #[serde(rename = "match-host")]
-    pub match_host: String,
+    pub match_host: MatchPattern,
     #[serde(rename = "match-header-name")]
-    pub match_header_name: String,
+    pub match_header_name: MatchPattern,
     #[serde(rename = "match-header-value")]
-    pub match_header_value: String,
+    pub match_header_value: MatchPattern,
     #[serde(rename = "destination-url")]
     pub destination_url: Option<String>,
 }
@@ -48,56 +220,430 @@ pub struct Settings {
 impl Default for Settings {
     fn default() -> Self {
         Self {
-            fail_before_code: 503,
-            fail_before_percentage: 0,
-            fail_after_percentage: 0,
-            fail_after_code: 502,
-            duplicate_percentage: 0,
-            delay_before_percentage: 0,
-            delay_before_ms: 0,
-            delay_after_percentage: 0,
-            delay_after_ms: 0,
-            match_uri: "*".to_string(),
-            match_uri_regex: "*".to_string(),
-            match_method: "*".to_string(),
-            match_uri_starts_with: "*".to_string(),
-            match_host: "*".to_string(),
-            match_header_name: "*".to_string(),
-            match_header_value: "*".to_string(),
+            fail_before_code: StatusCode::SERVICE_UNAVAILABLE.into(),
+            fail_before_percentage: Probability::ZERO,
+            fail_after_percentage: Probability::ZERO,
+            fail_after_code: StatusCode::BAD_GATEWAY.into(),
+            duplicate_percentage: Probability::ZERO,
+            delay_before_percentage: Probability::ZERO,
+            delay_before_ms: Millis::new(0),
+            delay_after_percentage: Probability::ZERO,
+            delay_after_ms: Millis::new(0),
+            stream_fault_percentage: Probability::ZERO,
+            stream_stall_after_bytes: 0,
+            stream_stall_ms: Millis::new(0),
+            stream_garbage_after_bytes: 0,
+            stream_garbage_bytes: 0,
+            stream_abort_at_percent: Percentage::ZERO,
+            request_buffering: true,
+            force_new_connection_percentage: Probability::ZERO,
+            shadow_fail_before: false,
+            pause_faults_when_unhealthy: false,
+            stream_loss_percentage: Percentage::ZERO,
+            stream_loss_burst_percentage: Percentage::ZERO,
+            stream_loss_stall_ms: Millis::new(200),
+            stream_loss_packet_bytes: 1460,
+            match_time_range: "*".to_string(),
+            match_days: "*".to_string(),
+            match_timezone: None,
+            fault_set: None,
+            preflight_mode: PreflightMode::PassThrough,
+            drop_trailers_percentage: Probability::ZERO,
+            corrupt_trailers_percentage: Probability::ZERO,
+            trigger_first_per_key: None,
+            trigger_first_window_ms: Millis::new(0),
+            match_deployment_marker: MatchPattern::Any,
+            duplicate_destination_url: None,
+            destination_urls: None,
+            sticky_key: None,
+            min_response_time_ms: Millis::new(0),
+            fault_bodiless_responses: false,
+            drop_percentage: Probability::ZERO,
+            drop_hold_ms: Millis::new(0),
+            strip_content_encoding_percentage: Probability::ZERO,
+            corrupt_response_percentage: Probability::ZERO,
+            throttle_percentage: Probability::ZERO,
+            reset_connection_percentage: Probability::ZERO,
+            override_status_percentage: Probability::ZERO,
+            dns_delay_percentage: Probability::ZERO,
+            mutate_request_percentage: Probability::ZERO,
+            mutate_response_percentage: Probability::ZERO,
+            degrade_socket_percentage: Probability::ZERO,
+            interim_response_percentage: Probability::ZERO,
+            strip_validators_percentage: Probability::ZERO,
+            corrupt_validators_percentage: Probability::ZERO,
+            force_not_modified_percentage: Probability::ZERO,
+            transform: None,
+            upstream_protocol: UpstreamProtocol::Auto,
+            http3_fallback_percentage: Probability::ZERO,
+            tls_server_name: None,
+            tls_skip_verify: false,
+            session_key: None,
+            session_window_ms: Millis::new(0),
+            match_session_request_index: 0,
+            response_tee_path: None,
+            max_triggered_per_second: 0,
+            strict_passthrough: false,
+            add_forwarded_by_header: false,
+            add_via_header: false,
+            user_agent: None,
+            corrupt_response_mode: CorruptResponseMode::Truncate,
+            throttle_bytes_per_second: 0,
+            override_status_codes: StatusCode::from_u16(599).expect("status").into(),
+            fail_before_mode: FailureMode::JsonError,
+            dns_delay_ms: Millis::new(0),
+            fail_before_every_nth: 0,
+            delay_before_jitter_ms: Millis::new(0),
+            delay_after_jitter_ms: Millis::new(0),
+            mutate_request_path: None,
+            mutate_request_mode: MutationMode::Null,
+            mutate_request_delta: 1,
+            mutate_response_path: None,
+            mutate_response_mode: MutationMode::Null,
+            mutate_response_delta: 1,
+            degrade_socket_buffer_bytes: 0,
+            retry_attempts: 0,
+            retry_backoff_ms: Millis::new(100),
+            stream_expiry_mode: StreamExpiryMode::Continue,
+            interim_response_code: 103,
+            match_uri: MatchPattern::Any,
+            match_uri_regex: MatchPattern::Any,
+            match_method: MatchPattern::Any,
+            match_uri_starts_with: MatchPattern::Any,
+            match_host: MatchPattern::Any,
+            match_header_name: MatchPattern::Any,
+            match_header_value: MatchPattern::Any,
             destination_url: None,
         }
     }
 }
 
 impl Settings {
+    pub fn copy_matchers(&mut self, other: &Settings) {
+        self.match_uri = other.match_uri.clone();
+        self.match_uri_regex = other.match_uri_regex.clone();
+        self.match_uri_starts_with = other.match_uri_starts_with.clone();
+        self.match_method = other.match_method.clone();
+        self.match_host = other.match_host.clone();
+        self.match_header_name = other.match_header_name.clone();
+        self.match_header_value = other.match_header_value.clone();
+        self.match_deployment_marker = other.match_deployment_marker.clone();
+        self.match_time_range = other.match_time_range.clone();
+        self.match_days = other.match_days.clone();
+        self.match_timezone = other.match_timezone.clone();
+    }
+
     pub fn apply_layer(&mut self, layer: &SettingsLayer) {
         if let Some(value) = layer.fail_before_code {
             self.fail_before_code = value;
         }
-        if let Some(value) = layer.fail_before_percentage {
-            self.fail_before_percentage = value;
+        if let Some(value) = &layer.fail_before_percentage {
+            self.fail_before_percentage = value.clone();
         }
-        if let Some(value) = layer.fail_after_percentage {
-            self.fail_after_percentage = value;
+        if let Some(value) = &layer.fail_after_percentage {
+            self.fail_after_percentage = value.clone();
         }
         if let Some(value) = layer.fail_after_code {
             self.fail_after_code = value;
         }
-        if let Some(value) = layer.duplicate_percentage {
-            self.duplicate_percentage = value;
+        if let Some(value) = &layer.duplicate_percentage {
+            self.duplicate_percentage = value.clone();
         }
-        if let Some(value) = layer.delay_before_percentage {
-            self.delay_before_percentage = value;
+        if let Some(value) = &layer.delay_before_percentage {
+            self.delay_before_percentage = value.clone();
         }
         if let Some(value) = layer.delay_before_ms {
             self.delay_before_ms = value;
         }
-        if let Some(value) = layer.delay_after_percentage {
-            self.delay_after_percentage = value;
+        if let Some(value) = &layer.delay_after_percentage {
+            self.delay_after_percentage = value.clone();
         }
         if let Some(value) = layer.delay_after_ms {
             self.delay_after_ms = value;
         }
+        if let Some(value) = &layer.stream_fault_percentage {
+            self.stream_fault_percentage = value.clone();
+        }
+        if let Some(value) = layer.stream_stall_after_bytes {
+            self.stream_stall_after_bytes = value;
+        }
+        if let Some(value) = layer.stream_stall_ms {
+            self.stream_stall_ms = value;
+        }
+        if let Some(value) = layer.stream_garbage_after_bytes {
+            self.stream_garbage_after_bytes = value;
+        }
+        if let Some(value) = layer.stream_garbage_bytes {
+            self.stream_garbage_bytes = value;
+        }
+        if let Some(value) = layer.stream_abort_at_percent {
+            self.stream_abort_at_percent = value;
+        }
+        if let Some(value) = layer.request_buffering {
+            self.request_buffering = value;
+        }
+        if let Some(value) = &layer.force_new_connection_percentage {
+            self.force_new_connection_percentage = value.clone();
+        }
+        if let Some(value) = layer.shadow_fail_before {
+            self.shadow_fail_before = value;
+        }
+        if let Some(value) = layer.pause_faults_when_unhealthy {
+            self.pause_faults_when_unhealthy = value;
+        }
+        if let Some(value) = layer.stream_loss_percentage {
+            self.stream_loss_percentage = value;
+        }
+        if let Some(value) = layer.stream_loss_burst_percentage {
+            self.stream_loss_burst_percentage = value;
+        }
+        if let Some(value) = layer.stream_loss_stall_ms {
+            self.stream_loss_stall_ms = value;
+        }
+        if let Some(value) = layer.stream_loss_packet_bytes {
+            self.stream_loss_packet_bytes = value;
+        }
+        if let Some(value) = &layer.match_time_range {
+            self.match_time_range = value.clone();
+        }
+        if let Some(value) = &layer.match_days {
+            self.match_days = value.clone();
+        }
+        if let Some(value) = &layer.match_timezone {
+            self.match_timezone = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = &layer.fault_set {
+            self.fault_set = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.preflight_mode {
+            self.preflight_mode = value;
+        }
+        if let Some(value) = &layer.drop_trailers_percentage {
+            self.drop_trailers_percentage = value.clone();
+        }
+        if let Some(value) = &layer.corrupt_trailers_percentage {
+            self.corrupt_trailers_percentage = value.clone();
+        }
+        if let Some(value) = &layer.trigger_first_per_key {
+            self.trigger_first_per_key = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.trigger_first_window_ms {
+            self.trigger_first_window_ms = value;
+        }
+        if let Some(value) = &layer.match_deployment_marker {
+            self.match_deployment_marker = value.clone();
+        }
+        if let Some(value) = &layer.duplicate_destination_url {
+            self.duplicate_destination_url = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = &layer.destination_urls {
+            self.destination_urls = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = &layer.sticky_key {
+            self.sticky_key = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.min_response_time_ms {
+            self.min_response_time_ms = value;
+        }
+        if let Some(value) = layer.fault_bodiless_responses {
+            self.fault_bodiless_responses = value;
+        }
+        if let Some(value) = &layer.drop_percentage {
+            self.drop_percentage = value.clone();
+        }
+        if let Some(value) = layer.drop_hold_ms {
+            self.drop_hold_ms = value;
+        }
+        if let Some(value) = &layer.strip_content_encoding_percentage {
+            self.strip_content_encoding_percentage = value.clone();
+        }
+        if let Some(value) = &layer.corrupt_response_percentage {
+            self.corrupt_response_percentage = value.clone();
+        }
+        if let Some(value) = &layer.throttle_percentage {
+            self.throttle_percentage = value.clone();
+        }
+        if let Some(value) = &layer.reset_connection_percentage {
+            self.reset_connection_percentage = value.clone();
+        }
+        if let Some(value) = &layer.override_status_percentage {
+            self.override_status_percentage = value.clone();
+        }
+        if let Some(value) = &layer.dns_delay_percentage {
+            self.dns_delay_percentage = value.clone();
+        }
+        if let Some(value) = &layer.mutate_request_percentage {
+            self.mutate_request_percentage = value.clone();
+        }
+        if let Some(value) = &layer.mutate_response_percentage {
+            self.mutate_response_percentage = value.clone();
+        }
+        if let Some(value) = &layer.degrade_socket_percentage {
+            self.degrade_socket_percentage = value.clone();
+        }
+        if let Some(value) = &layer.interim_response_percentage {
+            self.interim_response_percentage = value.clone();
+        }
+        if let Some(value) = &layer.strip_validators_percentage {
+            self.strip_validators_percentage = value.clone();
+        }
+        if let Some(value) = &layer.corrupt_validators_percentage {
+            self.corrupt_validators_percentage = value.clone();
+        }
+        if let Some(value) = &layer.force_not_modified_percentage {
+            self.force_not_modified_percentage = value.clone();
+        }
+        if let Some(value) = &layer.transform {
+            self.transform = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.upstream_protocol {
+            self.upstream_protocol = value;
+        }
+        if let Some(value) = &layer.http3_fallback_percentage {
+            self.http3_fallback_percentage = value.clone();
+        }
+        if let Some(value) = &layer.tls_server_name {
+            self.tls_server_name = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.tls_skip_verify {
+            self.tls_skip_verify = value;
+        }
+        if let Some(value) = &layer.session_key {
+            self.session_key = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.session_window_ms {
+            self.session_window_ms = value;
+        }
+        if let Some(value) = layer.match_session_request_index {
+            self.match_session_request_index = value;
+        }
+        if let Some(value) = &layer.response_tee_path {
+            self.response_tee_path = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.max_triggered_per_second {
+            self.max_triggered_per_second = value;
+        }
+        if let Some(value) = layer.strict_passthrough {
+            self.strict_passthrough = value;
+        }
+        if let Some(value) = layer.add_forwarded_by_header {
+            self.add_forwarded_by_header = value;
+        }
+        if let Some(value) = layer.add_via_header {
+            self.add_via_header = value;
+        }
+        if let Some(value) = &layer.user_agent {
+            self.user_agent = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.corrupt_response_mode {
+            self.corrupt_response_mode = value;
+        }
+        if let Some(value) = layer.throttle_bytes_per_second {
+            self.throttle_bytes_per_second = value;
+        }
+        if let Some(value) = &layer.override_status_codes {
+            self.override_status_codes = value.clone();
+        }
+        if let Some(value) = layer.fail_before_mode {
+            self.fail_before_mode = value;
+        }
+        if let Some(value) = layer.dns_delay_ms {
+            self.dns_delay_ms = value;
+        }
+        if let Some(value) = layer.fail_before_every_nth {
+            self.fail_before_every_nth = value;
+        }
+        if let Some(value) = layer.delay_before_jitter_ms {
+            self.delay_before_jitter_ms = value;
+        }
+        if let Some(value) = layer.delay_after_jitter_ms {
+            self.delay_after_jitter_ms = value;
+        }
+        if let Some(value) = &layer.mutate_request_path {
+            self.mutate_request_path = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.mutate_request_mode {
+            self.mutate_request_mode = value;
+        }
+        if let Some(value) = layer.mutate_request_delta {
+            self.mutate_request_delta = value;
+        }
+        if let Some(value) = &layer.mutate_response_path {
+            self.mutate_response_path = if value.is_empty() {
+                None
+            } else {
+                Some(value.clone())
+            };
+        }
+        if let Some(value) = layer.mutate_response_mode {
+            self.mutate_response_mode = value;
+        }
+        if let Some(value) = layer.mutate_response_delta {
+            self.mutate_response_delta = value;
+        }
+        if let Some(value) = layer.degrade_socket_buffer_bytes {
+            self.degrade_socket_buffer_bytes = value;
+        }
+        if let Some(value) = layer.retry_attempts {
+            self.retry_attempts = value;
+        }
+        if let Some(value) = layer.retry_backoff_ms {
+            self.retry_backoff_ms = value;
+        }
+        if let Some(value) = layer.stream_expiry_mode {
+            self.stream_expiry_mode = value;
+        }
+        if let Some(value) = layer.interim_response_code {
+            self.interim_response_code = value;
+        }
         if let Some(value) = &layer.match_uri {
             self.match_uri = value.clone();
         }
@@ -131,22 +677,99 @@ impl Settings {
 
 #[derive(Debug, Default, Clone)]
 pub struct SettingsLayer {
-    pub fail_before_code: Option<u16>,
-    pub fail_before_percentage: Option<u8>,
-    pub fail_after_percentage: Option<u8>,
-    pub fail_after_code: Option<u16>,
-    pub duplicate_percentage: Option<u8>,
-    pub delay_before_percentage: Option<u8>,
-    pub delay_before_ms: Option<u64>,
-    pub delay_after_percentage: Option<u8>,
-    pub delay_after_ms: Option<u64>,
-    pub match_uri: Option<String>,
-    pub match_uri_regex: Option<String>,
-    pub match_method: Option<String>,
-    pub match_uri_starts_with: Option<String>,
-    pub match_host: Option<String>,
-    pub match_header_name: Option<String>,
-    pub match_header_value: Option<String>,
+    pub fail_before_code: Option<StatusCodeSetting>,
+    pub fail_before_percentage: Option<Probability>,
+    pub fail_after_percentage: Option<Probability>,
+    pub fail_after_code: Option<StatusCodeSetting>,
+    pub duplicate_percentage: Option<Probability>,
+    pub delay_before_percentage: Option<Probability>,
+    pub delay_before_ms: Option<Millis>,
+    pub delay_after_percentage: Option<Probability>,
+    pub delay_after_ms: Option<Millis>,
+    pub stream_fault_percentage: Option<Probability>,
+    pub stream_stall_after_bytes: Option<u64>,
+    pub stream_stall_ms: Option<Millis>,
+    pub stream_garbage_after_bytes: Option<u64>,
+    pub stream_garbage_bytes: Option<u64>,
+    pub stream_abort_at_percent: Option<Percentage>,
+    pub request_buffering: Option<bool>,
+    pub force_new_connection_percentage: Option<Probability>,
+    pub shadow_fail_before: Option<bool>,
+    pub pause_faults_when_unhealthy: Option<bool>,
+    pub stream_loss_percentage: Option<Percentage>,
+    pub stream_loss_burst_percentage: Option<Percentage>,
+    pub stream_loss_stall_ms: Option<Millis>,
+    pub stream_loss_packet_bytes: Option<u64>,
+    pub match_time_range: Option<String>,
+    pub match_days: Option<String>,
+    pub match_timezone: Option<String>,
+    pub fault_set: Option<String>,
+    pub preflight_mode: Option<PreflightMode>,
+    pub drop_trailers_percentage: Option<Probability>,
+    pub corrupt_trailers_percentage: Option<Probability>,
+    pub trigger_first_per_key: Option<String>,
+    pub trigger_first_window_ms: Option<Millis>,
+    pub match_deployment_marker: Option<MatchPattern>,
+    pub duplicate_destination_url: Option<String>,
+    pub destination_urls: Option<String>,
+    pub sticky_key: Option<String>,
+    pub min_response_time_ms: Option<Millis>,
+    pub fault_bodiless_responses: Option<bool>,
+    pub drop_percentage: Option<Probability>,
+    pub drop_hold_ms: Option<Millis>,
+    pub strip_content_encoding_percentage: Option<Probability>,
+    pub corrupt_response_percentage: Option<Probability>,
+    pub throttle_percentage: Option<Probability>,
+    pub reset_connection_percentage: Option<Probability>,
+    pub override_status_percentage: Option<Probability>,
+    pub dns_delay_percentage: Option<Probability>,
+    pub mutate_request_percentage: Option<Probability>,
+    pub mutate_response_percentage: Option<Probability>,
+    pub degrade_socket_percentage: Option<Probability>,
+    pub interim_response_percentage: Option<Probability>,
+    pub strip_validators_percentage: Option<Probability>,
+    pub corrupt_validators_percentage: Option<Probability>,
+    pub force_not_modified_percentage: Option<Probability>,
+    pub transform: Option<Pipeline>,
+    pub upstream_protocol: Option<UpstreamProtocol>,
+    pub http3_fallback_percentage: Option<Probability>,
+    pub tls_server_name: Option<String>,
+    pub tls_skip_verify: Option<bool>,
+    pub session_key: Option<String>,
+    pub session_window_ms: Option<Millis>,
+    pub match_session_request_index: Option<u64>,
+    pub response_tee_path: Option<String>,
+    pub max_triggered_per_second: Option<u64>,
+    pub strict_passthrough: Option<bool>,
+    pub add_forwarded_by_header: Option<bool>,
+    pub add_via_header: Option<bool>,
+    pub user_agent: Option<String>,
+    pub corrupt_response_mode: Option<CorruptResponseMode>,
+    pub throttle_bytes_per_second: Option<u64>,
+    pub override_status_codes: Option<StatusCodes>,
+    pub fail_before_mode: Option<FailureMode>,
+    pub dns_delay_ms: Option<Millis>,
+    pub fail_before_every_nth: Option<u64>,
+    pub delay_before_jitter_ms: Option<Millis>,
+    pub delay_after_jitter_ms: Option<Millis>,
+    pub mutate_request_path: Option<String>,
+    pub mutate_request_mode: Option<MutationMode>,
+    pub mutate_request_delta: Option<i64>,
+    pub mutate_response_path: Option<String>,
+    pub mutate_response_mode: Option<MutationMode>,
+    pub mutate_response_delta: Option<i64>,
+    pub degrade_socket_buffer_bytes: Option<u32>,
+    pub retry_attempts: Option<u32>,
+    pub retry_backoff_ms: Option<Millis>,
+    pub stream_expiry_mode: Option<StreamExpiryMode>,
+    pub interim_response_code: Option<u16>,
+    pub match_uri: Option<MatchPattern>,
+    pub match_uri_regex: Option<MatchPattern>,
+    pub match_method: Option<MatchPattern>,
+    pub match_uri_starts_with: Option<MatchPattern>,
+    pub match_host: Option<MatchPattern>,
+    pub match_header_name: Option<MatchPattern>,
+    pub match_header_value: Option<MatchPattern>,
     pub destination_url: Option<String>,
 }
 
@@ -156,29 +779,261 @@ impl SettingsLayer {
             self.fail_before_code = other.fail_before_code;
         }
         if other.fail_before_percentage.is_some() {
-            self.fail_before_percentage = other.fail_before_percentage;
+            self.fail_before_percentage = other.fail_before_percentage.clone();
         }
         if other.fail_after_percentage.is_some() {
-            self.fail_after_percentage = other.fail_after_percentage;
+            self.fail_after_percentage = other.fail_after_percentage.clone();
         }
         if other.fail_after_code.is_some() {
             self.fail_after_code = other.fail_after_code;
         }
         if other.duplicate_percentage.is_some() {
-            self.duplicate_percentage = other.duplicate_percentage;
+            self.duplicate_percentage = other.duplicate_percentage.clone();
         }
         if other.delay_before_percentage.is_some() {
-            self.delay_before_percentage = other.delay_before_percentage;
+            self.delay_before_percentage = other.delay_before_percentage.clone();
         }
         if other.delay_before_ms.is_some() {
             self.delay_before_ms = other.delay_before_ms;
         }
         if other.delay_after_percentage.is_some() {
-            self.delay_after_percentage = other.delay_after_percentage;
+            self.delay_after_percentage = other.delay_after_percentage.clone();
         }
         if other.delay_after_ms.is_some() {
             self.delay_after_ms = other.delay_after_ms;
         }
+        if other.stream_fault_percentage.is_some() {
+            self.stream_fault_percentage = other.stream_fault_percentage.clone();
+        }
+        if other.stream_stall_after_bytes.is_some() {
+            self.stream_stall_after_bytes = other.stream_stall_after_bytes;
+        }
+        if other.stream_stall_ms.is_some() {
+            self.stream_stall_ms = other.stream_stall_ms;
+        }
+        if other.stream_garbage_after_bytes.is_some() {
+            self.stream_garbage_after_bytes = other.stream_garbage_after_bytes;
+        }
+        if other.stream_garbage_bytes.is_some() {
+            self.stream_garbage_bytes = other.stream_garbage_bytes;
+        }
+        if other.stream_abort_at_percent.is_some() {
+            self.stream_abort_at_percent = other.stream_abort_at_percent;
+        }
+        if other.request_buffering.is_some() {
+            self.request_buffering = other.request_buffering;
+        }
+        if other.force_new_connection_percentage.is_some() {
+            self.force_new_connection_percentage = other.force_new_connection_percentage.clone();
+        }
+        if other.shadow_fail_before.is_some() {
+            self.shadow_fail_before = other.shadow_fail_before;
+        }
+        if other.pause_faults_when_unhealthy.is_some() {
+            self.pause_faults_when_unhealthy = other.pause_faults_when_unhealthy;
+        }
+        if other.stream_loss_percentage.is_some() {
+            self.stream_loss_percentage = other.stream_loss_percentage;
+        }
+        if other.stream_loss_burst_percentage.is_some() {
+            self.stream_loss_burst_percentage = other.stream_loss_burst_percentage;
+        }
+        if other.stream_loss_stall_ms.is_some() {
+            self.stream_loss_stall_ms = other.stream_loss_stall_ms;
+        }
+        if other.stream_loss_packet_bytes.is_some() {
+            self.stream_loss_packet_bytes = other.stream_loss_packet_bytes;
+        }
+        if other.match_time_range.is_some() {
+            self.match_time_range = other.match_time_range.clone();
+        }
+        if other.match_days.is_some() {
+            self.match_days = other.match_days.clone();
+        }
+        if other.match_timezone.is_some() {
+            self.match_timezone = other.match_timezone.clone();
+        }
+        if other.fault_set.is_some() {
+            self.fault_set = other.fault_set.clone();
+        }
+        if other.preflight_mode.is_some() {
+            self.preflight_mode = other.preflight_mode;
+        }
+        if other.drop_trailers_percentage.is_some() {
+            self.drop_trailers_percentage = other.drop_trailers_percentage.clone();
+        }
+        if other.corrupt_trailers_percentage.is_some() {
+            self.corrupt_trailers_percentage = other.corrupt_trailers_percentage.clone();
+        }
+        if other.trigger_first_per_key.is_some() {
+            self.trigger_first_per_key = other.trigger_first_per_key.clone();
+        }
+        if other.trigger_first_window_ms.is_some() {
+            self.trigger_first_window_ms = other.trigger_first_window_ms;
+        }
+        if other.match_deployment_marker.is_some() {
+            self.match_deployment_marker = other.match_deployment_marker.clone();
+        }
+        if other.duplicate_destination_url.is_some() {
+            self.duplicate_destination_url = other.duplicate_destination_url.clone();
+        }
+        if other.destination_urls.is_some() {
+            self.destination_urls = other.destination_urls.clone();
+        }
+        if other.sticky_key.is_some() {
+            self.sticky_key = other.sticky_key.clone();
+        }
+        if other.min_response_time_ms.is_some() {
+            self.min_response_time_ms = other.min_response_time_ms;
+        }
+        if other.fault_bodiless_responses.is_some() {
+            self.fault_bodiless_responses = other.fault_bodiless_responses;
+        }
+        if other.drop_percentage.is_some() {
+            self.drop_percentage = other.drop_percentage.clone();
+        }
+        if other.drop_hold_ms.is_some() {
+            self.drop_hold_ms = other.drop_hold_ms;
+        }
+        if other.strip_content_encoding_percentage.is_some() {
+            self.strip_content_encoding_percentage =
+                other.strip_content_encoding_percentage.clone();
+        }
+        if other.corrupt_response_percentage.is_some() {
+            self.corrupt_response_percentage = other.corrupt_response_percentage.clone();
+        }
+        if other.throttle_percentage.is_some() {
+            self.throttle_percentage = other.throttle_percentage.clone();
+        }
+        if other.reset_connection_percentage.is_some() {
+            self.reset_connection_percentage = other.reset_connection_percentage.clone();
+        }
+        if other.override_status_percentage.is_some() {
+            self.override_status_percentage = other.override_status_percentage.clone();
+        }
+        if other.dns_delay_percentage.is_some() {
+            self.dns_delay_percentage = other.dns_delay_percentage.clone();
+        }
+        if other.mutate_request_percentage.is_some() {
+            self.mutate_request_percentage = other.mutate_request_percentage.clone();
+        }
+        if other.mutate_response_percentage.is_some() {
+            self.mutate_response_percentage = other.mutate_response_percentage.clone();
+        }
+        if other.degrade_socket_percentage.is_some() {
+            self.degrade_socket_percentage = other.degrade_socket_percentage.clone();
+        }
+        if other.interim_response_percentage.is_some() {
+            self.interim_response_percentage = other.interim_response_percentage.clone();
+        }
+        if other.strip_validators_percentage.is_some() {
+            self.strip_validators_percentage = other.strip_validators_percentage.clone();
+        }
+        if other.corrupt_validators_percentage.is_some() {
+            self.corrupt_validators_percentage = other.corrupt_validators_percentage.clone();
+        }
+        if other.force_not_modified_percentage.is_some() {
+            self.force_not_modified_percentage = other.force_not_modified_percentage.clone();
+        }
+        if other.transform.is_some() {
+            self.transform = other.transform.clone();
+        }
+        if other.upstream_protocol.is_some() {
+            self.upstream_protocol = other.upstream_protocol;
+        }
+        if other.http3_fallback_percentage.is_some() {
+            self.http3_fallback_percentage = other.http3_fallback_percentage.clone();
+        }
+        if other.tls_server_name.is_some() {
+            self.tls_server_name = other.tls_server_name.clone();
+        }
+        if other.tls_skip_verify.is_some() {
+            self.tls_skip_verify = other.tls_skip_verify;
+        }
+        if other.session_key.is_some() {
+            self.session_key = other.session_key.clone();
+        }
+        if other.session_window_ms.is_some() {
+            self.session_window_ms = other.session_window_ms;
+        }
+        if other.match_session_request_index.is_some() {
+            self.match_session_request_index = other.match_session_request_index;
+        }
+        if other.response_tee_path.is_some() {
+            self.response_tee_path = other.response_tee_path.clone();
+        }
+        if other.max_triggered_per_second.is_some() {
+            self.max_triggered_per_second = other.max_triggered_per_second;
+        }
+        if other.strict_passthrough.is_some() {
+            self.strict_passthrough = other.strict_passthrough;
+        }
+        if other.add_forwarded_by_header.is_some() {
+            self.add_forwarded_by_header = other.add_forwarded_by_header;
+        }
+        if other.add_via_header.is_some() {
+            self.add_via_header = other.add_via_header;
+        }
+        if other.user_agent.is_some() {
+            self.user_agent = other.user_agent.clone();
+        }
+        if other.corrupt_response_mode.is_some() {
+            self.corrupt_response_mode = other.corrupt_response_mode;
+        }
+        if other.throttle_bytes_per_second.is_some() {
+            self.throttle_bytes_per_second = other.throttle_bytes_per_second;
+        }
+        if other.override_status_codes.is_some() {
+            self.override_status_codes = other.override_status_codes.clone();
+        }
+        if other.fail_before_mode.is_some() {
+            self.fail_before_mode = other.fail_before_mode;
+        }
+        if other.dns_delay_ms.is_some() {
+            self.dns_delay_ms = other.dns_delay_ms;
+        }
+        if other.fail_before_every_nth.is_some() {
+            self.fail_before_every_nth = other.fail_before_every_nth;
+        }
+        if other.delay_before_jitter_ms.is_some() {
+            self.delay_before_jitter_ms = other.delay_before_jitter_ms;
+        }
+        if other.delay_after_jitter_ms.is_some() {
+            self.delay_after_jitter_ms = other.delay_after_jitter_ms;
+        }
+        if other.mutate_request_path.is_some() {
+            self.mutate_request_path = other.mutate_request_path.clone();
+        }
+        if other.mutate_request_mode.is_some() {
+            self.mutate_request_mode = other.mutate_request_mode;
+        }
+        if other.mutate_request_delta.is_some() {
+            self.mutate_request_delta = other.mutate_request_delta;
+        }
+        if other.mutate_response_path.is_some() {
+            self.mutate_response_path = other.mutate_response_path.clone();
+        }
+        if other.mutate_response_mode.is_some() {
+            self.mutate_response_mode = other.mutate_response_mode;
+        }
+        if other.mutate_response_delta.is_some() {
+            self.mutate_response_delta = other.mutate_response_delta;
+        }
+        if other.degrade_socket_buffer_bytes.is_some() {
+            self.degrade_socket_buffer_bytes = other.degrade_socket_buffer_bytes;
+        }
+        if other.retry_attempts.is_some() {
+            self.retry_attempts = other.retry_attempts;
+        }
+        if other.retry_backoff_ms.is_some() {
+            self.retry_backoff_ms = other.retry_backoff_ms;
+        }
+        if other.stream_expiry_mode.is_some() {
+            self.stream_expiry_mode = other.stream_expiry_mode;
+        }
+        if other.interim_response_code.is_some() {
+            self.interim_response_code = other.interim_response_code;
+        }
         if other.match_uri.is_some() {
             self.match_uri = other.match_uri.clone();
         }
@@ -207,60 +1062,269 @@ impl SettingsLayer {
 
     pub fn from_env() -> Self {
         SettingsLayer {
-            fail_before_code: parse_env_u16("FAIL_BEFORE_CODE"),
-            fail_before_percentage: parse_env_u8("FAIL_BEFORE_PERCENTAGE"),
-            fail_after_percentage: parse_env_u8("FAIL_AFTER_PERCENTAGE"),
-            fail_after_code: parse_env_u16("FAIL_AFTER_CODE"),
-            duplicate_percentage: parse_env_u8("DUPLICATE_PERCENTAGE"),
-            delay_before_percentage: parse_env_u8("DELAY_BEFORE_PERCENTAGE"),
-            delay_before_ms: parse_env_u64("DELAY_BEFORE_MS"),
-            delay_after_percentage: parse_env_u8("DELAY_AFTER_PERCENTAGE"),
-            delay_after_ms: parse_env_u64("DELAY_AFTER_MS"),
-            match_uri: env_string("MATCH_URI"),
-            match_uri_regex: env_string("MATCH_URI_REGEX"),
-            match_method: env_string("MATCH_METHOD"),
-            match_uri_starts_with: env_string("MATCH_URI_STARTS_WITH"),
-            match_host: env_string("MATCH_HOST"),
-            match_header_name: env_string("MATCH_HEADER_NAME").map(|v| v.to_ascii_lowercase()),
-            match_header_value: env_string("MATCH_HEADER_VALUE"),
+            fail_before_code: parse_env("FAIL_BEFORE_CODE"),
+            fail_before_percentage: parse_env("FAIL_BEFORE_PERCENTAGE"),
+            fail_after_percentage: parse_env("FAIL_AFTER_PERCENTAGE"),
+            fail_after_code: parse_env("FAIL_AFTER_CODE"),
+            duplicate_percentage: parse_env("DUPLICATE_PERCENTAGE"),
+            delay_before_percentage: parse_env("DELAY_BEFORE_PERCENTAGE"),
+            delay_before_ms: parse_env("DELAY_BEFORE_MS"),
+            delay_after_percentage: parse_env("DELAY_AFTER_PERCENTAGE"),
+            delay_after_ms: parse_env("DELAY_AFTER_MS"),
+            stream_fault_percentage: parse_env("STREAM_FAULT_PERCENTAGE"),
+            stream_stall_after_bytes: parse_env("STREAM_STALL_AFTER_BYTES"),
+            stream_stall_ms: parse_env("STREAM_STALL_MS"),
+            stream_garbage_after_bytes: parse_env("STREAM_GARBAGE_AFTER_BYTES"),
+            stream_garbage_bytes: std::env::var("STREAM_GARBAGE_BYTES")
+                .ok()
+                .and_then(|text| parse_garbage_bytes("STREAM_GARBAGE_BYTES", &text)),
+            stream_abort_at_percent: parse_env("STREAM_ABORT_AT_PERCENT"),
+            request_buffering: parse_env_bool("REQUEST_BUFFERING"),
+            force_new_connection_percentage: parse_env("FORCE_NEW_CONNECTION_PERCENTAGE"),
+            shadow_fail_before: parse_env_bool("SHADOW_FAIL_BEFORE"),
+            pause_faults_when_unhealthy: parse_env_bool("PAUSE_FAULTS_WHEN_UNHEALTHY"),
+            stream_loss_percentage: parse_env("STREAM_LOSS_PERCENTAGE"),
+            stream_loss_burst_percentage: parse_env("STREAM_LOSS_BURST_PERCENTAGE"),
+            stream_loss_stall_ms: parse_env("STREAM_LOSS_STALL_MS"),
+            stream_loss_packet_bytes: std::env::var("STREAM_LOSS_PACKET_BYTES")
+                .ok()
+                .and_then(|text| parse_packet_bytes("STREAM_LOSS_PACKET_BYTES", &text)),
+            match_time_range: env_string("MATCH_TIME_RANGE"),
+            match_days: env_string("MATCH_DAYS"),
+            match_timezone: env_string("MATCH_TIMEZONE"),
+            fault_set: env_string("FAULT_SET"),
+            preflight_mode: parse_env("PREFLIGHT_MODE"),
+            drop_trailers_percentage: parse_env("DROP_TRAILERS_PERCENTAGE"),
+            corrupt_trailers_percentage: parse_env("CORRUPT_TRAILERS_PERCENTAGE"),
+            trigger_first_per_key: env_string("TRIGGER_FIRST_PER_KEY"),
+            trigger_first_window_ms: parse_env("TRIGGER_FIRST_WINDOW_MS"),
+            match_deployment_marker: env_string("MATCH_DEPLOYMENT_MARKER")
+                .map(|v| MatchPattern::exact(&v)),
+            duplicate_destination_url: env_string("DUPLICATE_DESTINATION_URL"),
+            destination_urls: env_string("DESTINATION_URLS"),
+            sticky_key: env_string("STICKY_KEY"),
+            min_response_time_ms: parse_env("MIN_RESPONSE_TIME_MS"),
+            fault_bodiless_responses: parse_env_bool("FAULT_BODILESS_RESPONSES"),
+            drop_percentage: parse_env("DROP_PERCENTAGE"),
+            drop_hold_ms: parse_env("DROP_HOLD_MS"),
+            strip_content_encoding_percentage: parse_env("STRIP_CONTENT_ENCODING_PERCENTAGE"),
+            corrupt_response_percentage: parse_env("CORRUPT_RESPONSE_PERCENTAGE"),
+            throttle_percentage: parse_env("THROTTLE_PERCENTAGE"),
+            reset_connection_percentage: parse_env("RESET_CONNECTION_PERCENTAGE"),
+            override_status_percentage: parse_env("OVERRIDE_STATUS_PERCENTAGE"),
+            dns_delay_percentage: parse_env("DNS_DELAY_PERCENTAGE"),
+            mutate_request_percentage: parse_env("MUTATE_REQUEST_PERCENTAGE"),
+            mutate_response_percentage: parse_env("MUTATE_RESPONSE_PERCENTAGE"),
+            degrade_socket_percentage: parse_env("DEGRADE_SOCKET_PERCENTAGE"),
+            interim_response_percentage: parse_env("INTERIM_RESPONSE_PERCENTAGE"),
+            strip_validators_percentage: parse_env("STRIP_VALIDATORS_PERCENTAGE"),
+            corrupt_validators_percentage: parse_env("CORRUPT_VALIDATORS_PERCENTAGE"),
+            force_not_modified_percentage: parse_env("FORCE_NOT_MODIFIED_PERCENTAGE"),
+            transform: parse_env("TRANSFORM"),
+            upstream_protocol: parse_env("UPSTREAM_PROTOCOL"),
+            http3_fallback_percentage: parse_env("HTTP3_FALLBACK_PERCENTAGE"),
+            tls_server_name: env_string("TLS_SERVER_NAME"),
+            tls_skip_verify: parse_env_bool("TLS_SKIP_VERIFY"),
+            session_key: env_string("SESSION_KEY"),
+            session_window_ms: parse_env("SESSION_WINDOW_MS"),
+            match_session_request_index: parse_env("MATCH_SESSION_REQUEST_INDEX"),
+            response_tee_path: env_string("RESPONSE_TEE_PATH"),
+            max_triggered_per_second: parse_env("MAX_TRIGGERED_PER_SECOND"),
+            strict_passthrough: parse_env_bool("STRICT_PASSTHROUGH"),
+            add_forwarded_by_header: parse_env_bool("ADD_FORWARDED_BY_HEADER"),
+            add_via_header: parse_env_bool("ADD_VIA_HEADER"),
+            user_agent: env_string("USER_AGENT"),
+            corrupt_response_mode: parse_env("CORRUPT_RESPONSE_MODE"),
+            throttle_bytes_per_second: parse_env("THROTTLE_BYTES_PER_SECOND"),
+            override_status_codes: parse_env("OVERRIDE_STATUS_CODES"),
+            fail_before_mode: parse_env("FAIL_BEFORE_MODE"),
+            dns_delay_ms: parse_env("DNS_DELAY_MS"),
+            fail_before_every_nth: parse_env("FAIL_BEFORE_EVERY_NTH"),
+            delay_before_jitter_ms: parse_env("DELAY_BEFORE_JITTER_MS"),
+            delay_after_jitter_ms: parse_env("DELAY_AFTER_JITTER_MS"),
+            mutate_request_path: env_string("MUTATE_REQUEST_PATH"),
+            mutate_request_mode: parse_env("MUTATE_REQUEST_MODE"),
+            mutate_request_delta: parse_env("MUTATE_REQUEST_DELTA"),
+            mutate_response_path: env_string("MUTATE_RESPONSE_PATH"),
+            mutate_response_mode: parse_env("MUTATE_RESPONSE_MODE"),
+            mutate_response_delta: parse_env("MUTATE_RESPONSE_DELTA"),
+            degrade_socket_buffer_bytes: parse_env("DEGRADE_SOCKET_BUFFER_BYTES"),
+            retry_attempts: parse_env("RETRY_ATTEMPTS"),
+            retry_backoff_ms: parse_env("RETRY_BACKOFF_MS"),
+            stream_expiry_mode: parse_env("STREAM_EXPIRY_MODE"),
+            interim_response_code: parse_env("INTERIM_RESPONSE_CODE"),
+            match_uri: env_string("MATCH_URI").map(|v| MatchPattern::exact(&v)),
+            match_uri_regex: env_string("MATCH_URI_REGEX").map(|v| MatchPattern::regex(&v)),
+            match_method: env_string("MATCH_METHOD").map(|v| MatchPattern::exact_uppercase(&v)),
+            match_uri_starts_with: env_string("MATCH_URI_STARTS_WITH")
+                .map(|v| MatchPattern::prefix(&v)),
+            match_host: env_string("MATCH_HOST").map(|v| MatchPattern::exact(&v)),
+            match_header_name: env_string("MATCH_HEADER_NAME")
+                .map(|v| MatchPattern::exact_lowercase(&v)),
+            match_header_value: env_string("MATCH_HEADER_VALUE").map(|v| MatchPattern::exact(&v)),
             destination_url: env_string("DESTINATION_URL"),
         }
     }
 
     pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self::from_prefixed_headers(headers, HEADER_PREFIX)
+    }
+
+    pub fn from_prefixed_headers(headers: &HeaderMap, prefix: &str) -> Self {
         let mut layer = SettingsLayer::default();
         for (name, value) in headers.iter() {
             let key = name.as_str().to_ascii_lowercase();
-            if let Some(stripped) = key.strip_prefix(HEADER_PREFIX)
+            if let Some(stripped) = key.strip_prefix(prefix)
                 && let Ok(text) = value.to_str()
             {
-                match stripped {
-                    "fail-before-code" => layer.fail_before_code = text.parse().ok(),
-                    "fail-before-percentage" => layer.fail_before_percentage = text.parse().ok(),
-                    "fail-after-percentage" => layer.fail_after_percentage = text.parse().ok(),
-                    "fail-after-code" => layer.fail_after_code = text.parse().ok(),
-                    "duplicate-percentage" => layer.duplicate_percentage = text.parse().ok(),
-                    "delay-before-percentage" => layer.delay_before_percentage = text.parse().ok(),
-                    "delay-before-ms" => layer.delay_before_ms = text.parse().ok(),
-                    "delay-after-percentage" => layer.delay_after_percentage = text.parse().ok(),
-                    "delay-after-ms" => layer.delay_after_ms = text.parse().ok(),
-                    "match-uri" => layer.match_uri = Some(text.to_string()),
-                    "match-uri-regex" => layer.match_uri_regex = Some(text.to_string()),
-                    "match-method" => layer.match_method = Some(text.to_string()),
-                    "match-uri-starts-with" => layer.match_uri_starts_with = Some(text.to_string()),
-                    "match-host" => layer.match_host = Some(text.to_string()),
-                    "match-header-name" => {
-                        layer.match_header_name = Some(text.to_ascii_lowercase())
-                    }
-                    "match-header-value" => layer.match_header_value = Some(text.to_string()),
-                    "destination-url" => layer.destination_url = Some(text.to_string()),
-                    _ => {}
-                }
+                layer.set(stripped, text);
             }
         }
         layer
     }
 
+    pub fn set(&mut self, name: &str, text: &str) {
+        match name {
+            "fail-before-code" => self.fail_before_code = parse_value(name, text),
+            "fail-before-percentage" => self.fail_before_percentage = parse_value(name, text),
+            "fail-after-percentage" => self.fail_after_percentage = parse_value(name, text),
+            "fail-after-code" => self.fail_after_code = parse_value(name, text),
+            "duplicate-percentage" => self.duplicate_percentage = parse_value(name, text),
+            "delay-before-percentage" => self.delay_before_percentage = parse_value(name, text),
+            "delay-before-ms" => self.delay_before_ms = parse_value(name, text),
+            "delay-after-percentage" => self.delay_after_percentage = parse_value(name, text),
+            "delay-after-ms" => self.delay_after_ms = parse_value(name, text),
+            "stream-fault-percentage" => self.stream_fault_percentage = parse_value(name, text),
+            "stream-stall-after-bytes" => self.stream_stall_after_bytes = parse_value(name, text),
+            "stream-stall-ms" => self.stream_stall_ms = parse_value(name, text),
+            "stream-garbage-after-bytes" => {
+                self.stream_garbage_after_bytes = parse_value(name, text)
+            }
+            "stream-garbage-bytes" => self.stream_garbage_bytes = parse_garbage_bytes(name, text),
+            "stream-abort-at-percent" => self.stream_abort_at_percent = parse_value(name, text),
+            "request-buffering" => self.request_buffering = parse_bool(text),
+            "force-new-connection-percentage" => {
+                self.force_new_connection_percentage = parse_value(name, text)
+            }
+            "shadow-fail-before" => self.shadow_fail_before = parse_bool(text),
+            "pause-faults-when-unhealthy" => self.pause_faults_when_unhealthy = parse_bool(text),
+            "stream-loss-percentage" => self.stream_loss_percentage = parse_value(name, text),
+            "stream-loss-burst-percentage" => {
+                self.stream_loss_burst_percentage = parse_value(name, text)
+            }
+            "stream-loss-stall-ms" => self.stream_loss_stall_ms = parse_value(name, text),
+            "stream-loss-packet-bytes" => {
+                self.stream_loss_packet_bytes = parse_packet_bytes(name, text)
+            }
+            "match-time-range" => self.match_time_range = Some(text.to_string()),
+            "match-days" => self.match_days = Some(text.to_string()),
+            "match-timezone" => self.match_timezone = Some(text.to_string()),
+            "fault-set" => self.fault_set = Some(text.to_string()),
+            "preflight-mode" => self.preflight_mode = parse_value(name, text),
+            "drop-trailers-percentage" => self.drop_trailers_percentage = parse_value(name, text),
+            "corrupt-trailers-percentage" => {
+                self.corrupt_trailers_percentage = parse_value(name, text)
+            }
+            "trigger-first-per-key" => self.trigger_first_per_key = Some(text.to_string()),
+            "trigger-first-window-ms" => self.trigger_first_window_ms = parse_value(name, text),
+            "match-deployment-marker" => {
+                self.match_deployment_marker = Some(MatchPattern::exact(text))
+            }
+            "duplicate-destination-url" => self.duplicate_destination_url = Some(text.to_string()),
+            "destination-urls" => self.destination_urls = Some(text.to_string()),
+            "sticky-key" => self.sticky_key = Some(text.to_string()),
+            "min-response-time-ms" => self.min_response_time_ms = parse_value(name, text),
+            "fault-bodiless-responses" => self.fault_bodiless_responses = parse_bool(text),
+            "drop-percentage" => self.drop_percentage = parse_value(name, text),
+            "drop-hold-ms" => self.drop_hold_ms = parse_value(name, text),
+            "strip-content-encoding-percentage" => {
+                self.strip_content_encoding_percentage = parse_value(name, text)
+            }
+            "corrupt-response-percentage" => {
+                self.corrupt_response_percentage = parse_value(name, text)
+            }
+            "throttle-percentage" => self.throttle_percentage = parse_value(name, text),
+            "reset-connection-percentage" => {
+                self.reset_connection_percentage = parse_value(name, text)
+            }
+            "override-status-percentage" => {
+                self.override_status_percentage = parse_value(name, text)
+            }
+            "dns-delay-percentage" => self.dns_delay_percentage = parse_value(name, text),
+            "mutate-request-percentage" => self.mutate_request_percentage = parse_value(name, text),
+            "mutate-response-percentage" => {
+                self.mutate_response_percentage = parse_value(name, text)
+            }
+            "degrade-socket-percentage" => self.degrade_socket_percentage = parse_value(name, text),
+            "interim-response-percentage" => {
+                self.interim_response_percentage = parse_value(name, text)
+            }
+            "strip-validators-percentage" => {
+                self.strip_validators_percentage = parse_value(name, text)
+            }
+            "corrupt-validators-percentage" => {
+                self.corrupt_validators_percentage = parse_value(name, text)
+            }
+            "force-not-modified-percentage" => {
+                self.force_not_modified_percentage = parse_value(name, text)
+            }
+            "transform" => self.transform = parse_value(name, text),
+            "upstream-protocol" => self.upstream_protocol = parse_value(name, text),
+            "http3-fallback-percentage" => self.http3_fallback_percentage = parse_value(name, text),
+            "tls-server-name" => self.tls_server_name = Some(text.to_string()),
+            "tls-skip-verify" => self.tls_skip_verify = parse_bool(text),
+            "session-key" => self.session_key = Some(text.to_string()),
+            "session-window-ms" => self.session_window_ms = parse_value(name, text),
+            "match-session-request-index" => {
+                self.match_session_request_index = parse_value(name, text)
+            }
+            "response-tee-path" => self.response_tee_path = Some(text.to_string()),
+            "max-triggered-per-second" => self.max_triggered_per_second = parse_value(name, text),
+            "strict-passthrough" => self.strict_passthrough = parse_bool(text),
+            "add-forwarded-by-header" => self.add_forwarded_by_header = parse_bool(text),
+            "add-via-header" => self.add_via_header = parse_bool(text),
+            "user-agent" => self.user_agent = Some(text.to_string()),
+            "corrupt-response-mode" => self.corrupt_response_mode = parse_value(name, text),
+            "throttle-bytes-per-second" => self.throttle_bytes_per_second = parse_value(name, text),
+            "override-status-codes" => self.override_status_codes = parse_value(name, text),
+            "fail-before-mode" => self.fail_before_mode = parse_value(name, text),
+            "dns-delay-ms" => self.dns_delay_ms = parse_value(name, text),
+            "fail-before-every-nth" => self.fail_before_every_nth = parse_value(name, text),
+            "delay-before-jitter-ms" => self.delay_before_jitter_ms = parse_value(name, text),
+            "delay-after-jitter-ms" => self.delay_after_jitter_ms = parse_value(name, text),
+            "mutate-request-path" => self.mutate_request_path = Some(text.to_string()),
+            "mutate-request-mode" => self.mutate_request_mode = parse_value(name, text),
+            "mutate-request-delta" => self.mutate_request_delta = parse_value(name, text),
+            "mutate-response-path" => self.mutate_response_path = Some(text.to_string()),
+            "mutate-response-mode" => self.mutate_response_mode = parse_value(name, text),
+            "mutate-response-delta" => self.mutate_response_delta = parse_value(name, text),
+            "degrade-socket-buffer-bytes" => {
+                self.degrade_socket_buffer_bytes = parse_value(name, text)
+            }
+            "retry-attempts" => self.retry_attempts = parse_value(name, text),
+            "retry-backoff-ms" => self.retry_backoff_ms = parse_value(name, text),
+            "stream-expiry-mode" => self.stream_expiry_mode = parse_value(name, text),
+            "interim-response-code" => self.interim_response_code = parse_value(name, text),
+            "match-uri" => self.match_uri = Some(MatchPattern::exact(text)),
+            "match-uri-regex" => self.match_uri_regex = Some(MatchPattern::regex(text)),
+            "match-method" => self.match_method = Some(MatchPattern::exact_uppercase(text)),
+            "match-uri-starts-with" => {
+                self.match_uri_starts_with = Some(MatchPattern::prefix(text))
+            }
+            "match-host" => self.match_host = Some(MatchPattern::exact(text)),
+            "match-header-name" => {
+                self.match_header_name = Some(MatchPattern::exact_lowercase(text))
+            }
+            "match-header-value" => self.match_header_value = Some(MatchPattern::exact(text)),
+            "destination-url" => self.destination_url = Some(text.to_string()),
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
     pub fn entries(&self) -> Vec<(&'static str, String)> {
         let mut values = Vec::new();
         macro_rules! push_entry {
@@ -270,64 +1334,260 @@ impl SettingsLayer {
                 }
             };
         }
-        push_entry!(self.fail_before_code, "fail-before-code");
-        push_entry!(self.fail_before_percentage, "fail-before-percentage");
-        push_entry!(self.fail_after_percentage, "fail-after-percentage");
-        push_entry!(self.fail_after_code, "fail-after-code");
-        push_entry!(self.duplicate_percentage, "duplicate-percentage");
-        push_entry!(self.delay_before_percentage, "delay-before-percentage");
-        push_entry!(self.delay_before_ms, "delay-before-ms");
-        push_entry!(self.delay_after_percentage, "delay-after-percentage");
-        push_entry!(self.delay_after_ms, "delay-after-ms");
-        if let Some(value) = &self.match_uri {
-            values.push(("match-uri", value.clone()));
-        }
-        if let Some(value) = &self.match_uri_regex {
-            values.push(("match-uri-regex", value.clone()));
-        }
-        if let Some(value) = &self.match_method {
-            values.push(("match-method", value.clone()));
-        }
-        if let Some(value) = &self.match_uri_starts_with {
-            values.push(("match-uri-starts-with", value.clone()));
-        }
-        if let Some(value) = &self.match_host {
-            values.push(("match-host", value.clone()));
-        }
-        if let Some(value) = &self.match_header_name {
-            values.push(("match-header-name", value.clone()));
-        }
-        if let Some(value) = &self.match_header_value {
-            values.push(("match-header-value", value.clone()));
-        }
-        if let Some(value) = &self.destination_url {
-            values.push(("destination-url", value.clone()));
-        }
+        push_entry!(&self.fail_before_code, "fail-before-code");
+        push_entry!(&self.fail_before_percentage, "fail-before-percentage");
+        push_entry!(&self.fail_after_percentage, "fail-after-percentage");
+        push_entry!(&self.fail_after_code, "fail-after-code");
+        push_entry!(&self.duplicate_percentage, "duplicate-percentage");
+        push_entry!(&self.delay_before_percentage, "delay-before-percentage");
+        push_entry!(&self.delay_before_ms, "delay-before-ms");
+        push_entry!(&self.delay_after_percentage, "delay-after-percentage");
+        push_entry!(&self.delay_after_ms, "delay-after-ms");
+        push_entry!(&self.stream_fault_percentage, "stream-fault-percentage");
+        push_entry!(&self.stream_stall_after_bytes, "stream-stall-after-bytes");
+        push_entry!(&self.stream_stall_ms, "stream-stall-ms");
+        push_entry!(
+            &self.stream_garbage_after_bytes,
+            "stream-garbage-after-bytes"
+        );
+        push_entry!(&self.stream_garbage_bytes, "stream-garbage-bytes");
+        push_entry!(&self.stream_abort_at_percent, "stream-abort-at-percent");
+        push_entry!(&self.request_buffering, "request-buffering");
+        push_entry!(
+            &self.force_new_connection_percentage,
+            "force-new-connection-percentage"
+        );
+        push_entry!(&self.shadow_fail_before, "shadow-fail-before");
+        push_entry!(
+            &self.pause_faults_when_unhealthy,
+            "pause-faults-when-unhealthy"
+        );
+        push_entry!(&self.stream_loss_percentage, "stream-loss-percentage");
+        push_entry!(
+            &self.stream_loss_burst_percentage,
+            "stream-loss-burst-percentage"
+        );
+        push_entry!(&self.stream_loss_stall_ms, "stream-loss-stall-ms");
+        push_entry!(&self.stream_loss_packet_bytes, "stream-loss-packet-bytes");
+        push_entry!(&self.match_time_range, "match-time-range");
+        push_entry!(&self.match_days, "match-days");
+        push_entry!(&self.match_timezone, "match-timezone");
+        push_entry!(&self.fault_set, "fault-set");
+        push_entry!(&self.preflight_mode, "preflight-mode");
+        push_entry!(&self.drop_trailers_percentage, "drop-trailers-percentage");
+        push_entry!(
+            &self.corrupt_trailers_percentage,
+            "corrupt-trailers-percentage"
+        );
+        push_entry!(&self.trigger_first_per_key, "trigger-first-per-key");
+        push_entry!(&self.trigger_first_window_ms, "trigger-first-window-ms");
+        push_entry!(&self.match_deployment_marker, "match-deployment-marker");
+        push_entry!(&self.duplicate_destination_url, "duplicate-destination-url");
+        push_entry!(&self.destination_urls, "destination-urls");
+        push_entry!(&self.sticky_key, "sticky-key");
+        push_entry!(&self.min_response_time_ms, "min-response-time-ms");
+        push_entry!(&self.fault_bodiless_responses, "fault-bodiless-responses");
+        push_entry!(&self.drop_percentage, "drop-percentage");
+        push_entry!(&self.drop_hold_ms, "drop-hold-ms");
+        push_entry!(
+            &self.strip_content_encoding_percentage,
+            "strip-content-encoding-percentage"
+        );
+        push_entry!(
+            &self.corrupt_response_percentage,
+            "corrupt-response-percentage"
+        );
+        push_entry!(&self.throttle_percentage, "throttle-percentage");
+        push_entry!(
+            &self.reset_connection_percentage,
+            "reset-connection-percentage"
+        );
+        push_entry!(
+            &self.override_status_percentage,
+            "override-status-percentage"
+        );
+        push_entry!(&self.dns_delay_percentage, "dns-delay-percentage");
+        push_entry!(&self.mutate_request_percentage, "mutate-request-percentage");
+        push_entry!(
+            &self.mutate_response_percentage,
+            "mutate-response-percentage"
+        );
+        push_entry!(&self.degrade_socket_percentage, "degrade-socket-percentage");
+        push_entry!(
+            &self.interim_response_percentage,
+            "interim-response-percentage"
+        );
+        push_entry!(
+            &self.strip_validators_percentage,
+            "strip-validators-percentage"
+        );
+        push_entry!(
+            &self.corrupt_validators_percentage,
+            "corrupt-validators-percentage"
+        );
+        push_entry!(
+            &self.force_not_modified_percentage,
+            "force-not-modified-percentage"
+        );
+        push_entry!(&self.transform, "transform");
+        push_entry!(&self.upstream_protocol, "upstream-protocol");
+        push_entry!(&self.http3_fallback_percentage, "http3-fallback-percentage");
+        push_entry!(&self.tls_server_name, "tls-server-name");
+        push_entry!(&self.tls_skip_verify, "tls-skip-verify");
+        push_entry!(&self.session_key, "session-key");
+        push_entry!(&self.session_window_ms, "session-window-ms");
+        push_entry!(
+            &self.match_session_request_index,
+            "match-session-request-index"
+        );
+        push_entry!(&self.response_tee_path, "response-tee-path");
+        push_entry!(&self.max_triggered_per_second, "max-triggered-per-second");
+        push_entry!(&self.strict_passthrough, "strict-passthrough");
+        push_entry!(&self.add_forwarded_by_header, "add-forwarded-by-header");
+        push_entry!(&self.add_via_header, "add-via-header");
+        push_entry!(&self.user_agent, "user-agent");
+        push_entry!(&self.corrupt_response_mode, "corrupt-response-mode");
+        push_entry!(&self.throttle_bytes_per_second, "throttle-bytes-per-second");
+        push_entry!(&self.override_status_codes, "override-status-codes");
+        push_entry!(&self.fail_before_mode, "fail-before-mode");
+        push_entry!(&self.dns_delay_ms, "dns-delay-ms");
+        push_entry!(&self.fail_before_every_nth, "fail-before-every-nth");
+        push_entry!(&self.delay_before_jitter_ms, "delay-before-jitter-ms");
+        push_entry!(&self.delay_after_jitter_ms, "delay-after-jitter-ms");
+        push_entry!(&self.mutate_request_path, "mutate-request-path");
+        push_entry!(&self.mutate_request_mode, "mutate-request-mode");
+        push_entry!(&self.mutate_request_delta, "mutate-request-delta");
+        push_entry!(&self.mutate_response_path, "mutate-response-path");
+        push_entry!(&self.mutate_response_mode, "mutate-response-mode");
+        push_entry!(&self.mutate_response_delta, "mutate-response-delta");
+        push_entry!(
+            &self.degrade_socket_buffer_bytes,
+            "degrade-socket-buffer-bytes"
+        );
+        push_entry!(&self.retry_attempts, "retry-attempts");
+        push_entry!(&self.retry_backoff_ms, "retry-backoff-ms");
+        push_entry!(&self.stream_expiry_mode, "stream-expiry-mode");
+        push_entry!(&self.interim_response_code, "interim-response-code");
+        push_entry!(&self.match_uri, "match-uri");
+        push_entry!(&self.match_uri_regex, "match-uri-regex");
+        push_entry!(&self.match_method, "match-method");
+        push_entry!(&self.match_uri_starts_with, "match-uri-starts-with");
+        push_entry!(&self.match_host, "match-host");
+        push_entry!(&self.match_header_name, "match-header-name");
+        push_entry!(&self.match_header_value, "match-header-value");
+        push_entry!(&self.destination_url, "destination-url");
         values
     }
 }
 
-fn parse_env_u8(key: &str) -> Option<u8> {
-    std::env::var(key).ok()?.parse().ok()
+fn parse_env<T: FromStr<Err: fmt::Display>>(key: &str) -> Option<T> {
+    parse_value(key, &std::env::var(key).ok()?)
 }
 
-fn parse_env_u16(key: &str) -> Option<u16> {
-    std::env::var(key).ok()?.parse().ok()
+fn parse_value<T: FromStr<Err: fmt::Display>>(name: &str, text: &str) -> Option<T> {
+    text.parse()
+        .map_err(|err| warn!("Ignoring invalid {name}: {err}"))
+        .ok()
 }
 
-fn parse_env_u64(key: &str) -> Option<u64> {
-    std::env::var(key).ok()?.parse().ok()
+// Garbage is generated per response, so one request must not be able to ask
+// for more than the proxy can allocate.
+fn parse_garbage_bytes(name: &str, text: &str) -> Option<u64> {
+    parse_value(name, text).filter(|bytes| {
+        let allowed = *bytes <= MAX_STREAM_GARBAGE_BYTES;
+        if !allowed {
+            warn!("Ignoring {name} above {MAX_STREAM_GARBAGE_BYTES} bytes: {bytes}");
+        }
+        allowed
+    })
+}
+
+fn parse_packet_bytes(name: &str, text: &str) -> Option<u64> {
+    parse_value(name, text).filter(|bytes| {
+        let allowed = *bytes >= MIN_STREAM_LOSS_PACKET_BYTES;
+        if !allowed {
+            warn!("Ignoring {name} below {MIN_STREAM_LOSS_PACKET_BYTES} bytes: {bytes}");
+        }
+        allowed
+    })
+}
+
+fn parse_env_bool(key: &str) -> Option<bool> {
+    parse_bool(&std::env::var(key).ok()?)
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    if text.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if text.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
 }
 
 fn env_string(key: &str) -> Option<String> {
     std::env::var(key).ok().filter(|value| !value.is_empty())
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    #[default]
+    Allow,
+    Deny,
+    Allowlist(Vec<String>),
+}
+
+impl HeaderPolicy {
+    pub fn parse(policy: &str, allowlist: Option<&str>) -> Result<Self, String> {
+        match policy.to_ascii_lowercase().as_str() {
+            "" | "allow" => Ok(HeaderPolicy::Allow),
+            "deny" => Ok(HeaderPolicy::Deny),
+            "allowlist" => Ok(HeaderPolicy::Allowlist(
+                allowlist
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|field| field.trim().to_ascii_lowercase())
+                    .filter(|field| !field.is_empty())
+                    .collect(),
+            )),
+            other => Err(format!("unknown header policy {other:?}")),
+        }
+    }
+
+    pub fn allows(&self, field: &str) -> bool {
+        match self {
+            HeaderPolicy::Allow => true,
+            _ if field == "destination-url" => true,
+            HeaderPolicy::Deny => false,
+            HeaderPolicy::Allowlist(fields) => fields.iter().any(|allowed| allowed == field),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precedence {
+    #[default]
+    HeadersWin,
+    AdminWins,
+}
+
+impl Precedence {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "" | "headers-win" => Ok(Precedence::HeadersWin),
+            "admin-wins" => Ok(Precedence::AdminWins),
+            other => Err(format!("unknown settings precedence {other:?}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RequestContext {
     pub method: Method,
     pub uri: String,
     pub headers: HashMap<String, String>,
+    pub deployment_marker: Option<String>,
 }
 
 impl RequestContext {
@@ -336,6 +1596,7 @@ impl RequestContext {
             method,
             uri,
             headers,
+            deployment_marker: None,
         }
     }
 }
@@ -343,11 +1604,18 @@ impl RequestContext {
 pub fn from_parts(method: &Method, uri: &Uri, headers: &HeaderMap) -> RequestContext {
     RequestContext {
         method: method.clone(),
-        uri: uri
-            .path_and_query()
-            .map(|pq| pq.as_str().to_string())
-            .unwrap_or_else(|| uri.path().to_string()),
+        uri: request_target(uri),
         headers: headers_to_map(headers),
+        deployment_marker: None,
+    }
+}
+
+// The authority for CONNECT's authority-form, otherwise the path and query.
+pub fn request_target(uri: &Uri) -> String {
+    match (uri.path_and_query(), uri.authority()) {
+        (Some(pq), _) => pq.as_str().to_string(),
+        (None, Some(authority)) => authority.to_string(),
+        (None, None) => uri.path().to_string(),
     }
 }
 
@@ -362,64 +1630,153 @@ fn headers_to_map(headers: &HeaderMap) -> HashMap<String, String> {
 }
 
 pub fn matches_request(ctx: &RequestContext, settings: &Settings) -> bool {
-    matches_uri(&settings.match_uri, &ctx.uri)
-        && matches_uri_regex(&settings.match_uri_regex, &ctx.uri)
-        && matches_host(&settings.match_host, settings.destination_url.as_deref())
-        && matches_uri_starts_with(&settings.match_uri_starts_with, &ctx.uri)
-        && matches_method(&settings.match_method, &ctx.method)
-        && match_header(
-            &ctx.headers,
-            &settings.match_header_name,
-            &settings.match_header_value,
-        )
+    explain_match(ctx, settings)
+        .iter()
+        .all(|(_, matched)| *matched)
 }
 
-fn matches_uri(pattern: &str, uri: &str) -> bool {
-    pattern == "*" || pattern == uri
-}
-
-fn matches_uri_regex(pattern: &str, uri: &str) -> bool {
-    if pattern == "*" {
-        return true;
+pub fn explain_match(ctx: &RequestContext, settings: &Settings) -> Vec<(&'static str, bool)> {
+    let method = ctx.method.as_str().to_ascii_uppercase();
+    let host = settings
+        .destination_url
+        .as_deref()
+        .and_then(destination_host_fragment);
+    let checks = [
+        ("match-uri", &settings.match_uri, Some(ctx.uri.as_str())),
+        (
+            "match-uri-regex",
+            &settings.match_uri_regex,
+            Some(ctx.uri.as_str()),
+        ),
+        (
+            "match-uri-starts-with",
+            &settings.match_uri_starts_with,
+            Some(ctx.uri.as_str()),
+        ),
+        (
+            "match-method",
+            &settings.match_method,
+            Some(method.as_str()),
+        ),
+        ("match-host", &settings.match_host, host.as_deref()),
+        (
+            "match-deployment-marker",
+            &settings.match_deployment_marker,
+            ctx.deployment_marker.as_deref(),
+        ),
+    ];
+    let mut results: Vec<_> = checks
+        .iter()
+        .filter(|(_, pattern, _)| !pattern.is_any())
+        .map(|(name, pattern, subject)| (*name, pattern.matches(*subject)))
+        .collect();
+    if !settings.match_header_name.is_any() && !settings.match_header_value.is_any() {
+        results.push((
+            "match-header",
+            matches_header(
+                &ctx.headers,
+                &settings.match_header_name,
+                &settings.match_header_value,
+            ),
+        ));
     }
-    match Regex::new(pattern) {
-        Ok(regex) => regex
-            .find(uri)
-            .map(|m| m.start() == 0 && m.end() == uri.len())
-            .unwrap_or(false),
-        Err(err) => {
-            warn!("Invalid match-uri-regex pattern {pattern:?}: {err}");
-            false
-        }
+    if settings.match_time_range != "*" || settings.match_days != "*" {
+        results.push(("match-schedule", matches_schedule(settings)));
     }
+    results
 }
 
-fn matches_uri_starts_with(prefix: &str, uri: &str) -> bool {
-    prefix == "*" || uri.starts_with(prefix)
+fn matches_header(
+    headers: &HashMap<String, String>,
+    name: &MatchPattern,
+    value: &MatchPattern,
+) -> bool {
+    match name {
+        MatchPattern::Exact(name) if !value.is_any() => {
+            value.matches(headers.get(name).map(String::as_str))
+        }
+        _ => true,
+    }
 }
 
-fn matches_method(pattern: &str, method: &Method) -> bool {
-    pattern == "*" || pattern.eq_ignore_ascii_case(method.as_str())
+fn matches_schedule(settings: &Settings) -> bool {
+    let zone = match settings.match_timezone.as_deref() {
+        Some(name) => match TimeZone::get(name) {
+            Ok(zone) => zone,
+            Err(err) => {
+                warn!("Invalid match-timezone {name:?}: {err}");
+                return false;
+            }
+        },
+        None => TimeZone::system(),
+    };
+    let now = clock::timestamp().to_zoned(zone);
+    matches_time_range(&settings.match_time_range, now.time())
+        && matches_days(&settings.match_days, now.weekday())
 }
 
-fn match_header(headers: &HashMap<String, String>, name: &str, value: &str) -> bool {
-    if name == "*" || value == "*" {
+fn matches_time_range(pattern: &str, time: Time) -> bool {
+    if pattern == "*" {
         return true;
     }
-    headers
-        .get(&name.to_ascii_lowercase())
-        .map(|v| v == value)
-        .unwrap_or(false)
+    let range = pattern
+        .split_once('-')
+        .and_then(|(start, end)| Some((minute_of_day(start)?, minute_of_day(end)?)));
+    let Some((start, end)) = range else {
+        warn!("Invalid match-time-range {pattern:?}");
+        return false;
+    };
+    let minute = i16::from(time.hour()) * 60 + i16::from(time.minute());
+    match start.cmp(&end) {
+        std::cmp::Ordering::Less => start <= minute && minute < end,
+        std::cmp::Ordering::Greater => minute >= start || minute < end,
+        std::cmp::Ordering::Equal => true,
+    }
+}
+
+fn minute_of_day(text: &str) -> Option<i16> {
+    let (hour, minute) = text.trim().split_once(':')?;
+    let hour: i16 = hour.parse().ok()?;
+    let minute: i16 = minute.parse().ok()?;
+    ((0..24).contains(&hour) && (0..60).contains(&minute)).then_some(hour * 60 + minute)
 }
 
-fn matches_host(pattern: &str, destination: Option<&str>) -> bool {
+fn matches_days(pattern: &str, today: Weekday) -> bool {
     if pattern == "*" {
         return true;
     }
-    destination
-        .and_then(destination_host_fragment)
-        .map(|host| host == pattern)
-        .unwrap_or(false)
+    let today = today.to_monday_zero_offset();
+    let mut matched = false;
+    for part in pattern.split(',') {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let (Some(first), Some(last)) = (day_index(first), day_index(last)) else {
+            warn!("Invalid match-days {pattern:?}");
+            return false;
+        };
+        matched |= if first <= last {
+            (first..=last).contains(&today)
+        } else {
+            today >= first || today <= last
+        };
+    }
+    matched
+}
+
+fn day_index(text: &str) -> Option<i8> {
+    const DAYS: [&str; 7] = [
+        "monday",
+        "tuesday",
+        "wednesday",
+        "thursday",
+        "friday",
+        "saturday",
+        "sunday",
+    ];
+    let text = text.trim().to_ascii_lowercase();
+    let index = DAYS
+        .iter()
+        .position(|day| *day == text || (text.len() == 3 && day.starts_with(&text)))?;
+    Some(index as i8)
 }
 
 pub fn destination_host_fragment(url: &str) -> Option<String> {
@@ -0,0 +1,601 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::body::Body;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{debug, info, warn};
+
+use crate::response::{HeaderValues, header_map, json_response};
+use crate::settings::Percentage;
+
+pub const TEST_ID_HEADER: &str = "x-lowdown-test-id";
+
+const REDACTED: &str = "[redacted]";
+const REDACTED_HEADERS: [&str; 5] = [
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+    "x-api-key",
+];
+const VOLATILE_HEADERS: [&str; 1] = ["date"];
+const MAX_KEPT_EXCHANGES: usize = 1_000;
+pub const MAX_KEPT_TEST_IDS: usize = 1_000;
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+pub struct SnapshotWriter {
+    dir: Option<PathBuf>,
+    sequences: Mutex<HashMap<String, u64>>,
+    sessions: Mutex<BTreeMap<String, Arc<Session>>>,
+    exchanges: Mutex<BTreeMap<String, VecDeque<Arc<Exchange>>>>,
+    kept_order: Mutex<VecDeque<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RecordingSession {
+    #[serde(default = "always")]
+    pub sample_percentage: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_uri_starts_with: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_uri_regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<usize>,
+    #[serde(default)]
+    pub mode: RecordingMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordingMode {
+    #[default]
+    Record,
+    Replay,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionStatus {
+    pub test_id: String,
+    #[serde(flatten)]
+    pub config: Option<RecordingSession>,
+    pub recorded: u64,
+    pub skipped: u64,
+    pub exchanges: usize,
+}
+
+struct Session {
+    config: RecordingSession,
+    sample: Percentage,
+    uri_regex: Option<Regex>,
+    counts: Mutex<(u64, u64)>,
+    cursors: Mutex<HashMap<(String, String), usize>>,
+}
+
+fn always() -> u8 {
+    Percentage::ALWAYS.get()
+}
+
+impl Session {
+    fn new(config: RecordingSession) -> Result<Self, String> {
+        let sample = Percentage::new(config.sample_percentage)
+            .ok_or_else(|| "sample-percentage must be between 0 and 100".to_string())?;
+        let uri_regex = config
+            .match_uri_regex
+            .as_deref()
+            .map(|pattern| Regex::new(&format!("^(?:{pattern})$")))
+            .transpose()
+            .map_err(|err| format!("invalid match-uri-regex: {err}"))?;
+        Ok(Self {
+            config,
+            sample,
+            uri_regex,
+            counts: Mutex::new((0, 0)),
+            cursors: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn admits(&self, method: &str, uri: &str) -> bool {
+        let config = &self.config;
+        let matched = config
+            .match_method
+            .as_deref()
+            .is_none_or(|expected| expected.eq_ignore_ascii_case(method))
+            && config
+                .match_uri_starts_with
+                .as_deref()
+                .is_none_or(|prefix| uri.starts_with(prefix))
+            && self
+                .uri_regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(uri));
+        let admitted = matched && self.sample.roll();
+        let mut counts = self.counts.lock();
+        if admitted {
+            counts.0 += 1;
+        } else {
+            counts.1 += 1;
+        }
+        admitted
+    }
+
+    fn status(&self, test_id: &str, exchanges: usize) -> SessionStatus {
+        let (recorded, skipped) = *self.counts.lock();
+        SessionStatus {
+            test_id: test_id.to_string(),
+            config: Some(self.config.clone()),
+            recorded,
+            skipped,
+            exchanges,
+        }
+    }
+
+    // Each method and URI replays its recorded responses in order, then keeps
+    // repeating the last one.
+    fn next_replay(
+        &self,
+        exchanges: &[Arc<Exchange>],
+        method: &str,
+        uri: &str,
+    ) -> Option<Arc<Exchange>> {
+        let candidates: Vec<&Arc<Exchange>> = exchanges
+            .iter()
+            .filter(|exchange| {
+                exchange.request.method.as_deref() == Some(method)
+                    && exchange.request.uri.as_deref() == Some(uri)
+            })
+            .collect();
+        let mut cursors = self.cursors.lock();
+        let cursor = cursors
+            .entry((method.to_string(), uri.to_string()))
+            .or_default();
+        let exchange = candidates.get(*cursor).or(candidates.last())?;
+        *cursor += 1;
+        Some(Arc::clone(exchange))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Exchange {
+    test_id: String,
+    sequence: u64,
+    request: Message,
+    response: Message,
+    faults: Vec<String>,
+}
+
+impl Exchange {
+    fn replay(&self) -> Response<Body> {
+        let body = match (&self.response.body, &self.response.body_base64) {
+            (Some(text), _) => text.clone().into_bytes(),
+            (None, Some(encoded)) => STANDARD.decode(encoded).unwrap_or_default(),
+            (None, None) => Vec::new(),
+        };
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = self
+            .response
+            .status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::OK);
+        for (name, values) in &self.response.headers {
+            let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                continue;
+            };
+            if name == CONTENT_LENGTH || name == TRANSFER_ENCODING {
+                continue;
+            }
+            for value in values.iter() {
+                if let Ok(value) = HeaderValue::from_str(value) {
+                    response.headers_mut().append(name.clone(), value);
+                }
+            }
+        }
+        response
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Message {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    headers: BTreeMap<String, HeaderValues>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    body_truncated: bool,
+}
+
+impl Message {
+    fn new(headers: &HeaderMap, body: &CappedBody) -> Self {
+        let mut headers = header_map(headers);
+        headers.retain(|name, _| !VOLATILE_HEADERS.contains(&name.as_str()));
+        for (name, values) in headers.iter_mut() {
+            if REDACTED_HEADERS.contains(&name.as_str()) {
+                values.replace_each(REDACTED);
+            }
+        }
+        let (body_text, body_base64) = match std::str::from_utf8(&body.bytes) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(err) if body.truncated && err.error_len().is_none() => (
+                Some(String::from_utf8_lossy(&body.bytes[..err.valid_up_to()]).into_owned()),
+                None,
+            ),
+            Err(_) => (None, Some(STANDARD.encode(&body.bytes))),
+        };
+        Self {
+            method: None,
+            uri: None,
+            status: None,
+            headers,
+            body: body_text,
+            body_base64,
+            body_truncated: body.truncated,
+        }
+    }
+}
+
+pub struct Capture {
+    writer: Arc<SnapshotWriter>,
+    test_id: String,
+    method: String,
+    uri: String,
+    headers: HeaderMap,
+    request_body: Arc<Mutex<CappedBody>>,
+    max_body_bytes: Option<usize>,
+}
+
+#[derive(Default)]
+struct CappedBody {
+    bytes: Vec<u8>,
+    limit: Option<usize>,
+    truncated: bool,
+}
+
+impl CappedBody {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        let room = self
+            .limit
+            .map_or(data.len(), |limit| limit.saturating_sub(self.bytes.len()));
+        if data.len() > room {
+            self.truncated = true;
+        }
+        self.bytes.extend_from_slice(&data[..room.min(data.len())]);
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            ..Self::in_memory()
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            sequences: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(BTreeMap::new()),
+            exchanges: Mutex::new(BTreeMap::new()),
+            kept_order: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Test ids that were recorded without a session are listed too.
+    pub fn sessions(&self) -> Vec<SessionStatus> {
+        let sessions = self.sessions.lock();
+        let exchanges = self.exchanges.lock();
+        let sequences = self.sequences.lock();
+        let mut test_ids: Vec<&String> = sessions.keys().chain(exchanges.keys()).collect();
+        test_ids.sort();
+        test_ids.dedup();
+        test_ids
+            .into_iter()
+            .map(|test_id| {
+                let kept = exchanges.get(test_id).map_or(0, VecDeque::len);
+                match sessions.get(test_id) {
+                    Some(session) => session.status(test_id, kept),
+                    None => SessionStatus {
+                        test_id: test_id.clone(),
+                        config: None,
+                        recorded: sequences.get(test_id).copied().unwrap_or_default(),
+                        skipped: 0,
+                        exchanges: kept,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    pub fn session(&self, test_id: &str) -> Option<SessionStatus> {
+        let test_id = sanitize(test_id);
+        self.sessions()
+            .into_iter()
+            .find(|session| session.test_id == test_id)
+    }
+
+    pub fn recording(&self, test_id: &str) -> Option<Value> {
+        let status = self.session(test_id)?;
+        let exchanges = self.kept(&status.test_id);
+        let mut recording = serde_json::to_value(status).ok()?;
+        recording["exchanges"] = json!(exchanges.iter().map(Arc::as_ref).collect::<Vec<_>>());
+        Some(recording)
+    }
+
+    fn kept(&self, test_id: &str) -> Vec<Arc<Exchange>> {
+        self.exchanges
+            .lock()
+            .get(test_id)
+            .map(|kept| kept.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn start_session(
+        &self,
+        test_id: &str,
+        config: RecordingSession,
+    ) -> Result<SessionStatus, String> {
+        let test_id = sanitize(test_id);
+        if test_id.is_empty() {
+            return Err("test id must not be empty".to_string());
+        }
+        let session = Session::new(config)?;
+        if session.config.mode == RecordingMode::Replay
+            && self.kept(&test_id).is_empty()
+            && let Some(dir) = &self.dir
+        {
+            self.load(&test_id, &dir.join(&test_id))?;
+        }
+        let status = session.status(&test_id, self.kept(&test_id).len());
+        self.sessions.lock().insert(test_id, Arc::new(session));
+        Ok(status)
+    }
+
+    pub fn stop_session(&self, test_id: &str) -> Option<SessionStatus> {
+        let test_id = sanitize(test_id);
+        let session = self.sessions.lock().remove(&test_id)?;
+        Some(session.status(&test_id, self.kept(&test_id).len()))
+    }
+
+    // Replaying a test id recorded by an earlier run reads its snapshot files.
+    fn load(&self, test_id: &str, dir: &Path) -> Result<(), String> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(());
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        let mut loaded = VecDeque::new();
+        for path in paths.iter().rev().take(MAX_KEPT_EXCHANGES).rev() {
+            let exchange = std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|json| serde_json::from_slice(&json).map_err(|err| err.to_string()))
+                .map_err(|err| format!("invalid snapshot {}: {err}", path.display()))?;
+            loaded.push_back(Arc::new(exchange));
+        }
+        info!("Loaded {} snapshots to replay for {test_id}", loaded.len());
+        self.keep(test_id, |kept| *kept = loaded);
+        Ok(())
+    }
+
+    pub fn replay(&self, req: &Request<Body>, dev_mode: bool) -> Option<Response<Body>> {
+        let test_id = test_id(req)?;
+        let session = self.sessions.lock().get(&test_id).cloned()?;
+        if session.config.mode != RecordingMode::Replay {
+            return None;
+        }
+        let method = req.method().as_str();
+        let uri = req.uri().to_string();
+        match session.next_replay(&self.kept(&test_id), method, &uri) {
+            Some(exchange) => {
+                debug!(
+                    "Replaying {test_id} #{} for {method} {uri}",
+                    exchange.sequence
+                );
+                Some(exchange.replay())
+            }
+            None => Some(json_response(
+                StatusCode::NOT_FOUND,
+                &json!({"error":"no-recording","method":method,"uri":uri}),
+                dev_mode,
+            )),
+        }
+    }
+
+    pub fn capture(self: &Arc<Self>, req: Request<Body>) -> (Request<Body>, Option<Capture>) {
+        let Some(test_id) = test_id(&req) else {
+            return (req, None);
+        };
+        let session = self.sessions.lock().get(&test_id).cloned();
+        let method = req.method().to_string();
+        let uri = req.uri().to_string();
+        if let Some(session) = &session
+            && !session.admits(&method, &uri)
+        {
+            return (req, None);
+        }
+        let max_body_bytes = match session {
+            Some(session) => session.config.max_body_bytes,
+            None => Some(DEFAULT_MAX_BODY_BYTES),
+        };
+        let request_body = Arc::new(Mutex::new(CappedBody::new(max_body_bytes)));
+        let capture = Capture {
+            writer: self.clone(),
+            test_id,
+            method,
+            uri,
+            headers: req.headers().clone(),
+            request_body: request_body.clone(),
+            max_body_bytes,
+        };
+        (req.map(|body| tee(body, request_body)), Some(capture))
+    }
+
+    fn write(&self, mut exchange: Exchange) {
+        let sequence = {
+            let mut guard = self.sequences.lock();
+            let sequence = guard.entry(exchange.test_id.clone()).or_default();
+            *sequence += 1;
+            *sequence
+        };
+        exchange.sequence = sequence;
+        if let Some(dir) = &self.dir {
+            let dir = dir.join(&exchange.test_id);
+            let path = dir.join(format!("{sequence:04}.json"));
+            let result = serde_json::to_vec_pretty(&exchange)
+                .map_err(std::io::Error::from)
+                .and_then(|json| {
+                    std::fs::create_dir_all(&dir)?;
+                    std::fs::write(&path, json)
+                });
+            match result {
+                Ok(()) => debug!("Wrote snapshot {}", path.display()),
+                Err(err) => warn!("Failed to write snapshot {}: {err}", path.display()),
+            }
+        }
+        let test_id = exchange.test_id.clone();
+        self.keep(&test_id, |kept| {
+            if kept.len() == MAX_KEPT_EXCHANGES {
+                kept.pop_front();
+            }
+            kept.push_back(Arc::new(exchange));
+        });
+    }
+
+    // Only the exchanges of the last test ids are kept; the oldest one without
+    // a session is forgotten first.
+    fn keep(&self, test_id: &str, update: impl FnOnce(&mut VecDeque<Arc<Exchange>>)) {
+        let sessions = self.sessions.lock();
+        let mut exchanges = self.exchanges.lock();
+        let mut order = self.kept_order.lock();
+        if !exchanges.contains_key(test_id) {
+            order.push_back(test_id.to_string());
+        }
+        update(exchanges.entry(test_id.to_string()).or_default());
+        if exchanges.len() <= MAX_KEPT_TEST_IDS {
+            return;
+        }
+        let Some(index) = order
+            .iter()
+            .position(|kept| kept != test_id && !sessions.contains_key(kept))
+        else {
+            return;
+        };
+        if let Some(evicted) = order.remove(index) {
+            exchanges.remove(&evicted);
+            if self.dir.is_none() {
+                self.sequences.lock().remove(&evicted);
+            }
+            debug!("Forgot the recorded exchanges of {evicted}");
+        }
+    }
+}
+
+fn test_id(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get(TEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(sanitize)
+        .filter(|test_id| !test_id.is_empty())
+}
+
+impl Capture {
+    pub fn finish(self, response: Response<Body>, faults: Vec<&'static str>) -> Response<Body> {
+        let (parts, body) = response.into_parts();
+        let mut pending = PendingExchange {
+            status: parts.status.as_u16(),
+            headers: parts.headers.clone(),
+            body: CappedBody::new(self.max_body_bytes),
+            capture: Some(self),
+            faults,
+        };
+        let body = body.map_frame(move |frame| {
+            if let Some(data) = frame.data_ref() {
+                pending.body.extend(data);
+            }
+            frame
+        });
+        Response::from_parts(parts, Body::new(body))
+    }
+}
+
+struct PendingExchange {
+    capture: Option<Capture>,
+    status: u16,
+    headers: HeaderMap,
+    body: CappedBody,
+    faults: Vec<&'static str>,
+}
+
+impl Drop for PendingExchange {
+    fn drop(&mut self) {
+        let Some(capture) = self.capture.take() else {
+            return;
+        };
+        let mut request = Message::new(&capture.headers, &capture.request_body.lock());
+        request.method = Some(capture.method);
+        request.uri = Some(capture.uri);
+        let mut response = Message::new(&self.headers, &self.body);
+        response.status = Some(self.status);
+        capture.writer.write(Exchange {
+            test_id: capture.test_id,
+            sequence: 0,
+            request,
+            response,
+            faults: self.faults.iter().map(ToString::to_string).collect(),
+        });
+    }
+}
+
+fn tee(body: Body, sink: Arc<Mutex<CappedBody>>) -> Body {
+    Body::new(body.map_frame(move |frame| {
+        if let Some(data) = frame.data_ref() {
+            sink.lock().extend(data);
+        }
+        frame
+    }))
+}
+
+pub fn sanitize(test_id: &str) -> String {
+    test_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
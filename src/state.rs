@@ -1,32 +1,331 @@
+use http::HeaderMap;
+use jiff::Timestamp;
 use parking_lot::{Mutex, RwLock};
-use std::collections::VecDeque;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::watch;
 use tracing::info;
 use uuid::Uuid;
 
+use crate::access_log::AccessLog;
+use crate::admin_chaos::AdminChaos;
+use crate::assertions::AssertionRegistry;
+use crate::binding::BindingRegistry;
+use crate::breaker::{CircuitBreakers, CircuitBreakingClient};
+use crate::clock::{Clock, SystemClock};
+use crate::comparisons::ComparisonLog;
+use crate::config_file::ConfigFile;
+use crate::credentials::{AuthenticatingClient, CredentialRegistry};
+use crate::default_behavior::DefaultBehavior;
+use crate::destinations::DestinationRegistry;
+use crate::health::{HealthConfig, UpstreamHealth};
 use crate::http_client::SharedHttpClient;
-use crate::settings::{RequestContext, Settings, SettingsLayer, matches_request};
+use crate::kv::KvStore;
+use crate::limits::{LimitRegistry, RateLimitingClient, authority};
+use crate::logging::LogLevel;
+use crate::metrics::Metrics;
+use crate::random::SeededRng;
+use crate::rules::{NamedRule, RequestPredicate, Rule};
+use crate::scenarios::ScenarioRunner;
+use crate::settings::{
+    HEADER_PREFIX, HeaderPolicy, MIKKMOKK_HEADER_PREFIX, PROFILE_HEADER, Precedence,
+    RequestContext, Settings, SettingsLayer, matches_request,
+};
+use crate::snapshots::SnapshotWriter;
+use crate::streams::StreamRegistry;
+use crate::stubs::{StubRegistry, StubbingClient};
+use crate::supervisor::Supervision;
+use crate::tasks::TaskRegistry;
+use crate::toxiproxy::ToxiproxyRegistry;
+use crate::traffic::TrafficLog;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionConflict {
+    pub current: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOneOffError {
+    Conflict(VersionConflict),
+    // `MAX_TRACKED_ONE_OFFS` one-offs are pending already.
+    Full,
+}
+
+impl From<VersionConflict> for AddOneOffError {
+    fn from(conflict: VersionConflict) -> Self {
+        Self::Conflict(conflict)
+    }
+}
 
 pub struct AppState {
     env_layer: SettingsLayer,
+    file_layer: RwLock<SettingsLayer>,
+    file_references: RwLock<BTreeMap<String, String>>,
+    file_rules: Mutex<Vec<String>>,
+    config_file: Option<PathBuf>,
     admin_overrides: RwLock<SettingsLayer>,
+    settings_version: AtomicU64,
     one_off: Mutex<VecDeque<OneOffRule>>,
+    one_off_progress: Mutex<HashMap<Uuid, Arc<watch::Sender<OneOffProgress>>>>,
+    named_rules: RwLock<Vec<NamedRule>>,
+    profiles: RwLock<BTreeMap<String, SettingsLayer>>,
+    file_profiles: Mutex<Vec<String>>,
+    profile_references: RwLock<BTreeMap<String, BTreeMap<String, String>>>,
     client: SharedHttpClient,
-    body_trailer: String,
+    dev_mode: bool,
+    metrics: Metrics,
+    mikkmokk_compat: bool,
+    header_policy: HeaderPolicy,
+    precedence: Precedence,
+    health: UpstreamHealth,
+    toxiproxy: ToxiproxyRegistry,
+    stubs: Arc<StubRegistry>,
+    traffic: TrafficLog,
+    assertions: AssertionRegistry,
+    tasks: TaskRegistry,
+    scenarios: ScenarioRunner,
+    log_level: Option<Arc<LogLevel>>,
+    kv: KvStore,
+    deployment_marker: RwLock<Option<String>>,
+    comparisons: ComparisonLog,
+    admin_chaos: Option<RwLock<AdminChaos>>,
+    limits: Arc<LimitRegistry>,
+    breakers: Arc<CircuitBreakers>,
+    bindings: BindingRegistry,
+    credentials: Arc<CredentialRegistry>,
+    destination_settings: DestinationRegistry<SettingsLayer>,
+    streams: StreamRegistry,
+    default_behavior: RwLock<DefaultBehavior>,
+    snapshots: Option<Arc<SnapshotWriter>>,
+    response_tee_dir: Option<PathBuf>,
+    rng: Option<SeededRng>,
+    clock: Arc<dyn Clock>,
+    access_log: AccessLog,
+    supervision: Arc<Supervision>,
+    started_at: Timestamp,
 }
 
+pub struct AppStateBuilder {
+    env_layer: SettingsLayer,
+    dev_mode: bool,
+    client: SharedHttpClient,
+    mikkmokk_compat: bool,
+    header_policy: HeaderPolicy,
+    precedence: Precedence,
+    health_config: HealthConfig,
+    log_level: Option<Arc<LogLevel>>,
+    admin_chaos: bool,
+    kv: Option<KvStore>,
+    snapshots: Option<SnapshotWriter>,
+    response_tee_dir: Option<PathBuf>,
+    config_file: Option<PathBuf>,
+    random_seed: Option<u64>,
+    clock: Arc<dyn Clock>,
+    access_log: AccessLog,
+}
+
+impl AppStateBuilder {
+    pub fn env_layer(mut self, env_layer: SettingsLayer) -> Self {
+        self.env_layer = env_layer;
+        self
+    }
+
+    pub fn dev_mode(mut self, enabled: bool) -> Self {
+        self.dev_mode = enabled;
+        self
+    }
+
+    pub fn mikkmokk_compat(mut self, enabled: bool) -> Self {
+        self.mikkmokk_compat = enabled;
+        self
+    }
+
+    pub fn header_policy(mut self, policy: HeaderPolicy) -> Self {
+        self.header_policy = policy;
+        self
+    }
+
+    pub fn precedence(mut self, precedence: Precedence) -> Self {
+        self.precedence = precedence;
+        self
+    }
+
+    pub fn health_config(mut self, config: HealthConfig) -> Self {
+        self.health_config = config;
+        self
+    }
+
+    pub fn log_level(mut self, log_level: impl Into<Arc<LogLevel>>) -> Self {
+        self.log_level = Some(log_level.into());
+        self
+    }
+
+    pub fn admin_chaos(mut self, enabled: bool) -> Self {
+        self.admin_chaos = enabled;
+        self
+    }
+
+    pub fn kv_store(mut self, kv: KvStore) -> Self {
+        self.kv = Some(kv);
+        self
+    }
+
+    pub fn snapshots(mut self, snapshots: SnapshotWriter) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    pub fn response_tee_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.response_tee_dir = Some(dir.into());
+        self
+    }
+
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    pub fn random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    pub fn build(self) -> AppState {
+        let stubs = Arc::new(StubRegistry::default());
+        let limits = Arc::new(LimitRegistry::default());
+        let breakers = Arc::new(CircuitBreakers::default().with_clock(self.clock.clone()));
+        let limited = Arc::new(RateLimitingClient::new(limits.clone(), self.client));
+        let guarded = Arc::new(CircuitBreakingClient::new(
+            limits.clone(),
+            breakers.clone(),
+            limited,
+        ));
+        let credentials = Arc::new(CredentialRegistry::default());
+        let authenticated = Arc::new(AuthenticatingClient::new(credentials.clone(), guarded));
+        AppState {
+            env_layer: self.env_layer,
+            file_layer: RwLock::new(SettingsLayer::default()),
+            file_references: RwLock::new(BTreeMap::new()),
+            file_rules: Mutex::new(Vec::new()),
+            config_file: self.config_file,
+            admin_overrides: RwLock::new(SettingsLayer::default()),
+            settings_version: AtomicU64::new(1),
+            one_off: Mutex::new(VecDeque::new()),
+            one_off_progress: Mutex::new(HashMap::new()),
+            named_rules: RwLock::new(Vec::new()),
+            profiles: RwLock::new(BTreeMap::new()),
+            file_profiles: Mutex::new(Vec::new()),
+            profile_references: RwLock::new(BTreeMap::new()),
+            client: Arc::new(StubbingClient::new(stubs.clone(), authenticated)),
+            dev_mode: self.dev_mode,
+            metrics: Metrics::default(),
+            mikkmokk_compat: self.mikkmokk_compat,
+            header_policy: self.header_policy,
+            precedence: self.precedence,
+            health: UpstreamHealth::new(self.health_config).with_clock(self.clock.clone()),
+            toxiproxy: ToxiproxyRegistry::default(),
+            stubs,
+            traffic: TrafficLog::default(),
+            assertions: AssertionRegistry::default(),
+            tasks: TaskRegistry::default(),
+            scenarios: ScenarioRunner::default(),
+            log_level: self.log_level,
+            kv: self.kv.unwrap_or_default().with_clock(self.clock.clone()),
+            deployment_marker: RwLock::new(None),
+            comparisons: ComparisonLog::default(),
+            admin_chaos: self.admin_chaos.then(|| RwLock::new(AdminChaos::default())),
+            limits,
+            breakers,
+            bindings: BindingRegistry::default(),
+            credentials,
+            destination_settings: DestinationRegistry::default(),
+            streams: StreamRegistry::default(),
+            default_behavior: RwLock::new(DefaultBehavior::default()),
+            snapshots: self.snapshots.map(Arc::new),
+            response_tee_dir: self.response_tee_dir,
+            rng: self.random_seed.map(SeededRng::new),
+            clock: self.clock,
+            access_log: self.access_log,
+            supervision: Arc::new(Supervision::default()),
+            started_at: Timestamp::now(),
+        }
+    }
+}
+
+pub const MAX_TRACKED_ONE_OFFS: usize = 1_000;
+
 struct OneOffRule {
     id: Uuid,
     settings: Settings,
+    predicate: RequestPredicate,
+    remaining: u64,
+    progress: Arc<watch::Sender<OneOffProgress>>,
+    normalized: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingOneOff {
+    pub id: Uuid,
+    pub remaining: u64,
+    #[serde(flatten)]
+    pub rule: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OneOffProgress {
+    pub consumed: bool,
+    pub matches: Vec<OneOffMatch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OneOffMatch {
+    pub at: Timestamp,
+    pub method: String,
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_marker: Option<String>,
 }
 
 impl AppState {
-    pub fn new(env_layer: SettingsLayer, body_trailer: String, client: SharedHttpClient) -> Self {
-        Self {
-            env_layer,
-            admin_overrides: RwLock::new(SettingsLayer::default()),
-            one_off: Mutex::new(VecDeque::new()),
+    pub fn new(env_layer: SettingsLayer, client: SharedHttpClient) -> Self {
+        Self::builder(client).env_layer(env_layer).build()
+    }
+
+    pub fn builder(client: SharedHttpClient) -> AppStateBuilder {
+        AppStateBuilder {
+            env_layer: SettingsLayer::default(),
+            dev_mode: false,
             client,
-            body_trailer,
+            mikkmokk_compat: false,
+            header_policy: HeaderPolicy::default(),
+            precedence: Precedence::default(),
+            health_config: HealthConfig::default(),
+            log_level: None,
+            admin_chaos: false,
+            kv: None,
+            snapshots: None,
+            response_tee_dir: None,
+            config_file: None,
+            random_seed: None,
+            clock: Arc::new(SystemClock),
+            access_log: AccessLog::default(),
         }
     }
 
@@ -36,69 +335,667 @@ impl AppState {
         }
     }
 
-    pub fn body_trailer(&self) -> &str {
-        &self.body_trailer
+    pub fn dev_mode(&self) -> bool {
+        self.dev_mode
     }
 
     pub fn client(&self) -> SharedHttpClient {
         self.client.clone()
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn health(&self) -> &UpstreamHealth {
+        &self.health
+    }
+
+    pub fn stubs(&self) -> &StubRegistry {
+        &self.stubs
+    }
+
+    pub fn toxiproxy(&self) -> &ToxiproxyRegistry {
+        &self.toxiproxy
+    }
+
+    pub fn traffic(&self) -> &TrafficLog {
+        &self.traffic
+    }
+
+    pub fn assertions(&self) -> &AssertionRegistry {
+        &self.assertions
+    }
+
+    pub fn tasks(&self) -> &TaskRegistry {
+        &self.tasks
+    }
+
+    pub fn scenarios(&self) -> &ScenarioRunner {
+        &self.scenarios
+    }
+
+    pub fn log_level(&self) -> Option<&LogLevel> {
+        self.log_level.as_deref()
+    }
+
+    pub fn kv(&self) -> &KvStore {
+        &self.kv
+    }
+
+    pub fn snapshots(&self) -> Option<&Arc<SnapshotWriter>> {
+        self.snapshots.as_ref()
+    }
+
+    pub fn response_tee_dir(&self) -> Option<&Path> {
+        self.response_tee_dir.as_deref()
+    }
+
+    pub fn seeded_rng(&self) -> Option<SeededRng> {
+        self.rng.clone()
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    pub fn access_log(&self) -> &AccessLog {
+        &self.access_log
+    }
+
+    pub fn supervision(&self) -> Arc<Supervision> {
+        self.supervision.clone()
+    }
+
+    pub fn started_at(&self) -> Timestamp {
+        self.started_at
+    }
+
+    pub fn config_file(&self) -> Option<&Path> {
+        self.config_file.as_deref()
+    }
+
+    pub fn reload_config(&self) -> Result<Option<ConfigFile>, String> {
+        let Some(path) = self.config_file.as_deref() else {
+            return Ok(None);
+        };
+        let config = ConfigFile::load(path)?;
+        let _guard = self.admin_overrides.write();
+        let version = self.settings_version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.streams.expire(version);
+        *self.file_layer.write() = config.layer.clone();
+        *self.file_references.write() = config.references.clone();
+        let mut file_rules = self.file_rules.lock();
+        let mut rules = self.named_rules.write();
+        rules.retain(|named| !file_rules.contains(&named.name));
+        for named in &config.rules {
+            rules.retain(|existing| existing.name != named.name);
+            rules.push(named.clone());
+        }
+        rules.sort_by_key(|named| std::cmp::Reverse(named.priority));
+        *file_rules = config
+            .rules
+            .iter()
+            .map(|named| named.name.clone())
+            .collect();
+        let mut file_profiles = self.file_profiles.lock();
+        let mut profiles = self.profiles.write();
+        profiles.retain(|name, _| !file_profiles.contains(name));
+        profiles.extend(config.profiles.clone());
+        *file_profiles = config.profiles.keys().cloned().collect();
+        *self.profile_references.write() = config.profile_references.clone();
+        info!(
+            "Loaded config file {} with {} settings, {} rules and {} profiles",
+            path.display(),
+            config.layer.entries().len(),
+            config.rules.len(),
+            config.profiles.len()
+        );
+        Ok(Some(config))
+    }
+
+    pub fn deployment_marker(&self) -> Option<String> {
+        self.deployment_marker.read().clone()
+    }
+
+    pub fn set_deployment_marker(&self, marker: Option<String>) {
+        match &marker {
+            Some(marker) => info!("Deployment marker set to {marker}"),
+            None => info!("Deployment marker cleared"),
+        }
+        *self.deployment_marker.write() = marker;
+    }
+
+    pub fn default_behavior(&self) -> DefaultBehavior {
+        self.default_behavior.read().clone()
+    }
+
+    pub fn set_default_behavior(&self, behavior: DefaultBehavior) {
+        info!("Default behavior set to {behavior:?}");
+        *self.default_behavior.write() = behavior;
+    }
+
+    pub fn comparisons(&self) -> &ComparisonLog {
+        &self.comparisons
+    }
+
+    pub fn admin_chaos(&self) -> Option<AdminChaos> {
+        self.admin_chaos.as_ref().map(|chaos| chaos.read().clone())
+    }
+
+    pub fn set_admin_chaos(&self, config: AdminChaos) -> bool {
+        match &self.admin_chaos {
+            Some(chaos) => {
+                *chaos.write() = config;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn limits(&self) -> &LimitRegistry {
+        &self.limits
+    }
+
+    pub fn breakers(&self) -> &CircuitBreakers {
+        &self.breakers
+    }
+
+    pub fn bindings(&self) -> &BindingRegistry {
+        &self.bindings
+    }
+
+    pub fn credentials(&self) -> &CredentialRegistry {
+        &self.credentials
+    }
+
+    pub fn destination_settings(&self) -> &DestinationRegistry<SettingsLayer> {
+        &self.destination_settings
+    }
+
+    pub fn streams(&self) -> &StreamRegistry {
+        &self.streams
+    }
+
+    fn destination_layer(&self, destination_url: &str) -> Option<SettingsLayer> {
+        self.destination_settings.get(&authority(destination_url)?)
+    }
+
+    pub fn mikkmokk_compat(&self) -> bool {
+        self.mikkmokk_compat
+    }
+
+    pub fn layer_from_headers(&self, headers: &HeaderMap) -> SettingsLayer {
+        if !self.mikkmokk_compat {
+            return SettingsLayer::from_headers(headers);
+        }
+        let mut layer = SettingsLayer::from_prefixed_headers(headers, MIKKMOKK_HEADER_PREFIX);
+        layer.merge(&SettingsLayer::from_headers(headers));
+        layer
+    }
+
+    pub fn request_layer(&self, headers: &HeaderMap) -> SettingsLayer {
+        if self.header_policy == HeaderPolicy::Allow {
+            return self.layer_from_headers(headers);
+        }
+        let mut permitted = HeaderMap::new();
+        for (name, value) in headers {
+            let key = name.as_str();
+            let field = key
+                .strip_prefix(HEADER_PREFIX)
+                .or_else(|| key.strip_prefix(MIKKMOKK_HEADER_PREFIX));
+            if field.is_none_or(|field| self.header_policy.allows(field)) {
+                permitted.append(name, value.clone());
+            }
+        }
+        self.layer_from_headers(&permitted)
+    }
+
+    // Err carries the name of a profile that doesn't exist. The header policy
+    // treats `profile` like a setting key.
+    pub fn request_profile(&self, headers: &HeaderMap) -> Result<Option<SettingsLayer>, String> {
+        let Some(name) = headers
+            .get(PROFILE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && self.header_policy.allows("profile"))
+        else {
+            return Ok(None);
+        };
+        self.profile(name).map(Some).ok_or_else(|| name.to_string())
+    }
+
+    pub fn is_settings_header(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        name.starts_with(HEADER_PREFIX)
+            || (self.mikkmokk_compat && name.starts_with(MIKKMOKK_HEADER_PREFIX))
+    }
+
     pub fn merge_admin(&self, layer: SettingsLayer) -> Settings {
         let mut guard = self.admin_overrides.write();
+        let version = self.settings_version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.streams.expire(version);
         guard.merge(&layer);
         self.snapshot_locked(&guard)
     }
 
     pub fn reset_admin(&self, layer: SettingsLayer) -> Settings {
         let mut guard = self.admin_overrides.write();
+        let version = self.settings_version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.streams.expire(version);
         *guard = layer;
         self.snapshot_locked(&guard)
     }
 
+    pub fn merge_admin_if(
+        &self,
+        layer: SettingsLayer,
+        if_match: Option<u64>,
+    ) -> Result<(Settings, u64), VersionConflict> {
+        let mut guard = self.admin_overrides.write();
+        let version = self.bump_version(if_match)?;
+        guard.merge(&layer);
+        Ok((self.snapshot_locked(&guard), version))
+    }
+
+    pub fn reset_admin_if(
+        &self,
+        layer: SettingsLayer,
+        if_match: Option<u64>,
+    ) -> Result<(Settings, u64), VersionConflict> {
+        let mut guard = self.admin_overrides.write();
+        let version = self.bump_version(if_match)?;
+        *guard = layer;
+        Ok((self.snapshot_locked(&guard), version))
+    }
+
+    pub fn preview_admin(&self, layer: &SettingsLayer) -> (Settings, Settings, u64) {
+        let guard = self.admin_overrides.read();
+        let mut merged = guard.clone();
+        merged.merge(layer);
+        (
+            self.snapshot_locked(&guard),
+            self.snapshot_locked(&merged),
+            self.settings_version(),
+        )
+    }
+
+    pub fn settings_version(&self) -> u64 {
+        self.settings_version.load(Ordering::SeqCst)
+    }
+
+    // For settings kept outside the admin overrides, such as destination
+    // settings and toxiproxy toxics, so their changes expire streams too.
+    pub fn settings_changed(&self) -> u64 {
+        let _guard = self.admin_overrides.write();
+        let version = self.settings_version.fetch_add(1, Ordering::SeqCst) + 1;
+        self.streams.expire(version);
+        version
+    }
+
+    // Callers hold the admin_overrides write lock, which serializes bumps.
+    fn bump_version(&self, if_match: Option<u64>) -> Result<u64, VersionConflict> {
+        let current = self.settings_version();
+        if if_match.is_some_and(|expected| expected != current) {
+            return Err(VersionConflict { current });
+        }
+        self.settings_version.store(current + 1, Ordering::SeqCst);
+        self.streams.expire(current + 1);
+        Ok(current + 1)
+    }
+
+    pub fn admin_layer(&self) -> SettingsLayer {
+        self.admin_overrides.read().clone()
+    }
+
     pub fn admin_snapshot(&self) -> Settings {
         let guard = self.admin_overrides.read();
         self.snapshot_locked(&guard)
     }
 
-    pub fn effective_settings(&self, overrides: &SettingsLayer) -> Settings {
-        let mut snapshot = self.admin_snapshot();
-        snapshot.apply_layer(overrides);
-        snapshot
+    pub fn versioned_admin_snapshot(&self) -> (Settings, u64) {
+        let guard = self.admin_overrides.read();
+        (self.snapshot_locked(&guard), self.settings_version())
+    }
+
+    pub fn configured_layer(&self) -> SettingsLayer {
+        let mut layer = self.file_layer.read().clone();
+        layer.merge(&self.env_layer);
+        layer.merge(&self.admin_overrides.read());
+        layer
+    }
+
+    // Settings from the config file that were given as references, unless the
+    // environment or the admin API replaced them.
+    pub fn configured_references(&self) -> BTreeMap<String, String> {
+        let mut replaced = self.env_layer.entries();
+        replaced.extend(self.admin_overrides.read().entries());
+        self.file_references
+            .read()
+            .iter()
+            .filter(|(name, _)| !replaced.iter().any(|(setting, _)| setting == name))
+            .map(|(name, reference)| (name.clone(), reference.clone()))
+            .collect()
+    }
+
+    // A profile selected by the request sits just below its setting headers.
+    pub fn effective_settings(
+        &self,
+        overrides: &SettingsLayer,
+        profile: Option<&SettingsLayer>,
+        destination: Option<&str>,
+    ) -> Settings {
+        let mut settings = Settings::default();
+        if self.precedence == Precedence::AdminWins {
+            if let Some(profile) = profile {
+                settings.apply_layer(profile);
+            }
+            settings.apply_layer(overrides);
+        }
+        settings.apply_layer(&self.file_layer.read());
+        settings.apply_layer(&self.env_layer);
+        settings.apply_layer(&self.admin_overrides.read());
+        let destination = match (destination, self.precedence, &overrides.destination_url) {
+            (Some(url), _, _) => Some(url.to_string()),
+            (None, Precedence::HeadersWin, Some(url)) => {
+                Some(url.clone()).filter(|url| !url.is_empty())
+            }
+            _ => settings.destination_url.clone(),
+        };
+        if let Some(url) = destination {
+            if let Some(layer) = self.toxiproxy.layer_for(&url) {
+                settings.apply_layer(&layer);
+            }
+            if let Some(layer) = self.destination_layer(&url) {
+                settings.apply_layer(&layer);
+            }
+        }
+        if self.precedence == Precedence::HeadersWin {
+            if let Some(profile) = profile {
+                settings.apply_layer(profile);
+            }
+            settings.apply_layer(overrides);
+        }
+        settings
+    }
+
+    pub fn active_layers(
+        &self,
+        overrides: &SettingsLayer,
+        profile: bool,
+        destination_url: Option<&str>,
+    ) -> Vec<&'static str> {
+        let toxiproxy = destination_url.is_some_and(|url| self.toxiproxy.layer_for(url).is_some());
+        let destination = destination_url.is_some_and(|url| self.destination_layer(url).is_some());
+        let mut layers = vec![
+            ("file", !self.file_layer.read().is_empty()),
+            ("env", !self.env_layer.is_empty()),
+            ("admin", !self.admin_overrides.read().is_empty()),
+            ("toxiproxy", toxiproxy),
+            ("destination", destination),
+        ];
+        let request = [("profile", profile), ("headers", !overrides.is_empty())];
+        match self.precedence {
+            Precedence::HeadersWin => layers.extend(request),
+            Precedence::AdminWins => {
+                layers.splice(0..0, request);
+            }
+        }
+        std::iter::once("defaults")
+            .chain(
+                layers
+                    .into_iter()
+                    .filter(|(_, active)| *active)
+                    .map(|(name, _)| name),
+            )
+            .collect()
     }
 
-    pub fn add_one_off(&self, mut settings: Settings) -> Uuid {
+    pub fn add_one_off(
+        &self,
+        rule: Rule,
+        if_match: Option<u64>,
+    ) -> Result<(Uuid, u64), AddOneOffError> {
+        let _guard = self.admin_overrides.write();
+        // Consumed one-offs are kept for waiters until there is no room left;
+        // pending ones are never dropped, so past the cap new ones are refused.
+        let mut tracked = self.one_off_progress.lock();
+        if tracked.len() >= MAX_TRACKED_ONE_OFFS {
+            tracked.retain(|_, progress| !progress.borrow().consumed);
+        }
+        if tracked.len() >= MAX_TRACKED_ONE_OFFS {
+            return Err(AddOneOffError::Full);
+        }
+        let version = self.bump_version(if_match)?;
         let id = Uuid::new_v4();
+        let normalized = rule.to_json();
+        let mut settings = Settings::default();
+        settings.apply_layer(&rule.layer);
         settings.destination_url = None;
-        self.one_off.lock().push_back(OneOffRule { id, settings });
+        let progress = Arc::new(watch::Sender::new(OneOffProgress::default()));
+        tracked.insert(id, progress.clone());
+        drop(tracked);
+        self.one_off.lock().push_back(OneOffRule {
+            id,
+            settings,
+            predicate: rule.predicate,
+            remaining: rule.count,
+            progress,
+            normalized,
+        });
         info!("Added one-off rule {id}");
-        id
+        Ok((id, version))
+    }
+
+    pub fn pending_one_offs(&self) -> Vec<PendingOneOff> {
+        self.one_off
+            .lock()
+            .iter()
+            .map(|rule| PendingOneOff {
+                id: rule.id,
+                remaining: rule.remaining,
+                rule: rule.normalized.clone(),
+            })
+            .collect()
+    }
+
+    pub fn delete_one_off(
+        &self,
+        id: Uuid,
+        if_match: Option<u64>,
+    ) -> Result<Option<u64>, VersionConflict> {
+        let _guard = self.admin_overrides.write();
+        let mut pending = self.one_off.lock();
+        let Some(idx) = pending.iter().position(|rule| rule.id == id) else {
+            return Ok(None);
+        };
+        let version = self.bump_version(if_match)?;
+        pending.remove(idx);
+        self.one_off_progress.lock().remove(&id);
+        info!("Deleted one-off rule {id}");
+        Ok(Some(version))
     }
 
-    pub fn apply_one_off(&self, ctx: &RequestContext, current: Settings) -> Settings {
+    pub fn one_off_progress(&self, id: Uuid) -> Option<watch::Receiver<OneOffProgress>> {
+        self.one_off_progress
+            .lock()
+            .get(&id)
+            .map(|progress| progress.subscribe())
+    }
+
+    pub fn rules_need_body(&self) -> bool {
+        self.one_off
+            .lock()
+            .iter()
+            .any(|rule| rule.predicate.needs_body())
+            || self
+                .named_rules
+                .read()
+                .iter()
+                .any(|named| named.rule.predicate.needs_body())
+    }
+
+    pub fn profiles(&self) -> BTreeMap<String, SettingsLayer> {
+        self.profiles.read().clone()
+    }
+
+    // Settings of config file profiles that were given as references, until
+    // the admin API replaces the profile.
+    pub fn profile_references(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        self.profile_references.read().clone()
+    }
+
+    pub fn profile(&self, name: &str) -> Option<SettingsLayer> {
+        self.profiles.read().get(name).cloned()
+    }
+
+    pub fn put_profile(
+        &self,
+        name: &str,
+        layer: SettingsLayer,
+        if_match: Option<u64>,
+    ) -> Result<(bool, u64), VersionConflict> {
+        let _guard = self.admin_overrides.write();
+        let version = self.bump_version(if_match)?;
+        let created = self
+            .profiles
+            .write()
+            .insert(name.to_string(), layer)
+            .is_none();
+        self.profile_references.write().remove(name);
+        info!("Saved profile {name}");
+        Ok((created, version))
+    }
+
+    pub fn delete_profile(
+        &self,
+        name: &str,
+        if_match: Option<u64>,
+    ) -> Result<Option<u64>, VersionConflict> {
+        let _guard = self.admin_overrides.write();
+        let mut profiles = self.profiles.write();
+        if !profiles.contains_key(name) {
+            return Ok(None);
+        }
+        let version = self.bump_version(if_match)?;
+        profiles.remove(name);
+        self.profile_references.write().remove(name);
+        info!("Deleted profile {name}");
+        Ok(Some(version))
+    }
+
+    pub fn named_rules(&self) -> Vec<NamedRule> {
+        self.named_rules.read().clone()
+    }
+
+    pub fn put_named_rule(
+        &self,
+        named: NamedRule,
+        if_match: Option<u64>,
+    ) -> Result<(bool, u64), VersionConflict> {
+        let _guard = self.admin_overrides.write();
+        let version = self.bump_version(if_match)?;
+        let mut rules = self.named_rules.write();
+        let name = named.name.clone();
+        let created = match rules.iter_mut().find(|existing| existing.name == name) {
+            Some(existing) => {
+                *existing = named;
+                false
+            }
+            None => {
+                rules.push(named);
+                true
+            }
+        };
+        rules.sort_by_key(|named| std::cmp::Reverse(named.priority));
+        info!("Saved rule {name}");
+        Ok((created, version))
+    }
+
+    pub fn delete_named_rule(
+        &self,
+        name: &str,
+        if_match: Option<u64>,
+    ) -> Result<Option<u64>, VersionConflict> {
+        let _guard = self.admin_overrides.write();
+        let mut rules = self.named_rules.write();
+        let Some(idx) = rules.iter().position(|named| named.name == name) else {
+            return Ok(None);
+        };
+        let version = self.bump_version(if_match)?;
+        rules.remove(idx);
+        info!("Deleted rule {name}");
+        Ok(Some(version))
+    }
+
+    pub fn apply_named_rule(
+        &self,
+        ctx: &RequestContext,
+        body: Option<&[u8]>,
+        mut current: Settings,
+    ) -> (Settings, Option<String>) {
+        let rules = self.named_rules.read();
+        for named in rules.iter() {
+            let mut own = Settings::default();
+            own.apply_layer(&named.rule.layer);
+            own.destination_url = current.destination_url.clone();
+            if matches_request(ctx, &own) && named.rule.predicate.matches(ctx, body) {
+                current.apply_layer(&named.rule.layer);
+                current.copy_matchers(&own);
+                return (current, Some(named.name.clone()));
+            }
+        }
+        (current, None)
+    }
+
+    pub fn apply_one_off(
+        &self,
+        ctx: &RequestContext,
+        body: Option<&[u8]>,
+        current: Settings,
+    ) -> (Settings, Option<Uuid>) {
         let mut guard = self.one_off.lock();
         if guard.is_empty() {
-            return current;
+            return (current, None);
         }
         let destination = current.destination_url.clone();
         let idx = guard.iter().position(|rule| {
             let mut candidate = rule.settings.clone();
             candidate.destination_url = destination.clone();
-            matches_request(ctx, &candidate)
+            matches_request(ctx, &candidate) && rule.predicate.matches(ctx, body)
         });
 
-        if let Some(idx) = idx {
-            let mut rule = guard.remove(idx).expect("one-off rule");
-            rule.settings.destination_url = destination;
-            info!("Consuming one-off rule {}", rule.id);
-            rule.settings
+        let Some(idx) = idx else {
+            return (current, None);
+        };
+        let rule = &mut guard[idx];
+        rule.remaining -= 1;
+        let id = rule.id;
+        let consumed = rule.remaining == 0;
+        rule.progress.send_modify(|progress| {
+            progress.consumed = consumed;
+            progress.matches.push(OneOffMatch {
+                at: self.clock.timestamp(),
+                method: ctx.method.to_string(),
+                uri: ctx.uri.clone(),
+                host: ctx.headers.get("host").cloned(),
+                deployment_marker: ctx.deployment_marker.clone(),
+            });
+        });
+        let mut settings = if consumed {
+            info!("Consuming one-off rule {id}");
+            guard.remove(idx).expect("one-off rule").settings
         } else {
-            current
-        }
+            info!("Applying one-off rule {id}, {} uses left", rule.remaining);
+            rule.settings.clone()
+        };
+        settings.destination_url = destination;
+        (settings, Some(id))
     }
 
     fn snapshot_locked(&self, admin: &SettingsLayer) -> Settings {
         let mut settings = Settings::default();
+        settings.apply_layer(&self.file_layer.read());
         settings.apply_layer(&self.env_layer);
         settings.apply_layer(admin);
         settings
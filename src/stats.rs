@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use jiff::{SignedDuration, Timestamp};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde::Deserialize;
+
+use crate::report::latency_percentiles;
+use crate::traffic::{DEFAULT_RULE, TrafficRecord};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl StatsFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            StatsFormat::Csv => "text/csv; charset=utf-8",
+            StatsFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            StatsFormat::Csv => "csv",
+            StatsFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Request,
+    Minute,
+    Hour,
+}
+
+impl Granularity {
+    fn bucket(self) -> Option<SignedDuration> {
+        match self {
+            Granularity::Request => None,
+            Granularity::Minute => Some(SignedDuration::from_mins(1)),
+            Granularity::Hour => Some(SignedDuration::from_hours(1)),
+        }
+    }
+}
+
+enum Values {
+    Timestamp(Vec<Timestamp>),
+    Int(Vec<i64>),
+    Float(Vec<f64>),
+    Text(Vec<Option<String>>),
+}
+
+struct Column {
+    name: &'static str,
+    values: Values,
+}
+
+struct Table {
+    rows: usize,
+    columns: Vec<Column>,
+}
+
+pub fn export(
+    records: &[TrafficRecord],
+    granularity: Granularity,
+    format: StatsFormat,
+) -> Result<Vec<u8>, String> {
+    let table = match granularity.bucket() {
+        None => requests(records),
+        Some(bucket) => aggregated(records, bucket),
+    };
+    match format {
+        StatsFormat::Csv => Ok(to_csv(&table).into_bytes()),
+        StatsFormat::Parquet => to_parquet(&table).map_err(|err| err.to_string()),
+    }
+}
+
+fn rule(record: &TrafficRecord) -> String {
+    record
+        .rule
+        .clone()
+        .unwrap_or_else(|| DEFAULT_RULE.to_string())
+}
+
+fn requests(records: &[TrafficRecord]) -> Table {
+    let text = |value: fn(&TrafficRecord) -> Option<String>| {
+        Values::Text(records.iter().map(value).collect())
+    };
+    let int = |value: fn(&TrafficRecord) -> i64| Values::Int(records.iter().map(value).collect());
+    Table {
+        rows: records.len(),
+        columns: vec![
+            Column {
+                name: "timestamp",
+                values: Values::Timestamp(records.iter().map(|record| record.at).collect()),
+            },
+            Column {
+                name: "seq",
+                values: int(|record| record.seq as i64),
+            },
+            Column {
+                name: "method",
+                values: text(|record| Some(record.method.clone())),
+            },
+            Column {
+                name: "uri",
+                values: text(|record| Some(record.uri.clone())),
+            },
+            Column {
+                name: "destination",
+                values: text(|record| record.destination.clone()),
+            },
+            Column {
+                name: "rule",
+                values: text(|record| Some(rule(record))),
+            },
+            Column {
+                name: "deployment_marker",
+                values: text(|record| record.deployment_marker.clone()),
+            },
+            Column {
+                name: "faults",
+                values: text(|record| Some(record.faults.join(";"))),
+            },
+            Column {
+                name: "status",
+                values: int(|record| i64::from(record.status)),
+            },
+            Column {
+                name: "latency_ms",
+                values: int(|record| record.duration_ms as i64),
+            },
+        ],
+    }
+}
+
+// Requests with several faults count once towards each of them; requests
+// without faults are grouped under an empty fault.
+fn aggregated(records: &[TrafficRecord], bucket: SignedDuration) -> Table {
+    let width = bucket.as_millis() as i64;
+    let mut groups: BTreeMap<(i64, String, &str, u16), Vec<u64>> = BTreeMap::new();
+    for record in records {
+        let at = record.at.as_millisecond();
+        let start = at - at.rem_euclid(width);
+        let faults: &[&str] = if record.faults.is_empty() {
+            &[""]
+        } else {
+            &record.faults
+        };
+        for fault in faults {
+            groups
+                .entry((start, rule(record), fault, record.status))
+                .or_default()
+                .push(record.duration_ms);
+        }
+    }
+
+    let mut timestamps = Vec::new();
+    let mut rules = Vec::new();
+    let mut faults = Vec::new();
+    let mut statuses = Vec::new();
+    let mut counts = Vec::new();
+    let mut means = Vec::new();
+    let mut p50s = Vec::new();
+    let mut p90s = Vec::new();
+    let mut p99s = Vec::new();
+    let mut maxes = Vec::new();
+    for ((start, rule, fault, status), durations) in groups {
+        timestamps.push(Timestamp::from_millisecond(start).unwrap_or(Timestamp::UNIX_EPOCH));
+        rules.push(Some(rule));
+        faults.push(Some(fault.to_string()));
+        statuses.push(i64::from(status));
+        counts.push(durations.len() as i64);
+        means.push(durations.iter().sum::<u64>() as f64 / durations.len() as f64);
+        let latency = latency_percentiles(durations);
+        p50s.push(latency.p50 as i64);
+        p90s.push(latency.p90 as i64);
+        p99s.push(latency.p99 as i64);
+        maxes.push(latency.max as i64);
+    }
+    let column = |name, values| Column { name, values };
+    Table {
+        rows: timestamps.len(),
+        columns: vec![
+            column("timestamp", Values::Timestamp(timestamps)),
+            column("rule", Values::Text(rules)),
+            column("fault", Values::Text(faults)),
+            column("status", Values::Int(statuses)),
+            column("requests", Values::Int(counts)),
+            column("latency_mean_ms", Values::Float(means)),
+            column("latency_p50_ms", Values::Int(p50s)),
+            column("latency_p90_ms", Values::Int(p90s)),
+            column("latency_p99_ms", Values::Int(p99s)),
+            column("latency_max_ms", Values::Int(maxes)),
+        ],
+    }
+}
+
+fn to_csv(table: &Table) -> String {
+    let mut csv = table
+        .columns
+        .iter()
+        .map(|column| column.name)
+        .collect::<Vec<_>>()
+        .join(",");
+    csv.push('\n');
+    for row in 0..table.rows {
+        let fields: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| match &column.values {
+                Values::Timestamp(values) => values[row].to_string(),
+                Values::Int(values) => values[row].to_string(),
+                Values::Float(values) => values[row].to_string(),
+                Values::Text(values) => csv_quote(values[row].as_deref().unwrap_or_default()),
+            })
+            .collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_quote(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_parquet(table: &Table) -> parquet::errors::Result<Vec<u8>> {
+    let fields: Vec<String> = table
+        .columns
+        .iter()
+        .map(|column| match column.values {
+            Values::Timestamp(_) => {
+                format!("REQUIRED INT64 {} (TIMESTAMP(MILLIS,true));", column.name)
+            }
+            Values::Int(_) => format!("REQUIRED INT64 {};", column.name),
+            Values::Float(_) => format!("REQUIRED DOUBLE {};", column.name),
+            Values::Text(_) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column.name),
+        })
+        .collect();
+    let schema = parse_message_type(&format!("message stats {{ {} }}", fields.join(" ")))?;
+    let properties = WriterProperties::builder()
+        .set_created_by("lowdown".to_string())
+        .build();
+    let mut writer = SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for column in &table.columns {
+        let Some(mut writer) = row_group.next_column()? else {
+            break;
+        };
+        match &column.values {
+            Values::Timestamp(values) => {
+                let millis: Vec<i64> = values.iter().map(|at| at.as_millisecond()).collect();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&millis, None, None)?;
+            }
+            Values::Int(values) => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(values, None, None)?;
+            }
+            Values::Float(values) => {
+                writer
+                    .typed::<DoubleType>()
+                    .write_batch(values, None, None)?;
+            }
+            Values::Text(values) => {
+                let present: Vec<ByteArray> = values
+                    .iter()
+                    .flatten()
+                    .map(|value| ByteArray::from(value.as_str()))
+                    .collect();
+                let levels: Vec<i16> = values.iter().map(|value| value.is_some().into()).collect();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&present, Some(&levels), None)?;
+            }
+        }
+        writer.close()?;
+    }
+    row_group.close()?;
+    writer.into_inner()
+}
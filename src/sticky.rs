@@ -0,0 +1,63 @@
+use rand::seq::SliceRandom;
+use tracing::warn;
+
+use crate::random;
+
+// Pool entries are `url` or `weight:url`. Weights are relative and default to
+// 1, a weight of 0 drains a backend. Weights are at most 65535 so that their
+// sum can't overflow.
+fn parse_pool(pool: &str) -> Vec<(&str, u16)> {
+    pool.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.split_once(':') {
+            Some((weight, url)) if is_weight(weight.trim()) => match weight.trim().parse::<u16>() {
+                Ok(weight) => Some((url.trim(), weight)),
+                Err(_) => {
+                    warn!("Ignoring destination {entry:?}: weight is above 65535");
+                    None
+                }
+            },
+            _ => Some((entry, 1)),
+        })
+        .filter(|(url, _)| !url.is_empty())
+        .collect()
+}
+
+fn is_weight(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+pub fn select_destination(pool: &str, key: Option<&str>) -> Option<String> {
+    let destinations: Vec<(&str, u16)> = parse_pool(pool)
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0)
+        .collect();
+    let chosen = match key.filter(|key| !key.is_empty()) {
+        // Weighted rendezvous hashing: with equal weights this is the plain
+        // highest-hash pick.
+        Some(key) => destinations
+            .iter()
+            .map(|(url, weight)| {
+                let hash = fnv1a(&[key.as_bytes(), b"\n", url.as_bytes()]);
+                let unit = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+                (url, f64::from(*weight) / -unit.ln())
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(url, _)| *url),
+        None => destinations
+            .choose_weighted(&mut random::rng(), |(_, weight)| u64::from(*weight))
+            .ok()
+            .map(|(url, _)| *url),
+    };
+    chosen.map(|url| url.to_string())
+}
+
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
@@ -0,0 +1,342 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::{convert::Infallible, io, time::Duration};
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures_util::{StreamExt, future, stream};
+use http::{HeaderMap, HeaderValue};
+use http_body::Frame;
+use http_body_util::{BodyStream, StreamBody};
+use rand::{Rng, RngCore};
+
+use crate::clock::{self, Clock};
+use crate::random;
+use crate::settings::{MAX_STREAM_GARBAGE_BYTES, MIN_STREAM_LOSS_PACKET_BYTES, Settings};
+use crate::streams::StreamControl;
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamFaults {
+    pub stall_after_bytes: u64,
+    pub stall_ms: u64,
+    pub garbage_after_bytes: u64,
+    pub garbage_bytes: u64,
+    pub abort_at_percent: u8,
+    pub loss_percentage: u8,
+    pub loss_burst_percentage: u8,
+    pub loss_stall_ms: u64,
+    pub loss_packet_bytes: u64,
+}
+
+impl StreamFaults {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            stall_after_bytes: settings.stream_stall_after_bytes,
+            stall_ms: settings.stream_stall_ms.get(),
+            garbage_after_bytes: settings.stream_garbage_after_bytes,
+            garbage_bytes: settings.stream_garbage_bytes,
+            abort_at_percent: settings.stream_abort_at_percent.get(),
+            ..Self::loss_from_settings(settings)
+        }
+    }
+
+    pub fn loss_from_settings(settings: &Settings) -> Self {
+        Self {
+            loss_percentage: settings.stream_loss_percentage.get(),
+            loss_burst_percentage: settings.stream_loss_burst_percentage.get(),
+            loss_stall_ms: settings.stream_loss_stall_ms.get(),
+            loss_packet_bytes: settings.stream_loss_packet_bytes,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_noop(&self) -> bool {
+        self.stall_ms == 0
+            && self.garbage_bytes == 0
+            && self.abort_at_percent == 0
+            && !self.has_loss()
+    }
+
+    fn has_loss(&self) -> bool {
+        self.loss_percentage > 0 && self.loss_stall_ms > 0
+    }
+}
+
+const GARBAGE_CHUNK_BYTES: usize = 16 * 1024;
+
+enum Event {
+    Stall(Duration),
+    Garbage(usize),
+    Abort,
+}
+
+// Gilbert-style burst loss: each packet is "lost" with loss-percentage, or with
+// loss-burst-percentage right after a lost packet, and is redelivered after a stall.
+struct PacketLoss {
+    packet: usize,
+    stall: Duration,
+    percentage: u8,
+    burst_percentage: u8,
+    in_burst: bool,
+}
+
+impl PacketLoss {
+    fn lost(&mut self) -> bool {
+        let percentage = if self.in_burst {
+            self.burst_percentage
+        } else {
+            self.percentage
+        };
+        self.in_burst = percentage > random::rng().gen_range(0..100);
+        self.in_burst
+    }
+}
+
+// Walks the upstream body one frame at a time, cutting it at the fault
+// offsets and into packets as the client reads, so endless streams are
+// faulted as they flow and nothing is buffered.
+struct FaultyStream {
+    frames: BodyStream<Body>,
+    chunk: Bytes,
+    position: u64,
+    events: VecDeque<(u64, Event)>,
+    abort_after_first_chunk: bool,
+    loss: Option<PacketLoss>,
+    trailers: Option<HeaderMap>,
+    ended: bool,
+    clock: Arc<dyn Clock>,
+    control: StreamControl,
+}
+
+impl FaultyStream {
+    async fn next(&mut self) -> Option<Result<Frame<Bytes>, axum::Error>> {
+        loop {
+            if let Some((offset, _)) = self.events.front()
+                && (*offset <= self.position || self.ended)
+            {
+                let (offset, event) = self.events.pop_front()?;
+                match event {
+                    Event::Stall(duration) => {
+                        if self.control.faulting() {
+                            self.control.stall(&self.clock, duration).await;
+                        }
+                    }
+                    Event::Garbage(_) | Event::Abort if !self.control.faulting() => {}
+                    Event::Garbage(len) => {
+                        let chunk = len.min(GARBAGE_CHUNK_BYTES);
+                        if len > chunk {
+                            self.events
+                                .push_front((offset, Event::Garbage(len - chunk)));
+                        }
+                        return Some(Ok(Frame::data(garbage(chunk))));
+                    }
+                    Event::Abort => {
+                        return Some(Err(axum::Error::new(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "stream-abort fault",
+                        ))));
+                    }
+                }
+                continue;
+            }
+            if !self.chunk.is_empty() {
+                let mut len = self.chunk.len();
+                if let Some((offset, _)) = self.events.front() {
+                    let until = usize::try_from(offset - self.position).unwrap_or(usize::MAX);
+                    len = len.min(until);
+                }
+                if let Some(loss) = self.loss.as_mut() {
+                    len = len.min(loss.packet);
+                    if loss.lost() && self.control.faulting() {
+                        self.control.stall(&self.clock, loss.stall).await;
+                    }
+                }
+                self.position += len as u64;
+                return Some(Ok(Frame::data(self.chunk.split_to(len))));
+            }
+            if self.ended {
+                return self
+                    .trailers
+                    .take()
+                    .map(|trailers| Ok(Frame::trailers(trailers)));
+            }
+            match self.frames.next().await {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        if self.abort_after_first_chunk && !data.is_empty() {
+                            self.abort_after_first_chunk = false;
+                            self.schedule(self.position + data.len() as u64, Event::Abort);
+                        }
+                        self.chunk = data;
+                    }
+                    Err(frame) => {
+                        self.trailers = frame.into_trailers().ok();
+                        self.ended = true;
+                    }
+                },
+                Some(Err(err)) => return Some(Err(err)),
+                None => self.ended = true,
+            }
+        }
+    }
+
+    fn schedule(&mut self, offset: u64, event: Event) {
+        let index = self
+            .events
+            .iter()
+            .position(|(scheduled, _)| *scheduled > offset)
+            .unwrap_or(self.events.len());
+        self.events.insert(index, (offset, event));
+    }
+}
+
+// Once the stream's faults stop, stalls are cut short and the remaining
+// garbage and abort steps are skipped, so the client gets the rest of the body.
+// Without a known `length`, an abort comes right after the first chunk.
+pub fn faulty_body(
+    body: Body,
+    length: Option<u64>,
+    faults: &StreamFaults,
+    control: StreamControl,
+) -> Body {
+    let mut faulty = FaultyStream {
+        frames: BodyStream::new(body),
+        chunk: Bytes::new(),
+        position: 0,
+        events: VecDeque::new(),
+        abort_after_first_chunk: false,
+        loss: faults.has_loss().then(|| PacketLoss {
+            packet: usize::try_from(faults.loss_packet_bytes.max(MIN_STREAM_LOSS_PACKET_BYTES))
+                .unwrap_or(usize::MAX),
+            stall: Duration::from_millis(faults.loss_stall_ms),
+            percentage: faults.loss_percentage,
+            burst_percentage: faults.loss_burst_percentage,
+            in_burst: false,
+        }),
+        trailers: None,
+        ended: false,
+        clock: clock::current(),
+        control,
+    };
+    if faults.stall_ms > 0 {
+        faulty.schedule(
+            faults.stall_after_bytes,
+            Event::Stall(Duration::from_millis(faults.stall_ms)),
+        );
+    }
+    if faults.garbage_bytes > 0 {
+        faulty.schedule(
+            faults.garbage_after_bytes,
+            Event::Garbage(
+                usize::try_from(faults.garbage_bytes.min(MAX_STREAM_GARBAGE_BYTES))
+                    .unwrap_or(usize::MAX),
+            ),
+        );
+    }
+    if faults.abort_at_percent > 0 {
+        let percent = u128::from(faults.abort_at_percent.min(100));
+        match length {
+            Some(length) => {
+                let offset = u128::from(length) * percent / 100;
+                faulty.schedule(u64::try_from(offset).unwrap_or(u64::MAX), Event::Abort);
+            }
+            None => faulty.abort_after_first_chunk = true,
+        }
+    }
+    let stream = stream::unfold(Some(faulty), |faulty| async move {
+        let mut faulty = faulty?;
+        let frame = faulty.next().await?;
+        let next = frame.is_ok().then_some(faulty);
+        Some((frame, next))
+    });
+    Body::new(StreamBody::new(stream))
+}
+
+pub fn body_with_trailers(body: Bytes, trailers: Option<HeaderMap>) -> Body {
+    match trailers {
+        Some(_) => unsized_body(body, trailers),
+        None => Body::from(body),
+    }
+}
+
+// Without a size hint neither axum nor hyper add a content-length, so the
+// response keeps the chunked framing the upstream used.
+pub fn unsized_body(body: Bytes, trailers: Option<HeaderMap>) -> Body {
+    let frames = std::iter::once(Frame::data(body)).chain(trailers.map(Frame::trailers));
+    Body::new(StreamBody::new(stream::iter(
+        frames.map(Ok::<_, Infallible>),
+    )))
+}
+
+// Splits the body into chunks of a tenth of a second each and paces them, so
+// streamed and buffered bodies are throttled alike.
+pub fn throttled_body(body: Body, bytes_per_second: u64, control: StreamControl) -> Body {
+    let chunk = usize::try_from(bytes_per_second / 10)
+        .unwrap_or(usize::MAX)
+        .max(1);
+    let clock = clock::current();
+    let stream = BodyStream::new(body)
+        .flat_map(move |frame| {
+            let frames: Vec<_> = match frame.map(Frame::into_data) {
+                Ok(Ok(data)) => (0..data.len())
+                    .step_by(chunk)
+                    .map(|start| {
+                        Ok(Frame::data(
+                            data.slice(start..data.len().min(start.saturating_add(chunk))),
+                        ))
+                    })
+                    .collect(),
+                Ok(Err(frame)) => vec![Ok(frame)],
+                Err(err) => vec![Err(err)],
+            };
+            stream::iter(frames)
+        })
+        .then(move |frame| {
+            let clock = clock.clone();
+            let control = control.clone();
+            async move {
+                if let Ok(Some(data)) = frame.as_ref().map(Frame::data_ref)
+                    && control.faulting()
+                {
+                    let pace = data.len() as f64 / bytes_per_second as f64;
+                    control.stall(&clock, Duration::from_secs_f64(pace)).await;
+                }
+                frame
+            }
+        });
+    Body::new(StreamBody::new(stream))
+}
+
+// Drops or corrupts the trailers of a streamed body as they pass, so the body
+// itself isn't held back.
+pub fn faulty_trailers(body: Body, drop: bool) -> Body {
+    let stream = BodyStream::new(body).filter_map(move |frame| {
+        let frame = match frame.map(Frame::into_trailers) {
+            Ok(Ok(_)) if drop => None,
+            Ok(Ok(mut trailers)) => {
+                corrupt_trailers(&mut trailers);
+                Some(Ok(Frame::trailers(trailers)))
+            }
+            Ok(Err(frame)) => Some(Ok(frame)),
+            Err(err) => Some(Err(err)),
+        };
+        future::ready(frame)
+    });
+    Body::new(StreamBody::new(stream))
+}
+
+pub fn corrupt_trailers(trailers: &mut HeaderMap) {
+    let mut rng = random::rng();
+    for value in trailers.values_mut() {
+        let garbage: String = (0..value.len().max(1))
+            .map(|_| char::from(rng.gen_range(b'a'..=b'z')))
+            .collect();
+        *value = HeaderValue::from_str(&garbage).expect("ascii letters");
+    }
+}
+
+fn garbage(len: usize) -> Bytes {
+    let mut bytes = vec![0u8; len];
+    random::rng().fill_bytes(&mut bytes);
+    Bytes::from(bytes)
+}
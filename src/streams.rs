@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use futures_util::{StreamExt, stream};
+use http_body_util::{BodyStream, StreamBody};
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::metrics::HoldGuard;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamExpiryMode {
+    #[default]
+    Continue,
+    StopFaults,
+    Terminate,
+}
+
+impl StreamExpiryMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StreamExpiryMode::Continue => "continue",
+            StreamExpiryMode::StopFaults => "stop-faults",
+            StreamExpiryMode::Terminate => "terminate",
+        }
+    }
+}
+
+impl FromStr for StreamExpiryMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "continue" => Ok(StreamExpiryMode::Continue),
+            "stop-faults" => Ok(StreamExpiryMode::StopFaults),
+            "terminate" => Ok(StreamExpiryMode::Terminate),
+            other => Err(format!("unknown stream expiry mode {other:?}")),
+        }
+    }
+}
+
+impl fmt::Display for StreamExpiryMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamState {
+    #[default]
+    Faulting,
+    FaultsStopped,
+    Terminated,
+}
+
+// Shared by a faulted response body and the registry entry of its stream.
+// Bodies that aren't registered get a control that never changes.
+#[derive(Clone, Default)]
+pub struct StreamControl(Arc<watch::Sender<StreamState>>);
+
+impl StreamControl {
+    pub fn faulting(&self) -> bool {
+        *self.0.borrow() == StreamState::Faulting
+    }
+
+    // A stall of a fault, cut short when faults stop or the stream ends.
+    pub async fn stall(&self, clock: &Arc<dyn Clock>, duration: Duration) {
+        let mut changed = self.0.subscribe();
+        tokio::select! {
+            () = clock.sleep(duration) => {}
+            _ = changed.wait_for(|state| *state != StreamState::Faulting) => {}
+        }
+    }
+
+    pub async fn terminated(&self) {
+        let mut changed = self.0.subscribe();
+        let _ = changed
+            .wait_for(|state| *state == StreamState::Terminated)
+            .await;
+    }
+
+    fn set(&self, state: StreamState) {
+        self.0.send_if_modified(|current| {
+            let changed = (*current == StreamState::Faulting && state != StreamState::Faulting)
+                || (*current == StreamState::FaultsStopped && state == StreamState::Terminated);
+            if changed {
+                *current = state;
+            }
+            changed
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StreamInfo {
+    pub id: Uuid,
+    pub method: String,
+    pub uri: String,
+    pub destination: String,
+    pub rule: String,
+    pub faults: Vec<&'static str>,
+    pub started_at: Timestamp,
+    pub settings_version: u64,
+    pub expiry_mode: StreamExpiryMode,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StreamStatus {
+    #[serde(flatten)]
+    pub info: StreamInfo,
+    pub state: StreamState,
+}
+
+struct Entry {
+    info: StreamInfo,
+    control: StreamControl,
+}
+
+// Responses whose faults are applied while the body streams, e.g. throttled
+// server-sent events, for as long as the client reads them.
+#[derive(Clone, Default)]
+pub struct StreamRegistry {
+    entries: Arc<Mutex<BTreeMap<Uuid, Entry>>>,
+}
+
+pub struct Registration {
+    entries: Arc<Mutex<BTreeMap<Uuid, Entry>>>,
+    id: Uuid,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.entries.lock().remove(&self.id);
+    }
+}
+
+impl StreamRegistry {
+    pub fn register(&self, info: StreamInfo) -> (StreamControl, Registration) {
+        let control = StreamControl::default();
+        let id = info.id;
+        self.entries.lock().insert(
+            id,
+            Entry {
+                info,
+                control: control.clone(),
+            },
+        );
+        (
+            control,
+            Registration {
+                entries: self.entries.clone(),
+                id,
+            },
+        )
+    }
+
+    pub fn list(&self) -> Vec<StreamStatus> {
+        let mut streams: Vec<StreamStatus> = self
+            .entries
+            .lock()
+            .values()
+            .map(|entry| StreamStatus {
+                info: entry.info.clone(),
+                state: *entry.control.0.borrow(),
+            })
+            .collect();
+        streams.sort_by_key(|stream| stream.info.started_at);
+        streams
+    }
+
+    pub fn terminate(&self, id: Uuid) -> bool {
+        match self.entries.lock().get(&id) {
+            Some(entry) => {
+                info!("Terminating stream {id} {}", entry.info.uri);
+                entry.control.set(StreamState::Terminated);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The settings that faulted these streams changed to `version`.
+    pub fn expire(&self, version: u64) {
+        for (id, entry) in self.entries.lock().iter() {
+            if entry.info.settings_version >= version || !entry.control.faulting() {
+                continue;
+            }
+            let state = match entry.info.expiry_mode {
+                StreamExpiryMode::Continue => continue,
+                StreamExpiryMode::StopFaults => StreamState::FaultsStopped,
+                StreamExpiryMode::Terminate => StreamState::Terminated,
+            };
+            info!(
+                "Settings changed, {} stream {id} {}",
+                entry.info.expiry_mode, entry.info.uri
+            );
+            entry.control.set(state);
+        }
+    }
+}
+
+// Passes the body through until the stream is terminated, then ends it with an
+// error so the client sees an aborted response. The stream leaves the registry
+// when the body is dropped.
+pub fn registered_body(
+    body: Body,
+    control: StreamControl,
+    registration: Registration,
+    holds: Vec<HoldGuard>,
+) -> Body {
+    let frames = BodyStream::new(body);
+    let stream = stream::unfold(
+        Some((frames, control, registration, holds)),
+        |state| async move {
+            let (mut frames, control, registration, holds) = state?;
+            let frame = tokio::select! {
+                frame = frames.next() => frame?,
+                () = control.terminated() => Err(axum::Error::new(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "stream terminated",
+                ))),
+            };
+            let next = frame
+                .is_ok()
+                .then_some((frames, control, registration, holds));
+            Some((frame, next))
+        },
+    );
+    Body::new(StreamBody::new(stream))
+}
@@ -0,0 +1,277 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::clock::sleep;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::info;
+use url::Url;
+use uuid::Uuid;
+
+use crate::http_client::{
+    HttpClient, HttpClientError, OutgoingRequest, ProxiedResponse, RequestBody, SharedHttpClient,
+};
+
+#[derive(Debug, Clone)]
+pub struct Stub {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub priority: i64,
+    pub source: &'static str,
+    pub request: StubRequest,
+    pub response: StubResponse,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StubRequest {
+    pub method: Option<Method>,
+    pub url: UrlMatcher,
+    pub headers: Vec<(String, ValueMatcher)>,
+    pub query: Vec<(String, ValueMatcher)>,
+    pub body: Vec<ValueMatcher>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum UrlMatcher {
+    #[default]
+    Any,
+    Exact(String),
+    Path(String),
+    Pattern(Regex),
+    PathPattern(Regex),
+}
+
+#[derive(Debug, Clone)]
+pub enum ValueMatcher {
+    EqualTo {
+        value: String,
+        case_insensitive: bool,
+    },
+    Contains(String),
+    Matches(Regex),
+    DoesNotMatch(Regex),
+    Absent,
+    EqualToJson(Value),
+}
+
+#[derive(Debug, Clone)]
+pub struct StubResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub delay_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StubSummary {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub priority: i64,
+    pub source: &'static str,
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+}
+
+#[derive(Default)]
+pub struct StubRegistry {
+    stubs: RwLock<Vec<Stub>>,
+}
+
+impl StubRegistry {
+    pub fn add(&self, stub: Stub) -> Uuid {
+        let id = stub.id;
+        let mut guard = self.stubs.write();
+        guard.retain(|existing| existing.id != id);
+        guard.push(stub);
+        guard.sort_by_key(|stub| stub.priority);
+        id
+    }
+
+    pub fn remove(&self, id: Uuid) -> bool {
+        let mut guard = self.stubs.write();
+        let before = guard.len();
+        guard.retain(|stub| stub.id != id);
+        guard.len() != before
+    }
+
+    pub fn clear(&self) {
+        self.stubs.write().clear();
+    }
+
+    pub fn summaries(&self) -> Vec<StubSummary> {
+        self.stubs.read().iter().map(Stub::summary).collect()
+    }
+
+    pub fn find(&self, request: &OutgoingRequest) -> Option<Stub> {
+        let guard = self.stubs.read();
+        if guard.is_empty() {
+            return None;
+        }
+        let url = Url::parse(&request.url).ok()?;
+        guard
+            .iter()
+            .find(|stub| stub.request.matches(request, &url))
+            .cloned()
+    }
+}
+
+impl Stub {
+    fn summary(&self) -> StubSummary {
+        StubSummary {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            source: self.source,
+            method: self
+                .request
+                .method
+                .as_ref()
+                .map(|method| method.to_string())
+                .unwrap_or_else(|| "*".to_string()),
+            url: match &self.request.url {
+                UrlMatcher::Any => "*".to_string(),
+                UrlMatcher::Exact(url) | UrlMatcher::Path(url) => url.clone(),
+                UrlMatcher::Pattern(regex) | UrlMatcher::PathPattern(regex) => {
+                    regex.as_str().to_string()
+                }
+            },
+            status: self.response.status.as_u16(),
+        }
+    }
+}
+
+impl StubRequest {
+    fn matches(&self, request: &OutgoingRequest, url: &Url) -> bool {
+        if let Some(method) = &self.method
+            && method != request.method
+        {
+            return false;
+        }
+        let path = url.path();
+        let path_and_query = match url.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+        let url_matches = match &self.url {
+            UrlMatcher::Any => true,
+            UrlMatcher::Exact(expected) => *expected == path_and_query,
+            UrlMatcher::Path(expected) => expected == path,
+            UrlMatcher::Pattern(regex) => full_match(regex, &path_and_query),
+            UrlMatcher::PathPattern(regex) => full_match(regex, path),
+        };
+        if !url_matches {
+            return false;
+        }
+        let headers_match = self.headers.iter().all(|(name, matcher)| {
+            let value = request
+                .headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok());
+            matcher.matches(value)
+        });
+        let query_match = self.query.iter().all(|(name, matcher)| {
+            let value = url
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned());
+            matcher.matches(value.as_deref())
+        });
+        if !headers_match || !query_match {
+            return false;
+        }
+        if self.body.is_empty() {
+            return true;
+        }
+        match &request.body {
+            RequestBody::Buffered(bytes) => {
+                let text = String::from_utf8_lossy(bytes);
+                self.body.iter().all(|matcher| matcher.matches(Some(&text)))
+            }
+            RequestBody::Streaming(_) => false,
+        }
+    }
+}
+
+impl ValueMatcher {
+    fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (ValueMatcher::Absent, value) => value.is_none(),
+            (ValueMatcher::DoesNotMatch(regex), Some(value)) => !full_match(regex, value),
+            (ValueMatcher::DoesNotMatch(_), None) => true,
+            (_, None) => false,
+            (
+                ValueMatcher::EqualTo {
+                    value: expected,
+                    case_insensitive,
+                },
+                Some(value),
+            ) => {
+                if *case_insensitive {
+                    expected.eq_ignore_ascii_case(value)
+                } else {
+                    expected == value
+                }
+            }
+            (ValueMatcher::Contains(expected), Some(value)) => value.contains(expected.as_str()),
+            (ValueMatcher::Matches(regex), Some(value)) => full_match(regex, value),
+            (ValueMatcher::EqualToJson(expected), Some(value)) => {
+                serde_json::from_str::<Value>(value).is_ok_and(|actual| actual == *expected)
+            }
+        }
+    }
+}
+
+fn full_match(regex: &Regex, value: &str) -> bool {
+    regex
+        .find(value)
+        .is_some_and(|m| m.start() == 0 && m.end() == value.len())
+}
+
+pub fn header_map(headers: &[(String, String)]) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid header name {name:?}"))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|_| format!("invalid header value {value:?}"))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+pub struct StubbingClient {
+    stubs: Arc<StubRegistry>,
+    inner: SharedHttpClient,
+}
+
+impl StubbingClient {
+    pub fn new(stubs: Arc<StubRegistry>, inner: SharedHttpClient) -> Self {
+        Self { stubs, inner }
+    }
+}
+
+#[async_trait]
+impl HttpClient for StubbingClient {
+    async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError> {
+        let Some(stub) = self.stubs.find(&request) else {
+            return self.inner.execute(request).await;
+        };
+        info!(
+            "Serving stub {} for {} {}",
+            stub.id, request.method, request.url
+        );
+        if stub.response.delay_ms > 0 {
+            sleep(Duration::from_millis(stub.response.delay_ms)).await;
+        }
+        Ok(ProxiedResponse::new(
+            stub.response.status,
+            stub.response.headers,
+            stub.response.body,
+        ))
+    }
+}
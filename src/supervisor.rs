@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::{Notify, watch};
+use tracing::{error, info, warn};
+
+use crate::clock;
+use crate::connection::ListenerOptions;
+
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    #[default]
+    FailFast,
+    Retry,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().as_str() {
+            "fail-fast" => Ok(RestartPolicy::FailFast),
+            "retry" => Ok(RestartPolicy::Retry),
+            other => Err(format!("unknown restart policy {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServerState {
+    Starting,
+    Running,
+    Retrying,
+    Failed,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServerStatus {
+    pub address: SocketAddr,
+    pub state: ServerState,
+    pub restarts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<Timestamp>,
+}
+
+#[derive(Default)]
+pub struct Supervision {
+    servers: Mutex<BTreeMap<String, ServerStatus>>,
+    changed: Notify,
+}
+
+impl Supervision {
+    pub fn snapshot(&self) -> BTreeMap<String, ServerStatus> {
+        self.servers.lock().clone()
+    }
+
+    pub fn all_running(&self) -> bool {
+        self.servers
+            .lock()
+            .values()
+            .all(|status| status.state == ServerState::Running)
+    }
+
+    pub async fn running(&self, count: usize) {
+        loop {
+            let changed = self.changed.notified();
+            if self.servers.lock().len() >= count && self.all_running() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    fn set(&self, name: &str, address: SocketAddr, state: ServerState) {
+        self.servers
+            .lock()
+            .entry(name.to_string())
+            .or_insert(ServerStatus {
+                address,
+                state,
+                restarts: 0,
+                last_error: None,
+                last_error_at: None,
+            })
+            .state = state;
+        self.changed.notify_waiters();
+    }
+
+    fn fail(&self, name: &str, state: ServerState, err: String) {
+        if let Some(status) = self.servers.lock().get_mut(name) {
+            status.state = state;
+            status.last_error = Some(err);
+            status.last_error_at = Some(clock::timestamp());
+        }
+        self.changed.notify_waiters();
+    }
+
+    fn restarted(&self, name: &str) {
+        if let Some(status) = self.servers.lock().get_mut(name) {
+            status.restarts += 1;
+        }
+    }
+}
+
+// Runs servers until `shutdown` flips to true. With `FailFast` a bind error
+// or a crash ends `run` with an error, so joining the servers with `try_join!`
+// takes the whole process down. With `Retry` the server is rebound every
+// `retry` instead, and the other servers keep serving in the meantime.
+#[derive(Clone)]
+pub struct Supervisor {
+    supervision: Arc<Supervision>,
+    policy: RestartPolicy,
+    retry: Duration,
+    shutdown: watch::Receiver<bool>,
+    listener_options: ListenerOptions,
+    inherited: Arc<BTreeMap<String, std::net::TcpListener>>,
+    shutdown_grace: Duration,
+}
+
+impl Supervisor {
+    pub fn new(
+        supervision: Arc<Supervision>,
+        policy: RestartPolicy,
+        retry: Duration,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            supervision,
+            policy,
+            retry,
+            shutdown,
+            listener_options: ListenerOptions::default(),
+            inherited: Arc::default(),
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+        }
+    }
+
+    // How long in-flight requests may take to finish once shutdown starts.
+    // Connections still open after that are dropped with the server.
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    pub fn listener_options(mut self, options: ListenerOptions) -> Self {
+        self.listener_options = options;
+        self
+    }
+
+    // Listeners by server name, e.g. from systemd socket activation. They are
+    // used instead of binding, and again after a restart.
+    pub fn inherited(mut self, listeners: BTreeMap<String, std::net::TcpListener>) -> Self {
+        self.inherited = Arc::new(listeners);
+        self
+    }
+
+    pub async fn run<F, S>(
+        &self,
+        name: &str,
+        address: SocketAddr,
+        mut serve: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(TcpListener, BoxFuture<'static, ()>) -> S,
+        S: Future<Output = std::io::Result<()>>,
+    {
+        let address = match self.inherited.get(name) {
+            Some(listener) => listener.local_addr()?,
+            None => address,
+        };
+        self.supervision.set(name, address, ServerState::Starting);
+        let mut attempt = 0;
+        loop {
+            if attempt > 0 {
+                self.supervision.restarted(name);
+            }
+            attempt += 1;
+            let err = match self.listen(name, address) {
+                Ok(listener) => {
+                    info!("Serving {name} at {address}");
+                    self.supervision.set(name, address, ServerState::Running);
+                    let served = serve(listener, Box::pin(self.stopped()));
+                    let result = tokio::select! {
+                        result = served => result,
+                        () = self.grace_expired(name) => {
+                            warn!(
+                                "{name} server did not drain within {} ms, closing its remaining connections",
+                                self.shutdown_grace.as_millis()
+                            );
+                            Ok(())
+                        }
+                    };
+                    match result {
+                        Ok(()) => {
+                            info!("Shut down {name} server");
+                            self.supervision.set(name, address, ServerState::Stopped);
+                            return Ok(());
+                        }
+                        Err(err) => format!("{name} server exited with error: {err}"),
+                    }
+                }
+                Err(err) => format!("failed to bind {name} listener at {address}: {err}"),
+            };
+            if self.policy == RestartPolicy::FailFast {
+                error!("{err}");
+                self.supervision
+                    .fail(name, ServerState::Failed, err.clone());
+                return Err(anyhow!(err));
+            }
+            warn!("{err}, retrying in {} ms", self.retry.as_millis());
+            self.supervision.fail(name, ServerState::Retrying, err);
+            tokio::select! {
+                () = clock::sleep(self.retry) => {}
+                () = self.stopped() => {
+                    self.supervision.set(name, address, ServerState::Stopped);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn listen(&self, name: &str, address: SocketAddr) -> std::io::Result<TcpListener> {
+        match self.inherited.get(name) {
+            Some(listener) => TcpListener::from_std(listener.try_clone()?),
+            None => self.listener_options.bind(address),
+        }
+    }
+
+    async fn grace_expired(&self, name: &str) {
+        self.stopped().await;
+        info!(
+            "Draining {name} server for up to {} ms",
+            self.shutdown_grace.as_millis()
+        );
+        clock::sleep(self.shutdown_grace).await;
+    }
+
+    fn stopped(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut shutdown = self.shutdown.clone();
+        async move {
+            let _ = shutdown.wait_for(|stopped| *stopped).await;
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+const LISTEN_FDS_START: i32 = 3;
+
+// Listeners passed by systemd socket activation (`LISTEN_FDS`). A socket whose
+// `FileDescriptorName=` is one of `names` is used for that server; the others
+// are handed out to the remaining names in order, so a socket unit with two
+// `ListenStream=` lines and no names gives the proxy the first one.
+pub fn listeners(names: &[&str]) -> io::Result<BTreeMap<String, std::net::TcpListener>> {
+    let mut listeners = BTreeMap::new();
+    let count = match std::env::var("LISTEN_FDS") {
+        Ok(count) => count
+            .parse::<i32>()
+            .map_err(|_| invalid(format!("invalid LISTEN_FDS {count:?}")))?,
+        Err(_) => return Ok(listeners),
+    };
+    if std::env::var("LISTEN_PID").is_ok_and(|pid| pid != std::process::id().to_string()) {
+        debug!("LISTEN_FDS is meant for another process, ignoring it");
+        return Ok(listeners);
+    }
+    let fd_names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut fd_names = fd_names.split(':');
+    let mut unnamed = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let listener = inherit(fd)?;
+        match fd_names.next().filter(|name| names.contains(name)) {
+            Some(name) if !listeners.contains_key(name) => {
+                listeners.insert(name.to_string(), listener);
+            }
+            _ => unnamed.push(listener),
+        }
+    }
+    let mut unnamed = unnamed.into_iter();
+    for name in names {
+        if !listeners.contains_key(*name)
+            && let Some(listener) = unnamed.next()
+        {
+            listeners.insert(name.to_string(), listener);
+        }
+    }
+    if unnamed.len() > 0 {
+        warn!(
+            "Ignoring {} extra socket activation listeners",
+            unnamed.len()
+        );
+    }
+    Ok(listeners)
+}
+
+#[cfg(unix)]
+fn inherit(fd: i32) -> io::Result<std::net::TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    // SAFETY: systemd passes LISTEN_FDS open descriptors starting at 3, and
+    // each of them is taken over exactly once here.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.local_addr().map_err(|err| {
+        invalid(format!(
+            "socket activation fd {fd} is not a TCP listener: {err}"
+        ))
+    })?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn inherit(_fd: i32) -> io::Result<std::net::TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation is not supported on this platform",
+    ))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// Sends a state such as `READY=1` to the service manager. Does nothing when
+// lowdown isn't running under systemd with `Type=notify`.
+pub fn notify(state: &str) {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        warn!("Failed to notify systemd of {state:?}: {err}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send(path: &str, state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send(path: &str, state: &str) -> io::Result<()> {
+    std::os::unix::net::UnixDatagram::unbound()?.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_path: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "systemd notifications are not supported on this platform",
+    ))
+}
+
+// Half of `WatchdogSec=`, as sd_watchdog_enabled recommends.
+pub fn watchdog_interval() -> Option<Duration> {
+    if std::env::var("WATCHDOG_PID").is_ok_and(|pid| pid != std::process::id().to_string()) {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
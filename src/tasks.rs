@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+const FINISHED_CAPACITY: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TaskInfo {
+    pub id: Uuid,
+    pub kind: &'static str,
+    pub description: String,
+    pub status: TaskStatus,
+    pub started_at: Timestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct TaskEntry {
+    info: TaskInfo,
+    abort: Option<AbortHandle>,
+}
+
+type TaskMap = Arc<Mutex<HashMap<Uuid, TaskEntry>>>;
+
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: TaskMap,
+}
+
+#[derive(Clone)]
+pub struct TaskProgress {
+    id: Uuid,
+    tasks: TaskMap,
+}
+
+impl TaskProgress {
+    pub fn set(&self, percent: u8) {
+        if let Some(entry) = self.tasks.lock().get_mut(&self.id) {
+            entry.info.progress = Some(percent.min(100));
+        }
+    }
+}
+
+pub enum CancelError {
+    NotFound,
+    NotRunning(TaskStatus),
+}
+
+impl TaskRegistry {
+    pub fn spawn<F, Fut>(&self, kind: &'static str, description: String, task: F) -> Uuid
+    where
+        F: FnOnce(TaskProgress) -> Fut,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let future = task(TaskProgress {
+            id,
+            tasks: self.tasks.clone(),
+        });
+        let tasks = self.tasks.clone();
+        let mut guard = self.tasks.lock();
+        let handle = tokio::spawn(async move {
+            let result = future.await;
+            finish(&tasks, id, result);
+        });
+        guard.insert(
+            id,
+            TaskEntry {
+                info: TaskInfo {
+                    id,
+                    kind,
+                    description,
+                    status: TaskStatus::Running,
+                    started_at: Timestamp::now(),
+                    finished_at: None,
+                    progress: None,
+                    error: None,
+                },
+                abort: Some(handle.abort_handle()),
+            },
+        );
+        id
+    }
+
+    pub fn list(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<TaskInfo> = self
+            .tasks
+            .lock()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect();
+        tasks.sort_by_key(|task| task.started_at);
+        tasks
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<TaskInfo> {
+        self.tasks.lock().get(&id).map(|entry| entry.info.clone())
+    }
+
+    pub fn cancel(&self, id: Uuid) -> Result<TaskInfo, CancelError> {
+        let mut guard = self.tasks.lock();
+        let entry = guard.get_mut(&id).ok_or(CancelError::NotFound)?;
+        if entry.info.status != TaskStatus::Running {
+            return Err(CancelError::NotRunning(entry.info.status));
+        }
+        if let Some(abort) = entry.abort.take() {
+            abort.abort();
+        }
+        entry.info.status = TaskStatus::Cancelled;
+        entry.info.finished_at = Some(Timestamp::now());
+        let info = entry.info.clone();
+        prune(&mut guard);
+        Ok(info)
+    }
+}
+
+fn finish(tasks: &TaskMap, id: Uuid, result: Result<(), String>) {
+    let mut guard = tasks.lock();
+    let Some(entry) = guard.get_mut(&id) else {
+        return;
+    };
+    if entry.info.status != TaskStatus::Running {
+        return;
+    }
+    entry.abort = None;
+    entry.info.finished_at = Some(Timestamp::now());
+    match result {
+        Ok(()) => {
+            entry.info.status = TaskStatus::Completed;
+            entry.info.progress = Some(100);
+        }
+        Err(err) => {
+            entry.info.status = TaskStatus::Failed;
+            entry.info.error = Some(err);
+        }
+    }
+    prune(&mut guard);
+}
+
+fn prune(tasks: &mut HashMap<Uuid, TaskEntry>) {
+    let mut finished: Vec<(Timestamp, Uuid)> = tasks
+        .values()
+        .filter_map(|entry| entry.info.finished_at.map(|at| (at, entry.info.id)))
+        .collect();
+    if finished.len() <= FINISHED_CAPACITY {
+        return;
+    }
+    finished.sort();
+    for (_, id) in finished.iter().take(finished.len() - FINISHED_CAPACITY) {
+        tasks.remove(id);
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::clock;
+use crate::first_per_key::render_key;
+use crate::settings::RequestContext;
+use crate::snapshots::sanitize;
+
+pub fn render_path(dir: &Path, template: &str, ctx: &RequestContext) -> Option<PathBuf> {
+    let template = template
+        .replace("{id}", &Uuid::new_v4().to_string())
+        .replace(
+            "{timestamp}",
+            &clock::timestamp().as_millisecond().to_string(),
+        );
+    let relative: PathBuf = render_key(&template, ctx)
+        .split('/')
+        .map(sanitize)
+        .filter(|component| !component.is_empty())
+        .collect();
+    (relative.components().next().is_some()).then(|| dir.join(relative))
+}
+
+pub fn spawn_write(path: PathBuf, body: Bytes) {
+    tokio::spawn(async move {
+        let result = async {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let mut partial = path.clone().into_os_string();
+            partial.push(".part");
+            tokio::fs::write(&partial, &body).await?;
+            tokio::fs::rename(&partial, &path).await
+        }
+        .await;
+        match result {
+            Ok(()) => debug!("Wrote {} response bytes to {}", body.len(), path.display()),
+            Err(err) => warn!("Failed to write response body to {}: {err}", path.display()),
+        }
+    });
+}
@@ -0,0 +1,381 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{Path, State, rejection::JsonRejection},
+    http::{Response, StatusCode},
+    routing::{get, post},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::info;
+use url::Url;
+
+use crate::response::json_response;
+use crate::settings::{Millis, Percentage, Probability, SettingsLayer};
+use crate::state::AppState;
+
+#[derive(Default)]
+pub struct ToxiproxyRegistry {
+    proxies: Mutex<BTreeMap<String, ToxiProxy>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ToxiProxy {
+    name: String,
+    listen: String,
+    upstream: String,
+    enabled: bool,
+    toxics: Vec<Toxic>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Toxic {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "default_stream")]
+    stream: String,
+    #[serde(default = "default_toxicity")]
+    toxicity: f64,
+    #[serde(default)]
+    attributes: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProxyRequest {
+    name: Option<String>,
+    listen: Option<String>,
+    upstream: Option<String>,
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToxicUpdate {
+    toxicity: Option<f64>,
+    attributes: Option<BTreeMap<String, Value>>,
+}
+
+fn default_stream() -> String {
+    "downstream".to_string()
+}
+
+fn default_toxicity() -> f64 {
+    1.0
+}
+
+impl ToxiproxyRegistry {
+    pub fn layer_for(&self, destination_url: &str) -> Option<SettingsLayer> {
+        let authority = authority(destination_url)?;
+        let guard = self.proxies.lock();
+        let proxy = guard
+            .values()
+            .find(|proxy| proxy.upstream.eq_ignore_ascii_case(&authority))?;
+        let mut layer = SettingsLayer::default();
+        if !proxy.enabled {
+            layer.fail_before_percentage = Some(Probability::ALWAYS);
+            return Some(layer);
+        }
+        for toxic in &proxy.toxics {
+            apply_toxic(&mut layer, toxic);
+        }
+        Some(layer)
+    }
+}
+
+fn authority(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    let port = parsed.port_or_known_default()?;
+    Some(format!("{host}:{port}"))
+}
+
+fn apply_toxic(layer: &mut SettingsLayer, toxic: &Toxic) {
+    let percentage = Some(Probability::from(Percentage::saturating(
+        (toxic.toxicity.clamp(0.0, 1.0) * 100.0).round() as u64,
+    )));
+    let attribute = |key: &str| toxic.attributes.get(key).and_then(Value::as_u64);
+    let millis = |key: &str| Some(Millis::new(attribute(key).unwrap_or(0)));
+    match toxic.kind.as_str() {
+        "latency" => {
+            let latency = millis("latency");
+            let jitter = millis("jitter");
+            if toxic.stream == "upstream" {
+                layer.delay_before_ms = latency;
+                layer.delay_before_jitter_ms = jitter;
+                layer.delay_before_percentage = percentage;
+            } else {
+                layer.delay_after_ms = latency;
+                layer.delay_after_jitter_ms = jitter;
+                layer.delay_after_percentage = percentage;
+            }
+        }
+        "timeout" => {
+            layer.delay_before_ms = millis("timeout");
+            layer.delay_before_percentage = percentage.clone();
+            layer.fail_before_code = Some(StatusCode::GATEWAY_TIMEOUT.into());
+            layer.fail_before_percentage = percentage;
+        }
+        _ => {}
+    }
+}
+
+fn validate_toxic(toxic: &Toxic) -> Result<(), String> {
+    match toxic.kind.as_str() {
+        "latency" => Ok(()),
+        "timeout" if toxic.attributes.get("timeout").and_then(Value::as_u64) != Some(0) => Ok(()),
+        "timeout" => Err("timeout toxic with timeout 0 is not supported".to_string()),
+        other => Err(format!("toxic type {other} is not supported")),
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/version", get(version))
+        .route("/reset", post(reset))
+        .route("/proxies", get(list_proxies).post(create_proxy))
+        .route(
+            "/proxies/:proxy",
+            get(get_proxy).post(update_proxy).delete(delete_proxy),
+        )
+        .route(
+            "/proxies/:proxy/toxics",
+            get(list_toxics).post(create_toxic),
+        )
+        .route(
+            "/proxies/:proxy/toxics/:toxic",
+            get(get_toxic).post(update_toxic).delete(delete_toxic),
+        )
+}
+
+fn error(state: &AppState, status: StatusCode, message: impl Into<String>) -> Response<Body> {
+    json_response(
+        status,
+        &json!({"error": message.into(), "status": status.as_u16()}),
+        state.dev_mode(),
+    )
+}
+
+fn no_content() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .expect("building response")
+}
+
+async fn version(State(state): State<Arc<AppState>>) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        &json!({"version": env!("CARGO_PKG_VERSION")}),
+        state.dev_mode(),
+    )
+}
+
+async fn reset(State(state): State<Arc<AppState>>) -> Response<Body> {
+    for proxy in state.toxiproxy().proxies.lock().values_mut() {
+        proxy.enabled = true;
+        proxy.toxics.clear();
+    }
+    state.settings_changed();
+    info!("Toxiproxy reset");
+    no_content()
+}
+
+async fn list_proxies(State(state): State<Arc<AppState>>) -> Response<Body> {
+    let proxies = state.toxiproxy().proxies.lock().clone();
+    json_response(StatusCode::OK, &proxies, state.dev_mode())
+}
+
+async fn create_proxy(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<ProxyRequest>, JsonRejection>,
+) -> Response<Body> {
+    let request = match body {
+        Ok(Json(request)) => request,
+        Err(rejection) => return error(&state, StatusCode::BAD_REQUEST, rejection.body_text()),
+    };
+    let (Some(name), Some(upstream)) = (request.name, request.upstream) else {
+        return error(
+            &state,
+            StatusCode::BAD_REQUEST,
+            "missing required field: name, upstream",
+        );
+    };
+    let mut guard = state.toxiproxy().proxies.lock();
+    if guard.contains_key(&name) {
+        return error(&state, StatusCode::CONFLICT, "proxy already exists");
+    }
+    let proxy = ToxiProxy {
+        name: name.clone(),
+        listen: request.listen.unwrap_or_default(),
+        upstream,
+        enabled: request.enabled.unwrap_or(true),
+        toxics: Vec::new(),
+    };
+    info!("Toxiproxy created proxy {name} for {}", proxy.upstream);
+    guard.insert(name, proxy.clone());
+    json_response(StatusCode::CREATED, &proxy, state.dev_mode())
+}
+
+async fn get_proxy(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response<Body> {
+    match state.toxiproxy().proxies.lock().get(&name) {
+        Some(proxy) => json_response(StatusCode::OK, proxy, state.dev_mode()),
+        None => error(&state, StatusCode::NOT_FOUND, "proxy not found"),
+    }
+}
+
+async fn update_proxy(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    body: Result<Json<ProxyRequest>, JsonRejection>,
+) -> Response<Body> {
+    let request = match body {
+        Ok(Json(request)) => request,
+        Err(rejection) => return error(&state, StatusCode::BAD_REQUEST, rejection.body_text()),
+    };
+    let mut guard = state.toxiproxy().proxies.lock();
+    let Some(proxy) = guard.get_mut(&name) else {
+        return error(&state, StatusCode::NOT_FOUND, "proxy not found");
+    };
+    if let Some(listen) = request.listen {
+        proxy.listen = listen;
+    }
+    if let Some(upstream) = request.upstream {
+        proxy.upstream = upstream;
+    }
+    if let Some(enabled) = request.enabled {
+        proxy.enabled = enabled;
+    }
+    let proxy = proxy.clone();
+    drop(guard);
+    state.settings_changed();
+    json_response(StatusCode::OK, &proxy, state.dev_mode())
+}
+
+async fn delete_proxy(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response<Body> {
+    let removed = state.toxiproxy().proxies.lock().remove(&name);
+    match removed {
+        Some(_) => {
+            state.settings_changed();
+            no_content()
+        }
+        None => error(&state, StatusCode::NOT_FOUND, "proxy not found"),
+    }
+}
+
+async fn list_toxics(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response<Body> {
+    match state.toxiproxy().proxies.lock().get(&name) {
+        Some(proxy) => json_response(StatusCode::OK, &proxy.toxics, state.dev_mode()),
+        None => error(&state, StatusCode::NOT_FOUND, "proxy not found"),
+    }
+}
+
+async fn create_toxic(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    body: Result<Json<Toxic>, JsonRejection>,
+) -> Response<Body> {
+    let mut toxic = match body {
+        Ok(Json(toxic)) => toxic,
+        Err(rejection) => return error(&state, StatusCode::BAD_REQUEST, rejection.body_text()),
+    };
+    if let Err(message) = validate_toxic(&toxic) {
+        return error(&state, StatusCode::BAD_REQUEST, message);
+    }
+    if toxic.name.is_empty() {
+        toxic.name = format!("{}_{}", toxic.kind, toxic.stream);
+    }
+    let mut guard = state.toxiproxy().proxies.lock();
+    let Some(proxy) = guard.get_mut(&name) else {
+        return error(&state, StatusCode::NOT_FOUND, "proxy not found");
+    };
+    if proxy
+        .toxics
+        .iter()
+        .any(|existing| existing.name == toxic.name)
+    {
+        return error(&state, StatusCode::CONFLICT, "toxic already exists");
+    }
+    info!("Toxiproxy added toxic {} to proxy {name}", toxic.name);
+    proxy.toxics.push(toxic.clone());
+    drop(guard);
+    state.settings_changed();
+    json_response(StatusCode::OK, &toxic, state.dev_mode())
+}
+
+async fn get_toxic(
+    State(state): State<Arc<AppState>>,
+    Path((name, toxic_name)): Path<(String, String)>,
+) -> Response<Body> {
+    let guard = state.toxiproxy().proxies.lock();
+    let Some(proxy) = guard.get(&name) else {
+        return error(&state, StatusCode::NOT_FOUND, "proxy not found");
+    };
+    match proxy.toxics.iter().find(|toxic| toxic.name == toxic_name) {
+        Some(toxic) => json_response(StatusCode::OK, toxic, state.dev_mode()),
+        None => error(&state, StatusCode::NOT_FOUND, "toxic not found"),
+    }
+}
+
+async fn update_toxic(
+    State(state): State<Arc<AppState>>,
+    Path((name, toxic_name)): Path<(String, String)>,
+    body: Result<Json<ToxicUpdate>, JsonRejection>,
+) -> Response<Body> {
+    let update = match body {
+        Ok(Json(update)) => update,
+        Err(rejection) => return error(&state, StatusCode::BAD_REQUEST, rejection.body_text()),
+    };
+    let mut guard = state.toxiproxy().proxies.lock();
+    let Some(proxy) = guard.get_mut(&name) else {
+        return error(&state, StatusCode::NOT_FOUND, "proxy not found");
+    };
+    let Some(toxic) = proxy
+        .toxics
+        .iter_mut()
+        .find(|toxic| toxic.name == toxic_name)
+    else {
+        return error(&state, StatusCode::NOT_FOUND, "toxic not found");
+    };
+    let mut updated = toxic.clone();
+    if let Some(toxicity) = update.toxicity {
+        updated.toxicity = toxicity;
+    }
+    if let Some(attributes) = update.attributes {
+        updated.attributes.extend(attributes);
+    }
+    if let Err(message) = validate_toxic(&updated) {
+        return error(&state, StatusCode::BAD_REQUEST, message);
+    }
+    *toxic = updated.clone();
+    drop(guard);
+    state.settings_changed();
+    json_response(StatusCode::OK, &updated, state.dev_mode())
+}
+
+async fn delete_toxic(
+    State(state): State<Arc<AppState>>,
+    Path((name, toxic_name)): Path<(String, String)>,
+) -> Response<Body> {
+    let mut guard = state.toxiproxy().proxies.lock();
+    let Some(proxy) = guard.get_mut(&name) else {
+        return error(&state, StatusCode::NOT_FOUND, "proxy not found");
+    };
+    let before = proxy.toxics.len();
+    proxy.toxics.retain(|toxic| toxic.name != toxic_name);
+    if proxy.toxics.len() == before {
+        return error(&state, StatusCode::NOT_FOUND, "toxic not found");
+    }
+    drop(guard);
+    state.settings_changed();
+    no_content()
+}
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use jiff::Timestamp;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+const CAPACITY: usize = 10_000;
+
+pub const DEFAULT_RULE: &str = "settings";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrafficRecord {
+    pub seq: u64,
+    pub at: Timestamp,
+    pub method: String,
+    pub uri: String,
+    pub destination: Option<String>,
+    pub rule: Option<String>,
+    pub deployment_marker: Option<String>,
+    pub status: u16,
+    pub faults: Vec<&'static str>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AdminChange {
+    pub at: Timestamp,
+    pub method: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub settings: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment_marker: Option<String>,
+}
+
+#[derive(Default)]
+pub struct TrafficLog {
+    inner: Mutex<TrafficInner>,
+}
+
+#[derive(Default)]
+struct TrafficInner {
+    next_seq: u64,
+    records: VecDeque<TrafficRecord>,
+    changes: VecDeque<AdminChange>,
+}
+
+impl TrafficLog {
+    pub fn record(&self, mut record: TrafficRecord) {
+        let mut guard = self.inner.lock();
+        guard.next_seq += 1;
+        record.seq = guard.next_seq;
+        if guard.records.len() == CAPACITY {
+            guard.records.pop_front();
+        }
+        guard.records.push_back(record);
+    }
+
+    pub fn record_admin_change(&self, change: AdminChange) {
+        let mut guard = self.inner.lock();
+        if guard.changes.len() == CAPACITY {
+            guard.changes.pop_front();
+        }
+        guard.changes.push_back(change);
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.inner.lock().next_seq
+    }
+
+    // The flag is set when some of the records after `seq` were already
+    // dropped to make room for newer ones.
+    pub fn after(&self, seq: u64) -> (Vec<TrafficRecord>, bool) {
+        let guard = self.inner.lock();
+        let truncated = guard
+            .records
+            .front()
+            .is_some_and(|oldest| oldest.seq > seq.saturating_add(1));
+        let records = guard
+            .records
+            .iter()
+            .filter(|record| record.seq > seq)
+            .cloned()
+            .collect();
+        (records, truncated)
+    }
+
+    pub fn since(&self, since: Option<Timestamp>) -> (Vec<TrafficRecord>, Vec<AdminChange>) {
+        let guard = self.inner.lock();
+        let recent = |at: &Timestamp| since.is_none_or(|since| *at >= since);
+        (
+            guard
+                .records
+                .iter()
+                .filter(|record| recent(&record.at))
+                .cloned()
+                .collect(),
+            guard
+                .changes
+                .iter()
+                .filter(|change| recent(&change.at))
+                .cloned()
+                .collect(),
+        )
+    }
+}
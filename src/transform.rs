@@ -0,0 +1,222 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::clock::sleep;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use regex::bytes::Regex;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Request,
+    #[default]
+    Response,
+}
+
+#[derive(Debug, Clone)]
+pub struct BodyRegex(Regex);
+
+impl Serialize for BodyRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for BodyRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(Self).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum TransformStep {
+    SetHeader {
+        name: String,
+        value: String,
+        #[serde(default)]
+        phase: Phase,
+    },
+    AppendHeader {
+        name: String,
+        value: String,
+        #[serde(default)]
+        phase: Phase,
+    },
+    RemoveHeader {
+        name: String,
+        #[serde(default)]
+        phase: Phase,
+    },
+    ReplaceBodyRegex {
+        pattern: BodyRegex,
+        replacement: String,
+        #[serde(default)]
+        phase: Phase,
+    },
+    SetStatus {
+        status: u16,
+    },
+    Delay {
+        ms: u64,
+        #[serde(default)]
+        phase: Phase,
+    },
+}
+
+impl TransformStep {
+    fn phase(&self) -> Phase {
+        match self {
+            TransformStep::SetHeader { phase, .. }
+            | TransformStep::AppendHeader { phase, .. }
+            | TransformStep::RemoveHeader { phase, .. }
+            | TransformStep::ReplaceBodyRegex { phase, .. }
+            | TransformStep::Delay { phase, .. } => *phase,
+            TransformStep::SetStatus { .. } => Phase::Response,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            TransformStep::SetHeader { name, value, .. }
+            | TransformStep::AppendHeader { name, value, .. } => {
+                HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header name {name:?}"))?;
+                HeaderValue::from_str(value)
+                    .map_err(|_| format!("invalid header value {value:?}"))?;
+                Ok(())
+            }
+            TransformStep::RemoveHeader { name, .. } => HeaderName::from_bytes(name.as_bytes())
+                .map(|_| ())
+                .map_err(|_| format!("invalid header name {name:?}")),
+            TransformStep::SetStatus { status } => StatusCode::from_u16(*status)
+                .map(|_| ())
+                .map_err(|_| format!("invalid status {status}")),
+            TransformStep::ReplaceBodyRegex { .. } | TransformStep::Delay { .. } => Ok(()),
+        }
+    }
+}
+
+pub struct Message<'a> {
+    pub headers: &'a mut HeaderMap,
+    pub body: &'a mut Bytes,
+    pub status: Option<&'a mut StatusCode>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline(Vec<TransformStep>);
+
+impl Pipeline {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn has_phase(&self, phase: Phase) -> bool {
+        self.0.iter().any(|step| step.phase() == phase)
+    }
+
+    pub fn edits_request_body(&self) -> bool {
+        self.0.iter().any(|step| {
+            matches!(
+                step,
+                TransformStep::ReplaceBodyRegex {
+                    phase: Phase::Request,
+                    ..
+                }
+            )
+        })
+    }
+
+    pub fn edits_response_body(&self) -> bool {
+        self.0.iter().any(|step| {
+            matches!(
+                step,
+                TransformStep::ReplaceBodyRegex {
+                    phase: Phase::Response,
+                    ..
+                }
+            )
+        })
+    }
+
+    pub async fn run(&self, phase: Phase, message: Message<'_>) {
+        let Message {
+            headers,
+            body,
+            mut status,
+        } = message;
+        for step in self.0.iter().filter(|step| step.phase() == phase) {
+            debug!("transform {phase:?} {step:?}");
+            match step {
+                TransformStep::SetHeader { name, value, .. } => {
+                    if let (Ok(name), Ok(value)) = (
+                        HeaderName::from_bytes(name.as_bytes()),
+                        HeaderValue::from_str(value),
+                    ) {
+                        headers.insert(name, value);
+                    }
+                }
+                TransformStep::AppendHeader { name, value, .. } => {
+                    if let (Ok(name), Ok(value)) = (
+                        HeaderName::from_bytes(name.as_bytes()),
+                        HeaderValue::from_str(value),
+                    ) {
+                        headers.append(name, value);
+                    }
+                }
+                TransformStep::RemoveHeader { name, .. } => {
+                    headers.remove(name.as_str());
+                }
+                TransformStep::ReplaceBodyRegex {
+                    pattern,
+                    replacement,
+                    ..
+                } => {
+                    let replaced = pattern.0.replace_all(body, replacement.as_bytes());
+                    *body = Bytes::from(replaced.into_owned());
+                }
+                TransformStep::SetStatus { status: code } => {
+                    if let (Some(status), Ok(code)) =
+                        (status.as_deref_mut(), StatusCode::from_u16(*code))
+                    {
+                        *status = code;
+                    }
+                }
+                TransformStep::Delay { ms, .. } => sleep(Duration::from_millis(*ms)).await,
+            }
+        }
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let steps: Vec<TransformStep> =
+            serde_json::from_str(text).map_err(|err| format!("invalid transform: {err}"))?;
+        steps.iter().try_for_each(TransformStep::validate)?;
+        Ok(Self(steps))
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = serde_json::to_string(&self.0).map_err(|_| fmt::Error)?;
+        f.write_str(&text)
+    }
+}
+
+impl Serialize for Pipeline {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
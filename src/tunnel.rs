@@ -0,0 +1,37 @@
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::streams::{Registration, StreamControl};
+
+// Relays bytes between the upgraded client connection and `upstream` until
+// either side closes, then reports (bytes sent upstream, bytes received). A
+// terminated tunnel is closed without a report; it stays in the stream
+// registry until then.
+pub fn relay(
+    upgrade: OnUpgrade,
+    mut upstream: TcpStream,
+    (control, registration): (StreamControl, Registration),
+    finished: impl FnOnce(u64, u64) + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let upgraded = match upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                debug!("CONNECT upgrade failed: {err}");
+                return;
+            }
+        };
+        let mut client = TokioIo::new(upgraded);
+        let relayed = tokio::select! {
+            relayed = tokio::io::copy_bidirectional(&mut client, &mut upstream) => relayed,
+            () = control.terminated() => return,
+        };
+        drop(registration);
+        match relayed {
+            Ok((sent, received)) => finished(sent, received),
+            Err(err) => debug!("CONNECT tunnel closed: {err}"),
+        }
+    });
+}
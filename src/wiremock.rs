@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+
+use base64::Engine;
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::stubs::{Stub, StubRequest, StubResponse, UrlMatcher, ValueMatcher, header_map};
+
+const DEFAULT_PRIORITY: i64 = 5;
+
+#[derive(Debug, Error)]
+pub enum WireMockImportError {
+    #[error("could not parse WireMock mappings: {0}")]
+    Parse(String),
+    #[error("mapping {index}: {message}")]
+    Mapping { index: usize, message: String },
+}
+
+#[derive(Debug, Default)]
+pub struct WireMockImport {
+    pub stubs: Vec<Stub>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MappingsDocument {
+    Many { mappings: Vec<Mapping> },
+    One(Box<Mapping>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Mapping {
+    id: Option<Uuid>,
+    name: Option<String>,
+    priority: Option<i64>,
+    #[serde(default)]
+    request: RequestPattern,
+    #[serde(default)]
+    response: ResponseDefinition,
+    scenario_name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestPattern {
+    method: Option<String>,
+    url: Option<String>,
+    url_path: Option<String>,
+    url_pattern: Option<String>,
+    url_path_pattern: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, Value>,
+    #[serde(default)]
+    query_parameters: BTreeMap<String, Value>,
+    #[serde(default)]
+    body_patterns: Vec<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponseDefinition {
+    status: Option<u16>,
+    body: Option<String>,
+    json_body: Option<Value>,
+    base64_body: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, Value>,
+    fixed_delay_milliseconds: Option<u64>,
+    body_file_name: Option<String>,
+    fault: Option<String>,
+    #[serde(default)]
+    transformers: Vec<String>,
+}
+
+pub fn import_mappings(text: &str) -> Result<WireMockImport, WireMockImportError> {
+    let document: MappingsDocument =
+        serde_json::from_str(text).map_err(|err| WireMockImportError::Parse(err.to_string()))?;
+    let mappings = match document {
+        MappingsDocument::Many { mappings } => mappings,
+        MappingsDocument::One(mapping) => vec![*mapping],
+    };
+    let mut import = WireMockImport::default();
+    for (index, mapping) in mappings.into_iter().enumerate() {
+        let stub = convert(mapping, &mut import.warnings)
+            .map_err(|message| WireMockImportError::Mapping { index, message })?;
+        import.stubs.push(stub);
+    }
+    Ok(import)
+}
+
+fn convert(mapping: Mapping, warnings: &mut Vec<String>) -> Result<Stub, String> {
+    let id = mapping.id.unwrap_or_else(Uuid::new_v4);
+    if mapping.scenario_name.is_some() {
+        warnings.push(format!(
+            "{id}: scenarios are not supported and were ignored"
+        ));
+    }
+    Ok(Stub {
+        id,
+        name: mapping.name,
+        priority: mapping.priority.unwrap_or(DEFAULT_PRIORITY),
+        source: "wiremock",
+        request: convert_request(mapping.request)?,
+        response: convert_response(id, mapping.response, warnings)?,
+    })
+}
+
+fn convert_request(pattern: RequestPattern) -> Result<StubRequest, String> {
+    let method = match pattern.method.as_deref() {
+        None | Some("ANY") => None,
+        Some(method) => Some(
+            Method::from_bytes(method.as_bytes())
+                .map_err(|_| format!("invalid method {method:?}"))?,
+        ),
+    };
+    let url = if let Some(url) = pattern.url {
+        UrlMatcher::Exact(url)
+    } else if let Some(path) = pattern.url_path {
+        UrlMatcher::Path(path)
+    } else if let Some(regex) = pattern.url_pattern {
+        UrlMatcher::Pattern(compile(&regex)?)
+    } else if let Some(regex) = pattern.url_path_pattern {
+        UrlMatcher::PathPattern(compile(&regex)?)
+    } else {
+        UrlMatcher::Any
+    };
+    let headers = pattern
+        .headers
+        .into_iter()
+        .map(|(name, matcher)| Ok((name.to_ascii_lowercase(), value_matcher(&matcher)?)))
+        .collect::<Result<_, String>>()?;
+    let query = pattern
+        .query_parameters
+        .into_iter()
+        .map(|(name, matcher)| Ok((name, value_matcher(&matcher)?)))
+        .collect::<Result<_, String>>()?;
+    let body = pattern
+        .body_patterns
+        .iter()
+        .map(value_matcher)
+        .collect::<Result<_, String>>()?;
+    Ok(StubRequest {
+        method,
+        url,
+        headers,
+        query,
+        body,
+    })
+}
+
+fn value_matcher(value: &Value) -> Result<ValueMatcher, String> {
+    let case_insensitive = value
+        .get("caseInsensitive")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let text = |key: &str| value.get(key).and_then(Value::as_str);
+    if let Some(expected) = text("equalTo") {
+        Ok(ValueMatcher::EqualTo {
+            value: expected.to_string(),
+            case_insensitive,
+        })
+    } else if let Some(expected) = text("contains") {
+        Ok(ValueMatcher::Contains(expected.to_string()))
+    } else if let Some(regex) = text("matches") {
+        Ok(ValueMatcher::Matches(compile(regex)?))
+    } else if let Some(regex) = text("doesNotMatch") {
+        Ok(ValueMatcher::DoesNotMatch(compile(regex)?))
+    } else if value.get("absent").and_then(Value::as_bool) == Some(true) {
+        Ok(ValueMatcher::Absent)
+    } else if let Some(expected) = value.get("equalToJson") {
+        let expected = match expected {
+            Value::String(text) => serde_json::from_str(text)
+                .map_err(|err| format!("invalid equalToJson value: {err}"))?,
+            other => other.clone(),
+        };
+        Ok(ValueMatcher::EqualToJson(expected))
+    } else {
+        Err(format!("unsupported matcher {value}"))
+    }
+}
+
+fn compile(regex: &str) -> Result<Regex, String> {
+    Regex::new(regex).map_err(|err| format!("invalid regex {regex:?}: {err}"))
+}
+
+fn convert_response(
+    id: Uuid,
+    definition: ResponseDefinition,
+    warnings: &mut Vec<String>,
+) -> Result<StubResponse, String> {
+    let status = StatusCode::from_u16(definition.status.unwrap_or(200))
+        .map_err(|_| format!("invalid status {:?}", definition.status))?;
+    let mut headers = Vec::new();
+    for (name, value) in definition.headers {
+        match value {
+            Value::String(value) => headers.push((name, value)),
+            Value::Array(values) => headers.extend(
+                values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|value| (name.clone(), value.to_string())),
+            ),
+            other => headers.push((name, other.to_string())),
+        }
+    }
+    let body = if let Some(body) = definition.body {
+        Bytes::from(body)
+    } else if let Some(json) = definition.json_body {
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            headers.push(("content-type".to_string(), "application/json".to_string()));
+        }
+        Bytes::from(json.to_string())
+    } else if let Some(encoded) = definition.base64_body {
+        Bytes::from(
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|err| format!("invalid base64Body: {err}"))?,
+        )
+    } else {
+        Bytes::new()
+    };
+    if let Some(file) = definition.body_file_name {
+        warnings.push(format!(
+            "{id}: bodyFileName {file} is not supported, serving an empty body"
+        ));
+    }
+    if let Some(fault) = definition.fault {
+        warnings.push(format!(
+            "{id}: fault {fault} is not supported, use lowdown faults instead"
+        ));
+    }
+    if !definition.transformers.is_empty() {
+        warnings.push(format!("{id}: response transformers are not supported"));
+    }
+    Ok(StubResponse {
+        status,
+        headers: header_map(&headers)?,
+        body,
+        delay_ms: definition.fixed_delay_milliseconds.unwrap_or(0),
+    })
+}
@@ -1,6 +1,7 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::IntoFuture;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use axum::{
@@ -9,24 +10,52 @@ use axum::{
     http::{HeaderMap, HeaderValue, Method, Request, StatusCode},
 };
 use bytes::Bytes;
+use futures_util::StreamExt;
 use http::header::HeaderName;
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::ext::ReasonPhrase;
 use lowdown::{
+    access_log::{AccessLog, AccessLogFormat},
     admin,
+    assertions::{self, AssertionRegistry},
+    binding::OutboundBinding,
+    check,
+    clock::{Clock, VirtualClock},
+    config_file::ConfigFile,
+    fault_set::{Fault, FaultSet},
+    health::HealthConfig,
     http_client::{
-        HttpClient, HttpClientError, OutgoingRequest, ProxiedResponse, SharedHttpClient,
+        ClientConfig, HTTP3_SUPPORTED, HttpClient, HttpClientError, OutgoingRequest,
+        ProxiedResponse, RequestBody, ReqwestHttpClient, SharedHttpClient, TlsOverride,
+        UpstreamProtocol,
     },
+    instances::Instances,
+    kv::KvStore,
+    logging::LogLevel,
     proxy,
-    settings::SettingsLayer,
-    state::AppState,
+    settings::{HeaderPolicy, Millis, Precedence, SettingsLayer},
+    snapshots::{self, SnapshotWriter},
+    state::{AppState, AppStateBuilder, MAX_TRACKED_ONE_OFFS},
+    supervisor::{RestartPolicy, Supervision, Supervisor},
+    traffic::{TrafficLog, TrafficRecord},
 };
 use parking_lot::Mutex;
-use serde_json::Value;
+use serde_json::{Value, json};
+use tokio::sync::watch;
 use tower::util::ServiceExt;
 
 #[derive(Clone)]
 struct RecordedRequest {
     url: String,
     headers: HeaderMap,
+    body: Bytes,
+    trailers: Option<HeaderMap>,
+    binding: Option<OutboundBinding>,
+    protocol: UpstreamProtocol,
+    tls: TlsOverride,
+    preserve_encoding: bool,
+    dns_delay: Option<Duration>,
 }
 
 struct StubClient {
@@ -54,9 +83,17 @@ impl StubClient {
 #[async_trait]
 impl HttpClient for StubClient {
     async fn execute(&self, request: OutgoingRequest) -> Result<ProxiedResponse, HttpClientError> {
+        let body = request.body.collect().await?;
         self.recorded.lock().push(RecordedRequest {
-            url: request.url.clone(),
-            headers: request.headers.clone(),
+            url: request.url,
+            headers: request.headers,
+            body,
+            trailers: request.trailers,
+            binding: request.binding,
+            protocol: request.protocol,
+            tls: request.tls,
+            preserve_encoding: request.preserve_encoding,
+            dns_delay: request.dns_delay,
         });
         let response = self.responses.lock().pop_front().unwrap_or_else(|| {
             ProxiedResponse::new(StatusCode::OK, HeaderMap::new(), Bytes::from_static(b"ok"))
@@ -75,11 +112,18 @@ impl TestHarness {
     fn new() -> Self {
         let client = Arc::new(StubClient::new());
         let shared: SharedHttpClient = client.clone();
-        let state = Arc::new(AppState::new(
-            SettingsLayer::default(),
-            "".to_string(),
-            shared,
-        ));
+        let state = Arc::new(AppState::new(SettingsLayer::default(), shared));
+        Self::from_state(state, client)
+    }
+
+    fn with_builder(configure: impl FnOnce(AppStateBuilder) -> AppStateBuilder) -> Self {
+        let client = Arc::new(StubClient::new());
+        let shared: SharedHttpClient = client.clone();
+        let state = Arc::new(configure(AppState::builder(shared)).build());
+        Self::from_state(state, client)
+    }
+
+    fn from_state(state: Arc<AppState>, client: Arc<StubClient>) -> Self {
         Self {
             proxy: proxy::router(state.clone()),
             admin: admin::router(state),
@@ -100,16 +144,22 @@ impl TestHarness {
 
 struct ResponseParts {
     status: StatusCode,
+    headers: HeaderMap,
     body: Bytes,
 }
 
 impl ResponseParts {
     async fn from(response: axum::http::Response<Body>) -> Self {
         let status = response.status();
+        let headers = response.headers().clone();
         let body = body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
-        Self { status, body }
+        Self {
+            status,
+            headers,
+            body,
+        }
     }
 
     fn json(&self) -> Value {
@@ -298,6 +348,284 @@ async fn one_off_is_consumed_once() {
     assert_eq!(response.status, StatusCode::OK);
 }
 
+#[tokio::test]
+async fn one_off_accepts_a_json_rule() {
+    let harness = TestHarness::new();
+    let added = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .body(Body::from(
+                    json!({
+                        "settings": {"fail-before-percentage": 100, "fail-before-code": 500},
+                        "match": {
+                            "method": "post",
+                            "uri-starts-with": "/orders",
+                            "headers": {"X-Tenant": "acme", "x-debug": "*"},
+                            "body-contains": "\"sku\":\"abc\"",
+                        },
+                        "count": 2,
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(added.status, StatusCode::OK);
+    let added = added.json();
+    assert!(added["id"].is_string());
+    assert_eq!(
+        added["rule"],
+        json!({
+            "settings": {"fail-before-code": "500", "fail-before-percentage": "100"},
+            "match": {
+                "method": "POST",
+                "uri-starts-with": "/orders",
+                "headers": {"x-debug": "*", "x-tenant": "acme"},
+                "body-contains": "\"sku\":\"abc\"",
+            },
+            "count": 2,
+        })
+    );
+
+    let (header_name, header_value) = destination_header();
+    let call = |tenant: &str, body: &'static str| {
+        request_builder(Method::POST, "/orders/1")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-tenant", tenant)
+            .header("x-debug", "1")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let mut statuses = Vec::new();
+    for (tenant, body) in [
+        ("other", "{\"sku\":\"abc\"}"),
+        ("acme", "{\"sku\":\"xyz\"}"),
+        ("acme", "{\"sku\":\"abc\"}"),
+        ("acme", "{\"sku\":\"abc\"}"),
+        ("acme", "{\"sku\":\"abc\"}"),
+    ] {
+        statuses.push(harness.proxy_call(call(tenant, body)).await.status.as_u16());
+    }
+    assert_eq!(statuses, [200, 200, 500, 500, 200]);
+    let recorded = harness.client.recordings();
+    assert_eq!(recorded.len(), 3);
+    assert_eq!(recorded[2].body, Bytes::from_static(b"{\"sku\":\"abc\"}"));
+
+    let invalid = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .body(Body::from(
+                    r#"{"settings": {"fail-before-percentage": 150}}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.json()["error"], "invalid-rule");
+}
+
+#[tokio::test]
+async fn encoded_request_bodies_are_decoded_for_matching() {
+    use std::io::Write;
+
+    let harness = TestHarness::new();
+    let added = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .body(Body::from(
+                    json!({
+                        "settings": {"strip-content-encoding-percentage": 100},
+                        "match": {"body-contains": "\"sku\":\"abc\""},
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(added.status, StatusCode::OK);
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"{\"sku\":\"abc\"}").unwrap();
+    let encoded = Bytes::from(encoder.finish().unwrap());
+    let (header_name, header_value) = destination_header();
+    let call = || {
+        request_builder(Method::POST, "/orders")
+            .header(header_name.clone(), header_value.clone())
+            .header("content-encoding", "gzip")
+            .body(Body::from(encoded.clone()))
+            .unwrap()
+    };
+    harness.proxy_call(call()).await;
+    harness.proxy_call(call()).await;
+    let recorded = harness.client.recordings();
+    assert_eq!(recorded[0].body, encoded);
+    assert!(!recorded[0].headers.contains_key("content-encoding"));
+    assert_eq!(recorded[1].body, encoded);
+    assert_eq!(recorded[1].headers["content-encoding"], "gzip");
+}
+
+#[tokio::test]
+async fn one_off_wait_resolves_when_consumed() {
+    let harness = TestHarness::new();
+    let added = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-match-uri-starts-with", "/checkout")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    let id = added["id"].as_str().unwrap().to_string();
+    let wait = |timeout: u64| {
+        request_builder(
+            Method::GET,
+            &format!("/api/v1/one-off/{id}/wait?timeout-ms={timeout}"),
+        )
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    let pending = harness.admin_call(wait(10)).await;
+    assert_eq!(pending.status, StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(pending.json()["consumed"], false);
+
+    let (header_name, header_value) = destination_header();
+    let trigger = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        harness
+            .proxy_call(
+                request_builder(Method::POST, "/checkout?step=2")
+                    .header(header_name, header_value)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+    };
+    let (waited, triggered) = tokio::join!(harness.admin_call(wait(5_000)), trigger);
+    assert_eq!(triggered.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(waited.status, StatusCode::OK);
+    let waited = waited.json();
+    assert_eq!(waited["consumed"], true);
+    assert_eq!(waited["matches"][0]["method"], "POST");
+    assert_eq!(waited["matches"][0]["uri"], "/checkout?step=2");
+
+    let again = harness.admin_call(wait(10)).await;
+    assert_eq!(again.status, StatusCode::OK);
+    let unknown = harness
+        .admin_call(
+            request_builder(
+                Method::GET,
+                "/api/v1/one-off/00000000-0000-0000-0000-000000000000/wait",
+            )
+            .body(Body::empty())
+            .unwrap(),
+        )
+        .await;
+    assert_eq!(unknown.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn one_offs_can_be_listed_and_deleted() {
+    let harness = TestHarness::new();
+    let mut ids = Vec::new();
+    for rule in [
+        json!({"settings": {"fail-before-percentage": 100}, "count": 2}),
+        json!({"settings": {"fail-before-code": 500, "fail-before-percentage": 100}}),
+    ] {
+        let added = harness
+            .admin_call(
+                request_builder(Method::POST, "/api/v1/one-off")
+                    .body(Body::from(rule.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .json();
+        ids.push(added["id"].as_str().unwrap().to_string());
+    }
+    let list = || {
+        request_builder(Method::GET, "/api/v1/one-off")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let listed = harness.admin_call(list()).await.json();
+    assert_eq!(listed["one-offs"][0]["id"], ids[0]);
+    assert_eq!(listed["one-offs"][0]["remaining"], 2);
+    assert_eq!(
+        listed["one-offs"][0]["settings"],
+        json!({"fail-before-percentage": "100"})
+    );
+    assert_eq!(listed["one-offs"][1]["id"], ids[1]);
+
+    let delete = |id: &str| {
+        request_builder(Method::DELETE, &format!("/api/v1/one-off/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let deleted = harness.admin_call(delete(&ids[0])).await;
+    assert_eq!(deleted.status, StatusCode::OK);
+    assert!(deleted.headers.contains_key("etag"));
+    assert_eq!(
+        harness.admin_call(delete(&ids[0])).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let listed = harness.admin_call(list()).await.json();
+    assert_eq!(listed["one-offs"], json!([]));
+}
+
+#[tokio::test]
+async fn pending_one_offs_are_capped() {
+    let harness = TestHarness::new();
+    let add = |uri: &str| {
+        request_builder(Method::POST, "/api/v1/one-off")
+            .body(Body::from(
+                json!({"settings": {"fail-before-percentage": 100}, "match": {"uri": uri}})
+                    .to_string(),
+            ))
+            .unwrap()
+    };
+    assert_eq!(harness.admin_call(add("/")).await.status, StatusCode::OK);
+    for _ in 1..MAX_TRACKED_ONE_OFFS {
+        assert_eq!(
+            harness.admin_call(add("/elsewhere")).await.status,
+            StatusCode::OK
+        );
+    }
+    let rejected = harness.admin_call(add("/elsewhere")).await;
+    assert_eq!(rejected.status, StatusCode::CONFLICT);
+    assert_eq!(
+        rejected.json(),
+        json!({"error": "too-many-one-offs", "limit": MAX_TRACKED_ONE_OFFS})
+    );
+
+    let (header_name, header_value) = destination_header();
+    let consumed = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(consumed.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        harness.admin_call(add("/elsewhere")).await.status,
+        StatusCode::OK
+    );
+}
+
 #[tokio::test]
 async fn header_matching() {
     let harness = TestHarness::new();
@@ -327,16 +655,5794 @@ async fn header_matching() {
 
 #[tokio::test]
 async fn delay_before_introduces_latency() {
-    let harness = TestHarness::new();
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
     harness.client.enqueue(json_ok());
     let (header_name, header_value) = destination_header();
     let request = request_builder(Method::GET, "/")
         .header(header_name.clone(), header_value.clone())
         .header("x-lowdown-delay-before-percentage", "100")
-        .header("x-lowdown-delay-before-ms", "75")
+        .header("x-lowdown-delay-before-ms", "600000")
         .body(Body::empty())
         .unwrap();
     let start = Instant::now();
-    harness.proxy_call(request).await;
-    assert!(start.elapsed().as_millis() >= 60);
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(clock.elapsed(), Duration::from_secs(600));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn delay_jitter_spreads_latency() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let mut delays = Vec::new();
+    for _ in 0..5 {
+        harness.client.enqueue(json_ok());
+        let request = request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-delay-before-percentage", "100")
+            .header("x-lowdown-delay-before-ms", "150")
+            .header("x-lowdown-delay-before-jitter-ms", "100")
+            .body(Body::empty())
+            .unwrap();
+        let start = Instant::now();
+        harness.proxy_call(request).await;
+        delays.push(start.elapsed());
+    }
+    assert!(
+        delays
+            .iter()
+            .all(|delay| *delay >= Duration::from_millis(45))
+    );
+    assert!(delays.iter().all(|delay| *delay < Duration::from_secs(1)));
+    let spread = *delays.iter().max().unwrap() - *delays.iter().min().unwrap();
+    assert!(spread >= Duration::from_millis(10), "{delays:?}");
+}
+
+#[tokio::test]
+async fn throttle_paces_the_response_body() {
+    let harness = TestHarness::new();
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        HeaderMap::new(),
+        Bytes::from(vec![b'x'; 100]),
+    ));
+    let (header_name, header_value) = destination_header();
+    let request = request_builder(Method::GET, "/")
+        .header(header_name, header_value)
+        .header("x-lowdown-throttle-percentage", "100")
+        .header("x-lowdown-throttle-bytes-per-second", "500")
+        .body(Body::empty())
+        .unwrap();
+    let start = Instant::now();
+    let response = harness.proxy_call(request).await;
+    assert!(start.elapsed() >= Duration::from_millis(180));
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.len(), 100);
+    assert_eq!(response.headers["content-length"], "100");
+}
+
+#[tokio::test]
+async fn settings_changes_expire_faults_on_long_lived_streams() {
+    let harness = TestHarness::new();
+    let throttled = |mode: &'static str| {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from(vec![b'x'; 1000]),
+        ));
+        let (header_name, header_value) = destination_header();
+        let request = request_builder(Method::GET, &format!("/{mode}"))
+            .header(header_name, header_value)
+            .header("x-lowdown-throttle-percentage", "100")
+            .header("x-lowdown-throttle-bytes-per-second", "100")
+            .header("x-lowdown-stream-expiry-mode", mode)
+            .body(Body::empty())
+            .unwrap();
+        let proxy = harness.proxy.clone();
+        async move {
+            let response = proxy.oneshot(request).await.unwrap();
+            response.into_body()
+        }
+    };
+    let terminated = tokio::spawn(body::to_bytes(throttled("terminate").await, usize::MAX));
+    let stopped = tokio::spawn(body::to_bytes(throttled("stop-faults").await, usize::MAX));
+
+    let streams = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/streams")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    let streams = streams.as_array().unwrap();
+    assert_eq!(streams.len(), 2);
+    let stream = streams
+        .iter()
+        .find(|stream| stream["uri"] == "/terminate")
+        .unwrap();
+    assert_eq!(stream["expiry-mode"], "terminate");
+    assert_eq!(stream["faults"], json!(["throttle"]));
+    assert_eq!(stream["state"], "faulting");
+
+    let start = Instant::now();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-delay-before-ms", "10")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(terminated.await.unwrap().is_err());
+    assert_eq!(stopped.await.unwrap().unwrap().len(), 1000);
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let streams = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/streams")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(streams, json!([]));
+}
+
+#[tokio::test]
+async fn destination_settings_and_toxics_expire_streams_and_tunnels() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let harness = TestHarness::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        None,
+        lowdown::connection::ListenerOptions::default(),
+        std::future::pending(),
+    ));
+    let throttled = || {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from(vec![b'x'; 1000]),
+        ));
+        let (header_name, header_value) = destination_header();
+        let request = request_builder(Method::GET, "/events")
+            .header(header_name, header_value)
+            .header("x-lowdown-throttle-percentage", "100")
+            .header("x-lowdown-throttle-bytes-per-second", "100")
+            .header("x-lowdown-stream-expiry-mode", "terminate")
+            .body(Body::empty())
+            .unwrap();
+        let proxy = harness.proxy.clone();
+        async move {
+            let response = proxy.oneshot(request).await.unwrap();
+            response.into_body()
+        }
+    };
+    let streams = || async {
+        harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/streams")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json()
+    };
+
+    let mut tunnel = tokio::net::TcpStream::connect(addr).await.unwrap();
+    tunnel
+        .write_all(
+            format!(
+                "CONNECT {target} HTTP/1.1\r\nhost: {target}\r\n\
+                 x-lowdown-stream-expiry-mode: terminate\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut head = [0u8; 12];
+    tunnel.read_exact(&mut head).await.unwrap();
+    assert_eq!(&head, b"HTTP/1.1 200");
+    let events = tokio::spawn(body::to_bytes(throttled().await, usize::MAX));
+    let listed = streams().await;
+    let methods: Vec<_> = listed
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stream| stream["method"].clone())
+        .collect();
+    assert_eq!(methods, [json!("CONNECT"), json!("GET")]);
+
+    let start = Instant::now();
+    let stored = harness
+        .admin_call(
+            request_builder(Method::PUT, "/api/v1/destination-settings/example.com")
+                .body(Body::from(json!({"delay-before-ms": 10}).to_string()))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    assert!(events.await.unwrap().is_err());
+    let mut rest = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(2), tunnel.read_to_end(&mut rest))
+        .await
+        .expect("the tunnel is closed");
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(streams().await, json!([]));
+
+    let events = tokio::spawn(body::to_bytes(throttled().await, usize::MAX));
+    let created = harness
+        .admin_call(
+            request_builder(Method::POST, "/proxies")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({"name": "orders", "upstream": "example.com:80"}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let toxic = harness
+        .admin_call(
+            request_builder(Method::POST, "/proxies/orders/toxics")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({"type": "latency", "attributes": {"latency": 10}}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(toxic.status, StatusCode::OK);
+    assert!(events.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn reset_connection_closes_with_tcp_reset() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let harness = TestHarness::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        None,
+        lowdown::connection::ListenerOptions::default(),
+        std::future::pending(),
+    ));
+    let exchange = |extra: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET / HTTP/1.1\r\nhost: lowdown\r\nconnection: close\r\n\
+             x-lowdown-destination-url: http://example.com\r\n{extra}\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.map(|_| response)
+    };
+
+    let response = exchange("").await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    let err = exchange("x-lowdown-reset-connection-percentage: 100\r\n")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn listener_options_and_client_socket_faults() {
+    use lowdown::connection::ListenerOptions;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let options = ListenerOptions {
+        reuse_port: true,
+        backlog: 16,
+        nodelay: false,
+    };
+    let listener = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let second = options.bind(addr).unwrap();
+    drop(second);
+    assert!(ListenerOptions::default().bind(addr).is_err());
+
+    let harness = TestHarness::new();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        None,
+        options,
+        std::future::pending(),
+    ));
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nhost: lowdown\r\nconnection: close\r\n\
+              x-lowdown-destination-url: http://example.com\r\n\
+              x-lowdown-degrade-socket-percentage: 100\r\n\
+              x-lowdown-degrade-socket-buffer-bytes: 4096\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nhost: lowdown\r\nconnection: close\r\n\
+              x-lowdown-destination-url: http://example.com\r\n\
+              x-lowdown-interim-response-percentage: 100\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let (interim, last) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(
+        interim,
+        "HTTP/1.1 103 Early Hints\r\nlink: </lowdown-early-hint.css>; rel=preload; as=style"
+    );
+    assert!(last.starts_with("HTTP/1.1 200"));
+
+    let report = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report?since=1h")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(report["faults"]["degrade-socket"], 1);
+    assert_eq!(report["faults"]["interim-response"], 1);
+}
+
+#[tokio::test]
+async fn interim_responses_are_injected_and_forwarded() {
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio_rustls::rustls::{self, pki_types::CertificateDer, pki_types::pem::PemObject};
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0; 1];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        return;
+                    }
+                    request.push(byte[0]);
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload\r\n\r\n\
+                          HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    let cert = include_bytes!("fixtures/localhost.crt");
+    let key = include_bytes!("fixtures/localhost.key");
+    let client: SharedHttpClient = Arc::new(ReqwestHttpClient::new().unwrap());
+    let state = Arc::new(AppState::new(SettingsLayer::default(), client));
+    let harness = TestHarness::from_state(state, Arc::new(StubClient::new()));
+    let mut addresses = Vec::new();
+    for tls in [
+        None,
+        Some(lowdown::tls::acceptor_from_pem(cert, key).unwrap()),
+    ] {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addresses.push(listener.local_addr().unwrap());
+        tokio::spawn(lowdown::connection::serve(
+            listener,
+            harness.proxy.clone(),
+            tls,
+            lowdown::connection::ListenerOptions::default(),
+            std::future::pending(),
+        ));
+    }
+    let (plain, secure) = (addresses[0], addresses[1]);
+
+    async fn exchange(mut stream: impl AsyncRead + AsyncWrite + Unpin, request: String) -> String {
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        String::from_utf8(response).unwrap()
+    }
+    let request = |version: &str, extra: &str| {
+        format!(
+            "GET / {version}\r\nhost: lowdown\r\nconnection: close\r\n\
+             x-lowdown-destination-url: http://{upstream_addr}\r\n{extra}\r\n"
+        )
+    };
+    let forwarded = "HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload\r\n\r\n";
+    let injected = "HTTP/1.1 103 Early Hints\r\nlink: </lowdown-early-hint.css>; rel=preload; as=style\r\n\r\n";
+
+    let response = exchange(
+        tokio::net::TcpStream::connect(plain).await.unwrap(),
+        request("HTTP/1.1", ""),
+    )
+    .await;
+    let last = response.strip_prefix(forwarded).unwrap();
+    assert!(last.starts_with("HTTP/1.1 200 OK"));
+    assert!(last.ends_with("\r\n\r\nok"));
+
+    let response = exchange(
+        tokio::net::TcpStream::connect(plain).await.unwrap(),
+        request("HTTP/1.1", "x-lowdown-interim-response-percentage: 100\r\n"),
+    )
+    .await;
+    let last = response
+        .strip_prefix(injected)
+        .and_then(|rest| rest.strip_prefix(forwarded))
+        .unwrap();
+    assert!(last.starts_with("HTTP/1.1 200 OK"));
+
+    // HTTP/1.0 clients must never see an interim response.
+    let response = exchange(
+        tokio::net::TcpStream::connect(plain).await.unwrap(),
+        request("HTTP/1.0", "x-lowdown-interim-response-percentage: 100\r\n"),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.0 200 OK"));
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_slice(cert).unwrap())
+        .unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(
+            "localhost".try_into().unwrap(),
+            tokio::net::TcpStream::connect(secure).await.unwrap(),
+        )
+        .await
+        .unwrap();
+    let response = exchange(
+        stream,
+        request("HTTP/1.1", "x-lowdown-interim-response-percentage: 100\r\n"),
+    )
+    .await;
+    let last = response
+        .strip_prefix(injected)
+        .and_then(|rest| rest.strip_prefix(forwarded))
+        .unwrap();
+    assert!(last.starts_with("HTTP/1.1 200 OK"));
+}
+
+#[tokio::test]
+async fn proxy_listener_terminates_tls() {
+    let cert = include_bytes!("fixtures/localhost.crt");
+    let key = include_bytes!("fixtures/localhost.key");
+    let harness = TestHarness::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        Some(lowdown::tls::acceptor_from_pem(cert, key).unwrap()),
+        lowdown::connection::ListenerOptions::default(),
+        std::future::pending(),
+    ));
+    harness.client.enqueue(json_ok());
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .add_root_certificate(reqwest::Certificate::from_pem(cert).unwrap())
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{port}/orders"))
+        .header("x-lowdown-destination-url", "http://example.com")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), http::Version::HTTP_2);
+    assert_eq!(response.text().await.unwrap(), "upstream");
+    assert_eq!(
+        harness.client.recordings()[0].url,
+        "http://example.com/orders"
+    );
+
+    let plain = reqwest::get(format!("http://localhost:{port}/")).await;
+    assert!(plain.is_err());
+}
+
+#[tokio::test]
+async fn supervised_servers_retry_binding_and_report_status() {
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(AppState::new(SettingsLayer::default(), shared));
+    let harness = TestHarness::from_state(state.clone(), client);
+    let blocker = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = blocker.local_addr().unwrap();
+    let (stop, stopped) = watch::channel(false);
+    let serve = |listener, shutdown| {
+        axum::serve(listener, Router::new().into_make_service())
+            .with_graceful_shutdown(shutdown)
+            .into_future()
+    };
+
+    let fail_fast = Supervisor::new(
+        state.supervision(),
+        RestartPolicy::FailFast,
+        Duration::from_millis(10),
+        stopped.clone(),
+    );
+    assert!(fail_fast.run("first", address, serve).await.is_err());
+
+    let supervisor = Supervisor::new(
+        state.supervision(),
+        RestartPolicy::Retry,
+        Duration::from_millis(10),
+        stopped,
+    );
+    let running = tokio::spawn(async move { supervisor.run("second", address, serve).await });
+    async fn server_state(harness: &TestHarness, name: &str) -> Value {
+        let status = harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        status.json()["servers"][name].clone()
+    }
+    async fn wait_for(harness: &TestHarness, name: &str, state: &str) -> Value {
+        for _ in 0..200 {
+            let status = server_state(harness, name).await;
+            if status["state"] == state {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{name} never reached {state}");
+    }
+
+    let first = server_state(&harness, "first").await;
+    assert_eq!(first["state"], "failed");
+    assert!(
+        first["last-error"]
+            .as_str()
+            .unwrap()
+            .contains("failed to bind")
+    );
+    let retrying = wait_for(&harness, "second", "retrying").await;
+    assert_eq!(retrying["address"], address.to_string());
+
+    drop(blocker);
+    let running_status = wait_for(&harness, "second", "running").await;
+    assert!(running_status["restarts"].as_u64().unwrap() >= 1);
+    assert!(tokio::net::TcpStream::connect(address).await.is_ok());
+
+    stop.send(true).unwrap();
+    running.await.unwrap().unwrap();
+    assert_eq!(server_state(&harness, "second").await["state"], "stopped");
+}
+
+#[tokio::test]
+async fn supervisor_serves_inherited_listeners() {
+    let supervision = Arc::new(Supervision::default());
+    let inherited = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    inherited.set_nonblocking(true).unwrap();
+    let address = inherited.local_addr().unwrap();
+    let (stop, stopped) = watch::channel(false);
+    let supervisor = Supervisor::new(
+        supervision.clone(),
+        RestartPolicy::FailFast,
+        Duration::from_millis(10),
+        stopped,
+    )
+    .inherited(BTreeMap::from([("proxy".to_string(), inherited)]));
+    let running = tokio::spawn(async move {
+        supervisor
+            .run(
+                "proxy",
+                "192.0.2.1:1".parse().unwrap(),
+                |listener, shutdown| {
+                    axum::serve(listener, Router::new().into_make_service())
+                        .with_graceful_shutdown(shutdown)
+                        .into_future()
+                },
+            )
+            .await
+    });
+
+    tokio::time::timeout(Duration::from_secs(5), supervision.running(1))
+        .await
+        .unwrap();
+    assert!(supervision.all_running());
+    assert_eq!(supervision.snapshot()["proxy"].address, address);
+    assert!(tokio::net::TcpStream::connect(address).await.is_ok());
+
+    stop.send(true).unwrap();
+    running.await.unwrap().unwrap();
+    assert!(!supervision.all_running());
+}
+
+#[tokio::test]
+async fn shutdown_drains_in_flight_requests_within_the_grace_period() {
+    let harness = TestHarness::new();
+    let serve_until_stopped = |grace: Duration, delay_ms: u64| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (stop, stopped) = watch::channel(false);
+        let supervisor = Supervisor::new(
+            Arc::new(Supervision::default()),
+            RestartPolicy::FailFast,
+            Duration::from_millis(10),
+            stopped,
+        )
+        .inherited(BTreeMap::from([("proxy".to_string(), listener)]))
+        .shutdown_grace(grace);
+        let router = harness.proxy.clone();
+        let running = tokio::spawn(async move {
+            supervisor
+                .run("proxy", address, move |listener, shutdown| {
+                    lowdown::connection::serve(
+                        listener,
+                        router.clone(),
+                        None,
+                        lowdown::connection::ListenerOptions::default(),
+                        shutdown,
+                    )
+                })
+                .await
+        });
+        let request = tokio::spawn(
+            reqwest::Client::new()
+                .get(format!("http://{address}/"))
+                .header("x-lowdown-destination-url", "http://example.com")
+                .header("x-lowdown-delay-before-percentage", "100")
+                .header("x-lowdown-delay-before-ms", delay_ms.to_string())
+                .send(),
+        );
+        (stop, running, request)
+    };
+
+    let (stop, running, request) = serve_until_stopped(Duration::from_secs(5), 300);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.send(true).unwrap();
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    running.await.unwrap().unwrap();
+
+    let (stop, running, request) = serve_until_stopped(Duration::from_millis(100), 10_000);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(2), running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let cut_off = tokio::time::timeout(Duration::from_secs(2), request)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(cut_off.is_err());
+}
+
+#[tokio::test]
+async fn instances_run_with_independent_state() {
+    let stub = Arc::new(StubClient::new());
+    let instances = Instances::builder(2)
+        .spawn(|_| {
+            let shared: SharedHttpClient = stub.clone();
+            Ok(Arc::new(AppState::new(SettingsLayer::default(), shared)))
+        })
+        .await
+        .unwrap();
+    let [first, second] = instances.list() else {
+        panic!("expected two instances");
+    };
+    assert_ne!(first.proxy, second.proxy);
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(format!("http://{}/api/v1/update", first.admin))
+        .header("x-lowdown-fail-before-percentage", "100")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    for (instance, status) in [(first, 503), (second, 200)] {
+        let response = client
+            .get(format!("http://{}/", instance.proxy))
+            .header("x-lowdown-destination-url", "http://example.com")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), status);
+    }
+
+    let index = instances
+        .index_router()
+        .oneshot(
+            request_builder(Method::GET, "/api/v1/instances")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let index = ResponseParts::from(index).await.json();
+    assert_eq!(index["instances"][1]["index"], 1);
+    assert_eq!(
+        index["instances"][1]["admin"],
+        second.admin.to_string().as_str()
+    );
+    instances.shutdown().await;
+
+    let too_small = Instances::builder(2)
+        .ports(40000..=40002)
+        .spawn(|_| unreachable!())
+        .await;
+    assert!(too_small.is_err());
+}
+
+#[tokio::test]
+async fn connect_tunnels_and_absolute_form_is_forwarded() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = echo.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let harness = TestHarness::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        None,
+        lowdown::connection::ListenerOptions::default(),
+        std::future::pending(),
+    ));
+    let connect = |extra: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!("CONNECT {target} HTTP/1.1\r\nhost: {target}\r\n{extra}\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte).await.unwrap() == 0 {
+                break;
+            }
+            head.push(byte[0]);
+        }
+        (stream, String::from_utf8(head).unwrap())
+    };
+
+    let (mut tunnel, head) = connect("").await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    tunnel.write_all(b"ping").await.unwrap();
+    let mut echoed = [0u8; 4];
+    tunnel.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"ping");
+
+    let (_, head) = connect("x-lowdown-fail-before-percentage: 100\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 503"), "{head}");
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET http://example.com/orders?id=1 HTTP/1.1\r\nhost: example.com\r\n\
+              connection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    assert_eq!(
+        harness.client.recordings()[0].url,
+        "http://example.com/orders?id=1"
+    );
+}
+
+#[tokio::test]
+async fn fault_sequences_are_reproducible() {
+    async fn statuses(harness: &TestHarness, name: &'static str, value: &'static str) -> Vec<u16> {
+        let (header_name, header_value) = destination_header();
+        let mut statuses = Vec::new();
+        for _ in 0..12 {
+            let request = request_builder(Method::GET, "/")
+                .header(header_name.clone(), header_value.clone())
+                .header(name, value)
+                .body(Body::empty())
+                .unwrap();
+            statuses.push(harness.proxy_call(request).await.status.as_u16());
+        }
+        statuses
+    }
+
+    let harness = TestHarness::new();
+    let every_third = statuses(&harness, "x-lowdown-fail-before-every-nth", "3").await;
+    assert_eq!(every_third, [200, 200, 503].repeat(4));
+
+    let rolls = |seed| async move {
+        let harness = TestHarness::with_builder(|builder| builder.random_seed(seed));
+        statuses(&harness, "x-lowdown-fail-before-percentage", "50").await
+    };
+    let first = rolls(7).await;
+    assert_eq!(first, rolls(7).await);
+    assert!(first.contains(&200) && first.contains(&503));
+}
+
+#[tokio::test]
+async fn fail_before_mode_shapes_the_failure() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let harness = TestHarness::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(lowdown::connection::serve(
+        listener,
+        harness.proxy.clone(),
+        None,
+        lowdown::connection::ListenerOptions::default(),
+        std::future::pending(),
+    ));
+    let exchange = |mode: &'static str| async move {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET / HTTP/1.1\r\nhost: lowdown\r\nconnection: close\r\n\
+             x-lowdown-destination-url: http://example.com\r\n\
+             x-lowdown-fail-before-percentage: 100\r\n\
+             x-lowdown-fail-before-code: 502\r\n\
+             x-lowdown-fail-before-mode: {mode}\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        String::from_utf8(response).unwrap()
+    };
+
+    let json = exchange("json-error").await;
+    assert!(json.starts_with("HTTP/1.1 502"));
+    assert!(json.ends_with("{\"error\":\"fail-before\"}"));
+
+    let empty = exchange("empty-body").await;
+    assert!(empty.starts_with("HTTP/1.1 502"));
+    assert!(empty.contains("content-length: 0\r\n"));
+    assert!(empty.ends_with("\r\n\r\n"));
+
+    let headers_only = exchange("headers-only").await;
+    assert!(headers_only.starts_with("HTTP/1.1 502"));
+    assert!(headers_only.contains("transfer-encoding: chunked\r\n"));
+    assert!(headers_only.ends_with("\r\n\r\n"));
+
+    assert_eq!(exchange("connection-close").await, "");
+
+    let html = exchange("html-error-page").await;
+    assert!(html.contains("content-type: text/html"));
+    assert!(html.contains("<title>502 Bad Gateway</title>"));
+
+    let malformed = exchange("malformed-json").await;
+    assert!(malformed.contains("content-type: application/json"));
+    assert!(malformed.ends_with("{\"error\":\"fail-before\""));
+}
+
+#[tokio::test]
+async fn mutate_request_changes_selected_json_fields() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let call = |path: &str, mode: &str| {
+        request_builder(Method::POST, "/users")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-mutate-request-percentage", "100")
+            .header("x-lowdown-mutate-request-path", path)
+            .header("x-lowdown-mutate-request-mode", mode)
+            .body(Body::from(
+                r#"{"user":{"email":"me@example.com","age":"42"},"tags":["a","b"]}"#,
+            ))
+            .unwrap()
+    };
+    let cases = [
+        ("$.user.email", "null", json!({"email":null,"age":"42"})),
+        ("$.user.email", "remove", json!({"age":"42"})),
+        (
+            "$.user['age']",
+            "type-flip",
+            json!({"email":"me@example.com","age":42}),
+        ),
+    ];
+    for (path, mode, user) in cases {
+        harness.client.enqueue(json_ok());
+        let response = harness.proxy_call(call(path, mode)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        let recorded = harness.client.recordings().pop().unwrap();
+        let body: Value = serde_json::from_slice(&recorded.body).unwrap();
+        assert_eq!(body["user"], user, "{mode}");
+        assert_eq!(body["tags"], json!(["a", "b"]));
+    }
+
+    harness.client.enqueue(json_ok());
+    harness.proxy_call(call("$.tags[*]", "randomize")).await;
+    let recorded = harness.client.recordings().pop().unwrap();
+    let body: Value = serde_json::from_slice(&recorded.body).unwrap();
+    let tags = body["tags"].as_array().unwrap();
+    assert_eq!(tags.len(), 2);
+    assert!(
+        tags.iter()
+            .all(|tag| tag.as_str().is_some_and(|tag| tag.len() == 1))
+    );
+
+    harness.client.enqueue(json_ok());
+    harness.proxy_call(call("$.missing", "null")).await;
+    let recorded = harness.client.recordings().pop().unwrap();
+    assert_eq!(
+        recorded.body,
+        Bytes::from_static(br#"{"user":{"email":"me@example.com","age":"42"},"tags":["a","b"]}"#)
+    );
+}
+
+#[tokio::test]
+async fn mutate_response_changes_selected_json_fields() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let call = |path: &str, mode: &str| {
+        request_builder(Method::GET, "/cart")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-mutate-response-percentage", "100")
+            .header("x-lowdown-mutate-response-path", path)
+            .header("x-lowdown-mutate-response-mode", mode)
+            .header("x-lowdown-mutate-response-delta", "-5")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let cart = || {
+        ProxiedResponse::new(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from_static(br#"{"items":[{"price":100},{"price":2.5}],"total":"102.5"}"#),
+        )
+    };
+
+    harness.client.enqueue(cart());
+    let response = harness.proxy_call(call("$.items[*].price", "delta")).await;
+    assert_eq!(
+        response.json(),
+        json!({"items":[{"price":95},{"price":-2.5}],"total":"102.5"})
+    );
+    assert_eq!(
+        response.headers["content-length"],
+        response.body.len().to_string()
+    );
+
+    harness.client.enqueue(cart());
+    let response = harness.proxy_call(call("$.items[0]", "remove")).await;
+    assert_eq!(response.json()["items"], json!([{"price":2.5}]));
+
+    harness.client.enqueue(cart());
+    let response = harness.proxy_call(call("$.total", "delta")).await;
+    assert_eq!(response.json()["total"], "102.5");
+}
+
+#[tokio::test]
+async fn corrupt_response_truncates_flips_or_empties_the_body() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let call = |mode: &str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-corrupt-response-percentage", "100")
+            .header("x-lowdown-corrupt-response-mode", mode)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("truncate")).await;
+    assert!(response.body.len() < b"upstream".len());
+    assert!(b"upstream".starts_with(&response.body));
+    assert_eq!(
+        response.headers["content-length"],
+        response.body.len().to_string()
+    );
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("bit-flip")).await;
+    assert_eq!(response.body.len(), b"upstream".len());
+    assert_ne!(response.body, Bytes::from_static(b"upstream"));
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("empty-body")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.is_empty());
+}
+
+#[tokio::test]
+async fn stream_faults_inject_garbage_and_abort() {
+    let harness = TestHarness::new();
+    harness.client.enqueue(json_ok());
+    let (header_name, header_value) = destination_header();
+    let request = request_builder(Method::GET, "/")
+        .header(header_name.clone(), header_value.clone())
+        .header("x-lowdown-stream-fault-percentage", "100")
+        .header("x-lowdown-stream-garbage-after-bytes", "2")
+        .header("x-lowdown-stream-garbage-bytes", "5")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.len(), b"upstream".len() + 5);
+    assert_eq!(&response.body[..2], b"up");
+    assert_eq!(&response.body[7..], b"stream");
+
+    harness.client.enqueue(json_ok());
+    let request = request_builder(Method::GET, "/")
+        .header(header_name.clone(), header_value.clone())
+        .header("x-lowdown-stream-fault-percentage", "100")
+        .header("x-lowdown-stream-garbage-bytes", "18446744073709551615")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.body, Bytes::from_static(b"upstream"));
+
+    harness.client.enqueue(json_ok());
+    let request = request_builder(Method::GET, "/")
+        .header(header_name, header_value)
+        .header("x-lowdown-stream-fault-percentage", "100")
+        .header("x-lowdown-stream-abort-at-percent", "50")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn stream_loss_stalls_each_lost_packet() {
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let (header_name, header_value) = destination_header();
+    let body = Bytes::from(vec![b'x'; 4096]);
+    let request = |packet_bytes: &str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-stream-loss-percentage", "100")
+            .header("x-lowdown-stream-loss-burst-percentage", "100")
+            .header("x-lowdown-stream-loss-stall-ms", "20")
+            .header(
+                "x-lowdown-stream-loss-packet-bytes",
+                packet_bytes.to_string(),
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        HeaderMap::new(),
+        body.clone(),
+    ));
+    let response = harness.proxy_call(request("64")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, body);
+    assert_eq!(clock.elapsed(), Duration::from_millis(64 * 20));
+
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        HeaderMap::new(),
+        body.clone(),
+    ));
+    let response = harness.proxy_call(request("1")).await;
+    assert_eq!(response.body, body);
+    assert_eq!(clock.elapsed(), Duration::from_millis(64 * 20 + 3 * 20));
+}
+
+#[tokio::test]
+async fn stream_faults_apply_to_endless_upstream_streams() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = listener.local_addr().unwrap();
+    let events = Router::new().route(
+        "/events",
+        axum::routing::get(|| async {
+            let first = futures_util::stream::iter([Ok::<_, std::convert::Infallible>(
+                Bytes::from_static(b"data: 1\n\n"),
+            )]);
+            let body = Body::from_stream(first.chain(futures_util::stream::pending()));
+            ([("content-type", "text/event-stream")], body)
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, events).await });
+    let client: SharedHttpClient = Arc::new(ReqwestHttpClient::new().unwrap());
+    let state = Arc::new(AppState::new(SettingsLayer::default(), client));
+    let harness = TestHarness::from_state(state, Arc::new(StubClient::new()));
+    let subscribe = |faults: &[(&str, &str)]| {
+        let mut builder = request_builder(Method::GET, "/events")
+            .header("x-lowdown-destination-url", format!("http://{upstream}"))
+            .header("x-lowdown-stream-fault-percentage", "100");
+        for (name, value) in faults {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder.body(Body::empty()).unwrap();
+        let proxy = harness.proxy.clone();
+        async move {
+            let response = tokio::time::timeout(Duration::from_secs(5), proxy.oneshot(request))
+                .await
+                .expect("headers arrive before the stream ends")
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response.into_body()
+        }
+    };
+    async fn next(
+        body: &mut Body,
+    ) -> Result<Option<Result<Frame<Bytes>, axum::Error>>, tokio::time::error::Elapsed> {
+        tokio::time::timeout(Duration::from_secs(5), body.frame()).await
+    }
+
+    let mut body = subscribe(&[
+        ("x-lowdown-stream-stall-after-bytes", "9"),
+        ("x-lowdown-stream-stall-ms", "60000"),
+    ])
+    .await;
+    let mut received = Vec::new();
+    while received.len() < 9 {
+        let frame = next(&mut body).await.unwrap().unwrap().unwrap();
+        received.extend_from_slice(&frame.into_data().unwrap());
+    }
+    assert_eq!(received, b"data: 1\n\n");
+    let stalled = tokio::time::timeout(Duration::from_millis(200), body.frame()).await;
+    assert!(stalled.is_err());
+
+    let mut body = subscribe(&[("x-lowdown-stream-abort-at-percent", "50")]).await;
+    let frame = next(&mut body).await.unwrap().unwrap().unwrap();
+    assert_eq!(
+        frame.into_data().unwrap(),
+        Bytes::from_static(b"data: 1\n\n")
+    );
+    assert!(next(&mut body).await.unwrap().unwrap().is_err());
+
+    let mut body = subscribe(&[("x-lowdown-stream-garbage-bytes", "4")]).await;
+    let garbage = next(&mut body).await.unwrap().unwrap().unwrap();
+    assert_eq!(garbage.into_data().unwrap().len(), 4);
+    let frame = next(&mut body).await.unwrap().unwrap().unwrap();
+    assert_eq!(
+        frame.into_data().unwrap(),
+        Bytes::from_static(b"data: 1\n\n")
+    );
+}
+
+#[tokio::test]
+async fn request_body_streams_through_when_buffering_disabled() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    for buffering in ["true", "false"] {
+        harness.client.enqueue(json_ok());
+        let request = request_builder(Method::POST, "/upload")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-request-buffering", buffering)
+            .header("expect", "100-continue")
+            .body(Body::from("payload"))
+            .unwrap();
+        let response = harness.proxy_call(request).await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+    let recorded = harness.client.recordings();
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(recorded[0].body, Bytes::from_static(b"payload"));
+    assert!(recorded[0].headers.get("expect").is_none());
+    assert_eq!(recorded[1].body, Bytes::from_static(b"payload"));
+    assert_eq!(recorded[1].headers.get("expect").unwrap(), "100-continue");
+}
+
+#[tokio::test]
+async fn metrics_track_upstream_connections() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    for force in ["0", "100"] {
+        harness.client.enqueue(json_ok());
+        let request = request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-force-new-connection-percentage", force)
+            .body(Body::empty())
+            .unwrap();
+        harness.proxy_call(request).await;
+    }
+    let response = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let json = response.json();
+    let destination = &json["destinations"]["example.com"];
+    assert_eq!(destination["requests"], 2);
+    assert_eq!(destination["forced-new-connections"], 1);
+    assert_eq!(destination["unknown-connections"], 2);
+}
+
+#[tokio::test]
+async fn dns_delay_slows_down_name_resolution() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness.client.enqueue(json_ok());
+    let request = request_builder(Method::GET, "/")
+        .header(header_name, header_value)
+        .header("x-lowdown-dns-delay-percentage", "100")
+        .header("x-lowdown-dns-delay-ms", "300")
+        .body(Body::empty())
+        .unwrap();
+    harness.proxy_call(request).await;
+    let delay = harness.client.recordings()[0].dns_delay;
+    assert_eq!(delay, Some(Duration::from_millis(300)));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let upstream = Router::new().route("/", axum::routing::get(|| async { "upstream" }));
+    tokio::spawn(async move { axum::serve(listener, upstream).await });
+    let client = ReqwestHttpClient::new().unwrap();
+    for (host, delayed) in [("localhost", true), ("127.0.0.1", false)] {
+        let started = Instant::now();
+        let response = client
+            .execute(OutgoingRequest {
+                method: Method::GET,
+                url: format!("http://{host}:{port}/"),
+                headers: HeaderMap::new(),
+                body: RequestBody::Buffered(Bytes::new()),
+                trailers: None,
+                force_new_connection: true,
+                binding: None,
+                protocol: UpstreamProtocol::Auto,
+                tls: TlsOverride::default(),
+                preserve_encoding: false,
+                dns_delay: delay,
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(started.elapsed() >= Duration::from_millis(300), delayed);
+    }
+}
+
+#[tokio::test]
+async fn upstream_timeouts_return_gateway_timeout() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let upstream = Router::new().route(
+        "/",
+        axum::routing::get(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "late"
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, upstream).await });
+    let client: SharedHttpClient = Arc::new(
+        ReqwestHttpClient::with_config(
+            OutboundBinding::default(),
+            ClientConfig {
+                request_timeout: Some(Duration::from_millis(100)),
+                pool_max_idle_per_host: Some(1),
+                ..ClientConfig::default()
+            },
+        )
+        .unwrap(),
+    );
+    let state = Arc::new(AppState::new(SettingsLayer::default(), client));
+    let harness = TestHarness::from_state(state, Arc::new(StubClient::new()));
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(
+                    "x-lowdown-destination-url",
+                    format!("http://127.0.0.1:{port}"),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(response.json()["error"], "upstream-timeout");
+}
+
+#[tokio::test]
+async fn admin_lists_support_pagination_and_filtering() {
+    let harness = TestHarness::new();
+    let response = harness
+        .admin_call(
+            request_builder(
+                Method::POST,
+                "/api/v1/list-headers?filter=X-Test&offset=1&limit=2",
+            )
+            .header("x-test-c", "3")
+            .header("x-test-a", "1")
+            .header("x-test-b", "2")
+            .header("x-other", "4")
+            .body(Body::empty())
+            .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers.get("x-total-count").unwrap(), "3");
+    assert_eq!(response.json(), serde_json::json!(["x-test-b", "x-test-c"]));
+
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/list-headers?limit=lots")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["error"], "invalid-query");
+
+    let call = |method: Method, uri: &str, body: Value| {
+        request_builder(method, uri)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    for name in ["writes", "reads", "orders"] {
+        harness
+            .admin_call(call(
+                Method::POST,
+                "/api/v1/rules",
+                json!({"name": name, "priority": name.len(), "settings": {"fail-before-percentage": 100}}),
+            ))
+            .await;
+    }
+    let rules = harness
+        .admin_call(call(
+            Method::GET,
+            "/api/v1/rules?filter=s&limit=2",
+            json!(null),
+        ))
+        .await;
+    assert_eq!(rules.headers["x-total-count"], "3");
+    let names: Vec<_> = rules.json()["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rule| rule["name"].clone())
+        .collect();
+    assert_eq!(names, [json!("writes"), json!("orders")]);
+
+    for key in ["gate:a", "gate:b", "other"] {
+        harness
+            .admin_call(call(
+                Method::PUT,
+                &format!("/api/v1/kv/{key}"),
+                json!({"value": 1}),
+            ))
+            .await;
+    }
+    let kv = harness
+        .admin_call(call(
+            Method::GET,
+            "/api/v1/kv?prefix=gate:&offset=1",
+            json!(null),
+        ))
+        .await;
+    assert_eq!(kv.headers["x-total-count"], "2");
+    assert_eq!(
+        kv.json().as_object().unwrap().keys().collect::<Vec<_>>(),
+        ["gate:b"]
+    );
+    let invalid = harness
+        .admin_call(call(Method::GET, "/api/v1/kv?offset=-1", json!(null)))
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.json()["error"], "invalid-query");
+}
+
+#[tokio::test]
+async fn mikkmokk_compat_accepts_legacy_headers_and_paths() {
+    let harness = TestHarness::with_builder(|builder| builder.mikkmokk_compat(true));
+    let request = request_builder(Method::GET, "/")
+        .header("x-mikkmokk-destination-url", "http://example.com")
+        .header("x-mikkmokk-fail-before-percentage", "100")
+        .header("x-lowdown-fail-before-code", "418")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.status, StatusCode::IM_A_TEAPOT);
+
+    harness.client.enqueue(json_ok());
+    let request = request_builder(Method::GET, "/mikkmokk-forward-http/example.org/api")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(harness.client.recordings()[0].url, "http://example.org/api");
+
+    let harness = TestHarness::new();
+    let request = request_builder(Method::GET, "/")
+        .header("x-mikkmokk-destination-url", "http://example.com")
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy_call(request).await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.json()["error"], "missing-destination-url");
+}
+
+#[tokio::test]
+async fn envoy_fault_config_import() {
+    let harness = TestHarness::new();
+    let config = r#"
+name: envoy.filters.http.fault
+typed_config:
+  "@type": type.googleapis.com/envoy.extensions.filters.http.fault.v3.HTTPFault
+  abort:
+    http_status: 429
+    percentage:
+      numerator: 100
+      denominator: HUNDRED
+  delay:
+    fixed_delay: 0.25s
+    percentage:
+      numerator: 5000
+      denominator: TEN_THOUSAND
+  headers:
+    - name: X-User
+      string_match:
+        exact: abc
+  max_active_faults: 10
+"#;
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/import/envoy")
+                .body(Body::from(config))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let json = response.json();
+    assert_eq!(json["settings"]["fail-before-code"], 429);
+    assert_eq!(json["settings"]["fail-before-percentage"], 100);
+    assert_eq!(json["settings"]["delay-before-ms"], 250);
+    assert_eq!(json["settings"]["delay-before-percentage"], 50);
+    assert_eq!(json["settings"]["match-header-name"], "x-user");
+    assert_eq!(json["settings"]["match-header-value"], "abc");
+    assert_eq!(json["warnings"].as_array().unwrap().len(), 1);
+
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/import/envoy")
+                .body(Body::from("abort: [1, 2"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/import/envoy")
+                .body(Body::from(
+                    r#"
+abort:
+  http_status: 503
+  percentage: {numerator: 18446744073709551615}
+delay:
+  fixed_delay: {seconds: 18446744073709551615, nanos: 999999999}
+  percentage: {numerator: 100}
+"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let json = response.json();
+    assert_eq!(json["settings"]["fail-before-percentage"], 100);
+    assert_eq!(json["settings"]["delay-before-ms"], u64::MAX);
+}
+
+#[tokio::test]
+async fn toxiproxy_facade_controls_matching_destination() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let proxied = || {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let json_request = |method: Method, uri: &str, body: &str| {
+        request_builder(method, uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let response = harness
+        .admin_call(json_request(
+            Method::POST,
+            "/proxies",
+            r#"{"name":"web","listen":"127.0.0.1:26379","upstream":"example.com:80"}"#,
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let response = harness
+        .admin_call(json_request(
+            Method::POST,
+            "/proxies/web/toxics",
+            r#"{"type":"timeout","attributes":{"timeout":1}}"#,
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["name"], "timeout_downstream");
+    assert_eq!(
+        harness.proxy_call(proxied()).await.status,
+        StatusCode::GATEWAY_TIMEOUT
+    );
+
+    let response = harness
+        .admin_call(json_request(
+            Method::POST,
+            "/proxies/web/toxics",
+            r#"{"type":"slicer"}"#,
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    harness
+        .admin_call(json_request(Method::POST, "/reset", ""))
+        .await;
+    harness.client.enqueue(json_ok());
+    assert_eq!(harness.proxy_call(proxied()).await.status, StatusCode::OK);
+
+    harness
+        .admin_call(json_request(
+            Method::POST,
+            "/proxies/web",
+            r#"{"enabled":false}"#,
+        ))
+        .await;
+    assert_eq!(
+        harness.proxy_call(proxied()).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn wiremock_mappings_serve_stubs_with_faults_on_top() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let mappings = r#"{"mappings":[{
+        "request":{"method":"GET","urlPath":"/users","queryParameters":{"id":{"equalTo":"7"}}},
+        "response":{"status":201,"jsonBody":{"name":"ada"},"headers":{"x-stub":"yes"}}
+    }]}"#;
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/stubs/import/wiremock")
+                .body(Body::from(mappings))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["imported"].as_array().unwrap().len(), 1);
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/users?id=7")
+                .header(header_name.clone(), header_value.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.headers["x-stub"], "yes");
+    assert_eq!(response.json()["name"], "ada");
+    assert!(harness.client.recordings().is_empty());
+
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/users?id=8")
+                .header(header_name.clone(), header_value.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(harness.client.recordings().len(), 1);
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/users?id=7")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-after-percentage", "100")
+                .header("x-lowdown-fail-after-code", "502")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/stubs/import/wiremock")
+                .body(Body::from(r#"{"request":{"urlPattern":"("}}"#))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["error"], "invalid-wiremock-mappings");
+}
+
+#[tokio::test]
+async fn export_renders_configuration_snippets() {
+    let harness = TestHarness::new();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-percentage", "25")
+                .header("x-lowdown-match-header-value", "a b")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let export = |format: &str| {
+        request_builder(Method::GET, &format!("/api/v1/export?format={format}"))
+            .header("host", "admin:7070")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = harness.admin_call(export("env")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        "FAIL_BEFORE_PERCENTAGE=25\nMATCH_HEADER_VALUE='a b'\n"
+    );
+
+    let response = harness.admin_call(export("headers")).await;
+    assert_eq!(
+        response.body,
+        "x-lowdown-fail-before-percentage: 25\nx-lowdown-match-header-value: a b\n"
+    );
+
+    let response = harness.admin_call(export("curl")).await;
+    assert_eq!(
+        response.body,
+        "curl -XPOST \\\n  -H 'x-lowdown-fail-before-percentage: 25' \\\n  \
+         -H 'x-lowdown-match-header-value: a b' \\\n  http://admin:7070/api/v1/reset\n"
+    );
+
+    let response = harness.admin_call(export("yaml")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn header_policy_limits_per_request_overrides() {
+    let harness = TestHarness::with_builder(|builder| {
+        builder.header_policy(HeaderPolicy::Allowlist(vec!["delay-before-ms".to_string()]))
+    });
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "0")
+                .header("x-lowdown-delay-before-ms", "0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(harness.client.recordings().is_empty());
+
+    let policy = HeaderPolicy::parse("deny", None).unwrap();
+    assert!(!policy.allows("fail-before-percentage"));
+    assert!(policy.allows("destination-url"));
+    assert!(HeaderPolicy::parse("sometimes", None).is_err());
+}
+
+#[tokio::test]
+async fn admin_wins_precedence_ignores_conflicting_headers() {
+    let harness = TestHarness::with_builder(|builder| builder.precedence(Precedence::AdminWins));
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-code", "500")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-fail-before-code", "418")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(Precedence::parse("clients-win").is_err());
+}
+
+#[tokio::test]
+async fn shadow_requests_record_what_fail_before_hid() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::CREATED,
+        HeaderMap::new(),
+        Bytes::new(),
+    ));
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/orders")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-shadow-fail-before", "true")
+                .body(Body::from("order"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let mut shadow = Value::Null;
+    for _ in 0..50 {
+        let metrics = harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json();
+        shadow = metrics["destinations"]["example.com"].clone();
+        if shadow["shadow-requests"] == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(shadow["shadow-requests"], 1);
+    assert_eq!(shadow["shadow-statuses"]["201"], 1);
+    assert_eq!(shadow["requests"], 0);
+    let recordings = harness.client.recordings();
+    assert_eq!(recordings.len(), 1);
+    assert_eq!(recordings[0].body, Bytes::from_static(b"order"));
+}
+
+#[tokio::test]
+async fn unhealthy_upstream_pauses_faults() {
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| {
+        builder
+            .health_config(HealthConfig {
+                window: Duration::from_secs(60),
+                failure_percentage: 50,
+                min_requests: 2,
+            })
+            .clock(clock.clone())
+    });
+    let (header_name, header_value) = destination_header();
+    let proxied = |fail_before: &str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-fail-before-percentage", fail_before)
+            .header("x-lowdown-pause-faults-when-unhealthy", "true")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let status = || async {
+        harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json()["upstreams"]["example.com"]
+            .clone()
+    };
+
+    for _ in 0..2 {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            HeaderMap::new(),
+            Bytes::new(),
+        ));
+        harness.proxy_call(proxied("0")).await;
+    }
+    let health = status().await;
+    assert_eq!(health["failures"], 2);
+    assert_eq!(health["healthy"], false);
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(proxied("100")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(harness.client.recordings().len(), 3);
+
+    // The failures age out of the window on the injected clock.
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(status().await["healthy"], false);
+    clock.advance(Duration::from_secs(31));
+    let health = status().await;
+    assert_eq!(health["requests"], 0);
+    assert_eq!(health["healthy"], true);
+    let response = harness.proxy_call(proxied("100")).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn upstream_health_forgets_idle_destinations() {
+    use lowdown::health::{CAPACITY, UpstreamHealth};
+
+    let clock = Arc::new(VirtualClock::new());
+    let health = UpstreamHealth::new(HealthConfig {
+        window: Duration::from_secs(60),
+        failure_percentage: 50,
+        min_requests: 1,
+    })
+    .with_clock(clock.clone());
+    health.record("idle.example", true);
+    clock.advance(Duration::from_secs(30));
+    health.record("busy.example", true);
+    clock.advance(Duration::from_secs(31));
+    health.record("new.example", false);
+    let snapshot = health.snapshot();
+    assert_eq!(
+        snapshot.keys().collect::<Vec<_>>(),
+        ["busy.example", "new.example"]
+    );
+    assert!(health.is_unhealthy("busy.example"));
+
+    for n in 1..CAPACITY {
+        clock.advance(Duration::from_millis(1));
+        health.record(&format!("{n}.example"), false);
+    }
+    let snapshot = health.snapshot();
+    assert_eq!(snapshot.len(), CAPACITY);
+    assert!(!snapshot.contains_key("busy.example"));
+    assert!(snapshot.contains_key("new.example"));
+    assert!(snapshot.contains_key("1.example"));
+}
+
+#[tokio::test]
+async fn time_of_day_and_day_matchers() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let now = jiff::Timestamp::now().to_zoned(jiff::tz::TimeZone::UTC);
+    let at = |minutes: i64| {
+        let time = now.checked_add(jiff::Span::new().minutes(minutes)).unwrap();
+        format!("{:02}:{:02}", time.hour(), time.minute())
+    };
+    let today = format!("{:?}", now.weekday());
+    let request = |range: String, days: &str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-fail-before-percentage", "100")
+            .header("x-lowdown-match-timezone", "UTC")
+            .header("x-lowdown-match-time-range", range)
+            .header("x-lowdown-match-days", days.to_string())
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = harness
+        .proxy_call(request(format!("{}-{}", at(-60), at(60)), &today))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(request(format!("{}-{}", at(120), at(180)), "*"))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let tomorrow = format!("{:?}", now.tomorrow().unwrap().weekday());
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(request("*".to_string(), &tomorrow))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let clock = Arc::new(VirtualClock::new());
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let response = harness
+        .proxy_call(request("*".to_string(), &tomorrow))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn fault_set_picks_one_weighted_fault() {
+    let fault_set =
+        FaultSet::parse("30:delay-before=2000; 10:fail-before=503; 5:stream-garbage=8").unwrap();
+    assert_eq!(
+        fault_set.choose(0),
+        Some(&Fault::DelayBefore(Millis::new(2000)))
+    );
+    assert_eq!(
+        fault_set.choose(35),
+        Some(&Fault::FailBefore(StatusCode::SERVICE_UNAVAILABLE.into()))
+    );
+    assert_eq!(fault_set.choose(44), Some(&Fault::StreamGarbage(8)));
+    assert_eq!(fault_set.choose(45), None);
+    assert!(FaultSet::parse("60:duplicate;50:fail-after=502").is_err());
+    assert_eq!(
+        FaultSet::parse("4294967295:fail-before=503;1:duplicate"),
+        Err("weights add up to more than 100".to_string())
+    );
+    assert!(FaultSet::parse("10:explode").is_err());
+    assert!(FaultSet::parse("10:stream-garbage=18446744073709551615").is_err());
+
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-fault-set", "100:fail-before=418")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::IM_A_TEAPOT);
+}
+
+#[tokio::test]
+async fn preflights_and_grpc_web_survive_faults() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let preflight = |mode: &str| {
+        request_builder(Method::OPTIONS, "/rpc")
+            .header(header_name.clone(), header_value.clone())
+            .header("origin", "http://app.local")
+            .header("access-control-request-method", "POST")
+            .header("x-lowdown-fail-before-percentage", "100")
+            .header("x-lowdown-preflight-mode", mode)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(preflight("pass-through")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(harness.client.recordings().len(), 1);
+
+    let response = harness.proxy_call(preflight("synthetic")).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers["access-control-allow-origin"],
+        "http://app.local"
+    );
+    assert_eq!(response.headers["access-control-allow-methods"], "POST");
+    assert_eq!(harness.client.recordings().len(), 1);
+
+    let response = harness.proxy_call(preflight("fault")).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/rpc")
+                .header(header_name.clone(), header_value.clone())
+                .header("origin", "http://app.local")
+                .header("content-type", "application/grpc-web-text")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["grpc-status"], "14");
+    assert_eq!(
+        response.headers["content-type"],
+        "application/grpc-web-text"
+    );
+    assert_eq!(
+        response.headers["access-control-allow-origin"],
+        "http://app.local"
+    );
+}
+
+#[tokio::test]
+async fn trailers_pass_through_and_can_be_dropped_or_corrupted() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let upstream = || {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let mut response = json_ok();
+        response.trailers = Some(trailers);
+        response
+    };
+    let call = |fault: Option<&str>| {
+        let mut request_trailers = HeaderMap::new();
+        request_trailers.insert("x-checksum", HeaderValue::from_static("abc"));
+        let frames = futures_util::stream::iter([
+            Ok::<_, std::convert::Infallible>(Frame::data(Bytes::from_static(b"hi"))),
+            Ok(Frame::trailers(request_trailers)),
+        ]);
+        let mut builder =
+            request_builder(Method::POST, "/").header(header_name.clone(), header_value.clone());
+        if let Some(fault) = fault {
+            builder = builder.header(fault, "100");
+        }
+        let request = builder.body(Body::new(StreamBody::new(frames))).unwrap();
+        async {
+            let response = harness.proxy.clone().oneshot(request).await.unwrap();
+            response.into_body().collect().await.unwrap()
+        }
+    };
+
+    harness.client.enqueue(upstream());
+    let collected = call(None).await;
+    assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+    let recorded = harness.client.recordings();
+    assert_eq!(recorded[0].body, Bytes::from_static(b"hi"));
+    assert_eq!(recorded[0].trailers.as_ref().unwrap()["x-checksum"], "abc");
+
+    harness.client.enqueue(upstream());
+    let collected = call(Some("x-lowdown-drop-trailers-percentage")).await;
+    assert!(collected.trailers().is_none());
+    assert_eq!(collected.to_bytes(), Bytes::from_static(b"upstream"));
+
+    harness.client.enqueue(upstream());
+    let collected = call(Some("x-lowdown-corrupt-trailers-percentage")).await;
+    assert_ne!(collected.trailers().unwrap()["grpc-status"], "0");
+    let streamed = |end: Option<HeaderMap>| {
+        let data = futures_util::stream::iter([Ok::<_, std::convert::Infallible>(Frame::data(
+            Bytes::from_static(b"chunk"),
+        ))]);
+        let body = match end {
+            Some(trailers) => {
+                Body::new(StreamBody::new(data.chain(futures_util::stream::iter([
+                    Ok(Frame::trailers(trailers)),
+                ]))))
+            }
+            None => Body::new(StreamBody::new(data.chain(futures_util::stream::pending()))),
+        };
+        ProxiedResponse::streaming(StatusCode::OK, HeaderMap::new(), body)
+    };
+    for (fault, dropped) in [
+        ("x-lowdown-drop-trailers-percentage", true),
+        ("x-lowdown-corrupt-trailers-percentage", false),
+    ] {
+        harness.client.enqueue(streamed(upstream().trailers.take()));
+        let collected = call(Some(fault)).await;
+        assert_eq!(collected.trailers().is_none(), dropped);
+        if let Some(trailers) = collected.trailers() {
+            assert_ne!(trailers["grpc-status"], "0");
+        }
+        assert_eq!(collected.to_bytes(), Bytes::from_static(b"chunk"));
+
+        harness.client.enqueue(streamed(None));
+        let request = request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header(fault, "100")
+            .body(Body::empty())
+            .unwrap();
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            harness.proxy.clone().oneshot(request),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let mut body = response.into_body();
+        let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.into_data().unwrap(), Bytes::from_static(b"chunk"));
+    }
+}
+
+#[tokio::test]
+async fn assertions_count_matching_traffic() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let proxied = |path: &str, fail_before: &str| {
+        request_builder(Method::GET, path)
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-fail-before-percentage", fail_before)
+            .body(Body::empty())
+            .unwrap()
+    };
+    harness.client.enqueue(json_ok());
+    harness.proxy_call(proxied("/orders", "0")).await;
+
+    let created = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/assertions")
+                .body(Body::from(
+                    r#"[{"path":"/orders","fault":"fail-before","count":2},{"path-prefix":"/users","max-count":0}]"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(created.status, StatusCode::OK);
+    let id = created.json()["id"].as_str().unwrap().to_string();
+    let result = || async {
+        harness
+            .admin_call(
+                request_builder(Method::GET, &format!("/api/v1/assertions/{id}/result"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json()
+    };
+
+    harness.proxy_call(proxied("/orders?page=1", "100")).await;
+    let pending = result().await;
+    assert_eq!(pending["passed"], false);
+    assert_eq!(pending["results"][0]["actual"], 1);
+
+    harness.proxy_call(proxied("/orders", "100")).await;
+    let passed = result().await;
+    assert_eq!(passed["passed"], true);
+    assert_eq!(passed["results"][1]["passed"], true);
+
+    let invalid = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/assertions")
+                .body(Body::from(r#"{"count":1,"min-count":1}"#))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.json()["error"], "invalid-assertion");
+
+    let delete = || {
+        request_builder(Method::DELETE, &format!("/api/v1/assertions/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(harness.admin_call(delete()).await.status, StatusCode::OK);
+    assert_eq!(
+        harness.admin_call(delete()).await.status,
+        StatusCode::NOT_FOUND
+    );
+    let removed = harness
+        .admin_call(
+            request_builder(Method::GET, &format!("/api/v1/assertions/{id}/result"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(removed.status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn assertions_are_bounded_and_flag_truncated_traffic() {
+    let traffic = TrafficLog::default();
+    let record = |path: &str| TrafficRecord {
+        seq: 0,
+        at: jiff::Timestamp::now(),
+        method: "GET".to_string(),
+        uri: path.to_string(),
+        destination: None,
+        rule: None,
+        deployment_marker: None,
+        status: 200,
+        faults: Vec::new(),
+        duration_ms: 0,
+    };
+    let registry = AssertionRegistry::default();
+    let expectations =
+        assertions::parse_expectations(r#"{"path":"/orders","max-count":0}"#).unwrap();
+    let id = registry.add(traffic.last_seq(), expectations.clone());
+    traffic.record(record("/users"));
+    let result = registry.evaluate(id, |since| traffic.after(since)).unwrap();
+    assert!(result.passed);
+    assert!(!result.traffic_truncated);
+
+    traffic.record(record("/orders"));
+    for _ in 0..10_000 {
+        traffic.record(record("/users"));
+    }
+    let result = registry.evaluate(id, |since| traffic.after(since)).unwrap();
+    assert!(!result.passed);
+    assert!(result.traffic_truncated);
+    assert_eq!(result.results[0].actual, 0);
+    let recent = registry.add(traffic.last_seq(), expectations.clone());
+    let result = registry
+        .evaluate(recent, |since| traffic.after(since))
+        .unwrap();
+    assert!(!result.traffic_truncated);
+
+    for _ in 0..assertions::CAPACITY - 1 {
+        registry.add(traffic.last_seq(), expectations.clone());
+    }
+    assert!(
+        registry
+            .evaluate(id, |since| traffic.after(since))
+            .is_none()
+    );
+    assert!(registry.remove(recent));
+    assert!(!registry.remove(recent));
+}
+
+#[tokio::test]
+async fn report_summarizes_traffic_faults_and_admin_changes() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .header("x-lowdown-fail-before-percentage", "0")
+                .header("x-lowdown-delay-after-percentage", "100")
+                .header("x-lowdown-delay-after-ms", "1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let proxied = || {
+        request_builder(Method::GET, "/")
+            .body(Body::empty())
+            .unwrap()
+    };
+    harness.client.enqueue(json_ok());
+    harness.proxy_call(proxied()).await;
+    harness.proxy_call(proxied()).await;
+    harness.proxy_call(proxied()).await;
+
+    let report = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report?since=1h&slo=50")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(report["requests"], 3);
+    assert_eq!(report["statuses"]["503"], 2);
+    assert_eq!(report["faults"]["fail-before"], 2);
+    assert_eq!(report["faults-by-rule"]["settings"]["fail-before"], 2);
+    assert_eq!(
+        report["faulted-routes"],
+        json!([{"route": "GET /", "requests": 3, "faulted-requests": 3}])
+    );
+    assert_eq!(report["duration-seconds"], 3600);
+    let one_off = report["faults-by-rule"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .find(|rule| rule.starts_with("one-off:"))
+        .unwrap();
+    assert_eq!(report["faults-by-rule"][one_off]["delay-after"], 1);
+    assert_eq!(report["error-budget"]["errors"], 2);
+    assert_eq!(report["error-budget"]["allowed-errors"], 1.5);
+    let changes = report["admin-changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["path"], "/api/v1/update");
+    assert_eq!(changes[0]["settings"]["fail-before-percentage"], "100");
+
+    let markdown = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report?format=markdown")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let markdown = String::from_utf8(markdown.body.to_vec()).unwrap();
+    assert!(markdown.contains("| settings | fail-before | 2 |"));
+
+    let html = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report?format=html")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(html.headers["content-type"], "text/html; charset=utf-8");
+
+    let invalid = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report?since=yesterday-ish")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn shutdown_summary_is_written_in_the_format_of_its_extension() {
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(AppState::new(SettingsLayer::default(), shared));
+    let harness = TestHarness::from_state(state.clone(), client);
+    let (header_name, header_value) = destination_header();
+    for uri in ["/orders/1?page=2", "/orders/1", "/users"] {
+        let mut request =
+            request_builder(Method::GET, uri).header(header_name.clone(), header_value.clone());
+        if uri.starts_with("/orders") {
+            request = request.header("x-lowdown-fail-before-percentage", "100");
+        } else {
+            harness.client.enqueue(json_ok());
+        }
+        harness
+            .proxy_call(request.body(Body::empty()).unwrap())
+            .await;
+    }
+
+    let (records, changes) = state.traffic().since(None);
+    let summary = lowdown::report::build(
+        &records,
+        changes,
+        Some(state.started_at()),
+        lowdown::report::DEFAULT_SLO,
+        state.clock().timestamp(),
+    );
+    let dir = std::env::temp_dir();
+    let json_path = dir.join(format!("lowdown-summary-{}.json", std::process::id()));
+    let markdown_path = dir.join(format!("lowdown-summary-{}.md", std::process::id()));
+    lowdown::report::write_summary(&summary, &json_path).unwrap();
+    lowdown::report::write_summary(&summary, &markdown_path).unwrap();
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+    let markdown = std::fs::read_to_string(&markdown_path).unwrap();
+    std::fs::remove_file(&json_path).unwrap();
+    std::fs::remove_file(&markdown_path).unwrap();
+
+    assert_eq!(json["requests"], 3);
+    assert_eq!(json["faults"]["fail-before"], 2);
+    assert!(json["duration-seconds"].is_u64());
+    assert_eq!(
+        json["faulted-routes"],
+        json!([{"route": "GET /orders/1", "requests": 2, "faulted-requests": 2}])
+    );
+    assert!(markdown.contains("| GET /orders/1 | 2 | 2 |"));
+}
+
+#[tokio::test]
+async fn admin_mutations_honor_if_match() {
+    let harness = TestHarness::new();
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/list")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let etag = listed.headers["etag"].to_str().unwrap().to_string();
+    let update = |etag: &str, percentage: &str| {
+        request_builder(Method::POST, "/api/v1/update")
+            .header("if-match", etag)
+            .header("x-lowdown-fail-before-percentage", percentage)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let first = harness.admin_call(update(&etag, "10")).await;
+    assert_eq!(first.status, StatusCode::OK);
+    let next = first.headers["etag"].to_str().unwrap().to_string();
+    assert_ne!(next, etag);
+
+    let stale = harness.admin_call(update(&etag, "20")).await;
+    assert_eq!(stale.status, StatusCode::CONFLICT);
+    assert_eq!(stale.headers["etag"], next.as_str());
+    assert_eq!(stale.json()["error"], "version-conflict");
+    let one_off = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/one-off")
+                .header("if-match", &etag)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(one_off.status, StatusCode::CONFLICT);
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/list")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(listed.json()["fail-before-percentage"], 10);
+
+    let reset = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/reset")
+                .header("if-match", &next)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(reset.status, StatusCode::OK);
+    let unconditional = harness.admin_call(update("*", "30")).await;
+    assert_eq!(unconditional.status, StatusCode::OK);
+    let malformed = harness.admin_call(update("latest", "30")).await;
+    assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn update_dry_run_returns_diff_without_applying() {
+    let harness = TestHarness::new();
+    let preview = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update?dry-run=true")
+                .header("x-lowdown-fail-before-percentage", "20")
+                .header("x-lowdown-delay-before-ms", "0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(preview.status, StatusCode::OK);
+    let preview = preview.json();
+    assert_eq!(preview["dry-run"], true);
+    assert_eq!(
+        preview["changes"],
+        json!({"fail-before-percentage": {"from": 0, "to": 20}})
+    );
+    assert_eq!(preview["settings"]["fail-before-percentage"], 20);
+
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/list")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(listed.json()["fail-before-percentage"], 0);
+    let report = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(report["admin-changes"], json!([]));
+}
+
+#[tokio::test]
+async fn faults_target_a_request_index_within_each_session() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header(header_name, header_value)
+                .header("x-lowdown-session-key", "{cookie:session}")
+                .header("x-lowdown-match-session-request-index", "2")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let call = |session: &str| {
+        request_builder(Method::GET, "/")
+            .header("cookie", format!("theme=dark; session={session}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let mut statuses = Vec::new();
+    for session in ["a", "a", "b", "a", "b"] {
+        statuses.push(harness.proxy_call(call(session)).await.status.as_u16());
+    }
+    assert_eq!(statuses, [200, 503, 200, 200, 503]);
+    let anonymous = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(anonymous.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn retries_mask_upstream_failures() {
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let (header_name, header_value) = destination_header();
+    let unavailable = || {
+        ProxiedResponse::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            HeaderMap::new(),
+            Bytes::from_static(b"busy"),
+        )
+    };
+    let call = |attempts: &str| {
+        request_builder(Method::POST, "/orders")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-retry-attempts", attempts)
+            .header("x-lowdown-retry-backoff-ms", "100")
+            .body(Body::from("order"))
+            .unwrap()
+    };
+
+    harness.client.enqueue(unavailable());
+    harness.client.enqueue(unavailable());
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("2")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let recordings = harness.client.recordings();
+    assert_eq!(recordings.len(), 3);
+    assert!(recordings.iter().all(|request| request.body == "order"));
+    assert_eq!(clock.elapsed(), Duration::from_millis(300));
+
+    harness.client.enqueue(unavailable());
+    harness.client.enqueue(unavailable());
+    let response = harness.proxy_call(call("1")).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(harness.client.recordings().len(), 5);
+
+    let report = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/report")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(report["faults"]["retry"], 2);
+}
+
+#[tokio::test]
+async fn max_triggered_per_second_caps_injected_faults() {
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-max-triggered-per-second", "2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let into_second = clock.timestamp().subsec_nanosecond();
+    clock.advance(Duration::from_nanos((1_000_000_000 - into_second) as u64));
+
+    let statuses = || async {
+        let mut statuses = Vec::new();
+        for _ in 0..5 {
+            let response = harness
+                .proxy_call(
+                    request_builder(Method::GET, "/")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            statuses.push(response.status.as_u16());
+        }
+        statuses
+    };
+    assert_eq!(statuses().await, [503, 503, 200, 200, 200]);
+    clock.advance(Duration::from_millis(999));
+    assert_eq!(statuses().await, [200; 5]);
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(statuses().await, [503, 503, 200, 200, 200]);
+}
+
+#[tokio::test]
+async fn named_rules_apply_in_priority_order() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header(header_name, header_value)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let put = |rule: &str| {
+        request_builder(Method::POST, "/api/v1/rules")
+            .body(Body::from(rule.to_string()))
+            .unwrap()
+    };
+    let created = harness
+        .admin_call(put(
+            r#"{"name":"reads","settings":{"fail-before-percentage":100,"fail-before-code":502},"match":{"method":"GET"}}"#,
+        ))
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let orders = harness
+        .admin_call(put(
+            r#"{"name":"orders","priority":10,"settings":{"fail-before-percentage":100,"fail-before-code":500},"match":{"uri-starts-with":"/orders"}}"#,
+        ))
+        .await;
+    assert_eq!(orders.status, StatusCode::CREATED);
+    assert_eq!(orders.json()["match"]["uri-starts-with"], "/orders");
+    let invalid = harness.admin_call(put(r#"{"settings":{}}"#)).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/rules")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(listed["rules"][0]["name"], "orders");
+    assert_eq!(listed["rules"][1]["name"], "reads");
+
+    let call =
+        |method: Method, path: &str| request_builder(method, path).body(Body::empty()).unwrap();
+    let mut statuses = Vec::new();
+    for (method, path) in [
+        (Method::GET, "/orders/1"),
+        (Method::POST, "/orders"),
+        (Method::GET, "/users"),
+        (Method::POST, "/users"),
+    ] {
+        statuses.push(harness.proxy_call(call(method, path)).await.status.as_u16());
+    }
+    assert_eq!(statuses, [500, 500, 502, 200]);
+
+    let deleted = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/rules/orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(deleted.status, StatusCode::OK);
+    let after = harness.proxy_call(call(Method::GET, "/orders/1")).await;
+    assert_eq!(after.status, StatusCode::BAD_GATEWAY);
+    let missing = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/rules/orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn profiles_are_selected_per_request() {
+    let harness = TestHarness::new();
+    let created = harness
+        .admin_call(
+            request_builder(Method::PUT, "/api/v1/profiles/flaky")
+                .body(Body::from(
+                    r#"{"fail-before-percentage": 100, "fail-before-code": 429}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    assert_eq!(created.json()["settings"]["fail-before-code"], "429");
+
+    let call = |profile: Option<&'static str>, percentage: Option<&'static str>| {
+        let mut builder = request_builder(Method::GET, "/")
+            .header("x-lowdown-destination-url", "http://example.com");
+        if let Some(profile) = profile {
+            builder = builder.header("x-lowdown-profile", profile);
+        }
+        if let Some(percentage) = percentage {
+            builder = builder.header("x-lowdown-fail-before-percentage", percentage);
+        }
+        harness.proxy_call(builder.body(Body::empty()).unwrap())
+    };
+    assert_eq!(
+        call(Some("flaky"), None).await.status,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(call(None, None).await.status, StatusCode::OK);
+    assert_eq!(call(Some("flaky"), Some("0")).await.status, StatusCode::OK);
+    let unknown = call(Some("flakey"), None).await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+    assert_eq!(unknown.json()["error"], "unknown-profile");
+    assert!(
+        harness.client.recordings()[0]
+            .headers
+            .get("x-lowdown-profile")
+            .is_none()
+    );
+
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/profiles")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(listed["profiles"]["flaky"]["fail-before-percentage"], "100");
+    let deleted = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/profiles/flaky")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(deleted.status, StatusCode::OK);
+    assert_eq!(
+        call(Some("flaky"), None).await.status,
+        StatusCode::BAD_REQUEST
+    );
+
+    let config = ConfigFile::parse("profiles: {slow: {delay-before-ms: 5}}").unwrap();
+    assert_eq!(
+        config.profiles["slow"].delay_before_ms,
+        Some(Millis::new(5))
+    );
+    assert!(ConfigFile::parse("profiles: {slow: {delay-ms: 5}}").is_err());
+}
+
+#[tokio::test]
+async fn rules_analysis_reports_unreachable_rules() {
+    let harness = TestHarness::new();
+    for rule in [
+        r#"{"name":"orders","priority":10,"match":{"uri-starts-with":"/orders"}}"#,
+        r#"{"name":"order-writes","match":{"uri-starts-with":"/orders","method":"POST"}}"#,
+        r#"{"name":"confused","match":{"uri":"/users","uri-starts-with":"/orders"}}"#,
+        r#"{"name":"users","match":{"uri-starts-with":"/users"}}"#,
+    ] {
+        let created = harness
+            .admin_call(
+                request_builder(Method::POST, "/api/v1/rules")
+                    .body(Body::from(rule))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(created.status, StatusCode::CREATED);
+    }
+    let analysis = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/rules/analysis")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(analysis.status, StatusCode::OK);
+    assert_eq!(
+        analysis.json(),
+        json!({
+            "never-match": [{
+                "rule": "confused",
+                "reason": "match-uri \"/users\" never satisfies match-uri-starts-with",
+            }],
+            "shadowed": [{"rule": "order-writes", "by": "orders"}],
+            "overlapping": [],
+        })
+    );
+}
+
+#[tokio::test]
+async fn rules_analysis_reports_overlapping_rules() {
+    let harness = TestHarness::new();
+    for rule in [
+        r#"{"name":"api-orders","priority":9,"settings":{"fail-before-percentage":100},"match":{"uri-starts-with":"/api/orders"}}"#,
+        r#"{"name":"checkout","priority":8,"settings":{"delay-before-ms":100},"match":{"uri":"/api/orders/checkout"}}"#,
+        r#"{"name":"api","priority":7,"settings":{"delay-before-ms":100},"match":{"uri-starts-with":"/api"}}"#,
+        r#"{"name":"v2","priority":5,"settings":{"fail-before-percentage":100},"match":{"uri-regex":"/v2/.*"}}"#,
+        r#"{"name":"v2-health","priority":4,"match":{"uri":"/v2/health"}}"#,
+        r#"{"name":"v2-prefix","priority":3,"settings":{"delay-before-ms":50},"match":{"uri-starts-with":"/v2/"}}"#,
+        r#"{"name":"reads","priority":2,"settings":{"fail-before-percentage":100},"match":{"uri-starts-with":"/v3","method":"GET"}}"#,
+        r#"{"name":"writes","priority":1,"settings":{"delay-before-ms":100},"match":{"uri-starts-with":"/v3","method":"POST"}}"#,
+    ] {
+        let created = harness
+            .admin_call(
+                request_builder(Method::POST, "/api/v1/rules")
+                    .body(Body::from(rule))
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(created.status, StatusCode::CREATED);
+    }
+    let analysis = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/rules/analysis")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(analysis.status, StatusCode::OK);
+    assert_eq!(
+        analysis.json(),
+        json!({
+            "never-match": [],
+            "shadowed": [
+                {"rule": "checkout", "by": "api-orders"},
+                {"rule": "v2-health", "by": "v2"},
+            ],
+            "overlapping": [
+                {"rule": "api", "by": "api-orders", "uri": "/api/orders"},
+                {"rule": "v2-prefix", "by": "v2", "uri": "/v2/"},
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn strict_passthrough_forwards_responses_unchanged() {
+    let harness = TestHarness::new();
+    let upstream = || {
+        let mut headers = HeaderMap::new();
+        headers.insert("access-control-allow-origin", HeaderValue::from_static("*"));
+        headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+        let mut response = ProxiedResponse::new(
+            StatusCode::OK,
+            headers,
+            Bytes::from_static(b"\x1f\x8b\x08\x00compressed"),
+        );
+        response.reason = Some(ReasonPhrase::from_static(b"Fine By Me"));
+        response
+    };
+    let call = |strict: &'static str| {
+        let (header_name, header_value) = destination_header();
+        request_builder(Method::GET, "/download")
+            .header(header_name, header_value)
+            .header("origin", "https://app.example.com")
+            .header("x-lowdown-strict-passthrough", strict)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    harness.client.enqueue(upstream());
+    let rewritten = harness.proxy.clone().oneshot(call("false")).await.unwrap();
+    assert!(rewritten.extensions().get::<ReasonPhrase>().is_none());
+    let rewritten = ResponseParts::from(rewritten).await;
+    assert_eq!(
+        rewritten.headers["access-control-allow-origin"],
+        "https://app.example.com"
+    );
+    assert!(rewritten.headers.get("transfer-encoding").is_none());
+
+    harness.client.enqueue(upstream());
+    let strict = harness.proxy.clone().oneshot(call("true")).await.unwrap();
+    assert_eq!(
+        strict
+            .extensions()
+            .get::<ReasonPhrase>()
+            .unwrap()
+            .as_bytes(),
+        b"Fine By Me"
+    );
+    let strict = ResponseParts::from(strict).await;
+    let expected = upstream();
+    assert_eq!(strict.status, expected.status);
+    assert_eq!(strict.headers, expected.headers);
+    assert_eq!(strict.body, expected.bytes().unwrap());
+    let recorded = harness.client.recordings();
+    assert!(!recorded[0].preserve_encoding);
+    assert!(recorded[1].preserve_encoding);
+
+    harness.client.enqueue(upstream());
+    let (header_name, header_value) = destination_header();
+    let faulted = harness
+        .proxy_call(
+            request_builder(Method::GET, "/download")
+                .header(header_name, header_value)
+                .header("x-lowdown-strict-passthrough", "true")
+                .header("x-lowdown-stream-fault-percentage", "100")
+                .header("x-lowdown-stream-garbage-bytes", "4")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(faulted.headers.get("transfer-encoding").is_none());
+    assert_eq!(faulted.headers["access-control-allow-origin"], "*");
+}
+
+#[tokio::test]
+async fn non_standard_status_codes_keep_their_code_and_reason() {
+    let harness = TestHarness::new();
+    let call = |headers: &[(&str, String)]| {
+        let harness = &harness;
+        let (header_name, header_value) = destination_header();
+        let mut request = request_builder(Method::GET, "/").header(header_name, header_value);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        async move {
+            let response = harness.proxy.clone().oneshot(request).await.unwrap();
+            let reason = response
+                .extensions()
+                .get::<ReasonPhrase>()
+                .map(|reason| String::from_utf8_lossy(reason.as_bytes()).into_owned());
+            (ResponseParts::from(response).await, reason)
+        }
+    };
+
+    let mut upstream = ProxiedResponse::new(
+        StatusCode::from_u16(599).unwrap(),
+        HeaderMap::new(),
+        Bytes::from_static(b"upstream"),
+    );
+    upstream.reason = Some(ReasonPhrase::from_static(b"Network Connect Timeout Error"));
+    harness.client.enqueue(upstream);
+    let transform = json!([{"step": "set-header", "name": "x-seen", "value": "yes"}]);
+    let (response, reason) = call(&[("x-lowdown-transform", transform.to_string())]).await;
+    assert_eq!(response.status.as_u16(), 599);
+    assert_eq!(response.headers["x-seen"], "yes");
+    assert_eq!(reason.unwrap(), "Network Connect Timeout Error");
+
+    harness.client.enqueue(json_ok());
+    let (response, reason) = call(&[
+        ("x-lowdown-override-status-percentage", "100".to_string()),
+        ("x-lowdown-override-status-codes", "799".to_string()),
+    ])
+    .await;
+    assert_eq!(response.status.as_u16(), 799);
+    assert_eq!(response.body, Bytes::from("upstream"));
+    assert_eq!(reason.unwrap(), "Unknown");
+
+    let (response, reason) = call(&[
+        ("x-lowdown-fail-before-percentage", "100".to_string()),
+        ("x-lowdown-fail-before-code", "599".to_string()),
+    ])
+    .await;
+    assert_eq!(response.status.as_u16(), 599);
+    assert_eq!(reason.unwrap(), "Server Error");
+}
+
+#[tokio::test]
+async fn response_bodies_stream_without_buffering() {
+    let harness = TestHarness::new();
+    let (release, released) = tokio::sync::oneshot::channel::<()>();
+    let frames = futures_util::StreamExt::chain(
+        futures_util::stream::iter([Ok::<_, std::convert::Infallible>(Frame::data(
+            Bytes::from_static(b"first "),
+        ))]),
+        futures_util::stream::once(async {
+            released.await.unwrap();
+            Ok(Frame::data(Bytes::from_static(b"second")))
+        }),
+    );
+    harness.client.enqueue(ProxiedResponse::streaming(
+        StatusCode::OK,
+        HeaderMap::new(),
+        Body::new(StreamBody::new(frames)),
+    ));
+    let (header_name, header_value) = destination_header();
+    let request = request_builder(Method::GET, "/")
+        .header(header_name, header_value)
+        .body(Body::empty())
+        .unwrap();
+    let response = harness.proxy.clone().oneshot(request).await.unwrap();
+    assert!(!response.headers().contains_key("content-length"));
+    let mut body = response.into_body();
+    let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+    assert_eq!(first, Bytes::from_static(b"first "));
+    release.send(()).unwrap();
+    let rest = body.collect().await.unwrap().to_bytes();
+    assert_eq!(rest, Bytes::from_static(b"second"));
+
+    let metrics = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(metrics["destinations"]["example.com"]["bytes-in"], 12);
+}
+
+#[tokio::test]
+async fn config_file_is_loaded_and_reloaded() {
+    let path = std::env::temp_dir().join(format!("lowdown-config-{}.yaml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+settings:
+  destination-urls: [http://example.com, http://example.com]
+  fail-before-percentage: 100
+rules:
+  - name: health
+    priority: 5
+    settings:
+      fail-before-percentage: 0
+    match:
+      uri-starts-with: /health
+"#,
+    )
+    .unwrap();
+    let harness = TestHarness::with_builder(|builder| builder.config_file(&path));
+    let reload = || {
+        request_builder(Method::POST, "/api/v1/reload")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let call = |path: &str| {
+        request_builder(Method::GET, path)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let loaded = harness.admin_call(reload()).await;
+    assert_eq!(loaded.status, StatusCode::OK);
+    assert_eq!(loaded.json()["rules"], json!(["health"]));
+    assert_eq!(
+        harness.proxy_call(call("/orders")).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        harness.proxy_call(call("/health")).await.status,
+        StatusCode::OK
+    );
+
+    std::fs::write(
+        &path,
+        "settings:\n  destination-url: http://example.com\n  fail-before-percentage: 0\n",
+    )
+    .unwrap();
+    assert_eq!(harness.admin_call(reload()).await.status, StatusCode::OK);
+    assert_eq!(
+        harness.proxy_call(call("/orders")).await.status,
+        StatusCode::OK
+    );
+    let rules = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/rules")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(rules.json()["rules"], json!([]));
+
+    std::fs::write(&path, "settings:\n  fail-before-percentage: lots\n").unwrap();
+    let invalid = harness.admin_call(reload()).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.json()["error"], "invalid-config-file");
+    assert_eq!(
+        harness.proxy_call(call("/orders")).await.status,
+        StatusCode::OK
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    let disabled = TestHarness::new().admin_call(reload()).await;
+    assert_eq!(disabled.status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn config_file_references_are_resolved_but_not_exported() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("lowdown-secret-config-{}.yaml", std::process::id()));
+    let secret = dir.join(format!("lowdown-agent-{}", std::process::id()));
+    std::fs::write(&secret, "secret-agent/1.0\n").unwrap();
+    let reference = format!("file://{}", secret.display());
+    std::fs::write(
+        &path,
+        format!(
+            "settings:\n  destination-url: http://example.com\n  user-agent: {reference}\n\
+             rules:\n  - name: agents\n    settings: {{user-agent: {reference}}}\n\
+             \x20   match: {{headers: {{X-Agent: {reference}}}}}\n\
+             profiles:\n  other:\n    user-agent: {reference}\n"
+        ),
+    )
+    .unwrap();
+    let harness = TestHarness::with_builder(|builder| builder.config_file(&path));
+    let reload = || {
+        request_builder(Method::POST, "/api/v1/reload")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let export = || {
+        request_builder(Method::GET, "/api/v1/export")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let loaded = harness.admin_call(reload()).await;
+    assert_eq!(loaded.status, StatusCode::OK);
+    assert_eq!(loaded.json()["settings"]["user-agent"], reference);
+    harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(
+        harness.client.recordings()[0].headers["user-agent"],
+        "secret-agent/1.0"
+    );
+    let exported = String::from_utf8(harness.admin_call(export()).await.body.to_vec()).unwrap();
+    assert!(exported.contains(&format!("USER_AGENT={reference}")));
+    assert!(!exported.contains("secret-agent"));
+    let rules = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/rules")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(
+        rules.json()["rules"][0]["settings"]["user-agent"],
+        reference
+    );
+    assert_eq!(
+        rules.json()["rules"][0]["match"]["headers"]["x-agent"],
+        reference
+    );
+    let profiles = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/profiles")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(
+        profiles.json()["profiles"]["other"]["user-agent"],
+        reference
+    );
+    for listed in [&rules, &profiles] {
+        assert!(!String::from_utf8_lossy(&listed.body).contains("secret-agent"));
+    }
+
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-user-agent", "admin-agent")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let exported = String::from_utf8(harness.admin_call(export()).await.body.to_vec()).unwrap();
+    assert!(exported.contains("USER_AGENT=admin-agent"));
+
+    std::fs::write(
+        &path,
+        "settings:\n  user-agent: env://LOWDOWN_TEST_UNSET_AGENT\n",
+    )
+    .unwrap();
+    let invalid = harness.admin_call(reload()).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        invalid.json()["message"],
+        "settings.user-agent: environment variable LOWDOWN_TEST_UNSET_AGENT is not set"
+    );
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&secret).unwrap();
+}
+
+#[test]
+fn check_flags_shadowed_rules() {
+    let config = ConfigFile::parse(
+        r#"
+rules:
+  - name: all-orders
+    priority: 10
+    match: {uri-starts-with: /orders}
+  - name: tenant-orders
+    match: {uri-starts-with: /orders, headers: {x-tenant: acme}}
+  - name: users
+    match: {uri-starts-with: /users, headers: {x-tenant: "*"}}
+  - name: acme-users
+    match: {uri-starts-with: /users, headers: {x-tenant: acme}}
+"#,
+    )
+    .unwrap();
+    let warnings = check::lint(&config);
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].contains("\"tenant-orders\" is unreachable, rule \"all-orders\""));
+    assert!(warnings[1].contains("\"acme-users\" is unreachable, rule \"users\""));
+    assert!(check::lint(&ConfigFile::parse("settings: {delay-before-ms: 5}").unwrap()).is_empty());
+}
+
+#[test]
+fn check_subcommand_reports_config_problems() {
+    let dir = std::env::temp_dir();
+    let check = |name: &str, contents: &str| {
+        let path = dir.join(format!("lowdown-check-{name}-{}.yaml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_lowdown"))
+            .args(["check", "-f"])
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let stdout = String::from_utf8(output.stdout)
+            .unwrap()
+            .replace(&*path.to_string_lossy(), "config.yaml");
+        (output.status.code(), stdout)
+    };
+
+    let (code, stdout) = check("valid", "settings: {delay-before-ms: 5}\n");
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "config.yaml: 1 settings, 0 rules, 0 warnings\n");
+
+    let (code, stdout) = check(
+        "shadowed",
+        r#"
+rules:
+  - name: all-orders
+    priority: 10
+    match: {uri-starts-with: /orders}
+  - name: order-writes
+    match: {uri-starts-with: /orders, method: POST}
+"#,
+    );
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stdout,
+        "warning: config.yaml: rule \"order-writes\" is unreachable, rule \"all-orders\" matches every request it matches first\n\
+         config.yaml: 0 settings, 2 rules, 1 warnings\n"
+    );
+
+    let (code, stdout) = check("invalid", "settings: {delay-before-ms: soon}\n");
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stdout,
+        "error: config.yaml: invalid value for delay-before-ms: \"soon\"\n"
+    );
+
+    let usage = std::process::Command::new(env!("CARGO_BIN_EXE_lowdown"))
+        .arg("check")
+        .output()
+        .unwrap();
+    assert_eq!(usage.status.code(), Some(1));
+    assert_eq!(usage.stderr, b"usage: lowdown check -f <config file>\n");
+}
+
+#[tokio::test]
+async fn proxy_identity_headers_are_configurable() {
+    let harness = TestHarness::new();
+    let call = |identify: bool| {
+        let (header_name, header_value) = destination_header();
+        let mut builder = request_builder(Method::GET, "/")
+            .header(header_name, header_value)
+            .header("user-agent", "curl/8.5.0")
+            .header("via", "1.1 edge");
+        if identify {
+            builder = builder
+                .header("x-lowdown-user-agent", "lowdown-test/1")
+                .header("x-lowdown-add-via-header", "true")
+                .header("x-lowdown-add-forwarded-by-header", "true");
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    harness.proxy_call(call(false)).await;
+    harness.proxy_call(call(true)).await;
+
+    let recorded = harness.client.recordings();
+    assert_eq!(recorded[0].headers["user-agent"], "curl/8.5.0");
+    assert_eq!(recorded[0].headers.get_all("via").iter().count(), 1);
+    assert!(recorded[0].headers.get("x-forwarded-by").is_none());
+
+    let version = env!("CARGO_PKG_VERSION");
+    assert_eq!(recorded[1].headers["user-agent"], "lowdown-test/1");
+    let via: Vec<_> = recorded[1].headers.get_all("via").iter().collect();
+    assert_eq!(via.len(), 2);
+    assert_eq!(via[0], "1.1 edge");
+    assert_eq!(via[1], format!("1.1 lowdown (lowdown/{version})").as_str());
+    assert_eq!(
+        recorded[1].headers["x-forwarded-by"],
+        format!("lowdown/{version}").as_str()
+    );
+}
+
+#[tokio::test]
+async fn stats_export_as_csv_and_parquet() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    for path in ["/a", "/b?x=1,2"] {
+        harness
+            .proxy_call(
+                request_builder(Method::GET, path)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+    }
+
+    let csv = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/stats/export?format=csv&since=1h")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(csv.status, StatusCode::OK);
+    assert_eq!(csv.headers["content-type"], "text/csv; charset=utf-8");
+    let csv = String::from_utf8(csv.body.to_vec()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "timestamp,seq,method,uri,destination,rule,deployment_marker,faults,status,latency_ms"
+    );
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains(",GET,/a,example.com,settings,,fail-before,503,"));
+    assert!(lines[2].contains(",\"/b?x=1,2\","));
+
+    let aggregated = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/stats/export?granularity=minute")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let aggregated = String::from_utf8(aggregated.body.to_vec()).unwrap();
+    let rows: Vec<&str> = aggregated.lines().skip(1).collect();
+    assert!(rows.len() <= 2);
+    assert!(
+        rows.iter()
+            .all(|row| row.contains(",settings,fail-before,503,"))
+    );
+
+    let parquet = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/stats/export?format=parquet")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(parquet.status, StatusCode::OK);
+    assert_eq!(
+        parquet.headers["content-type"],
+        "application/vnd.apache.parquet"
+    );
+    assert!(parquet.body.starts_with(b"PAR1"));
+    assert!(parquet.body.ends_with(b"PAR1"));
+
+    let invalid = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/stats/export?format=xlsx")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn tasks_are_listed_and_can_be_cancelled() {
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(AppState::builder(shared).build());
+    let harness = TestHarness::from_state(state.clone(), client);
+    let id = state
+        .tasks()
+        .spawn("test", "waits forever".to_string(), |progress| async move {
+            progress.set(40);
+            std::future::pending::<()>().await;
+            Ok(())
+        });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let tasks = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/tasks")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(tasks[0]["id"], id.to_string());
+    assert_eq!(tasks[0]["status"], "running");
+    assert_eq!(tasks[0]["progress"], 40);
+
+    let cancel = || {
+        request_builder(Method::DELETE, &format!("/api/v1/tasks/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let cancelled = harness.admin_call(cancel()).await;
+    assert_eq!(cancelled.status, StatusCode::OK);
+    assert_eq!(cancelled.json()["status"], "cancelled");
+    assert_eq!(
+        harness.admin_call(cancel()).await.status,
+        StatusCode::CONFLICT
+    );
+}
+
+#[tokio::test]
+async fn log_level_can_be_changed_at_runtime() {
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let recorded = applied.clone();
+    let log_level = LogLevel::new("info", move |directives| {
+        if directives.contains("shout") {
+            return Err("invalid level".to_string());
+        }
+        recorded.lock().push(directives.to_string());
+        Ok(())
+    });
+    let harness = TestHarness::with_builder(|builder| builder.log_level(log_level));
+    let set = |directives: &'static str| {
+        request_builder(Method::PUT, "/api/v1/log-level")
+            .body(Body::from(directives))
+            .unwrap()
+    };
+
+    let response = harness.admin_call(set("info,lowdown::proxy=debug\n")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        *applied.lock(),
+        vec!["info,lowdown::proxy=debug".to_string()]
+    );
+
+    let response = harness.admin_call(set("lowdown=shout")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["error"], "invalid-log-level");
+
+    let current = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/log-level")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(current.json()["log-level"], "info,lowdown::proxy=debug");
+}
+
+#[tokio::test]
+async fn dev_mode_pretty_prints_and_adds_debug_headers() {
+    let harness = TestHarness::with_builder(|builder| builder.dev_mode(true));
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers["x-lowdown-debug-matched"], "true");
+    assert_eq!(response.headers["x-lowdown-debug-faults"], "fail-before");
+    assert_eq!(
+        response.body,
+        Bytes::from("{\n  \"error\": \"fail-before\"\n}")
+    );
+    assert_eq!(response.json()["error"], "fail-before");
+
+    let health = harness
+        .admin_call(
+            request_builder(Method::GET, "/health")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert!(!health.body.ends_with(b"\n"));
+    assert_eq!(
+        health.headers["content-length"],
+        health.body.len().to_string().as_str()
+    );
+}
+
+#[tokio::test]
+async fn content_length_matches_the_body_actually_sent() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let upstream = || {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-length", HeaderValue::from_static("999"));
+        headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+        ProxiedResponse::new(StatusCode::OK, headers, Bytes::from_static(b"upstream"))
+    };
+    let request = |method: Method, garbage: &str| {
+        request_builder(method, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-stream-fault-percentage", "100")
+            .header("x-lowdown-stream-garbage-bytes", garbage)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    harness.client.enqueue(upstream());
+    let response = harness.proxy_call(request(Method::GET, "0")).await;
+    assert_eq!(response.headers["content-length"], "8");
+    assert!(!response.headers.contains_key("transfer-encoding"));
+
+    harness.client.enqueue(upstream());
+    let response = harness.proxy_call(request(Method::GET, "4")).await;
+    assert_eq!(response.body.len(), 12);
+    assert!(!response.headers.contains_key("content-length"));
+
+    let mut head = upstream();
+    head.clear_body();
+    harness.client.enqueue(head);
+    let response = harness.proxy_call(request(Method::HEAD, "0")).await;
+    assert_eq!(response.headers["content-length"], "999");
+}
+
+#[tokio::test]
+async fn held_requests_are_reported_while_delayed() {
+    let harness = Arc::new(TestHarness::new());
+    let (header_name, header_value) = destination_header();
+    let delayed = {
+        let harness = harness.clone();
+        tokio::spawn(async move {
+            harness
+                .proxy_call(
+                    request_builder(Method::GET, "/")
+                        .header(header_name, header_value)
+                        .header("x-lowdown-delay-before-percentage", "100")
+                        .header("x-lowdown-delay-before-ms", "300")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+        })
+    };
+    let held = || async {
+        harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json()["held"]
+            .clone()
+    };
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(held().await["settings"]["delay-before"], 1);
+    let metrics = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(metrics["held"]["settings"]["delay-before"], 1);
+
+    assert_eq!(delayed.await.unwrap().status, StatusCode::OK);
+    assert_eq!(held().await, serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn trigger_first_per_key_faults_only_new_keys() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let request = |user: &str| {
+        request_builder(Method::GET, "/profile")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-user", user.to_string())
+            .header("x-lowdown-fail-before-percentage", "100")
+            .header("x-lowdown-trigger-first-per-key", "{header:x-user}:{path}")
+            .header("x-lowdown-trigger-first-window-ms", "100")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    assert_eq!(
+        harness.proxy_call(request("a")).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        harness.proxy_call(request("a")).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        harness.proxy_call(request("b")).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(
+        harness.proxy_call(request("a")).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn deployment_marker_scopes_faults_and_reports() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let request = || {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-fail-before-percentage", "100")
+            .header("x-lowdown-match-deployment-marker", "v2")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let set_marker = |marker: &'static str| {
+        request_builder(Method::PUT, "/api/v1/deployment-marker")
+            .body(Body::from(marker))
+            .unwrap()
+    };
+
+    assert_eq!(harness.proxy_call(request()).await.status, StatusCode::OK);
+    harness.admin_call(set_marker("v1")).await;
+    assert_eq!(harness.proxy_call(request()).await.status, StatusCode::OK);
+    let response = harness.admin_call(set_marker("v2\n")).await;
+    assert_eq!(response.json()["deployment-marker"], "v2");
+    assert_eq!(
+        harness.proxy_call(request()).await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let get = |path: &str| {
+        request_builder(Method::GET, path)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let metrics = harness.admin_call(get("/api/v1/metrics")).await.json();
+    assert_eq!(metrics["deployment-marker"], "v2");
+    let report = harness.admin_call(get("/api/v1/report")).await.json();
+    assert_eq!(report["requests-by-deployment-marker"]["v1"], 1);
+    assert_eq!(report["requests-by-deployment-marker"]["v2"], 1);
+    assert_eq!(report["admin-changes"][1]["deployment-marker"], "v2");
+
+    let cleared = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/deployment-marker")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(cleared.json()["deployment-marker"], Value::Null);
+    assert_eq!(
+        harness.admin_call(set_marker(" ")).await.status,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn duplicate_destination_compares_responses() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    harness.client.enqueue(json_ok());
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        HeaderMap::new(),
+        Bytes::from_static(b"canary broke"),
+    ));
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/orders?id=1")
+                .header(header_name, header_value)
+                .header("x-lowdown-duplicate-percentage", "100")
+                .header(
+                    "x-lowdown-duplicate-destination-url",
+                    "http://canary.local:8081",
+                )
+                .body(Body::from("order"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, Bytes::from_static(b"upstream"));
+
+    let recordings = harness.client.recordings();
+    assert_eq!(recordings[0].url, "http://example.com/orders?id=1");
+    assert_eq!(recordings[1].url, "http://canary.local:8081/orders?id=1");
+    assert_eq!(recordings[1].headers["host"], "canary.local:8081");
+    assert_eq!(recordings[1].body, Bytes::from_static(b"order"));
+
+    let comparisons = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/duplicates")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    let comparison = &comparisons[0];
+    assert_eq!(comparison["uri"], "/orders?id=1");
+    assert_eq!(comparison["primary"]["status"], 200);
+    assert_eq!(comparison["duplicate"]["destination"], "canary.local:8081");
+    assert_eq!(comparison["duplicate"]["status"], 500);
+    assert_eq!(comparison["status-matches"], false);
+    assert_eq!(comparison["body-matches"], false);
+}
+
+#[tokio::test]
+async fn sticky_key_pins_users_to_one_destination() {
+    let harness = TestHarness::new();
+    let request = |user: String, failing_shard: &str| {
+        request_builder(Method::GET, "/cart")
+            .header(
+                "x-lowdown-destination-urls",
+                "http://shard-a.local, http://shard-b.local, http://shard-c.local",
+            )
+            .header("x-lowdown-sticky-key", "{cookie:session}")
+            .header("cookie", format!("theme=dark; session={user}"))
+            .header("x-lowdown-match-host", failing_shard.to_string())
+            .header("x-lowdown-fail-before-percentage", "100")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    for _ in 0..5 {
+        harness
+            .proxy_call(request("alice".to_string(), "none"))
+            .await;
+    }
+    let recordings = harness.client.recordings();
+    assert!(recordings.iter().all(|r| r.url == recordings[0].url));
+
+    for user in 0..30 {
+        harness
+            .proxy_call(request(format!("user-{user}"), "none"))
+            .await;
+    }
+    let used: std::collections::HashSet<String> = harness
+        .client
+        .recordings()
+        .into_iter()
+        .map(|r| r.url)
+        .collect();
+    assert_eq!(used.len(), 3);
+
+    let alice_shard = url::Url::parse(&recordings[0].url)
+        .unwrap()
+        .host_str()
+        .unwrap()
+        .to_string();
+    let response = harness
+        .proxy_call(request("alice".to_string(), &alice_shard))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn weighted_destinations_split_traffic_with_their_own_settings() {
+    let harness = TestHarness::with_builder(|builder| builder.random_seed(7));
+    let canary = |body: Value| {
+        request_builder(Method::PUT, "/api/v1/destination-settings/Canary.local")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let stored = harness
+        .admin_call(canary(
+            json!({"fail-before-percentage": 100, "fail-before-code": 502}),
+        ))
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    assert_eq!(stored.json()["fail-before-code"], "502");
+    for invalid in [json!({}), json!({"destination-url": "http://other.local"})] {
+        let response = harness.admin_call(canary(invalid)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["error"], "invalid-destination-settings");
+    }
+
+    let split = |pool: &'static str| {
+        request_builder(Method::POST, "/api/v1/update")
+            .header("x-lowdown-destination-urls", pool)
+            .body(Body::empty())
+            .unwrap()
+    };
+    harness
+        .admin_call(split("90:http://prod.local, 10:http://canary.local"))
+        .await;
+    let mut failed = 0;
+    for _ in 0..200 {
+        let response = harness
+            .proxy_call(
+                request_builder(Method::GET, "/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        if response.status == StatusCode::BAD_GATEWAY {
+            failed += 1;
+        }
+    }
+    let recordings = harness.client.recordings();
+    assert!(
+        (5..=40).contains(&failed),
+        "{failed} requests went to the canary"
+    );
+    assert_eq!(recordings.len(), 200 - failed);
+    assert!(recordings.iter().all(|r| r.url == "http://prod.local/"));
+
+    harness
+        .admin_call(split("0:http://prod.local, 1:http://canary.local"))
+        .await;
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    let removed = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/destination-settings/canary.local")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(removed.status, StatusCode::OK);
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        harness.client.recordings().last().unwrap().url,
+        "http://canary.local/"
+    );
+
+    harness
+        .admin_call(split(
+            "4294967295:http://prod.local, 65535:http://canary.local, 65535:http://canary.local",
+        ))
+        .await;
+    for _ in 0..5 {
+        let response = harness
+            .proxy_call(
+                request_builder(Method::GET, "/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            harness.client.recordings().last().unwrap().url,
+            "http://canary.local/"
+        );
+    }
+}
+
+#[tokio::test]
+async fn min_response_time_pads_fast_responses_to_a_floor() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let request = |floor: &str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-min-response-time-ms", floor.to_string())
+            .header("x-lowdown-delay-before-percentage", "100")
+            .header("x-lowdown-delay-before-ms", "100")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let started = Instant::now();
+    harness.proxy_call(request("250")).await;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(250));
+    assert!(elapsed < Duration::from_millis(340));
+
+    let started = Instant::now();
+    harness.proxy_call(request("50")).await;
+    assert!(started.elapsed() < Duration::from_millis(180));
+}
+
+#[tokio::test]
+async fn admin_chaos_faults_the_control_plane_when_enabled() {
+    let disabled = TestHarness::new();
+    let configure = |body: &'static str| {
+        request_builder(Method::PUT, "/api/v1/admin-chaos")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    assert_eq!(
+        disabled
+            .admin_call(configure(r#"{"fail-percentage":100}"#))
+            .await
+            .status,
+        StatusCode::NOT_IMPLEMENTED
+    );
+
+    let harness = TestHarness::with_builder(|builder| builder.admin_chaos(true));
+    let list = || {
+        request_builder(Method::GET, "/api/v1/list")
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(harness.admin_call(list()).await.status, StatusCode::OK);
+
+    let response = harness
+        .admin_call(configure(r#"{"fail-percentage":100,"fail-code":500}"#))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["fail-code"], 500);
+    let failed = harness.admin_call(list()).await;
+    assert_eq!(failed.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(failed.json()["error"], "admin-chaos");
+
+    assert_eq!(
+        harness
+            .admin_call(configure(r#"{"fail-percentage":101}"#))
+            .await
+            .status,
+        StatusCode::BAD_REQUEST
+    );
+    harness
+        .admin_call(configure(r#"{"delay-ms":150,"delay-percentage":100}"#))
+        .await;
+    let started = Instant::now();
+    assert_eq!(harness.admin_call(list()).await.status, StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_millis(150));
+
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let seeded = TestHarness::with_builder(|builder| builder.admin_chaos(true).random_seed(11));
+        seeded
+            .admin_call(configure(r#"{"fail-percentage":50}"#))
+            .await;
+        let mut statuses = Vec::new();
+        for _ in 0..20 {
+            statuses.push(seeded.admin_call(list()).await.status);
+        }
+        runs.push(statuses);
+    }
+    assert_eq!(runs[0], runs[1]);
+    assert!(runs[0].contains(&StatusCode::OK));
+    assert!(runs[0].contains(&StatusCode::SERVICE_UNAVAILABLE));
+}
+
+#[tokio::test]
+async fn schema_describes_every_setting() {
+    let harness = TestHarness::new();
+    let response = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/schema")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let schema = response.json();
+    let entries = schema.as_array().unwrap();
+    let settings = serde_json::to_value(lowdown::settings::Settings::default()).unwrap();
+    assert_eq!(entries.len(), settings.as_object().unwrap().len());
+    for entry in entries {
+        assert!(
+            !entry["description"].as_str().unwrap().is_empty(),
+            "{} has no description",
+            entry["name"]
+        );
+    }
+
+    let find = |name: &str| {
+        entries
+            .iter()
+            .find(|entry| entry["name"] == name)
+            .cloned()
+            .unwrap()
+    };
+    let fail_before = find("fail-before-code");
+    assert_eq!(fail_before["type"], "integer");
+    assert_eq!(fail_before["default"], 503);
+    assert_eq!(fail_before["minimum"], 100);
+    assert_eq!(fail_before["maximum"], 999);
+    assert_eq!(fail_before["header"], "x-lowdown-fail-before-code");
+    assert_eq!(fail_before["env"], "FAIL_BEFORE_CODE");
+    assert_eq!(find("delay-after-percentage")["maximum"], 100);
+    assert_eq!(find("request-buffering")["type"], "boolean");
+    assert_eq!(find("destination-url")["nullable"], true);
+    assert_eq!(find("preflight-mode")["enum"][1], "synthetic");
+}
+
+#[tokio::test]
+async fn typed_settings_reject_invalid_values() {
+    let harness = TestHarness::new();
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-fail-before-code", "418")
+                .header("x-lowdown-match-method", "post")
+                .header("x-lowdown-destination-url", "http://example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let settings = response.json();
+    assert_eq!(settings["fail-before-percentage"], 100);
+    assert_eq!(settings["match-method"], "POST");
+    assert_eq!(settings["match-uri"], "*");
+
+    let response = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-percentage", "150")
+                .header("x-lowdown-fail-before-code", "42")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    let settings = response.json();
+    assert_eq!(settings["fail-before-percentage"], 100);
+    assert_eq!(settings["fail-before-code"], 418);
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::IM_A_TEAPOT);
+
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header("x-lowdown-match-uri-regex", "([")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn debug_header_returns_evaluation_trace() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/orders")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-debug", "true")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-match-uri-starts-with", "/orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    let trace: Value =
+        serde_json::from_str(response.headers["x-lowdown-trace"].to_str().unwrap()).unwrap();
+    assert_eq!(trace["layers"], json!(["defaults", "headers"]));
+    assert_eq!(trace["rule"], "settings");
+    assert_eq!(
+        trace["matchers"],
+        json!([{"name": "match-uri-starts-with", "matched": true}])
+    );
+    assert_eq!(trace["matched"], true);
+    assert_eq!(
+        trace["rolls"],
+        json!([{"fault": "fail-before", "percentage": 100, "triggered": true}])
+    );
+    assert_eq!(trace["faults"], json!(["fail-before"]));
+    let body = response.json();
+    assert_eq!(body["error"], "fail-before");
+    assert_eq!(body["trace"], trace);
+
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/users")
+                .header(header_name, header_value)
+                .header("x-lowdown-debug", "true")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-match-uri-starts-with", "/orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, Bytes::from("upstream"));
+    let trace: Value =
+        serde_json::from_str(response.headers["x-lowdown-trace"].to_str().unwrap()).unwrap();
+    assert_eq!(trace["matched"], false);
+    assert_eq!(trace["matchers"][0]["matched"], false);
+    assert_eq!(trace["rolls"], json!([]));
+}
+
+#[tokio::test]
+async fn trace_always_forces_sampling_upstream() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let call = |traceparent: Option<&'static str>| {
+        let mut builder = request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-trace", "always");
+        if let Some(traceparent) = traceparent {
+            builder = builder.header("traceparent", traceparent);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    let response = harness
+        .proxy_call(call(Some(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+        )))
+        .await;
+    assert!(response.headers.contains_key("x-lowdown-trace"));
+    harness.proxy_call(call(None)).await;
+
+    let recorded = harness.client.recordings();
+    assert_eq!(
+        recorded[0].headers["traceparent"],
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    );
+    let generated = recorded[1].headers["traceparent"].to_str().unwrap();
+    assert_eq!(generated.len(), 55);
+    assert!(generated.starts_with("00-") && generated.ends_with("-01"));
+}
+
+#[tokio::test]
+async fn destination_body_limits_and_byte_metrics() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let set_limits = |body: &'static str| {
+        request_builder(Method::PUT, "/api/v1/limits/example.com")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let response = harness.admin_call(set_limits("{}")).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["error"], "invalid-limits");
+    let response = harness
+        .admin_call(set_limits(
+            r#"{"max-request-body-bytes":5,"max-response-body-bytes":100}"#,
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-request-buffering", "false")
+                .body(Body::from("too large"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.json()["error"], "request-body-too-large");
+    assert!(harness.client.recordings().is_empty());
+
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/")
+                .header(header_name.clone(), header_value.clone())
+                .body(Body::from("small"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    harness
+        .admin_call(set_limits(r#"{"max-response-body-bytes":4}"#))
+        .await;
+    harness.client.enqueue(json_ok());
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(response.json()["error"], "response-body-too-large");
+
+    let metrics = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    let destination = &metrics["destinations"]["example.com"];
+    assert_eq!(destination["bytes-out"], 5);
+    assert_eq!(destination["bytes-in"], 16);
+
+    let response = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/limits/example.com")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let limits = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/limits")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(limits, json!({}));
+}
+
+#[tokio::test]
+async fn destination_max_rps_queues_or_rejects() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let set_limits = |body: &'static str| {
+        request_builder(Method::PUT, "/api/v1/limits/example.com")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let call = || {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = harness
+        .admin_call(set_limits(r#"{"max-rps":1,"rate-limit-mode":"reject"}"#))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(harness.proxy_call(call()).await.status, StatusCode::OK);
+    let response = harness.proxy_call(call()).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.json()["error"], "destination-rate-limited");
+    assert_eq!(harness.client.recordings().len(), 1);
+
+    harness.admin_call(set_limits(r#"{"max-rps":20}"#)).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let start = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(harness.proxy_call(call()).await.status, StatusCode::OK);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(harness.client.recordings().len(), 4);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_after_failures_and_probes() {
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let (header_name, header_value) = destination_header();
+    let call = || {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let status = || async {
+        harness
+            .admin_call(
+                request_builder(Method::GET, "/api/v1/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .json()["circuits"]["example.com"]["state"]
+            .clone()
+    };
+    let response = harness
+        .admin_call(
+            request_builder(Method::PUT, "/api/v1/limits/example.com")
+                .body(Body::from(
+                    r#"{"breaker-failures":2,"breaker-open-ms":60000}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    for _ in 0..2 {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            HeaderMap::new(),
+            Bytes::new(),
+        ));
+        let response = harness.proxy_call(call()).await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let response = harness.proxy_call(call()).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.json()["error"], "circuit-open");
+    assert_eq!(harness.client.recordings().len(), 2);
+    assert_eq!(status().await, "open");
+
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(status().await, "half-open");
+    assert_eq!(harness.proxy_call(call()).await.status, StatusCode::OK);
+    assert_eq!(status().await, "closed");
+}
+
+#[tokio::test]
+async fn bodiless_responses_skip_body_faults() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let request = |method: Method, extra: Option<(&'static str, &'static str)>| {
+        let mut builder = request_builder(method, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-debug", "true")
+            .header("x-lowdown-stream-fault-percentage", "100")
+            .header("x-lowdown-stream-garbage-bytes", "4");
+        if let Some((name, value)) = extra {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    let no_content = || {
+        ProxiedResponse::new(
+            StatusCode::NO_CONTENT,
+            HeaderMap::new(),
+            Bytes::from_static(b"oops"),
+        )
+    };
+
+    harness.client.enqueue(no_content());
+    let response = harness.proxy_call(request(Method::GET, None)).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    assert!(response.body.is_empty());
+    let trace: Value =
+        serde_json::from_str(response.headers["x-lowdown-trace"].to_str().unwrap()).unwrap();
+    assert_eq!(trace["faults"], json!([]));
+
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(request(Method::HEAD, None)).await;
+    assert!(response.body.is_empty());
+
+    harness.client.enqueue(no_content());
+    let response = harness
+        .proxy_call(request(
+            Method::GET,
+            Some(("x-lowdown-fault-bodiless-responses", "true")),
+        ))
+        .await;
+    assert_eq!(response.body.len(), 8);
+
+    let response = harness
+        .proxy_call(request(
+            Method::HEAD,
+            Some(("x-lowdown-fail-before-percentage", "100")),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.body.is_empty());
+}
+
+#[tokio::test]
+async fn experiment_templates_apply_and_restore_settings() {
+    let harness = TestHarness::new();
+    let start = |template: &str, body: Value| {
+        request_builder(
+            Method::POST,
+            &format!("/api/v1/experiments/{template}/start"),
+        )
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+    };
+
+    let templates = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/experiments")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(templates.as_array().unwrap().len(), 5);
+    assert_eq!(templates[0]["name"], "dependency-outage");
+
+    let response = harness.admin_call(start("meteor-strike", json!({}))).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = harness
+        .admin_call(start("duplicate-delivery", json!({"status": 500})))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.json()["error"], "invalid-experiment");
+
+    let started = harness
+        .admin_call(start(
+            "dependency-outage",
+            json!({"status": 502, "match-uri-starts-with": "/orders", "duration-seconds": 60}),
+        ))
+        .await
+        .json();
+    assert_eq!(started["experiment"], "dependency-outage");
+    assert_eq!(started["settings"]["fail-before-percentage"], 100);
+    assert_eq!(started["settings"]["fail-before-code"], 502);
+
+    let (header_name, header_value) = destination_header();
+    let call = |uri: &str| {
+        request_builder(Method::GET, uri)
+            .header(header_name.clone(), header_value.clone())
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = harness.proxy_call(call("/orders/1")).await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("/users/1")).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let task = started["task"].as_str().unwrap();
+    let cancelled = harness
+        .admin_call(
+            request_builder(Method::DELETE, &format!("/api/v1/tasks/{task}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(cancelled.status, StatusCode::OK);
+    let list = || {
+        request_builder(Method::GET, "/api/v1/list")
+            .body(Body::empty())
+            .unwrap()
+    };
+    for _ in 0..50 {
+        if harness.admin_call(list()).await.json()["fail-before-percentage"] == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        harness.admin_call(list()).await.json()["fail-before-percentage"],
+        0
+    );
+    harness.client.enqueue(json_ok());
+    let response = harness.proxy_call(call("/orders/1")).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn experiment_guardrail_rolls_back_settings() {
+    let harness = TestHarness::new();
+    let started = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/experiments/slow-dependency/start")
+                .body(Body::from(
+                    json!({
+                        "delay-ms": 1,
+                        "guardrails": {"max-upstream-error-percentage": 50, "min-requests": 2},
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(started["settings"]["delay-before-percentage"], 100);
+
+    let (header_name, header_value) = destination_header();
+    for _ in 0..2 {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            HeaderMap::new(),
+            Bytes::from_static(b"boom"),
+        ));
+        let response = harness
+            .proxy_call(
+                request_builder(Method::GET, "/")
+                    .header(header_name.clone(), header_value.clone())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let tasks = || {
+        request_builder(Method::GET, "/api/v1/tasks")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let mut task = Value::Null;
+    for _ in 0..100 {
+        task = harness.admin_call(tasks()).await.json()[0].clone();
+        if task["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(task["id"], started["task"]);
+    assert_eq!(task["status"], "failed");
+    assert!(
+        task["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("guardrail tripped: upstream error rate 100%")
+    );
+
+    let settings = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/list")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(settings["delay-before-percentage"], 0);
+    let metrics = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(metrics["destinations"]["example.com"]["upstream-errors"], 2);
+    assert_eq!(metrics["destinations"]["example.com"]["error-responses"], 2);
+}
+
+#[tokio::test]
+async fn scenarios_step_through_phases_and_restore_settings() {
+    let scenario = json!({
+        "name": "brownout",
+        "phases": [
+            {"name": "outage", "duration-seconds": 120, "settings": {"fail-before-percentage": 50, "fail-before-code": 503}},
+            {"name": "slow", "duration-seconds": 300, "settings": {"delay-before-percentage": 100, "delay-before-ms": 300}},
+            {"name": "recover", "duration-seconds": 60},
+        ],
+    });
+    let start = |body: &Value| {
+        request_builder(Method::POST, "/api/v1/scenario")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let status = || {
+        request_builder(Method::GET, "/api/v1/scenario/status")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let cancel = || {
+        request_builder(Method::DELETE, "/api/v1/scenario")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let list = || {
+        request_builder(Method::GET, "/api/v1/list")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let harness = TestHarness::new();
+    assert_eq!(harness.admin_call(status()).await.json()["status"], "idle");
+    for duration in [0, 604_801, u64::MAX] {
+        let response = harness
+            .admin_call(start(&json!({"phases": [{"duration-seconds": duration}]})))
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["error"], "invalid-scenario");
+    }
+    let response = harness.admin_call(cancel()).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+
+    let started = harness.admin_call(start(&scenario)).await;
+    assert_eq!(started.status, StatusCode::OK);
+    assert_eq!(
+        started.json()["phases"][0]["settings"]["fail-before-code"],
+        "503"
+    );
+    let response = harness.admin_call(start(&scenario)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.json()["error"], "scenario-running");
+
+    let mut current = Value::Null;
+    for _ in 0..50 {
+        current = harness.admin_call(status()).await.json();
+        if harness.admin_call(list()).await.json()["fail-before-percentage"] == 50 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(current["name"], "brownout");
+    assert_eq!(current["status"], "running");
+    assert_eq!(current["phase"]["index"], 0);
+    assert_eq!(current["phase"]["name"], "outage");
+
+    let cancelled = harness.admin_call(cancel()).await;
+    assert_eq!(cancelled.status, StatusCode::OK);
+    for _ in 0..50 {
+        if harness.admin_call(list()).await.json()["fail-before-percentage"] == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        harness.admin_call(list()).await.json()["fail-before-percentage"],
+        0
+    );
+    assert_eq!(
+        harness.admin_call(status()).await.json()["status"],
+        "cancelled"
+    );
+
+    let clock = Arc::new(VirtualClock::new());
+    let harness = TestHarness::with_builder(|builder| builder.clock(clock.clone()));
+    let started = harness.admin_call(start(&scenario)).await;
+    assert_eq!(started.status, StatusCode::OK);
+    let mut current = Value::Null;
+    for _ in 0..50 {
+        current = harness.admin_call(status()).await.json();
+        if current["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(current["status"], "completed");
+    assert_eq!(clock.elapsed(), Duration::from_secs(480));
+    let settings = harness.admin_call(list()).await.json();
+    assert_eq!(settings["fail-before-percentage"], 0);
+    assert_eq!(settings["delay-before-percentage"], 0);
+}
+
+#[tokio::test]
+async fn access_log_writes_one_json_line_per_request() {
+    let path = std::env::temp_dir().join(format!("lowdown-access-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let access_log = AccessLog::to_file(AccessLogFormat::Json, &path).unwrap();
+    let harness = TestHarness::with_builder(|builder| builder.access_log(access_log));
+    let (header_name, header_value) = destination_header();
+
+    harness.client.enqueue(json_ok());
+    harness
+        .proxy_call(
+            request_builder(Method::GET, "/plain")
+                .header(header_name.clone(), header_value.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    harness
+        .proxy_call(
+            request_builder(Method::POST, "/failing")
+                .header(header_name, header_value)
+                .header("x-lowdown-fail-before-percentage", "100")
+                .header("x-lowdown-fail-before-code", "503")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+    let lines: Vec<Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["method"], "GET");
+    assert_eq!(lines[0]["uri"], "/plain");
+    assert_eq!(lines[0]["status"], 200);
+    assert_eq!(lines[0]["faults"], json!([]));
+    assert!(lines[0]["destination"].is_string());
+    assert!(lines[0]["latency_ms"].is_u64());
+    assert_eq!(lines[1]["method"], "POST");
+    assert_eq!(lines[1]["status"], 503);
+    assert_eq!(lines[1]["faults"], json!(["fail-before"]));
+}
+
+#[tokio::test]
+async fn kv_store_tracks_state_and_persists() {
+    let path = std::env::temp_dir().join(format!("lowdown-kv-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(
+        AppState::builder(shared)
+            .kv_store(KvStore::persistent(&path).unwrap())
+            .build(),
+    );
+    let harness = TestHarness::from_state(state.clone(), client);
+
+    let put = |key: &str, body: Value| {
+        request_builder(Method::PUT, &format!("/api/v1/kv/{key}"))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let get = |uri: &str| {
+        request_builder(Method::GET, uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let stored = harness
+        .admin_call(put("gate:orders", json!({"value": {"open": false}})))
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    assert_eq!(stored.json(), json!({"value": {"open": false}}));
+    let expiring = harness
+        .admin_call(put("gate:short", json!({"value": 1, "ttl-ms": 1})))
+        .await
+        .json();
+    assert!(expiring["expires-at"].is_string());
+    assert_eq!(
+        harness
+            .admin_call(put("bad", json!({"ttl-ms": 1})))
+            .await
+            .status,
+        StatusCode::BAD_REQUEST
+    );
+
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-trigger-first-per-key", "{header:x-user}")
+                .header("x-user", "alice")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(state.kv().increment("counter", 2, None), 2);
+    assert_eq!(state.kv().increment("counter", 3, None), 5);
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let gates = harness
+        .admin_call(get("/api/v1/kv?prefix=gate:"))
+        .await
+        .json();
+    assert_eq!(gates, json!({"gate:orders": {"value": {"open": false}}}));
+    let all = harness.admin_call(get("/api/v1/kv")).await.json();
+    assert!(
+        all.as_object()
+            .unwrap()
+            .contains_key("first-seen:{header:x-user}\nalice")
+    );
+    assert_eq!(
+        harness
+            .admin_call(get("/api/v1/kv/gate:short"))
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+
+    state.kv().flush().unwrap();
+    let reloaded = KvStore::persistent(&path).unwrap();
+    assert_eq!(reloaded.get("gate:orders"), Some(json!({"open": false})));
+    assert_eq!(reloaded.get("counter"), Some(json!(5)));
+
+    let deleted = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/kv/gate:orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(deleted.status, StatusCode::OK);
+    assert_eq!(state.kv().get("gate:orders"), None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn kv_store_evicts_the_oldest_keys_and_expires_on_its_clock() {
+    let clock = Arc::new(VirtualClock::new());
+    let kv = KvStore::default().with_clock(clock.clone());
+    kv.set("short", json!(1), Some(Duration::from_secs(1)));
+    kv.set("long", json!(2), Some(Duration::from_secs(60)));
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(kv.get("short"), None);
+    assert_eq!(kv.get("long"), Some(json!(2)));
+
+    for index in 0..100_000 {
+        kv.increment(&format!("every-nth:{index}"), 1, None);
+    }
+    assert_eq!(kv.get("short"), None);
+    assert_eq!(kv.get("long"), None);
+    assert_eq!(kv.get("every-nth:0"), Some(json!(1)));
+    kv.set("every-nth:0", json!(2), None);
+    kv.set("new", json!(3), None);
+    assert_eq!(kv.get("every-nth:0"), Some(json!(2)));
+    assert_eq!(kv.get("every-nth:1"), None);
+    assert_eq!(kv.get("new"), Some(json!(3)));
+    assert_eq!(kv.list("").len(), 100_000);
+}
+
+#[tokio::test]
+async fn drop_fault_never_forwards_nor_answers() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let started = Instant::now();
+    let response = harness
+        .proxy
+        .clone()
+        .oneshot(
+            request_builder(Method::POST, "/webhooks")
+                .header(header_name, header_value)
+                .header("x-lowdown-drop-percentage", "100")
+                .header("x-lowdown-drop-hold-ms", "50")
+                .body(Body::from("event"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert!(response.into_body().collect().await.is_err());
+    assert!(harness.client.recordings().is_empty());
+}
+
+#[tokio::test]
+async fn conditional_request_faults_break_cache_validators() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let cacheable = || {
+        let mut headers = HeaderMap::new();
+        headers.insert("etag", HeaderValue::from_static("W/\"v1\""));
+        headers.insert(
+            "last-modified",
+            HeaderValue::from_static("Wed, 01 May 2024 12:00:00 GMT"),
+        );
+        ProxiedResponse::new(StatusCode::OK, headers, Bytes::from_static(b"upstream"))
+    };
+    let request = |fault: &'static str, conditional: bool| {
+        let mut builder = request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header(fault, "100");
+        if conditional {
+            builder = builder.header("if-none-match", "W/\"v0\"");
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    harness.client.enqueue(cacheable());
+    let response = harness
+        .proxy_call(request("x-lowdown-strip-validators-percentage", false))
+        .await;
+    assert!(response.headers.get("etag").is_none());
+    assert!(response.headers.get("last-modified").is_none());
+
+    harness.client.enqueue(cacheable());
+    let response = harness
+        .proxy_call(request("x-lowdown-corrupt-validators-percentage", false))
+        .await;
+    let etag = response.headers["etag"].to_str().unwrap();
+    assert!(etag.starts_with("W/\"lowdown-"));
+    assert_eq!(
+        response.headers["last-modified"],
+        "Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+
+    harness.client.enqueue(cacheable());
+    let response = harness
+        .proxy_call(request("x-lowdown-force-not-modified-percentage", false))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert!(response.body.is_empty());
+    assert_eq!(response.headers["etag"], "W/\"v1\"");
+
+    harness.client.enqueue(cacheable());
+    let response = harness
+        .proxy_call(request("x-lowdown-force-not-modified-percentage", true))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, Bytes::from("upstream"));
+}
+
+#[tokio::test]
+async fn outbound_bindings_apply_per_destination() {
+    let harness = TestHarness::new();
+    let binding = |method: Method, body: Body| {
+        request_builder(method, "/api/v1/outbound-bindings/Example.com")
+            .body(body)
+            .unwrap()
+    };
+    let stored = harness
+        .admin_call(binding(
+            Method::PUT,
+            Body::from(json!({"local-address": "127.0.0.2"}).to_string()),
+        ))
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    assert_eq!(stored.json(), json!({"local-address": "127.0.0.2"}));
+    for invalid in [json!({}), json!({"local-address": "not-an-ip"})] {
+        let response = harness
+            .admin_call(binding(Method::PUT, Body::from(invalid.to_string())))
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["error"], "invalid-outbound-binding");
+    }
+
+    let (header_name, header_value) = destination_header();
+    let call = |destination: Option<&'static str>| {
+        let mut builder = request_builder(Method::GET, "/");
+        builder = match destination {
+            Some(url) => builder.header("x-lowdown-destination-url", url),
+            None => builder.header(header_name.clone(), header_value.clone()),
+        };
+        builder.body(Body::empty()).unwrap()
+    };
+    harness.proxy_call(call(None)).await;
+    harness
+        .proxy_call(call(Some("http://other.example.com")))
+        .await;
+    let recordings = harness.client.recordings();
+    assert_eq!(
+        recordings[0].binding,
+        Some(OutboundBinding {
+            local_address: Some("127.0.0.2".parse().unwrap()),
+            interface: None,
+        })
+    );
+    assert_eq!(recordings[1].binding, None);
+
+    let removed = harness
+        .admin_call(binding(Method::DELETE, Body::empty()))
+        .await;
+    assert_eq!(removed.status, StatusCode::OK);
+    assert_eq!(
+        harness
+            .admin_call(binding(Method::GET, Body::empty()))
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn upstream_credentials_are_injected_per_destination() {
+    let harness = TestHarness::new();
+    let credentials = |method: Method, body: Body| {
+        request_builder(method, "/api/v1/upstream-credentials/Example.com")
+            .body(body)
+            .unwrap()
+    };
+    let put = |body: Value| credentials(Method::PUT, Body::from(body.to_string()));
+    let stored = harness
+        .admin_call(put(json!({"scheme": "bearer", "secret": "s3cret"})))
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    assert_eq!(
+        stored.json(),
+        json!({"scheme": "bearer", "secret": "<redacted>"})
+    );
+    for invalid in [
+        json!({"scheme": "bearer"}),
+        json!({"scheme": "basic", "secret": "pw"}),
+        json!({"scheme": "header", "header": "x-api-key", "template": "Key", "secret": "k"}),
+        json!({"scheme": "bearer", "secret-env": "LOWDOWN_TEST_UNSET_SECRET"}),
+    ] {
+        let response = harness.admin_call(put(invalid)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["error"], "invalid-upstream-credentials");
+    }
+
+    let call = |destination: &'static str| {
+        request_builder(Method::GET, "/")
+            .header("x-lowdown-destination-url", destination)
+            .header("authorization", "Bearer client")
+            .body(Body::empty())
+            .unwrap()
+    };
+    harness.proxy_call(call("http://example.com")).await;
+    harness.proxy_call(call("http://other.example.com")).await;
+    let recordings = harness.client.recordings();
+    assert_eq!(recordings[0].headers["authorization"], "Bearer s3cret");
+    assert_eq!(recordings[1].headers["authorization"], "Bearer client");
+
+    harness
+        .admin_call(put(
+            json!({"scheme": "basic", "username": "svc", "secret": "pw"}),
+        ))
+        .await;
+    harness.proxy_call(call("http://example.com")).await;
+    assert_eq!(
+        harness.client.recordings()[2].headers["authorization"],
+        "Basic c3ZjOnB3"
+    );
+
+    let path = std::env::temp_dir().join(format!("lowdown-secret-{}", std::process::id()));
+    std::fs::write(&path, "from-file\n").unwrap();
+    let stored = harness
+        .admin_call(put(json!({
+            "scheme": "header",
+            "header": "x-api-key",
+            "template": "Key {secret}",
+            "secret-file": path,
+        })))
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    harness.proxy_call(call("http://example.com")).await;
+    assert_eq!(
+        harness.client.recordings()[3].headers["x-api-key"],
+        "Key from-file"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+    let response = harness.proxy_call(call("http://example.com")).await;
+    assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        response.json(),
+        json!({"error": "upstream-credentials-unavailable", "destination": "example.com"})
+    );
+    assert_eq!(harness.client.recordings().len(), 4);
+
+    let listed = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/upstream-credentials")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(listed.json()["example.com"]["scheme"], "header");
+    let removed = harness
+        .admin_call(credentials(Method::DELETE, Body::empty()))
+        .await;
+    assert_eq!(removed.status, StatusCode::OK);
+    assert_eq!(
+        harness
+            .admin_call(credentials(Method::GET, Body::empty()))
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn upstream_redirects_are_passed_through_without_credentials() {
+    let leaked = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let other = listener.local_addr().unwrap();
+    let seen = leaked.clone();
+    let upstream = Router::new().route(
+        "/leak",
+        axum::routing::get(move |headers: HeaderMap| async move {
+            seen.lock().push(headers.get("x-api-key").cloned());
+            "leaked"
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, upstream).await });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let redirecting = listener.local_addr().unwrap();
+    let location = format!("http://{other}/leak");
+    let upstream = Router::new().route(
+        "/",
+        axum::routing::get(move || async move {
+            (StatusCode::FOUND, [("location", location)], "moved")
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+    let client: SharedHttpClient = Arc::new(ReqwestHttpClient::new().unwrap());
+    let state = Arc::new(AppState::new(SettingsLayer::default(), client));
+    let harness = TestHarness::from_state(state, Arc::new(StubClient::new()));
+    let stored = harness
+        .admin_call(
+            request_builder(
+                Method::PUT,
+                &format!("/api/v1/upstream-credentials/{redirecting}"),
+            )
+            .body(Body::from(
+                json!({"scheme": "header", "header": "x-api-key", "secret": "k"}).to_string(),
+            ))
+            .unwrap(),
+        )
+        .await;
+    assert_eq!(stored.status, StatusCode::OK);
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header("x-lowdown-destination-url", format!("http://{redirecting}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FOUND);
+    assert_eq!(
+        response.headers["location"],
+        format!("http://{other}/leak").as_str()
+    );
+    assert!(leaked.lock().is_empty());
+}
+
+#[tokio::test]
+async fn default_behavior_handles_requests_without_destination() {
+    let harness = TestHarness::new();
+    let set = |body: Value| {
+        request_builder(Method::PUT, "/api/v1/default-behavior")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let call = || {
+        request_builder(Method::POST, "/orders?id=1")
+            .header("x-request-id", "abc")
+            .body(Body::from("hello"))
+            .unwrap()
+    };
+
+    let response = harness.proxy_call(call()).await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.json()["error"], "missing-destination-url");
+
+    for invalid in [
+        json!({"mode": "upstream", "url": "ftp://example.com"}),
+        json!({"mode": "respond", "status": 42}),
+        json!({"mode": "teleport"}),
+    ] {
+        let response = harness.admin_call(set(invalid)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.json()["error"], "invalid-default-behavior");
+    }
+
+    harness
+        .admin_call(set(json!({
+            "mode": "respond",
+            "status": 404,
+            "body": "no route",
+            "content-type": "text/plain",
+        })))
+        .await;
+    let response = harness.proxy_call(call()).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.headers["content-type"], "text/plain");
+    assert_eq!(response.body, Bytes::from("no route"));
+
+    harness.admin_call(set(json!({"mode": "echo"}))).await;
+    let echoed = harness.proxy_call(call()).await.json();
+    assert_eq!(echoed["method"], "POST");
+    assert_eq!(echoed["uri"], "/orders?id=1");
+    assert_eq!(echoed["headers"]["x-request-id"], "abc");
+    assert_eq!(echoed["body"], "hello");
+
+    let current = harness
+        .admin_call(set(
+            json!({"mode": "upstream", "url": "http://fallback.example.com"}),
+        ))
+        .await
+        .json();
+    assert_eq!(current["url"], "http://fallback.example.com");
+    let response = harness.proxy_call(call()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        harness.client.recordings()[0].url,
+        "http://fallback.example.com/orders?id=1"
+    );
+
+    let reset = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/default-behavior")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(reset, json!({"mode": "error"}));
+}
+
+#[tokio::test]
+async fn transform_pipeline_runs_steps_in_order() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let pipeline = json!([
+        {"step": "set-header", "name": "x-tenant", "value": "blue", "phase": "request"},
+        {"step": "replace-body-regex", "pattern": "(\\d+)", "replacement": "<$1>", "phase": "request"},
+        {"step": "remove-header", "name": "x-internal"},
+        {"step": "replace-body-regex", "pattern": "upstream", "replacement": "first"},
+        {"step": "replace-body-regex", "pattern": "first", "replacement": "second"},
+        {"step": "set-status", "status": 418},
+        {"step": "set-header", "name": "x-transformed", "value": "yes"},
+    ]);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-internal", HeaderValue::from_static("secret"));
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        headers,
+        Bytes::from_static(b"upstream"),
+    ));
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/orders")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-request-buffering", "false")
+                .header("x-lowdown-transform", pipeline.to_string())
+                .body(Body::from("order 42"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::IM_A_TEAPOT);
+    assert_eq!(response.body, Bytes::from("second"));
+    assert_eq!(response.headers["x-transformed"], "yes");
+    assert!(response.headers.get("x-internal").is_none());
+    let recorded = &harness.client.recordings()[0];
+    assert_eq!(recorded.headers["x-tenant"], "blue");
+    assert_eq!(recorded.body, Bytes::from("order <42>"));
+
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header(
+                    "x-lowdown-transform",
+                    json!([{"step": "set-status", "status": 42}]).to_string(),
+                )
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn repeated_response_headers_survive_faults_and_transforms() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let mut headers = HeaderMap::new();
+    headers.append("set-cookie", HeaderValue::from_static("a=1; Path=/"));
+    headers.append(
+        "set-cookie",
+        HeaderValue::from_static("b=2; Expires=Wed, 21 Oct 2026 07:28:00 GMT"),
+    );
+    headers.append("vary", HeaderValue::from_static("accept"));
+    headers.append("vary", HeaderValue::from_static("accept-encoding"));
+    headers.append("etag", HeaderValue::from_static("\"v1\""));
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        headers,
+        Bytes::from_static(b"upstream"),
+    ));
+    let pipeline = json!([
+        {"step": "append-header", "name": "set-cookie", "value": "c=3"},
+        {"step": "replace-body-regex", "pattern": "upstream", "replacement": "edited"},
+    ]);
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/")
+                .header(header_name, header_value)
+                .header("x-lowdown-transform", pipeline.to_string())
+                .header("x-lowdown-corrupt-validators-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.body, Bytes::from("edited"));
+    let values = |name: &str| -> Vec<&str> {
+        response
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    };
+    assert_eq!(
+        values("set-cookie"),
+        [
+            "a=1; Path=/",
+            "b=2; Expires=Wed, 21 Oct 2026 07:28:00 GMT",
+            "c=3"
+        ]
+    );
+    assert_eq!(values("vary"), ["accept", "accept-encoding"]);
+    assert_ne!(values("etag"), ["\"v1\""]);
+}
+
+#[tokio::test]
+async fn snapshots_dump_tagged_exchanges() {
+    let dir = std::env::temp_dir().join(format!("lowdown-snapshots-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(
+        AppState::builder(shared)
+            .snapshots(SnapshotWriter::new(&dir))
+            .build(),
+    );
+    let harness = TestHarness::from_state(state, client);
+
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::POST, "/orders?id=1")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-test-id", "checkout/happy path")
+                .header("authorization", "Bearer secret")
+                .header("accept", "application/json")
+                .header("accept", "text/plain")
+                .body(Body::from("{\"sku\":\"abc\"}"))
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/orders")
+                .header(header_name.clone(), header_value.clone())
+                .header("x-lowdown-test-id", "checkout/happy path")
+                .header("x-lowdown-fail-before-percentage", "100")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    harness
+        .proxy_call(
+            request_builder(Method::GET, "/untagged")
+                .header(header_name, header_value)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+
+    let test_dir = dir.join("checkout_happy_path");
+    let read = |name: &str| -> Value {
+        serde_json::from_slice(&std::fs::read(test_dir.join(name)).unwrap()).unwrap()
+    };
+    let first = read("0001.json");
+    assert_eq!(first["test-id"], "checkout_happy_path");
+    assert_eq!(first["sequence"], 1);
+    assert_eq!(first["request"]["method"], "POST");
+    assert_eq!(first["request"]["uri"], "/orders?id=1");
+    assert_eq!(first["request"]["headers"]["authorization"], "[redacted]");
+    assert_eq!(
+        first["request"]["headers"]["accept"],
+        json!(["application/json", "text/plain"])
+    );
+    assert_eq!(first["request"]["body"], "{\"sku\":\"abc\"}");
+    assert_eq!(first["response"]["status"], 200);
+    assert_eq!(first["response"]["body"], "ok");
+    assert_eq!(first["faults"], json!([]));
+    let second = read("0002.json");
+    assert_eq!(second["sequence"], 2);
+    assert_eq!(second["response"]["status"], 503);
+    assert_eq!(second["faults"], json!(["fail-before"]));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recorded_exchanges_can_be_inspected_and_replayed() {
+    let dir = std::env::temp_dir().join(format!("lowdown-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let harness =
+        |writer: SnapshotWriter| TestHarness::with_builder(|builder| builder.snapshots(writer));
+    async fn call(harness: &TestHarness, uri: &str, fail: &str) -> ResponseParts {
+        let request = request_builder(Method::GET, uri)
+            .header("x-lowdown-destination-url", "http://example.com")
+            .header("x-lowdown-test-id", "orders")
+            .header("x-lowdown-fail-before-percentage", fail)
+            .body(Body::empty())
+            .unwrap();
+        harness.proxy_call(request).await
+    }
+
+    let recorder = harness(SnapshotWriter::new(&dir));
+    recorder.client.enqueue(json_ok());
+    assert_eq!(call(&recorder, "/orders", "0").await.status, StatusCode::OK);
+    assert_eq!(
+        call(&recorder, "/orders", "100").await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    let listed = recorder
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/recordings")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(listed[0]["test-id"], "orders");
+    assert_eq!(listed[0]["exchanges"], 2);
+    let recording = recorder
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/recordings/orders")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(recording["exchanges"][0]["response"]["body"], "upstream");
+    assert_eq!(recording["exchanges"][1]["faults"], json!(["fail-before"]));
+
+    let replayer = harness(SnapshotWriter::new(&dir));
+    let started = replayer
+        .admin_call(
+            request_builder(Method::PUT, "/api/v1/recordings/orders")
+                .body(Body::from(r#"{"mode":"replay"}"#))
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(started["mode"], "replay");
+    assert_eq!(started["exchanges"], 2);
+    let first = call(&replayer, "/orders", "0").await;
+    assert_eq!(first.status, StatusCode::OK);
+    assert_eq!(first.body, Bytes::from_static(b"upstream"));
+    for _ in 0..2 {
+        let replayed = call(&replayer, "/orders", "0").await;
+        assert_eq!(replayed.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(replayed.json()["error"], "fail-before");
+    }
+    let missing = call(&replayer, "/refunds", "0").await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert_eq!(missing.json()["error"], "no-recording");
+    assert!(replayer.client.recordings().is_empty());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recording_sessions_sample_and_cap_bodies() {
+    let dir = std::env::temp_dir().join(format!("lowdown-recordings-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(
+        AppState::builder(shared)
+            .snapshots(SnapshotWriter::new(&dir))
+            .build(),
+    );
+    let harness = TestHarness::from_state(state, client);
+    let start = |test_id: &str, config: Value| {
+        request_builder(Method::PUT, &format!("/api/v1/recordings/{test_id}"))
+            .body(Body::from(config.to_string()))
+            .unwrap()
+    };
+    let capped = harness
+        .admin_call(start(
+            "capped",
+            json!({"match-method": "post", "match-uri-starts-with": "/orders", "max-body-bytes": 4}),
+        ))
+        .await;
+    assert_eq!(capped.status, StatusCode::OK);
+    assert_eq!(capped.json()["sample-percentage"], 100);
+    harness
+        .admin_call(start("muted", json!({"sample-percentage": 0})))
+        .await;
+    let invalid = harness
+        .admin_call(start("bad", json!({"sample-percentage": 101})))
+        .await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.json()["error"], "invalid-recording-session");
+
+    let (header_name, header_value) = destination_header();
+    for (test_id, method, path) in [
+        ("capped", Method::POST, "/orders"),
+        ("capped", Method::GET, "/orders"),
+        ("capped", Method::POST, "/users"),
+        ("muted", Method::POST, "/orders"),
+    ] {
+        harness.client.enqueue(ProxiedResponse::new(
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from_static(b"upstream body"),
+        ));
+        harness
+            .proxy_call(
+                request_builder(method, path)
+                    .header(header_name.clone(), header_value.clone())
+                    .header("x-lowdown-test-id", test_id)
+                    .body(Body::from("{\"sku\":\"abc\"}"))
+                    .unwrap(),
+            )
+            .await;
+    }
+
+    let exchange: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("capped/0001.json")).unwrap()).unwrap();
+    assert_eq!(exchange["request"]["body"], "{\"sk");
+    assert_eq!(exchange["request"]["body-truncated"], true);
+    assert_eq!(exchange["response"]["body"], "upst");
+    assert_eq!(exchange["response"]["body-truncated"], true);
+    assert_eq!(std::fs::read_dir(dir.join("capped")).unwrap().count(), 1);
+    assert!(!dir.join("muted").exists());
+
+    let sessions = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/recordings")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(sessions[0]["test-id"], "capped");
+    assert_eq!(sessions[0]["recorded"], 1);
+    assert_eq!(sessions[0]["skipped"], 2);
+    assert_eq!(sessions[1]["test-id"], "muted");
+    assert_eq!(sessions[1]["skipped"], 1);
+    let stopped = harness
+        .admin_call(
+            request_builder(Method::DELETE, "/api/v1/recordings/muted")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(stopped.status, StatusCode::OK);
+    let missing = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/recordings/muted")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recordings_without_a_session_are_bounded() {
+    let harness =
+        TestHarness::with_builder(|builder| builder.snapshots(SnapshotWriter::in_memory()));
+    let (header_name, header_value) = destination_header();
+    let record = |test_id: String, body: Vec<u8>| {
+        request_builder(Method::POST, "/orders")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-test-id", test_id)
+            .body(Body::from(body))
+            .unwrap()
+    };
+    harness
+        .proxy_call(record(
+            "first".to_string(),
+            vec![b'a'; snapshots::DEFAULT_MAX_BODY_BYTES + 1],
+        ))
+        .await;
+    let recording = |test_id: &str| {
+        request_builder(Method::GET, &format!("/api/v1/recordings/{test_id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let first = harness.admin_call(recording("first")).await.json();
+    let body = first["exchanges"][0]["request"]["body"].as_str().unwrap();
+    assert_eq!(body.len(), snapshots::DEFAULT_MAX_BODY_BYTES);
+    assert_eq!(first["exchanges"][0]["request"]["body-truncated"], true);
+
+    harness
+        .admin_call(
+            request_builder(Method::PUT, "/api/v1/recordings/session")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await;
+    harness
+        .proxy_call(record("session".to_string(), Vec::new()))
+        .await;
+    for index in 0..snapshots::MAX_KEPT_TEST_IDS {
+        harness
+            .proxy_call(record(format!("test-{index}"), Vec::new()))
+            .await;
+    }
+    let evicted = harness.admin_call(recording("first")).await;
+    assert_eq!(evicted.status, StatusCode::NOT_FOUND);
+    let kept = harness.admin_call(recording("session")).await.json();
+    assert_eq!(kept["exchanges"].as_array().unwrap().len(), 1);
+    let evicted = harness.admin_call(recording("test-0")).await;
+    assert_eq!(evicted.status, StatusCode::NOT_FOUND);
+    let last = format!("test-{}", snapshots::MAX_KEPT_TEST_IDS - 1);
+    let kept = harness.admin_call(recording(&last)).await;
+    assert_eq!(kept.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn response_bodies_are_written_to_files() {
+    let dir = std::env::temp_dir().join(format!("lowdown-tee-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let client = Arc::new(StubClient::new());
+    let shared: SharedHttpClient = client.clone();
+    let state = Arc::new(AppState::builder(shared).response_tee_dir(&dir).build());
+    let harness = TestHarness::from_state(state, client);
+    let payload = Bytes::from_static(&[0x89, b'P', b'N', b'G', 0x00, 0xff]);
+    harness.client.enqueue(ProxiedResponse::new(
+        StatusCode::OK,
+        HeaderMap::new(),
+        payload.clone(),
+    ));
+    let (header_name, header_value) = destination_header();
+    let response = harness
+        .proxy_call(
+            request_builder(Method::GET, "/images/logo.png")
+                .header(header_name, header_value)
+                .header(
+                    "x-lowdown-response-tee-path",
+                    "../{header:x-request-id}/{method}{path}",
+                )
+                .header("x-request-id", "abc 123")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(response.body, payload);
+
+    let path = dir.join("abc_123/GET/images/logo.png");
+    let mut written = None;
+    for _ in 0..100 {
+        if let Ok(bytes) = std::fs::read(&path) {
+            written = Some(bytes);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(written.as_deref(), Some(payload.as_ref()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn upstream_protocol_is_selected_per_rule() {
+    let harness = TestHarness::new();
+    let (header_name, header_value) = destination_header();
+    let call = |protocol: &'static str, fallback: &'static str| {
+        request_builder(Method::GET, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-debug", "true")
+            .header("x-lowdown-upstream-protocol", protocol)
+            .header("x-lowdown-http3-fallback-percentage", fallback)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    harness.proxy_call(call("http1", "0")).await;
+    harness.proxy_call(call("http2", "100")).await;
+    let response = harness.proxy_call(call("http3", "100")).await;
+    harness.proxy_call(call("http3", "0")).await;
+    let trace: Value =
+        serde_json::from_str(response.headers["x-lowdown-trace"].to_str().unwrap()).unwrap();
+
+    let protocols: Vec<UpstreamProtocol> = harness
+        .client
+        .recordings()
+        .iter()
+        .map(|request| request.protocol)
+        .collect();
+    if HTTP3_SUPPORTED {
+        assert_eq!(trace["faults"], json!(["http3-fallback"]));
+        assert_eq!(
+            protocols,
+            vec![
+                UpstreamProtocol::Http1,
+                UpstreamProtocol::Http2,
+                UpstreamProtocol::Http2,
+                UpstreamProtocol::Http3,
+            ]
+        );
+    } else {
+        assert_eq!(trace["faults"], json!([]));
+        assert_eq!(
+            protocols,
+            vec![
+                UpstreamProtocol::Http1,
+                UpstreamProtocol::Http2,
+                UpstreamProtocol::Auto,
+                UpstreamProtocol::Auto,
+            ]
+        );
+    }
+
+    let schema = harness
+        .admin_call(
+            request_builder(Method::GET, "/api/v1/schema")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    let setting = schema
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|setting| setting["name"] == "upstream-protocol")
+        .unwrap()
+        .clone();
+    assert_eq!(setting["default"], "auto");
+    assert_eq!(setting["enum"], json!(["auto", "http1", "http2", "http3"]));
+}
+
+#[tokio::test]
+async fn tls_overrides_are_passed_to_the_client() {
+    let harness = TestHarness::new();
+    let update = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-tls-server-name", "a.example.com")
+                .header("x-lowdown-match-host", "10.0.0.5")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+    assert_eq!(update.status, StatusCode::OK);
+
+    let call = |destination: &'static str, skip_verify: Option<&'static str>| {
+        let mut builder = request_builder(Method::GET, "/")
+            .header("x-lowdown-destination-url", destination)
+            .header("host", destination.trim_start_matches("https://"));
+        if let Some(value) = skip_verify {
+            builder = builder.header("x-lowdown-tls-skip-verify", value);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    let response = harness
+        .proxy_call(call("https://10.0.0.5", Some("true")))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    harness.proxy_call(call("https://example.com", None)).await;
+
+    let recordings = harness.client.recordings();
+    assert_eq!(recordings[0].url, "https://10.0.0.5/");
+    assert_eq!(recordings[0].headers["host"], "10.0.0.5");
+    assert_eq!(
+        recordings[0].tls,
+        TlsOverride {
+            server_name: Some("a.example.com".to_string()),
+            skip_verify: true,
+        }
+    );
+    assert_eq!(recordings[1].tls, TlsOverride::default());
+}
+
+#[tokio::test]
+async fn percentage_expressions_use_request_attributes() {
+    let harness = TestHarness::new();
+    let expression = r#"if header x-tier == "free" and method == "GET" then 100 else if header x-retries >= 3 then 100 else 0"#;
+    let update = harness
+        .admin_call(
+            request_builder(Method::POST, "/api/v1/update")
+                .header("x-lowdown-fail-before-percentage", expression)
+                .header("x-lowdown-delay-before-percentage", "if path ==")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .json();
+    assert_eq!(update["fail-before-percentage"], expression);
+    assert_eq!(update["delay-before-percentage"], 0);
+
+    let (header_name, header_value) = destination_header();
+    let call = |method: Method, headers: &[(&'static str, &'static str)]| {
+        let mut builder = request_builder(method, "/")
+            .header(header_name.clone(), header_value.clone())
+            .header("x-lowdown-debug", "true");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+    let free = harness
+        .proxy_call(call(Method::GET, &[("x-tier", "free")]))
+        .await;
+    assert_eq!(free.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(free.json()["trace"]["rolls"][0]["percentage"], 100);
+    let paid = harness
+        .proxy_call(call(Method::GET, &[("x-tier", "paid")]))
+        .await;
+    assert_eq!(paid.status, StatusCode::OK);
+    let free_post = harness
+        .proxy_call(call(Method::POST, &[("x-tier", "free")]))
+        .await;
+    assert_eq!(free_post.status, StatusCode::OK);
+    let retried = harness
+        .proxy_call(call(Method::POST, &[("x-retries", "4")]))
+        .await;
+    assert_eq!(retried.status, StatusCode::SERVICE_UNAVAILABLE);
 }